@@ -1,11 +1,14 @@
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use recursion::{
     examples::expr::{
+        bytecode::{compile, run},
+        cse::{cse, eval_shared},
         eval::{eval_layer, eval_lazy, eval_lazy_with_fused_compile, naive_eval},
         naive::ExprAST,
         BlocAllocExpr, DFSStackExpr, Expr,
     },
     recursive::{Collapse, Expand},
+    recursive_tree::CollapseBuffer,
 };
 
 fn bench_eval(criterion: &mut Criterion) {
@@ -37,7 +40,16 @@ fn bench_eval(criterion: &mut Criterion) {
 
         // println!("heap size for depth {}: dfs {}", big_expr_dfs.len);
 
-        test_cases.push((depth, big_expr_bloc_alloc, big_expr_dfs, boxed_big_expr));
+        // compiled ahead of time, so only the interpreter is measured
+        let program = compile(big_expr_bloc_alloc.as_ref());
+
+        test_cases.push((
+            depth,
+            big_expr_bloc_alloc,
+            big_expr_dfs,
+            boxed_big_expr,
+            program,
+        ));
     }
 
     let mut group = criterion.benchmark_group("evaluate expression tree");
@@ -45,7 +57,9 @@ fn bench_eval(criterion: &mut Criterion) {
     // let plot_config = PlotConfiguration::default().summary_scale(AxisScale::Logarithmic);
     // group.plot_config(plot_config);
 
-    for (depth, big_expr_bloc_alloc, big_expr_dfs, boxed_big_expr) in test_cases.into_iter() {
+    for (depth, big_expr_bloc_alloc, big_expr_dfs, boxed_big_expr, program) in
+        test_cases.into_iter()
+    {
         group.bench_with_input(
             BenchmarkId::new("traditional boxed method", depth),
             &boxed_big_expr,
@@ -56,6 +70,14 @@ fn bench_eval(criterion: &mut Criterion) {
             &big_expr_bloc_alloc,
             |b, expr| b.iter(|| expr.as_ref().collapse_layers(eval_layer)),
         );
+        group.bench_with_input(
+            BenchmarkId::new("my new fold method, reusing a buffer", depth),
+            &big_expr_bloc_alloc,
+            |b, expr| {
+                let mut buffer = CollapseBuffer::new();
+                b.iter(|| buffer.collapse_layers(expr.as_ref(), eval_layer))
+            },
+        );
         group.bench_with_input(
             BenchmarkId::new("fold dfs stack", depth),
             &big_expr_dfs,
@@ -71,9 +93,39 @@ fn bench_eval(criterion: &mut Criterion) {
             &boxed_big_expr,
             |b, expr| b.iter(|| eval_lazy_with_fused_compile(expr)),
         );
+        group.bench_with_input(
+            BenchmarkId::new("stack bytecode interpreter", depth),
+            &program,
+            |b, program| b.iter(|| run(program)),
+        );
+    }
+    group.finish();
+}
+
+fn bench_shared(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("evaluate shared subexpressions");
+
+    for n in [15, 20, 25] {
+        // fibonacci-style duplication: the tree grows exponentially with n, the dag linearly
+        let tree = BlocAllocExpr::expand_layers(n, |n| match n {
+            0 | 1 => Expr::LiteralInt(n),
+            _ => Expr::Add(n - 1, n - 2),
+        });
+        let (dag, _) = cse(&tree);
+
+        group.bench_with_input(BenchmarkId::new("tree", n), &tree, |b, expr| {
+            b.iter(|| expr.as_ref().collapse_layers(eval_layer))
+        });
+        group.bench_with_input(BenchmarkId::new("dag", n), &dag, |b, dag| {
+            b.iter(|| eval_shared(dag))
+        });
+        // hash-consing visits every node of the tree, so it only pays off if the dag is reused
+        group.bench_with_input(BenchmarkId::new("cse then dag", n), &tree, |b, expr| {
+            b.iter(|| eval_shared(&cse(expr).0))
+        });
     }
     group.finish();
 }
 
-criterion_group!(benches, bench_eval);
+criterion_group!(benches, bench_eval, bench_shared);
 criterion_main!(benches);
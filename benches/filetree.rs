@@ -0,0 +1,51 @@
+//! Searching a checkout, comparing async reads on tokio with blocking reads on a rayon pool. The
+//! directory searched is 'FILETREE_BENCH_DIR', or this repository if it isn't set: point it at a
+//! large checkout for numbers that mean something.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::path::PathBuf;
+
+// the filetree example, of which the benchmark only uses part. Its tests aren't run from here, so
+// their imports go unused when checked with 'cfg(test)'
+#[allow(dead_code, unused_imports)]
+#[path = "../examples/filetree/mod.rs"]
+mod filetree;
+
+use filetree::build::{build_file_tree, BuildOptions};
+use filetree::search::{search, search_parallel, SearchOptions};
+
+fn bench_search(criterion: &mut Criterion) {
+    let dir = std::env::var_os("FILETREE_BENCH_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    // as a checkout would be searched, leaving out eg build output
+    let build_options = BuildOptions {
+        respect_ignore_files: true,
+        ..BuildOptions::default()
+    };
+    let tree = runtime
+        .block_on(build_file_tree(
+            dir.to_string_lossy().to_string(),
+            &build_options,
+        ))
+        .unwrap();
+    let regex = regex::Regex::new(r"fn \w+_layer").unwrap();
+    let options = SearchOptions::default();
+
+    let mut group = criterion.benchmark_group("search a checkout");
+    group.bench_function("async", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(search(tree.clone(), dir.clone(), &regex, &options))
+                .unwrap()
+        })
+    });
+    group.bench_function("rayon", |b| {
+        b.iter(|| search_parallel(&tree, &dir, &regex, &options))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_search);
+criterion_main!(benches);
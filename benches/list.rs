@@ -0,0 +1,34 @@
+//! Building a string from a linked list of its chars, by copying the rest of the string into a new
+//! one for each char, and by pushing each char onto one buffer.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use recursion::{
+    examples::linked_list::{from_str, to_str, RecursiveString},
+    list::ListLayer,
+    recursive::Collapse,
+};
+
+// the quadratic version 'to_str' replaced
+fn to_str_by_format(r: RecursiveString) -> String {
+    r.collapse_layers(|layer| match layer {
+        ListLayer::Cons(c, s) => format!("{}{}", c, s),
+        ListLayer::Nil => String::new(),
+    })
+}
+
+fn bench_to_str(criterion: &mut Criterion) {
+    let s = "abc".repeat(1000);
+    let list = from_str(&s);
+
+    let mut group = criterion.benchmark_group("build a string from a list");
+    group.bench_function("format each layer", |b| {
+        b.iter(|| to_str_by_format(list.clone()))
+    });
+    group.bench_function("collapse into a buffer", |b| {
+        b.iter(|| to_str(list.clone()))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_to_str);
+criterion_main!(benches);
@@ -0,0 +1,247 @@
+//! Runs each of the example structures on the arguments given, printing what it computes, so that
+//! every one of them has an entry point that can be tried out from the command line. File trees
+//! have their own, richer, command line: see the 'grep' example, eg
+//! 'cargo run --example grep -- du'.
+//!
+//! Run with eg 'cargo run --example demo --features expr_example -- expr "let x = 2 in x * 3"'.
+
+use clap::{Parser, Subcommand};
+use recursion::examples::avl;
+use recursion::examples::bst::BstLayer;
+use recursion::examples::expr::lang::eval::eval_arena;
+use recursion::examples::expr::lang::optimize::optimize;
+use recursion::examples::expr::lang::parse::parse;
+use recursion::examples::expr::lang::pretty::pretty;
+use recursion::examples::expr::lang::Value;
+use recursion::examples::minimax::{alpha_beta, game_tree, Board, Position};
+use recursion::examples::quadtree::{self, Point, QuadLayerRef, Rect};
+use recursion::examples::rope::Rope;
+use recursion::examples::trie;
+use recursion::list::{ListLayer, RecursiveList};
+use recursion::recursive::Collapse;
+use recursion::render::TreeLines;
+
+/// Try out the example structures
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// parse, optimize and evaluate an expression
+    Expr { source: String },
+
+    /// build a list from the items given and print it
+    List { items: Vec<String> },
+
+    /// split text into a rope and print the chars in 'start..end'
+    Rope {
+        text: String,
+        start: usize,
+        end: usize,
+        /// the most chars in a single leaf
+        #[clap(long, default_value = "8")]
+        leaf_len: usize,
+    },
+
+    /// build a trie from the words given and print those starting with 'prefix'
+    Trie { prefix: String, words: Vec<String> },
+
+    /// insert each value into an AVL tree, and print the balanced tree
+    Avl {
+        #[clap(allow_hyphen_values = true)]
+        values: Vec<i64>,
+    },
+
+    /// score a tic-tac-toe board, written as its nine cells, eg 'x.o......'
+    Minimax {
+        board: String,
+        /// how many moves ahead to look
+        #[clap(long, default_value = "9")]
+        depth: usize,
+    },
+
+    /// index a 'size' by 'size' grid of points, and print how many are within a range
+    Quadtree {
+        size: usize,
+        min_x: f64,
+        min_y: f64,
+        max_x: f64,
+        max_y: f64,
+    },
+}
+
+fn show(value: &Value) -> String {
+    match value {
+        Value::Int(x) => x.to_string(),
+        Value::Float(x) => format!("{:?}", x),
+        Value::Bool(x) => x.to_string(),
+        Value::Closure { param, .. } => format!("<function of {}>", param),
+    }
+}
+
+/// The output for a single command
+fn run(command: Command) -> Result<String, String> {
+    Ok(match command {
+        Command::Expr { source } => {
+            let expr = parse(&source).map_err(|e| format!("parse error: {}", e))?;
+            let optimized = optimize(&expr);
+            let value =
+                eval_arena(&optimized).map_err(|e| format!("runtime error: {:?}", e.kind))?;
+            format!("{}\n= {}", pretty(&optimized), show(&value))
+        }
+
+        Command::List { items } => {
+            let list: RecursiveList<String> = items.into_iter().collect();
+            list.as_ref()
+                .collapse_layers(|layer: ListLayer<&String, String>| match layer {
+                    ListLayer::Cons(item, rest) => format!("{} -> {}", item, rest),
+                    ListLayer::Nil => "nil".to_string(),
+                })
+        }
+
+        Command::Rope {
+            text,
+            start,
+            end,
+            leaf_len,
+        } => {
+            let rope = Rope::from_str(&text, leaf_len);
+            let end = end.min(rope.len());
+            rope.slice(start.min(end)..end).to_string()
+        }
+
+        Command::Trie { prefix, words } => {
+            let trie = trie::from_words(words.iter().map(|word| word.as_str()));
+            trie::completions(&trie, &prefix).join("\n")
+        }
+
+        Command::Avl { values } => {
+            let tree = avl::from_values(values);
+            // empty subtrees are only drawn beside non-empty ones, to tell left from right
+            let lines = tree
+                .as_ref()
+                .collapse_layers(|layer: BstLayer<Option<TreeLines>>| match layer {
+                    BstLayer::Empty => None,
+                    BstLayer::Node(None, x, None) => Some(TreeLines::leaf(x.to_string())),
+                    BstLayer::Node(l, x, r) => {
+                        let empty = || TreeLines::leaf("-");
+                        let children = [l.unwrap_or_else(empty), r.unwrap_or_else(empty)];
+                        Some(TreeLines::node(x.to_string(), children))
+                    }
+                });
+            lines.map_or_else(|| "empty".to_string(), |lines| lines.render())
+        }
+
+        Command::Minimax { board, depth } => {
+            let board = Board::parse(&board).ok_or("a board is nine cells of 'x', 'o' or '.'")?;
+            let tree = game_tree(board, depth);
+            let (score, visited) = alpha_beta(&tree);
+            let positions = tree.as_ref().collapse_layers(|position: Position<usize>| {
+                1 + position.moves.iter().sum::<usize>()
+            });
+            format!(
+                "score for {:?}: {}\nvisited {} of {} positions",
+                board.to_move(),
+                score,
+                visited,
+                positions
+            )
+        }
+
+        Command::Quadtree {
+            size,
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        } => {
+            let points: Vec<Point> = (0..size * size)
+                .map(|idx| Point {
+                    x: (idx % size) as f64,
+                    y: (idx / size) as f64,
+                })
+                .collect();
+            let rect = |min_x, min_y, max_x, max_y| Rect {
+                min: Point { x: min_x, y: min_y },
+                max: Point { x: max_x, y: max_y },
+            };
+            let tree = quadtree::build(rect(0.0, 0.0, size as f64, size as f64), &points, 4);
+            let (found, visited) = quadtree::query(&tree, rect(min_x, min_y, max_x, max_y));
+            let nodes = tree
+                .as_ref()
+                .collapse_layers(|layer: QuadLayerRef<usize>| match layer {
+                    QuadLayerRef::Leaf(..) => 1,
+                    QuadLayerRef::Split(_, quadrants) => 1 + quadrants.iter().sum::<usize>(),
+                });
+            format!(
+                "{} points found, visiting {} of {} nodes",
+                found.len(),
+                visited,
+                nodes
+            )
+        }
+    })
+}
+
+fn main() {
+    match run(Cli::parse().command) {
+        Ok(output) => println!("{}", output),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// run with 'cargo test --example demo --features expr_example'
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn demo(args: &[&str]) -> Result<String, String> {
+        let cli = Cli::try_parse_from(["demo"].iter().chain(args)).map_err(|e| e.to_string())?;
+        run(cli.command)
+    }
+
+    #[test]
+    fn every_command() {
+        assert_eq!(
+            demo(&["expr", "let x = 2 in x * (3 + 4)"]),
+            Ok("let x = 2 in x * 7\n= 14".to_string())
+        );
+        assert_eq!(demo(&["list", "a", "b"]), Ok("a -> b -> nil".to_string()));
+        assert_eq!(
+            demo(&["rope", "hello, world", "7", "100", "--leaf-len", "2"]),
+            Ok("world".to_string())
+        );
+        assert_eq!(
+            demo(&["trie", "ca", "car", "cat", "dog"]),
+            Ok("car\ncat".to_string())
+        );
+        assert_eq!(
+            demo(&["avl", "1", "2", "3", "-4"]),
+            Ok(["2", "├── 1", "│   ├── -4", "│   └── -", "└── 3"].join("\n"))
+        );
+        assert_eq!(
+            demo(&["minimax", "xx.oo...."]),
+            Ok("score for X: 1\nvisited 2 of 157 positions".to_string())
+        );
+        assert!(demo(&["quadtree", "16", "0", "0", "2", "2"])
+            .unwrap()
+            .starts_with("4 points found"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            demo(&["expr", "1 +"]),
+            Err("parse error: expected expression at 3..3".to_string())
+        );
+        assert!(demo(&["minimax", "xx"]).is_err());
+        assert!(demo(&["nope"]).is_err());
+    }
+}
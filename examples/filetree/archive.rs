@@ -0,0 +1,331 @@
+//! Archives as file trees, expanded from an archive's listing rather than from a directory on disk.
+//! Only the coalgebra differs: the tree built is the same, so every algebra over file trees, eg
+//! search, disk usage, diffs and rendering, works on an archive without extracting it.
+//!
+//! An archive's contents are read into memory up front, and served by a 'ContentCache' at the path
+//! each file would have if the archive were a directory, eg 'src.tar/src/main.rs'. Directories
+//! that only appear in the paths of other entries are included, without metadata.
+
+use crate::filetree::contents::ContentCache;
+use crate::filetree::error::{Error, WithPath};
+use crate::filetree::{FileId, FileTree, Metadata, RecursiveFileTree};
+use recursion::recursive::Expand;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// A file tree read from an archive, along with the contents of its files
+pub struct Archive {
+    pub tree: RecursiveFileTree,
+    pub contents: ContentCache,
+}
+
+/// Whether 'path' names an archive that 'open' can read, by its extension
+pub fn is_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    [".tar", ".tar.gz", ".tgz", ".zip"]
+        .iter()
+        .any(|extension| name.ends_with(extension))
+}
+
+/// Read the tar, gzipped tar, or zip archive at 'path', by its extension
+pub fn open(path: &Path) -> Result<Archive, Error> {
+    let name = path.to_string_lossy();
+    let file = File::open(path).at(path)?;
+    let listing = if name.ends_with(".zip") {
+        read_zip(file)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        read_tar(flate2::read::GzDecoder::new(file))
+    } else {
+        read_tar(file)
+    }
+    .at(path)?;
+    Ok(listing.into_archive(path))
+}
+
+enum Entry {
+    File(Metadata, Arc<[u8]>),
+    Dir(Metadata),
+    Symlink(PathBuf),
+}
+
+// every entry in an archive, by its path within it
+#[derive(Default)]
+struct Listing {
+    entries: BTreeMap<PathBuf, Entry>,
+    // the names of the entries of each directory, including those that aren't listed themselves
+    children: BTreeMap<PathBuf, BTreeSet<OsString>>,
+}
+
+// the path of an entry relative to the root of the archive, or none if it would be outside it
+fn relative(path: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(relative)
+}
+
+impl Listing {
+    fn insert(&mut self, path: PathBuf, entry: Entry) {
+        let mut child = path.as_path();
+        while let Some(parent) = child.parent() {
+            if let Some(name) = child.file_name() {
+                self.children
+                    .entry(parent.to_path_buf())
+                    .or_default()
+                    .insert(name.to_os_string());
+            }
+            child = parent;
+        }
+        self.entries.insert(path, entry);
+    }
+
+    // a single layer of the tree, with each entry of a directory seeded with its path
+    fn layer(&self, path: PathBuf) -> FileTree<PathBuf> {
+        match self.entries.get(&path) {
+            Some(Entry::File(metadata, _)) => FileTree::File(metadata.clone()),
+            Some(Entry::Symlink(target)) => FileTree::Symlink(target.clone()),
+            dir => {
+                let metadata = match dir {
+                    Some(Entry::Dir(metadata)) => metadata.clone(),
+                    _ => Metadata {
+                        is_dir: true,
+                        ..Metadata::default()
+                    },
+                };
+                let entries = self
+                    .children
+                    .get(&path)
+                    .into_iter()
+                    .flatten()
+                    .map(|name| (name.clone(), path.join(name)))
+                    .collect();
+                FileTree::Dir(metadata, entries)
+            }
+        }
+    }
+
+    fn into_archive(self, root: &Path) -> Archive {
+        let tree = RecursiveFileTree::expand_layers(PathBuf::new(), |path| self.layer(path));
+        let files = self
+            .entries
+            .into_iter()
+            .filter_map(|(path, entry)| match entry {
+                Entry::File(_, contents) => Some((root.join(path), contents)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+        Archive {
+            tree,
+            contents: ContentCache::from_source(Arc::new(files)),
+        }
+    }
+}
+
+fn read_tar(reader: impl Read) -> std::io::Result<Listing> {
+    let mut listing = Listing::default();
+    // hard links only name the file they link to, which comes before them
+    let mut links: HashMap<PathBuf, FileId> = HashMap::new();
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(path) = relative(&entry.path()?) else {
+            continue;
+        };
+        let header = entry.header();
+        let mut metadata = Metadata {
+            is_dir: header.entry_type().is_dir(),
+            len: header.size()?,
+            modified: header
+                .mtime()
+                .ok()
+                .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+            readonly: header.mode().is_ok_and(|mode| mode & 0o222 == 0),
+            mode: header.mode().ok(),
+            uid: header.uid().ok().map(|uid| uid as u32),
+            hardlink: None,
+        };
+        let entry_type = header.entry_type();
+        if entry_type.is_dir() {
+            listing.insert(path, Entry::Dir(metadata));
+        } else if entry_type.is_symlink() {
+            if let Some(target) = entry.link_name()? {
+                listing.insert(path, Entry::Symlink(target.into_owned()));
+            }
+        } else if entry_type.is_hard_link() {
+            let Some(target) = entry.link_name()?.and_then(|target| relative(&target)) else {
+                continue;
+            };
+            let next = links.len() as u64;
+            let id = *links.entry(target.clone()).or_insert((0, next));
+            if let Some(Entry::File(target, contents)) = listing.entries.get_mut(&target) {
+                target.hardlink = Some(id);
+                let entry = Entry::File(target.clone(), contents.clone());
+                listing.insert(path, entry);
+            }
+        } else if entry_type.is_file() {
+            let mut contents = Vec::with_capacity(metadata.len as usize);
+            entry.read_to_end(&mut contents)?;
+            metadata.len = contents.len() as u64;
+            listing.insert(path, Entry::File(metadata, contents.into()));
+        }
+    }
+    Ok(listing)
+}
+
+// seconds since the unix epoch, for a time with no time zone, as zip archives record them
+fn epoch_secs(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> u64 {
+    // days since the epoch of a date in the proleptic gregorian calendar, treating january and
+    // february as the end of the previous year so that leap days come last
+    let (year, month) = if month <= 2 {
+        (year as u64 - 1, month as u64 + 9)
+    } else {
+        (year as u64, month as u64 - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * month + 2) / 5 + day as u64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    days * 86400 + hour as u64 * 3600 + minute as u64 * 60 + second as u64
+}
+
+fn read_zip(file: File) -> std::io::Result<Listing> {
+    let mut listing = Listing::default();
+    let mut archive = zip::ZipArchive::new(file).map_err(std::io::Error::other)?;
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx).map_err(std::io::Error::other)?;
+        let Some(path) = entry.enclosed_name() else {
+            continue;
+        };
+        let modified = entry.last_modified().map(|time| {
+            let secs = epoch_secs(
+                time.year(),
+                time.month(),
+                time.day(),
+                time.hour(),
+                time.minute(),
+                time.second(),
+            );
+            SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+        });
+        let mut metadata = Metadata {
+            is_dir: entry.is_dir(),
+            len: entry.size(),
+            modified,
+            readonly: entry.unix_mode().is_some_and(|mode| mode & 0o222 == 0),
+            mode: entry.unix_mode(),
+            ..Metadata::default()
+        };
+        if entry.is_dir() {
+            listing.insert(path, Entry::Dir(metadata));
+            continue;
+        }
+        let mut contents = Vec::with_capacity(metadata.len as usize);
+        entry.read_to_end(&mut contents)?;
+        if entry.is_symlink() {
+            // a link's target is stored as its contents
+            let target = String::from_utf8_lossy(&contents).into_owned();
+            listing.insert(path, Entry::Symlink(PathBuf::from(target)));
+        } else {
+            metadata.len = contents.len() as u64;
+            listing.insert(path, Entry::File(metadata, contents.into()));
+        }
+    }
+    Ok(listing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::diff::diff;
+    use crate::filetree::du::sizes;
+    use crate::filetree::paths::Normalization;
+    use crate::filetree::render;
+    use crate::filetree::search::{search, SearchOptions};
+    use crate::filetree::test_dir;
+    use std::io::Write;
+
+    #[test]
+    fn dates() {
+        assert_eq!(epoch_secs(1970, 1, 1, 0, 0, 0), 0);
+        assert_eq!(epoch_secs(2000, 3, 1, 12, 30, 15), 951913815);
+        assert_eq!(epoch_secs(2024, 2, 29, 0, 0, 0), 1709164800);
+    }
+
+    #[tokio::test]
+    async fn archives() {
+        let dir = test_dir("archive");
+
+        // the same files, in each kind of archive, with 'src' only implied in the tar
+        let tar_path = dir.join("files.tar");
+        let mut tar = tar::Builder::new(File::create(&tar_path).unwrap());
+        for (path, contents) in [("src/main.rs", "fn main() {}\n"), ("README", "hello\n")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(1000);
+            tar.append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        tar.append_link(&mut header, "src/lib.rs", "src/main.rs")
+            .unwrap();
+        tar.into_inner().unwrap();
+
+        let zip_path = dir.join("files.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.add_directory("src/", options).unwrap();
+        for (path, contents) in [
+            ("src/main.rs", "fn main() {}\n"),
+            ("src/lib.rs", "fn main() {}\n"),
+            ("README", "hello\n"),
+        ] {
+            zip.start_file(path, options).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        assert!(is_archive(&tar_path) && is_archive(&zip_path));
+        let tar = open(&tar_path).unwrap();
+        let zip = open(&zip_path).unwrap();
+        let rendered = ".\n├── README\n└── src/\n    ├── lib.rs\n    └── main.rs";
+        assert_eq!(render(&tar.tree, "."), rendered);
+        assert_eq!(render(&zip.tree, "."), rendered);
+
+        // the hard link is counted once
+        assert_eq!(sizes(&tar.tree).total, 19);
+        assert_eq!(sizes(&zip.tree).total, 32);
+
+        let options = SearchOptions {
+            contents: Arc::new(tar.contents),
+            ..SearchOptions::default()
+        };
+        let results = search(tar.tree.clone(), tar_path.clone(), "main", &options)
+            .await
+            .unwrap();
+        let paths: Vec<PathBuf> = results.matches.into_iter().map(|m| m.path).collect();
+        assert_eq!(
+            paths,
+            vec![tar_path.join("src/lib.rs"), tar_path.join("src/main.rs")]
+        );
+
+        // only metadata differs: the tar's implied directory has none, and the zip's times are
+        // those it was written at
+        let differences = diff(&tar.tree, &zip.tree, &Normalization::default());
+        assert!(differences.contains_key(Path::new("src/main.rs")));
+        assert!(!differences.contains_key(Path::new("src")));
+    }
+}
@@ -0,0 +1,114 @@
+//! Security audit queries over permissions and ownership, as algebras in the style of 'query'.
+//!
+//! Each finding is a predicate over the metadata of a file or directory, and 'matching' collects
+//! the paths of every entry that satisfies one. Links are never matched, as their own permissions
+//! aren't meaningful. Truncated directories are matched by their own metadata alone.
+
+use crate::filetree::query::under;
+use crate::filetree::{FileTreeRef, Metadata};
+use std::path::PathBuf;
+
+const WORLD_WRITABLE: u32 = 0o002;
+const STICKY: u32 = 0o1000;
+const SETGID: u32 = 0o2000;
+const SETUID: u32 = 0o4000;
+
+// entries whose permissions aren't known never match
+fn mode(metadata: &Metadata) -> u32 {
+    metadata.mode.unwrap_or(0)
+}
+
+/// Files anyone can modify, and directories anyone can add or remove entries from. Directories
+/// with the sticky bit set, like '/tmp', are excluded, as only owners can remove entries from them.
+pub fn world_writable(metadata: &Metadata) -> bool {
+    let mode = mode(metadata);
+    mode & WORLD_WRITABLE != 0 && !(metadata.is_dir && mode & STICKY != 0)
+}
+
+/// Files that run as their owner, regardless of who runs them
+pub fn setuid(metadata: &Metadata) -> bool {
+    !metadata.is_dir && mode(metadata) & SETUID != 0
+}
+
+/// Files that run as their group, regardless of who runs them
+pub fn setgid(metadata: &Metadata) -> bool {
+    !metadata.is_dir && mode(metadata) & SETGID != 0
+}
+
+/// Files and directories owned by the user 'uid'
+pub fn owned_by(uid: u32) -> impl Fn(&Metadata) -> bool {
+    move |metadata| metadata.uid == Some(uid)
+}
+
+/// Every file and directory for which 'finding' holds, sorted by path. The directory the query is
+/// run for is included, with an empty path.
+pub fn matching(
+    finding: impl Fn(&Metadata) -> bool,
+) -> impl Fn(FileTreeRef<Vec<PathBuf>>) -> Vec<PathBuf> {
+    move |node| match node {
+        FileTreeRef::File(metadata) | FileTreeRef::Truncated(metadata, _) if finding(metadata) => {
+            vec![PathBuf::new()]
+        }
+        FileTreeRef::File(_) | FileTreeRef::Symlink(_) | FileTreeRef::Truncated(..) => Vec::new(),
+        FileTreeRef::Dir(metadata, children) => {
+            let mut paths: Vec<PathBuf> = children
+                .into_iter()
+                .flat_map(|(name, paths)| paths.into_iter().map(|path| under(name, path)))
+                .collect();
+            if finding(metadata) {
+                paths.push(PathBuf::new());
+            }
+            paths.sort();
+            paths
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::build::{build_file_tree, BuildOptions};
+    use crate::filetree::test_dir;
+    use recursion::recursive::Collapse;
+    use std::fs::Permissions;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    #[tokio::test]
+    async fn audit() {
+        let dir = test_dir("audit");
+        std::fs::create_dir_all(dir.join("open")).unwrap();
+        std::fs::create_dir_all(dir.join("tmp")).unwrap();
+        for (path, mode) in [
+            ("open", 0o777),
+            ("tmp", 0o1777),
+            ("open/notes", 0o666),
+            ("tool", 0o4755),
+            ("group-tool", 0o2755),
+            ("plain", 0o644),
+        ] {
+            if !dir.join(path).exists() {
+                std::fs::write(dir.join(path), "").unwrap();
+            }
+            std::fs::set_permissions(dir.join(path), Permissions::from_mode(mode)).unwrap();
+        }
+        std::fs::set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap();
+        let find =
+            |finding: &dyn Fn(&Metadata) -> bool| tree.as_ref().collapse_layers(matching(finding));
+
+        assert_eq!(
+            find(&world_writable),
+            vec![PathBuf::from("open"), PathBuf::from("open/notes")]
+        );
+        assert_eq!(find(&setuid), vec![PathBuf::from("tool")]);
+        assert_eq!(find(&setgid), vec![PathBuf::from("group-tool")]);
+
+        let me = std::fs::metadata(&dir).unwrap().uid();
+        assert_eq!(find(&owned_by(me)).len(), 7);
+        assert_eq!(find(&owned_by(me + 1)), Vec::<PathBuf>::new());
+    }
+}
@@ -1,58 +1,590 @@
-use crate::filetree::{FileTree, RecursiveFileTree};
+use crate::filetree::error::{Error, WithPath};
+use crate::filetree::globs::Globs;
+use crate::filetree::paths::Normalization;
+use crate::filetree::{FileTree, RecursiveFileTree, Truncation};
 use futures::FutureExt;
-use recursion::recursive::ExpandAsync;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
 use std::ffi::OsString;
-use std::{collections::HashMap, path::Path};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use tokio::fs::DirEntry;
 
-pub async fn build_file_tree<F: for<'x> Fn(&'x OsString) -> bool + Send + Sync>(
+/// What to do on encountering a symbolic link
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Symlinks {
+    /// leave links out of the tree entirely
+    Ignore,
+    /// record each link as a 'Symlink' node holding its target, without following it
+    #[default]
+    Record,
+    /// expand each link as whatever it points to. A link to a directory that has already been
+    /// expanded, eg one of its own ancestors, is recorded instead, so cycles are only walked once.
+    /// Broken links are recorded too.
+    Follow,
+}
+
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    pub symlinks: Symlinks,
+    /// leave out anything matched by '.gitignore' or '.ignore' files, or by git's global excludes
+    /// file, along with '.git' directories themselves
+    pub respect_ignore_files: bool,
+    /// maximum number of entries read at once
+    pub parallelism: usize,
+    /// which entries to keep, by their paths relative to the root
+    pub globs: Globs,
+    /// only read entries up to this many directories deep, so that eg 1 reads just the root's
+    /// entries. Directories at the limit are kept, with their entries left out.
+    pub max_depth: Option<usize>,
+    /// leave out the entries of directories with more than this many, after filtering. Reading
+    /// stops as soon as the limit is passed, so huge directories aren't read in full either.
+    pub max_entries_per_dir: Option<usize>,
+    /// only expand directories under these paths, relative to the root, and those leading to them.
+    /// Other directories are kept without their entries, and other files are left out. Everything
+    /// is expanded if there are none.
+    pub prefixes: Vec<PathBuf>,
+    /// how paths are compared with 'prefixes'. Set the same normalization for 'globs' too.
+    pub normalization: Normalization,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            symlinks: Symlinks::default(),
+            respect_ignore_files: false,
+            parallelism: 16,
+            globs: Globs::default(),
+            max_depth: None,
+            max_entries_per_dir: None,
+            prefixes: Vec::new(),
+            normalization: Normalization::default(),
+        }
+    }
+}
+
+// the ignore rules in effect in some directory: those from its own ignore files, then those of
+// each ancestor in turn, so that the closest match wins
+#[derive(Clone, Default)]
+struct Ignores(Option<Arc<(Gitignore, Ignores)>>);
+
+impl Ignores {
+    // add the rules from a directory's ignore files, with '.ignore' taking precedence
+    async fn within(&self, dir: &Path) -> Result<Self, Error> {
+        let mut builder = GitignoreBuilder::new(dir);
+        for name in [".gitignore", ".ignore"] {
+            let path = dir.join(name);
+            match tokio::fs::read_to_string(&path).await {
+                Ok(contents) => {
+                    for line in contents.lines() {
+                        builder
+                            .add_line(Some(path.clone()), line)
+                            .map_err(|source| Error::Ignore {
+                                path: path.clone(),
+                                source,
+                            })?;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).at(path),
+            }
+        }
+        let gitignore = builder.build().map_err(|source| Error::Ignore {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+        Ok(Self(Some(Arc::new((gitignore, self.clone())))))
+    }
+
+    fn matched(&self, path: &Path, is_dir: bool) -> Match<()> {
+        let mut current = self;
+        while let Some(node) = &current.0 {
+            let (gitignore, parent) = node.as_ref();
+            match gitignore.matched(path, is_dir) {
+                Match::None => current = parent,
+                m => return m.map(|_| ()),
+            }
+        }
+        Match::None
+    }
+}
+
+// an entry to be expanded, along with the ignore rules in effect in its parent directory and
+// how many directories deep it is
+struct Seed {
+    entry: Option<DirEntry>,
+    ignores: Ignores,
+    depth: usize,
+}
+
+// identifies a directory regardless of the path it was reached by
+#[cfg(unix)]
+type DirId = (u64, u64);
+
+#[cfg(unix)]
+async fn dir_id(_path: &Path, metadata: &Metadata) -> std::io::Result<DirId> {
+    use std::os::unix::fs::MetadataExt;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+type DirId = std::path::PathBuf;
+
+#[cfg(not(unix))]
+async fn dir_id(path: &Path, _metadata: &Metadata) -> std::io::Result<DirId> {
+    tokio::fs::canonicalize(path).await
+}
+
+// state shared by every layer of a single build
+struct Build<'a> {
+    root_path: &'a str,
+    // what globs and prefixes are matched relative to, which is above 'root_path' when building a
+    // subtree
+    globs_root: &'a Path,
+    options: &'a BuildOptions,
+    // 'options.prefixes', normalized once up front
+    prefixes: Vec<PathBuf>,
+    // directories expanded so far, only tracked when following links
+    visited: Mutex<HashSet<DirId>>,
+    // global excludes, with lower precedence than any ignore file
+    global: Gitignore,
+}
+
+pub async fn build_file_tree(
     root_path: String,
-    filter: &F,
-) -> std::io::Result<RecursiveFileTree> {
-    RecursiveFileTree::expand_layers_async(None, |dir_entry: Option<DirEntry>| {
-        async { build_layer(&root_path, dir_entry, filter).await }.boxed()
-    })
+    options: &BuildOptions,
+) -> Result<RecursiveFileTree, Error> {
+    build_from(
+        root_path.clone(),
+        Path::new(&root_path),
+        Ignores::default(),
+        0,
+        options,
+    )
     .await
 }
 
-async fn build_layer<F: for<'x> Fn(&'x OsString) -> bool + Send + Sync>(
+/// Build the tree under 'dir', a directory below 'root_path', exactly as it would appear within
+/// the tree built from 'root_path', ie with the ignore rules of each directory in between applied
+#[cfg(feature = "notify")]
+pub async fn build_subtree(
     root_path: &str,
-    maybe_dir_entry: Option<DirEntry>,
-    filter: &F,
-) -> std::io::Result<FileTree<Option<DirEntry>>> {
-    match maybe_dir_entry {
-        None => {
-            let entries = process_dir(root_path, filter).await?;
-            Ok(FileTree::Dir(entries))
+    dir: &Path,
+    options: &BuildOptions,
+) -> Result<RecursiveFileTree, Error> {
+    let mut ignores = Ignores::default();
+    let mut ancestor = std::path::PathBuf::from(root_path);
+    for component in dir.components() {
+        if options.respect_ignore_files {
+            ignores = ignores.within(&ancestor).await?;
         }
-        Some(dir_entry) => {
-            let file_type = dir_entry.file_type().await?;
-            if file_type.is_dir() {
-                let entries = process_dir(dir_entry.path(), filter).await?;
-                Ok(FileTree::Dir(entries))
-            } else if file_type.is_file() {
-                let metadata = dir_entry.metadata().await?;
-                Ok(FileTree::File(metadata))
-            } else {
-                panic!("only dirs and files currently supported")
+        ancestor.push(component);
+    }
+    let path = ancestor.to_string_lossy().into_owned();
+    let depth = dir.components().count();
+    build_from(path, Path::new(root_path), ignores, depth, options).await
+}
+
+// build the tree under 'root_path', given the ignore rules in effect in its parent and its depth
+async fn build_from(
+    root_path: String,
+    globs_root: &Path,
+    ignores: Ignores,
+    depth: usize,
+    options: &BuildOptions,
+) -> Result<RecursiveFileTree, Error> {
+    let global = if options.respect_ignore_files {
+        // a missing or malformed global excludes file just means no global excludes
+        Gitignore::global().0
+    } else {
+        Gitignore::empty()
+    };
+    let build = Build {
+        root_path: &root_path,
+        globs_root,
+        options,
+        prefixes: options
+            .prefixes
+            .iter()
+            .map(|prefix| options.normalization.path(prefix))
+            .collect(),
+        visited: Mutex::new(HashSet::new()),
+        global,
+    };
+    let build = &build;
+    let root = Seed {
+        entry: None,
+        ignores,
+        depth,
+    };
+    RecursiveFileTree::expand_layers_async_bounded(root, options.parallelism, |seed: Seed| {
+        async move { build.layer(seed).await }.boxed()
+    })
+    .await
+}
+
+impl Build<'_> {
+    async fn layer(
+        &self,
+        Seed {
+            entry,
+            ignores,
+            depth,
+        }: Seed,
+    ) -> Result<FileTree<Seed>, Error> {
+        match entry {
+            None => {
+                let metadata = tokio::fs::metadata(self.root_path)
+                    .await
+                    .at(self.root_path)?;
+                self.dir(Path::new(self.root_path), &metadata, &ignores, depth)
+                    .await
+            }
+            Some(dir_entry) => {
+                let path = dir_entry.path();
+                let file_type = dir_entry.file_type().await.at(&path)?;
+                if file_type.is_symlink() {
+                    let target = tokio::fs::read_link(&path).await.at(&path)?;
+                    if self.options.symlinks != Symlinks::Follow {
+                        return Ok(FileTree::Symlink(target));
+                    }
+                    match tokio::fs::metadata(&path).await {
+                        Ok(metadata) if metadata.is_dir() => {
+                            let id = dir_id(&path, &metadata).await.at(&path)?;
+                            // checked and marked at once, as other layers are built concurrently
+                            if self.visited.lock().unwrap().insert(id) {
+                                self.expand_dir(&path, &metadata, &ignores, depth).await
+                            } else {
+                                Ok(FileTree::Symlink(target))
+                            }
+                        }
+                        Ok(metadata) => Ok(FileTree::File((&metadata).into())),
+                        Err(_) => Ok(FileTree::Symlink(target)),
+                    }
+                } else if file_type.is_dir() {
+                    let metadata = dir_entry.metadata().await.at(&path)?;
+                    self.dir(&path, &metadata, &ignores, depth).await
+                } else if file_type.is_file() {
+                    let metadata = dir_entry.metadata().await.at(&path)?;
+                    Ok(FileTree::File((&metadata).into()))
+                } else {
+                    panic!("only dirs, files and symlinks currently supported")
+                }
             }
         }
     }
+
+    async fn dir(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        ignores: &Ignores,
+        depth: usize,
+    ) -> Result<FileTree<Seed>, Error> {
+        if self.options.symlinks == Symlinks::Follow {
+            let id = dir_id(path, metadata).await.at(path)?;
+            self.visited.lock().unwrap().insert(id);
+        }
+        self.expand_dir(path, metadata, ignores, depth).await
+    }
+
+    async fn expand_dir(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        ignores: &Ignores,
+        depth: usize,
+    ) -> Result<FileTree<Seed>, Error> {
+        let metadata = crate::filetree::Metadata::from(metadata);
+        if self.options.max_depth.is_some_and(|max| depth >= max) {
+            return Ok(FileTree::Truncated(metadata, Truncation::Depth));
+        }
+        if !self.in_prefixes(path.strip_prefix(self.globs_root).unwrap_or(path)) {
+            return Ok(FileTree::Truncated(metadata, Truncation::Sparse));
+        }
+        let ignores = if self.options.respect_ignore_files {
+            ignores.within(path).await?
+        } else {
+            Ignores::default()
+        };
+        match self.process_dir(path, &ignores, depth + 1).await? {
+            Some(entries) => Ok(FileTree::Dir(metadata, entries)),
+            None => Ok(FileTree::Truncated(metadata, Truncation::Entries)),
+        }
+    }
+
+    // a directory's entries, at 'depth', or none if there are more than the limit
+    async fn process_dir(
+        &self,
+        path: impl AsRef<Path>,
+        ignores: &Ignores,
+        depth: usize,
+    ) -> Result<Option<BTreeMap<OsString, Seed>>, Error> {
+        let mut entries = BTreeMap::new();
+        // root dir special case
+        // TODO: leaves file handles open and is fucky
+        let path = path.as_ref();
+        let mut dirs = tokio::fs::read_dir(path).await.at(path)?;
+        while let Some(next) = dirs.next_entry().await.at(path)? {
+            let file_type = next.file_type().await.at(next.path())?;
+            if self.options.symlinks == Symlinks::Ignore && file_type.is_symlink() {
+                continue;
+            }
+            if self.options.respect_ignore_files && self.ignored(&next, file_type.is_dir(), ignores)
+            {
+                continue;
+            }
+            let name = next.file_name();
+            let path = next.path();
+            let relative = path.strip_prefix(self.globs_root).unwrap_or(&path);
+            // directories outside the prefixes are kept, to be truncated once they're expanded
+            if !file_type.is_dir() && !self.in_prefixes(relative) {
+                continue;
+            }
+            if self.options.globs.keep(relative, file_type.is_dir()) {
+                let seed = Seed {
+                    entry: Some(next),
+                    ignores: ignores.clone(),
+                    depth,
+                };
+                entries.insert(name, seed);
+                if self
+                    .options
+                    .max_entries_per_dir
+                    .is_some_and(|max| entries.len() > max)
+                {
+                    return Ok(None);
+                }
+            }
+        }
+
+        Ok(Some(entries))
+    }
+
+    // whether the entry at 'relative' is under some prefix, or on the way to one
+    fn in_prefixes(&self, relative: &Path) -> bool {
+        if self.prefixes.is_empty() {
+            return true;
+        }
+        let relative = self.options.normalization.path(relative);
+        self.prefixes
+            .iter()
+            .any(|prefix| relative.starts_with(prefix) || prefix.starts_with(&relative))
+    }
+
+    fn ignored(&self, entry: &DirEntry, is_dir: bool, ignores: &Ignores) -> bool {
+        if is_dir && entry.file_name() == ".git" {
+            return true;
+        }
+        let path = entry.path();
+        match ignores.matched(&path, is_dir) {
+            Match::None => self.global.matched(&path, is_dir).is_ignore(),
+            m => m.is_ignore(),
+        }
+    }
 }
 
-async fn process_dir<F: for<'x> Fn(&'x OsString) -> bool + Send + Sync>(
-    path: impl AsRef<Path>,
-    filter: &F,
-) -> std::io::Result<HashMap<OsString, Option<DirEntry>>> {
-    let mut entries = HashMap::new();
-    // root dir special case
-    // TODO: leaves file handles open and is fucky
-    let mut dirs = tokio::fs::read_dir(path).await?;
-    while let Some(next) = dirs.next_entry().await? {
-        if filter(&next.file_name()) {
-            entries.insert(next.file_name(), Some(next));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::{render, test_dir};
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks() {
+        let dir = test_dir("symlinks");
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::write(dir.join("a/b/file"), "contents").unwrap();
+        // a cycle back to 'a', and a broken link
+        std::os::unix::fs::symlink("..", dir.join("a/b/up")).unwrap();
+        std::os::unix::fs::symlink("missing", dir.join("a/broken")).unwrap();
+
+        let build = |symlinks| {
+            let root = dir.to_string_lossy().to_string();
+            async move {
+                let options = BuildOptions {
+                    symlinks,
+                    ..BuildOptions::default()
+                };
+                let tree = build_file_tree(root, &options).await.unwrap();
+                render(&tree, ".")
+            }
+        };
+
+        assert_eq!(
+            build(Symlinks::Ignore).await,
+            ".\n└── a/\n    └── b/\n        └── file"
+        );
+        assert_eq!(
+            build(Symlinks::Record).await,
+            ".\n└── a/\n    ├── b/\n    │   ├── file\n    │   └── up -> ..\n    └── broken -> missing"
+        );
+        // 'a/b/up' is 'a', which was already expanded
+        assert_eq!(build(Symlinks::Follow).await, build(Symlinks::Record).await);
+
+        // following a link to a directory that isn't otherwise reachable expands it
+        let other = test_dir("symlinks_target");
+        std::fs::write(other.join("inner"), "").unwrap();
+        std::os::unix::fs::symlink(&other, dir.join("a/other")).unwrap();
+        assert!(build(Symlinks::Follow)
+            .await
+            .ends_with("└── other/\n        └── inner"));
+    }
+
+    #[tokio::test]
+    async fn ignore_files() {
+        let dir = test_dir("ignore_files");
+        std::fs::create_dir_all(dir.join("build")).unwrap();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "*.log\nbuild/\n!keep.log\n").unwrap();
+        // rules from deeper directories win
+        std::fs::write(dir.join("sub/.ignore"), "secret\n!*.log\n").unwrap();
+        for file in [
+            "a.log",
+            "keep.log",
+            "main.rs",
+            "build/out",
+            "sub/secret",
+            "sub/x.log",
+        ] {
+            std::fs::write(dir.join(file), "").unwrap();
         }
+
+        let build = |respect_ignore_files| {
+            let root = dir.to_string_lossy().to_string();
+            async move {
+                let options = BuildOptions {
+                    respect_ignore_files,
+                    ..BuildOptions::default()
+                };
+                let tree = build_file_tree(root, &options).await.unwrap();
+                render(&tree, ".")
+            }
+        };
+
+        assert_eq!(
+            build(true).await,
+            ".\n├── .gitignore\n├── keep.log\n├── main.rs\n└── sub/\n    ├── .ignore\n    └── x.log"
+        );
+        assert!(build(false).await.contains("a.log"));
+    }
+
+    #[tokio::test]
+    async fn globs() {
+        let dir = test_dir("globs");
+        std::fs::create_dir_all(dir.join("src/vendor")).unwrap();
+        for file in ["README.md", "src/main.rs", "src/vendor/dep.rs"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+
+        let options = BuildOptions {
+            globs: Globs::new(&["**/*.rs", "!**/vendor/**"], Normalization::default()).unwrap(),
+            ..BuildOptions::default()
+        };
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &options).await.unwrap();
+        assert_eq!(render(&tree, "."), ".\n└── src/\n    └── main.rs");
+    }
+
+    #[tokio::test]
+    async fn limits() {
+        let dir = test_dir("limits");
+        std::fs::create_dir_all(dir.join("a/b/c")).unwrap();
+        std::fs::create_dir_all(dir.join("many")).unwrap();
+        std::fs::write(dir.join("a/b/c/deep"), "").unwrap();
+        for file in ["1", "2", "3"] {
+            std::fs::write(dir.join("many").join(file), "").unwrap();
+        }
+
+        let build = |max_depth, max_entries_per_dir| {
+            let root = dir.to_string_lossy().to_string();
+            async move {
+                let options = BuildOptions {
+                    max_depth,
+                    max_entries_per_dir,
+                    ..BuildOptions::default()
+                };
+                let tree = build_file_tree(root, &options).await.unwrap();
+                render(&tree, ".")
+            }
+        };
+
+        assert_eq!(
+            build(Some(2), Some(2)).await,
+            ".\n├── a/\n│   └── b/ [max depth reached]\n└── many/ [too many entries]"
+        );
+        assert_eq!(build(Some(0), None).await, ".");
+        assert!(build(None, Some(3))
+            .await
+            .ends_with("deep\n└── many/\n    ├── 1\n    ├── 2\n    └── 3"));
+    }
+
+    #[tokio::test]
+    async fn prefixes() {
+        let dir = test_dir("prefixes");
+        for sub in ["services/api/src", "services/web", "libs/core"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        for file in [
+            "README",
+            "services/api/src/main.rs",
+            "services/web/index.js",
+        ] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+
+        let options = BuildOptions {
+            prefixes: vec![PathBuf::from("services/api")],
+            ..BuildOptions::default()
+        };
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &options).await.unwrap();
+        assert_eq!(
+            render(&tree, "."),
+            [
+                ".",
+                "├── libs/ [not under a prefix]",
+                "└── services/",
+                "    ├── api/",
+                "    │   └── src/",
+                "    │       └── main.rs",
+                "    └── web/ [not under a prefix]",
+            ]
+            .join("\n")
+        );
     }
 
-    Ok(entries)
+    #[tokio::test]
+    async fn errors() {
+        let dir = test_dir("errors");
+        let missing = dir.join("missing");
+        match build_file_tree(
+            missing.to_string_lossy().to_string(),
+            &BuildOptions::default(),
+        )
+        .await
+        {
+            Err(Error::Io { path, source }) => {
+                assert_eq!(path, missing);
+                assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+            }
+            _ => panic!("expected a missing root to be reported"),
+        }
+
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/.gitignore"), "a[b\n").unwrap();
+        let options = BuildOptions {
+            respect_ignore_files: true,
+            ..BuildOptions::default()
+        };
+        let root = dir.to_string_lossy().to_string();
+        match build_file_tree(root, &options).await {
+            Err(Error::Ignore { path, .. }) => assert_eq!(path, dir.join("sub/.gitignore")),
+            _ => panic!("expected a malformed ignore file to be reported"),
+        }
+    }
 }
@@ -0,0 +1,215 @@
+//! File contents, loaded on first access and shared between analyses, so that eg grepping for two
+//! patterns, or hashing and then grepping, only reads each file from disk once.
+//!
+//! Contents are cached up to a limit on total bytes, evicting the least recently used file first.
+//! Each entry remembers the size and modification time it was read at, and is re-read if the
+//! metadata it's accessed with differs, eg after a tree is rebuilt or updated.
+//!
+//! Contents may instead come from some other 'Source', for files that aren't on disk, eg those of an
+//! archive held in memory, or of a git commit. These are read from their source on every access.
+
+use crate::filetree::Metadata;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+// what an entry was read at, to detect changes since
+type Version = (u64, Option<SystemTime>);
+
+fn version(metadata: &Metadata) -> Version {
+    (metadata.len, metadata.modified)
+}
+
+/// Where to read the contents of files that aren't on disk from
+pub trait Source: std::fmt::Debug + Send + Sync {
+    /// The contents of the file at 'path'
+    fn read(&self, path: &Path) -> std::io::Result<Arc<[u8]>>;
+}
+
+/// Contents already in memory, by path
+impl Source for HashMap<PathBuf, Arc<[u8]>> {
+    fn read(&self, path: &Path) -> std::io::Result<Arc<[u8]>> {
+        self.get(path).cloned().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, path.display().to_string())
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Lru {
+    entries: HashMap<PathBuf, (Arc<[u8]>, Version, u64)>,
+    // paths by when they were last used, least recent first
+    by_use: BTreeMap<u64, PathBuf>,
+    clock: u64,
+    bytes: usize,
+}
+
+impl Lru {
+    fn remove(&mut self, path: &Path) {
+        if let Some((contents, _, used)) = self.entries.remove(path) {
+            self.by_use.remove(&used);
+            self.bytes -= contents.len();
+        }
+    }
+}
+
+/// A cache of file contents, by path. The default has no capacity, and so holds no contents.
+#[derive(Debug, Default)]
+pub struct ContentCache {
+    capacity: usize,
+    lru: Mutex<Lru>,
+    // where contents are read from, if not from disk
+    source: Option<Arc<dyn Source>>,
+}
+
+impl ContentCache {
+    /// A cache holding at most 'capacity' bytes of contents
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lru: Mutex::new(Lru::default()),
+            source: None,
+        }
+    }
+
+    /// Contents read from 'source' rather than from disk
+    pub fn from_source(source: Arc<dyn Source>) -> Self {
+        Self {
+            source: Some(source),
+            ..Self::default()
+        }
+    }
+
+    /// Whether a file of 'len' bytes would be kept after being read, or else isn't read from disk
+    /// and so can't be read in parts
+    pub fn fits(&self, len: u64) -> bool {
+        self.source.is_some() || len <= self.capacity as u64
+    }
+
+    /// Total bytes currently cached
+    pub fn cached_bytes(&self) -> usize {
+        self.lru.lock().unwrap().bytes
+    }
+
+    /// The contents of the file at 'path', which has 'metadata', from the cache if they were read
+    /// since it last changed, or else from disk or its source
+    pub async fn read(&self, path: &Path, metadata: &Metadata) -> std::io::Result<Arc<[u8]>> {
+        if let Some(source) = &self.source {
+            return source.read(path);
+        }
+        if let Some(contents) = self.cached(path, version(metadata)) {
+            return Ok(contents);
+        }
+        // the lock isn't held while reading, so other files can be read meanwhile
+        let contents: Arc<[u8]> = tokio::fs::read(path).await?.into();
+        self.insert(path, version(metadata), &contents);
+        Ok(contents)
+    }
+
+    /// As 'read', but reading from disk on the current thread, eg from a thread pool
+    pub fn read_blocking(&self, path: &Path, metadata: &Metadata) -> std::io::Result<Arc<[u8]>> {
+        if let Some(source) = &self.source {
+            return source.read(path);
+        }
+        if let Some(contents) = self.cached(path, version(metadata)) {
+            return Ok(contents);
+        }
+        let contents: Arc<[u8]> = std::fs::read(path)?.into();
+        self.insert(path, version(metadata), &contents);
+        Ok(contents)
+    }
+
+    // the contents of 'path', if they're cached and were read at 'version', marking them as used
+    fn cached(&self, path: &Path, version: Version) -> Option<Arc<[u8]>> {
+        let mut lru = self.lru.lock().unwrap();
+        let lru = &mut *lru;
+        lru.clock += 1;
+        match lru.entries.get_mut(path) {
+            Some((contents, cached, used)) if *cached == version => {
+                let path = lru.by_use.remove(used).expect("every entry has a use");
+                *used = lru.clock;
+                lru.by_use.insert(lru.clock, path);
+                Some(contents.clone())
+            }
+            Some(_) => {
+                lru.remove(path);
+                None
+            }
+            None => None,
+        }
+    }
+
+    // cache 'contents' if they fit, evicting others as needed
+    fn insert(&self, path: &Path, version: Version, contents: &Arc<[u8]>) {
+        if !self.fits(contents.len() as u64) {
+            return;
+        }
+        let mut lru = self.lru.lock().unwrap();
+        // read concurrently, and already cached by whoever finished first
+        lru.remove(path);
+        while lru.bytes + contents.len() > self.capacity {
+            let (_, oldest) = lru.by_use.pop_first().expect("the cache is over capacity");
+            lru.remove(&oldest);
+        }
+        lru.clock += 1;
+        let used = lru.clock;
+        lru.bytes += contents.len();
+        lru.by_use.insert(used, path.to_path_buf());
+        lru.entries
+            .insert(path.to_path_buf(), (contents.clone(), version, used));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::test_dir;
+
+    #[tokio::test]
+    async fn lru() {
+        let dir = test_dir("contents");
+        for (name, len) in [("a", 40), ("b", 40), ("c", 40), ("big", 200)] {
+            std::fs::write(dir.join(name), vec![b'x'; len]).unwrap();
+        }
+        let cache = ContentCache::new(100);
+        let read = |name: &str| {
+            let path = dir.join(name);
+            let cache = &cache;
+            async move {
+                let metadata = Metadata::from(&std::fs::metadata(&path).unwrap());
+                cache.read(&path, &metadata).await.map(|c| c.len())
+            }
+        };
+
+        assert_eq!(read("a").await.unwrap(), 40);
+        assert_eq!(read("b").await.unwrap(), 40);
+        let b = Metadata::from(&std::fs::metadata(dir.join("b")).unwrap());
+        // cached files are served without going to disk
+        std::fs::rename(dir.join("a"), dir.join("a.moved")).unwrap();
+        let moved = Metadata::from(&std::fs::metadata(dir.join("a.moved")).unwrap());
+        assert_eq!(cache.read(&dir.join("a"), &moved).await.unwrap().len(), 40);
+        std::fs::rename(dir.join("a.moved"), dir.join("a")).unwrap();
+
+        // 'b' is now the least recently used, so it's evicted to make room
+        assert_eq!(read("c").await.unwrap(), 40);
+        assert_eq!(cache.cached_bytes(), 80);
+        std::fs::remove_file(dir.join("b")).unwrap();
+        assert!(cache.read(&dir.join("b"), &b).await.is_err());
+
+        // too big to cache at all
+        assert_eq!(read("big").await.unwrap(), 200);
+        assert_eq!(cache.cached_bytes(), 80);
+
+        // a change is noticed via the metadata it's read with
+        std::fs::write(dir.join("c"), vec![b'x'; 10]).unwrap();
+        assert_eq!(read("c").await.unwrap(), 10);
+        assert_eq!(cache.cached_bytes(), 50);
+
+        // the default cache holds nothing
+        let none = ContentCache::default();
+        let metadata = Metadata::from(&std::fs::metadata(dir.join("a")).unwrap());
+        none.read(&dir.join("a"), &metadata).await.unwrap();
+        assert_eq!(none.cached_bytes(), 0);
+    }
+}
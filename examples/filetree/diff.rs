@@ -0,0 +1,353 @@
+//! Differences between two file trees, eg snapshots of the same directory taken before and after
+//! some build step, by path relative to the root of each.
+//!
+//! Directories are only reported if they're added or removed, or become or stop being
+//! directories: any other change to one is reported as changes to its entries. Everything under an
+//! added or removed directory is reported as added or removed too. Nothing is known of what's under
+//! a truncated directory, so no change is reported there, unless it stops or starts being truncated.
+//!
+//! Paths are compared once normalized, eg to diff a tree read on macOS against one read on linux.
+//! Where several entries of one tree have the same normalized path, they can't be matched up, and
+//! their path is reported as ambiguous rather than any one of them being compared.
+
+use crate::filetree::hash::HashTree;
+use crate::filetree::paths::{entry_paths, Normalization};
+use crate::filetree::{FileTree, RecursiveFileTree};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    /// changed in place, or replaced by a different kind of entry
+    Modified,
+    /// the normalized path of more than one entry in either tree, eg 'README' and 'readme' when
+    /// folding case, so that there's no telling which to compare
+    Ambiguous,
+}
+
+/// Every changed entry, by path
+pub type Diff = BTreeMap<PathBuf, Change>;
+
+// what's compared for each entry
+#[derive(PartialEq)]
+enum Signature {
+    File {
+        len: u64,
+        modified: Option<SystemTime>,
+        readonly: bool,
+        mode: Option<u32>,
+    },
+    Dir,
+    Symlink(PathBuf),
+    Truncated,
+}
+
+// every entry below the root, by normalized path, along with the paths of more than one entry
+fn signatures(
+    tree: &RecursiveFileTree,
+    normalization: &Normalization,
+) -> (BTreeMap<PathBuf, Signature>, BTreeSet<PathBuf>) {
+    let paths = entry_paths(tree, |name| normalization.name(OsStr::new(name)));
+    let mut signatures = BTreeMap::new();
+    let mut ambiguous = BTreeSet::new();
+    for (idx, path) in paths.iter().filter(|(idx, _)| *idx != tree.root()) {
+        let signature = match tree.layer(idx) {
+            FileTree::File(metadata) => Signature::File {
+                len: metadata.len,
+                modified: metadata.modified,
+                readonly: metadata.readonly,
+                mode: metadata.mode,
+            },
+            FileTree::Dir(..) => Signature::Dir,
+            FileTree::Symlink(target) => Signature::Symlink(target.clone()),
+            FileTree::Truncated(..) => Signature::Truncated,
+        };
+        if signatures.insert(path.clone(), signature).is_some() {
+            ambiguous.insert(path.clone());
+        }
+    }
+    (signatures, ambiguous)
+}
+
+/// Compare two trees by metadata: each file's size, modification time and permissions, and each
+/// link's target. Entries are matched up by their paths as 'normalization' makes them, eg to compare
+/// a tree read on macOS with one read on linux, and changes are reported by those paths.
+pub fn diff(
+    old: &RecursiveFileTree,
+    new: &RecursiveFileTree,
+    normalization: &Normalization,
+) -> Diff {
+    let (old, old_ambiguous) = signatures(old, normalization);
+    let (mut new, new_ambiguous) = signatures(new, normalization);
+    let mut diff = Diff::new();
+    for (path, old) in old {
+        match new.remove(&path) {
+            None => {
+                diff.insert(path, Change::Removed);
+            }
+            Some(new) if new != old => {
+                diff.insert(path, Change::Modified);
+            }
+            Some(_) => {}
+        }
+    }
+    diff.extend(new.into_keys().map(|path| (path, Change::Added)));
+    // whatever was found for an ambiguous path is only a guess
+    let ambiguous = old_ambiguous.into_iter().chain(new_ambiguous);
+    diff.extend(ambiguous.map(|path| (path, Change::Ambiguous)));
+    diff
+}
+
+/// Compare two trees by content hash, only descending into directories whose hashes differ.
+/// Entries are matched up by their paths as 'normalization' makes them, as in 'diff'.
+pub fn diff_hashes(old: &HashTree, new: &HashTree, normalization: &Normalization) -> Diff {
+    let mut diff = Diff::new();
+    diff_hashes_under(old, new, Path::new(""), normalization, &mut diff);
+    diff
+}
+
+// a directory's entries by normalized name, with 'None' for a name that several normalize to
+fn normalized<'a>(
+    entries: &'a BTreeMap<String, HashTree>,
+    normalization: &Normalization,
+) -> BTreeMap<String, Option<&'a HashTree>> {
+    let mut normalized = BTreeMap::new();
+    for (name, entry) in entries {
+        normalized
+            .entry(normalization.name(OsStr::new(name)))
+            .and_modify(|entry| *entry = None)
+            .or_insert(Some(entry));
+    }
+    normalized
+}
+
+fn diff_hashes_under(
+    old: &HashTree,
+    new: &HashTree,
+    path: &Path,
+    normalization: &Normalization,
+    diff: &mut Diff,
+) {
+    if old.hash() == new.hash() {
+        return;
+    }
+    match (old, new) {
+        (HashTree::Dir { entries: old, .. }, HashTree::Dir { entries: new, .. }) => {
+            let old = normalized(old, normalization);
+            let mut new = normalized(new, normalization);
+            for (name, old) in old {
+                let path = path.join(&name);
+                match (old, new.remove(&name)) {
+                    (Some(old), Some(Some(new))) => {
+                        diff_hashes_under(old, new, &path, normalization, diff)
+                    }
+                    (Some(old), None) => {
+                        all_under(old, &path, Change::Removed, normalization, diff)
+                    }
+                    _ => {
+                        diff.insert(path, Change::Ambiguous);
+                    }
+                }
+            }
+            for (name, new) in new {
+                let path = path.join(&name);
+                match new {
+                    Some(new) => all_under(new, &path, Change::Added, normalization, diff),
+                    None => {
+                        diff.insert(path, Change::Ambiguous);
+                    }
+                }
+            }
+        }
+        _ => {
+            // the root itself is never reported, as it's never added or removed
+            if path != Path::new("") {
+                diff.insert(path.to_path_buf(), Change::Modified);
+            }
+            // a replaced directory's entries are all gone, and a replacing one's are all new
+            for (tree, change) in [(old, Change::Removed), (new, Change::Added)] {
+                if let HashTree::Dir { entries, .. } = tree {
+                    for (name, entry) in entries {
+                        let path = path.join(normalization.name(OsStr::new(name)));
+                        all_under(entry, &path, change, normalization, diff);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// report 'tree' and everything under it
+fn all_under(
+    tree: &HashTree,
+    path: &Path,
+    change: Change,
+    normalization: &Normalization,
+    diff: &mut Diff,
+) {
+    diff.insert(path.to_path_buf(), change);
+    if let HashTree::Dir { entries, .. } = tree {
+        for (name, entry) in entries {
+            let path = path.join(normalization.name(OsStr::new(name)));
+            all_under(entry, &path, change, normalization, diff);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::build::{build_file_tree, BuildOptions};
+    use crate::filetree::contents::ContentCache;
+    use crate::filetree::hash::hash_tree;
+    use crate::filetree::paths::Unicode;
+    use crate::filetree::test_dir;
+
+    async fn build(dir: &Path) -> RecursiveFileTree {
+        let root = dir.to_string_lossy().to_string();
+        build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap()
+    }
+
+    fn expected(changes: &[(&str, Change)]) -> Diff {
+        changes
+            .iter()
+            .map(|(path, change)| (PathBuf::from(path), *change))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn before_and_after() {
+        let dir = test_dir("diff");
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::create_dir_all(dir.join("gone")).unwrap();
+        std::fs::write(dir.join("a/x"), "x").unwrap();
+        std::fs::write(dir.join("a/b/y"), "y").unwrap();
+        std::fs::write(dir.join("gone/z"), "z").unwrap();
+        std::fs::write(dir.join("same"), "same").unwrap();
+        std::fs::write(dir.join("kind"), "").unwrap();
+
+        let old = build(&dir).await;
+        let old_hashes = hash_tree(&old, dir.clone(), &ContentCache::default())
+            .await
+            .unwrap();
+        assert_eq!(diff(&old, &old, &Normalization::default()), Diff::new());
+        assert_eq!(
+            diff_hashes(&old_hashes, &old_hashes, &Normalization::default()),
+            Diff::new()
+        );
+
+        std::fs::write(dir.join("a/b/y"), "longer").unwrap();
+        std::fs::remove_dir_all(dir.join("gone")).unwrap();
+        std::fs::create_dir_all(dir.join("new")).unwrap();
+        std::fs::write(dir.join("new/w"), "").unwrap();
+        std::fs::remove_file(dir.join("kind")).unwrap();
+        std::fs::create_dir_all(dir.join("kind")).unwrap();
+        std::fs::write(dir.join("kind/v"), "").unwrap();
+
+        let new = build(&dir).await;
+        let new_hashes = hash_tree(&new, dir.clone(), &ContentCache::default())
+            .await
+            .unwrap();
+        let changes = expected(&[
+            ("a/b/y", Change::Modified),
+            ("gone", Change::Removed),
+            ("gone/z", Change::Removed),
+            ("kind", Change::Modified),
+            ("kind/v", Change::Added),
+            ("new", Change::Added),
+            ("new/w", Change::Added),
+        ]);
+        assert_eq!(diff(&old, &new, &Normalization::default()), changes);
+        assert_eq!(
+            diff_hashes(&old_hashes, &new_hashes, &Normalization::default()),
+            changes
+        );
+
+        // only a hash catches a change that leaves metadata as it was
+        let before = std::fs::metadata(dir.join("a/x"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        std::fs::write(dir.join("a/x"), "X").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("a/x"))
+            .unwrap()
+            .set_modified(before)
+            .unwrap();
+        let touched = build(&dir).await;
+        let touched_hashes = hash_tree(&touched, dir.clone(), &ContentCache::default())
+            .await
+            .unwrap();
+        assert_eq!(diff(&new, &touched, &Normalization::default()), Diff::new());
+        assert_eq!(
+            diff_hashes(&new_hashes, &touched_hashes, &Normalization::default()),
+            expected(&[("a/x", Change::Modified)])
+        );
+    }
+
+    #[tokio::test]
+    async fn normalized() {
+        // the same files, as macOS and linux might name them
+        let dir = test_dir("diff_normalized");
+        let at = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1000);
+        for (side, name) in [("mac", "Cafe\u{301}"), ("linux", "caf\u{e9}")] {
+            std::fs::create_dir_all(dir.join(side).join(name)).unwrap();
+            let file = dir.join(side).join(name).join("README");
+            std::fs::write(&file, "").unwrap();
+            std::fs::File::open(&file)
+                .unwrap()
+                .set_modified(at)
+                .unwrap();
+        }
+        let mac = build(&dir.join("mac")).await;
+        let linux = build(&dir.join("linux")).await;
+
+        assert_eq!(diff(&mac, &linux, &Normalization::default()).len(), 4);
+        let normalization = Normalization {
+            unicode: Unicode::Nfc,
+            fold_case: true,
+        };
+        assert_eq!(diff(&mac, &linux, &normalization), Diff::new());
+
+        let contents = ContentCache::default();
+        let hashes = |tree, side| hash_tree(tree, dir.join(side), &contents);
+        let mac_hashes = hashes(&mac, "mac").await.unwrap();
+        let linux_hashes = hashes(&linux, "linux").await.unwrap();
+        let default = Normalization::default();
+        assert_eq!(diff_hashes(&mac_hashes, &linux_hashes, &default).len(), 4);
+        assert_eq!(
+            diff_hashes(&mac_hashes, &linux_hashes, &normalization),
+            Diff::new()
+        );
+
+        // names that only differ in case can't be matched up once it's folded
+        #[cfg(target_os = "linux")]
+        {
+            for name in ["readme", "README", "other"] {
+                std::fs::create_dir_all(dir.join("cased")).unwrap();
+                std::fs::write(dir.join("cased").join(name), name).unwrap();
+            }
+            let cased = build(&dir.join("cased")).await;
+            let cased_hashes = hashes(&cased, "cased").await.unwrap();
+            let changes = expected(&[
+                ("caf\u{e9}", Change::Removed),
+                ("caf\u{e9}/readme", Change::Removed),
+                ("other", Change::Added),
+                ("readme", Change::Ambiguous),
+            ]);
+            assert_eq!(diff(&linux, &cased, &normalization), changes);
+            assert_eq!(
+                diff_hashes(&linux_hashes, &cased_hashes, &normalization),
+                changes
+            );
+            assert_eq!(diff(&cased, &cased, &default), Diff::new());
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Disk usage: the apparent size of each file, and the cumulative size of each directory, counting
+//! files with several hard links once.
+
+use crate::filetree::query::under;
+use crate::filetree::{FileId, FileTreeRef, Metadata, RecursiveFileTree};
+use recursion::recursive::Collapse;
+use recursion::render::TreeLines;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Directories with their sizes, by path
+pub type DirSizes = Vec<(PathBuf, u64)>;
+
+/// Sizes in bytes, by path relative to the root of the tree
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sizes {
+    pub total: u64,
+    /// every directory below the root, including the size of everything under it
+    pub dirs: DirSizes,
+}
+
+impl Sizes {
+    /// The 'n' largest directories, largest first. See 'query::largest' for the largest files.
+    pub fn largest_dirs(&self, n: usize) -> DirSizes {
+        let mut dirs = self.dirs.clone();
+        // ties are broken by path, for stable output
+        dirs.sort_by(|(a_path, a), (b_path, b)| b.cmp(a).then_with(|| a_path.cmp(b_path)));
+        dirs.truncate(n);
+        dirs
+    }
+}
+
+// a total size, along with the hard linked files it includes, so that each is only counted once
+// however many of its links are in the tree
+#[derive(Default)]
+struct Total {
+    bytes: u64,
+    linked: HashMap<FileId, u64>,
+}
+
+impl Total {
+    fn file(metadata: &Metadata) -> Self {
+        let mut total = Self {
+            bytes: metadata.len,
+            ..Self::default()
+        };
+        if let Some(id) = metadata.hardlink {
+            total.linked.insert(id, metadata.len);
+        }
+        total
+    }
+
+    fn add(&mut self, other: Total) {
+        self.bytes += other.bytes;
+        for (id, len) in other.linked {
+            if self.linked.insert(id, len).is_some() {
+                self.bytes -= len;
+            }
+        }
+    }
+}
+
+/// Compute sizes in a single pass. Links count as empty, as do truncated directories, and files
+/// with several hard links are counted once in the size of each directory containing any of them.
+pub fn sizes(tree: &RecursiveFileTree) -> Sizes {
+    let (_, total, dirs) =
+        tree.as_ref()
+            .collapse_layers(|node: FileTreeRef<(bool, Total, DirSizes)>| match node {
+                FileTreeRef::File(metadata) => (false, Total::file(metadata), Vec::new()),
+                FileTreeRef::Symlink(_) => (false, Total::default(), Vec::new()),
+                FileTreeRef::Truncated(..) => (true, Total::default(), Vec::new()),
+                FileTreeRef::Dir(_, children) => {
+                    let mut total = Total::default();
+                    let mut dirs = Vec::new();
+                    for (name, (is_dir, child, below)) in children {
+                        if is_dir {
+                            dirs.push((PathBuf::from(name), child.bytes));
+                        }
+                        dirs.extend(
+                            below
+                                .into_iter()
+                                .map(|(path, size)| (under(name, path), size)),
+                        );
+                        total.add(child);
+                    }
+                    (true, total, dirs)
+                }
+            });
+    Sizes {
+        total: total.bytes,
+        dirs,
+    }
+}
+
+/// Format a byte count with binary units, eg '1.5 KiB'
+pub fn human(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// render 'tree'-command style like 'render', with each entry labeled by its size
+pub fn render_sizes(tree: &RecursiveFileTree, root: &str) -> String {
+    let (total, entries) =
+        tree.as_ref()
+            .collapse_layers(
+                |node: FileTreeRef<(Total, Option<Vec<TreeLines>>)>| match node {
+                    FileTreeRef::File(metadata) => (Total::file(metadata), None),
+                    FileTreeRef::Symlink(_) => (Total::default(), None),
+                    FileTreeRef::Truncated(..) => (Total::default(), Some(Vec::new())),
+                    FileTreeRef::Dir(_, children) => {
+                        let mut total = Total::default();
+                        let entries = children
+                            .into_iter()
+                            .map(|(name, (size, entries))| {
+                                let name = name.to_string_lossy();
+                                let label = human(size.bytes);
+                                total.add(size);
+                                match entries {
+                                    Some(entries) => {
+                                        TreeLines::node(format!("{}/ ({})", name, label), entries)
+                                    }
+                                    None => TreeLines::leaf(format!("{} ({})", name, label)),
+                                }
+                            })
+                            .collect();
+                        (total, Some(entries))
+                    }
+                },
+            );
+    TreeLines::node(
+        format!("{} ({})", root, human(total.bytes)),
+        entries.unwrap_or_default(),
+    )
+    .render()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::build::{build_file_tree, BuildOptions};
+    use crate::filetree::test_dir;
+
+    #[test]
+    fn human_readable() {
+        assert_eq!(human(0), "0 B");
+        assert_eq!(human(1023), "1023 B");
+        assert_eq!(human(1536), "1.5 KiB");
+        assert_eq!(human(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[tokio::test]
+    async fn du() {
+        let dir = test_dir("du");
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::create_dir_all(dir.join("c")).unwrap();
+        std::fs::write(dir.join("top"), vec![0; 10]).unwrap();
+        std::fs::write(dir.join("a/x"), vec![0; 100]).unwrap();
+        std::fs::write(dir.join("a/b/y"), vec![0; 2000]).unwrap();
+
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap();
+        let sizes = sizes(&tree);
+
+        assert_eq!(sizes.total, 2110);
+        assert_eq!(
+            sizes.largest_dirs(2),
+            vec![(PathBuf::from("a"), 2100), (PathBuf::from("a/b"), 2000)]
+        );
+        assert_eq!(sizes.largest_dirs(5).last(), Some(&(PathBuf::from("c"), 0)));
+
+        assert_eq!(
+            render_sizes(&tree, "."),
+            [
+                ". (2.1 KiB)",
+                "├── a/ (2.1 KiB)",
+                "│   ├── b/ (2.0 KiB)",
+                "│   │   └── y (2.0 KiB)",
+                "│   └── x (100 B)",
+                "├── c/ (0 B)",
+                "└── top (10 B)",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hardlinks() {
+        let dir = test_dir("du_hardlinks");
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        std::fs::write(dir.join("a/x"), vec![0; 1000]).unwrap();
+        std::fs::hard_link(dir.join("a/x"), dir.join("a/y")).unwrap();
+        std::fs::hard_link(dir.join("a/x"), dir.join("b/z")).unwrap();
+        std::fs::write(dir.join("b/w"), vec![0; 10]).unwrap();
+
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap();
+        let sizes = sizes(&tree);
+
+        // each directory counts the file once, as does their parent
+        assert_eq!(sizes.total, 1010);
+        assert_eq!(
+            sizes.dirs,
+            vec![(PathBuf::from("a"), 1000), (PathBuf::from("b"), 1010)]
+        );
+        assert!(render_sizes(&tree, ".").starts_with(". (1010 B)\n├── a/ (1000 B)"));
+    }
+}
@@ -0,0 +1,121 @@
+//! Everything that can go wrong building, searching or watching a file tree, with the path each
+//! failure happened at where there is one, so that callers can tell eg a missing root from a
+//! malformed ignore file, and report which file was at fault.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+    /// reading or writing the file or directory at 'path' failed
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// the ignore file at 'path' has a malformed pattern
+    Ignore {
+        path: PathBuf,
+        source: ignore::Error,
+    },
+    /// a glob given to filter the tree is malformed
+    Glob(globset::Error),
+    /// a search pattern is malformed
+    Regex(regex::Error),
+    /// a set of literal search patterns couldn't be compiled
+    Patterns(aho_corasick::BuildError),
+    /// the saved hashes at 'path' couldn't be read or written
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// reading a commit from a git repository failed
+    #[cfg(feature = "git")]
+    Git(git2::Error),
+    /// watching the filesystem for changes failed
+    #[cfg(feature = "notify")]
+    Watch(notify::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Ignore { path, source } => {
+                write!(f, "invalid ignore file {}: {}", path.display(), source)
+            }
+            Error::Glob(e) => write!(f, "invalid glob: {}", e),
+            Error::Regex(e) => write!(f, "invalid pattern: {}", e),
+            Error::Patterns(e) => write!(f, "invalid patterns: {}", e),
+            Error::Json { path, source } => {
+                write!(f, "invalid saved hashes {}: {}", path.display(), source)
+            }
+            #[cfg(feature = "git")]
+            Error::Git(e) => write!(f, "git error: {}", e),
+            #[cfg(feature = "notify")]
+            Error::Watch(e) => write!(f, "watch error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Ignore { source, .. } => Some(source),
+            Error::Glob(e) => Some(e),
+            Error::Regex(e) => Some(e),
+            Error::Patterns(e) => Some(e),
+            Error::Json { source, .. } => Some(source),
+            #[cfg(feature = "git")]
+            Error::Git(e) => Some(e),
+            #[cfg(feature = "notify")]
+            Error::Watch(e) => Some(e),
+        }
+    }
+}
+
+impl From<globset::Error> for Error {
+    fn from(e: globset::Error) -> Self {
+        Error::Glob(e)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Self {
+        Error::Regex(e)
+    }
+}
+
+impl From<aho_corasick::BuildError> for Error {
+    fn from(e: aho_corasick::BuildError) -> Self {
+        Error::Patterns(e)
+    }
+}
+
+#[cfg(feature = "git")]
+impl From<git2::Error> for Error {
+    fn from(e: git2::Error) -> Self {
+        Error::Git(e)
+    }
+}
+
+#[cfg(feature = "notify")]
+impl From<notify::Error> for Error {
+    fn from(e: notify::Error) -> Self {
+        Error::Watch(e)
+    }
+}
+
+/// Attaches the path an io error happened at, as 'std::io::Error' doesn't record it
+pub trait WithPath<T> {
+    fn at(self, path: impl AsRef<Path>) -> Result<T, Error>;
+}
+
+impl<T> WithPath<T> for std::io::Result<T> {
+    fn at(self, path: impl AsRef<Path>) -> Result<T, Error> {
+        self.map_err(|source| Error::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        })
+    }
+}
@@ -0,0 +1,245 @@
+//! JSON export, for consumption outside of this process: either the whole tree as one nested
+//! object, or as newline-delimited objects, one per entry, each labeled with its path.
+//!
+//! Both describe each entry the same way, via 'fields': its type, its modification time in seconds
+//! since the unix epoch, and its size or link target. Truncated directories have no entries, and
+//! say why in a 'truncated' field.
+//!
+//! The tree's structure alone can also be exported as a graphviz digraph, for visualizing it.
+//!
+//! Paths are written with '/' between components on every platform, so that exports compare equal
+//! wherever they were made.
+
+use crate::filetree::paths::{entry_paths, portable};
+use crate::filetree::query::under;
+use crate::filetree::{FileTree, FileTreeRef, Metadata, RecursiveFileTree, Truncation};
+use recursion::recursive::Collapse;
+use serde_json::{Map, Value};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+fn modified(metadata: &Metadata) -> Value {
+    metadata
+        .modified
+        .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(Value::Null, |since| since.as_secs().into())
+}
+
+// an entry's own fields, not including anything about its children
+fn fields<A>(node: &FileTreeRef<A>) -> Map<String, Value> {
+    let mut fields = Map::new();
+    match node {
+        FileTreeRef::File(metadata) => {
+            fields.insert("type".into(), "file".into());
+            fields.insert("size".into(), metadata.len.into());
+            fields.insert("modified".into(), modified(metadata));
+        }
+        FileTreeRef::Dir(metadata, _) => {
+            fields.insert("type".into(), "dir".into());
+            fields.insert("modified".into(), modified(metadata));
+        }
+        FileTreeRef::Truncated(metadata, truncation) => {
+            let truncation = match truncation {
+                Truncation::Depth => "depth",
+                Truncation::Entries => "entries",
+                Truncation::Sparse => "sparse",
+            };
+            fields.insert("type".into(), "dir".into());
+            fields.insert("modified".into(), modified(metadata));
+            fields.insert("truncated".into(), truncation.into());
+        }
+        FileTreeRef::Symlink(target) => {
+            fields.insert("type".into(), "symlink".into());
+            fields.insert("target".into(), target.to_string_lossy().into());
+        }
+    }
+    fields
+}
+
+/// The tree as a single object, with each directory's entries in an 'entries' object, by name
+pub fn to_json(tree: &RecursiveFileTree) -> Value {
+    tree.as_ref().collapse_layers(|node: FileTreeRef<Value>| {
+        let mut fields = fields(&node);
+        if let FileTreeRef::Dir(_, children) = node {
+            let entries = children
+                .into_iter()
+                .map(|(name, entry)| (name.to_string_lossy().into_owned(), entry))
+                .collect();
+            fields.insert("entries".into(), Value::Object(entries));
+        }
+        Value::Object(fields)
+    })
+}
+
+/// One object per line for each entry below the root, sorted by path, with its path relative to
+/// the root in a 'path' field
+pub fn to_ndjson(tree: &RecursiveFileTree) -> String {
+    // every entry with its fields, by path relative to the current layer
+    type Described = Vec<(PathBuf, Map<String, Value>)>;
+    let entries = tree
+        .as_ref()
+        .collapse_layers(|node: FileTreeRef<Described>| {
+            let mut entries = vec![(PathBuf::new(), fields(&node))];
+            if let FileTreeRef::Dir(_, children) = node {
+                for (name, below) in children {
+                    entries.extend(below.into_iter().map(|(path, e)| (under(name, path), e)));
+                }
+            }
+            entries
+        });
+
+    let mut entries: Vec<_> = entries
+        .into_iter()
+        .filter(|(path, _)| !path.as_os_str().is_empty())
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut out = String::new();
+    for (path, mut fields) in entries {
+        fields.insert("path".into(), portable(&path).into());
+        out.push_str(&Value::Object(fields).to_string());
+        out.push('\n');
+    }
+    out
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// each entry's node id is its path, with the root as '.'
+fn node_id(path: &Path) -> String {
+    if path.as_os_str().is_empty() {
+        quote(".")
+    } else {
+        quote(&portable(path))
+    }
+}
+
+/// A graphviz 'digraph' with a node per entry, sorted by path, and edges from each directory to
+/// its entries. Entries are labeled as 'render' prints them, with the root labeled 'root'.
+pub fn to_dot(tree: &RecursiveFileTree, root: &str) -> String {
+    // every entry with its label, named by the label of the edge leading to it
+    let paths = entry_paths(tree, str::to_string);
+    let mut entries: Vec<(&PathBuf, String)> = paths
+        .iter()
+        .map(|(idx, path)| {
+            let suffix = match tree.layer(idx) {
+                FileTree::File(_) => String::new(),
+                FileTree::Dir(..) => "/".to_string(),
+                FileTree::Symlink(target) => format!(" -> {}", target.display()),
+                FileTree::Truncated(_, truncation) => format!("/ [{}]", truncation),
+            };
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            (path, format!("{}{}", name, suffix))
+        })
+        .collect();
+
+    entries.sort_by_key(|(path, _)| *path);
+    let mut out = String::from("digraph files {\n");
+    for (path, label) in entries {
+        let label = if path.as_os_str().is_empty() {
+            root.to_string()
+        } else {
+            label
+        };
+        writeln!(out, "  {} [label={}];", node_id(path), quote(&label)).unwrap();
+        if let Some(parent) = path.parent() {
+            writeln!(out, "  {} -> {};", node_id(parent), node_id(path)).unwrap();
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::build::{build_file_tree, BuildOptions};
+    use crate::filetree::test_dir;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn export() {
+        let dir = test_dir("export");
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::write(dir.join("a/x"), "12345").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("a/x", dir.join("link")).unwrap();
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        for path in [dir.join("a/x"), dir.join("a"), dir.clone()] {
+            std::fs::File::open(&path)
+                .unwrap()
+                .set_modified(at)
+                .unwrap();
+        }
+
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap();
+
+        let mut expected = json!({
+            "type": "dir",
+            "modified": 1000,
+            "entries": {
+                "a": {
+                    "type": "dir",
+                    "modified": 1000,
+                    "entries": {
+                        "x": {"type": "file", "size": 5, "modified": 1000},
+                    },
+                },
+            },
+        });
+        #[cfg(unix)]
+        {
+            expected["entries"]["link"] = json!({"type": "symlink", "target": "a/x"});
+        }
+        assert_eq!(to_json(&tree), expected);
+
+        let lines: Vec<Value> = to_ndjson(&tree)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines[0],
+            json!({"path": "a", "type": "dir", "modified": 1000})
+        );
+        assert_eq!(
+            lines[1],
+            json!({"path": "a/x", "type": "file", "size": 5, "modified": 1000})
+        );
+        assert_eq!(lines.len(), if cfg!(unix) { 3 } else { 2 });
+    }
+
+    #[tokio::test]
+    async fn dot() {
+        let dir = test_dir("export_dot");
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::write(dir.join("a/x"), "").unwrap();
+        std::fs::write(dir.join("\"quoted\""), "").unwrap();
+
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            to_dot(&tree, "."),
+            [
+                "digraph files {",
+                "  \".\" [label=\".\"];",
+                "  \"\\\"quoted\\\"\" [label=\"\\\"quoted\\\"\"];",
+                "  \".\" -> \"\\\"quoted\\\"\";",
+                "  \"a\" [label=\"a/\"];",
+                "  \".\" -> \"a\";",
+                "  \"a/x\" [label=\"x\"];",
+                "  \"a\" -> \"a/x\";",
+                "}",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! A git commit as a file tree, expanded from git's object store rather than from a checkout: each
+//! tree object is a directory, and each blob a file or link. Every algebra over file trees then
+//! works on any commit, eg finding the largest blob reachable from 'HEAD', or searching an old
+//! revision, without checking it out.
+//!
+//! Git doesn't record modification times, so every entry has the commit's time, and only files'
+//! executable bits are kept as their permissions. Submodules are left out, as their commits are
+//! in other repositories.
+
+use crate::filetree::contents::{ContentCache, Source};
+use crate::filetree::error::Error;
+use crate::filetree::{FileTree, Metadata, RecursiveFileTree};
+use git2::{ObjectType, Oid, Repository};
+use recursion::recursive::TryExpand;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+// file modes, as git records them in tree entries
+const TREE: i32 = 0o040000;
+const LINK: i32 = 0o120000;
+const SUBMODULE: i32 = 0o160000;
+
+/// The tree of a single commit, along with the contents of its files
+pub struct Revision {
+    pub tree: RecursiveFileTree,
+    /// reads each file's blob, by the path it would be checked out at under 'root'
+    pub contents: ContentCache,
+    /// the repository's working directory, or the repository itself if it's bare
+    pub root: PathBuf,
+}
+
+// blobs by the path they'd be checked out at, read from the object store as they're needed
+struct Blobs {
+    repo: Mutex<Repository>,
+    paths: HashMap<PathBuf, Oid>,
+}
+
+impl std::fmt::Debug for Blobs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Blobs")
+            .field("paths", &self.paths)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Source for Blobs {
+    fn read(&self, path: &Path) -> std::io::Result<Arc<[u8]>> {
+        let oid = self.paths.get(path).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, path.display().to_string())
+        })?;
+        let repo = self.repo.lock().unwrap();
+        let blob = repo.find_blob(*oid).map_err(std::io::Error::other)?;
+        Ok(blob.content().into())
+    }
+}
+
+#[cfg(unix)]
+fn os_string(name: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(name.to_vec())
+}
+
+#[cfg(not(unix))]
+fn os_string(name: &[u8]) -> OsString {
+    String::from_utf8_lossy(name).into_owned().into()
+}
+
+/// Expand the commit 'rev', eg 'HEAD' or a branch name, of the repository containing 'path'
+pub fn open(path: &Path, rev: &str) -> Result<Revision, Error> {
+    let repo = Repository::discover(path)?;
+    let (tree_id, modified) = {
+        let commit = repo.revparse_single(rev)?.peel_to_commit()?;
+        let modified = u64::try_from(commit.time().seconds())
+            .ok()
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        (commit.tree_id(), modified)
+    };
+    let root = repo.workdir().unwrap_or(repo.path()).to_path_buf();
+    let odb = repo.odb()?;
+
+    // each blob's path, recorded as the tree is expanded
+    let paths = RefCell::new(HashMap::new());
+    let seed = (PathBuf::new(), tree_id, TREE);
+    let tree = RecursiveFileTree::try_expand_layers(seed, |(path, oid, mode)| {
+        let metadata = Metadata {
+            is_dir: mode == TREE,
+            modified,
+            ..Metadata::default()
+        };
+        let layer = match mode {
+            TREE => {
+                let entries = repo
+                    .find_tree(oid)?
+                    .iter()
+                    .filter(|entry| entry.filemode() != SUBMODULE)
+                    .map(|entry| {
+                        let name = os_string(entry.name_bytes());
+                        let seed = (path.join(&name), entry.id(), entry.filemode());
+                        (name, seed)
+                    })
+                    .collect();
+                FileTree::Dir(metadata, entries)
+            }
+            LINK => {
+                let blob = repo.find_blob(oid)?;
+                let target = String::from_utf8_lossy(blob.content()).into_owned();
+                FileTree::Symlink(PathBuf::from(target))
+            }
+            _ => {
+                let (len, kind) = odb.read_header(oid)?;
+                if kind != ObjectType::Blob {
+                    return Err(git2::Error::from_str("tree entry isn't a blob"));
+                }
+                paths.borrow_mut().insert(root.join(&path), oid);
+                FileTree::File(Metadata {
+                    len: len as u64,
+                    mode: Some(mode as u32 & 0o777),
+                    ..metadata
+                })
+            }
+        };
+        Ok::<_, git2::Error>(layer)
+    })?;
+    drop(odb);
+
+    let blobs = Blobs {
+        repo: Mutex::new(repo),
+        paths: paths.into_inner(),
+    };
+    Ok(Revision {
+        tree,
+        contents: ContentCache::from_source(Arc::new(blobs)),
+        root,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::query::largest;
+    use crate::filetree::render;
+    use crate::filetree::search::{search, SearchOptions};
+    use crate::filetree::test_dir;
+    use recursion::recursive::Collapse;
+
+    // commit every file in the working directory of 'repo'
+    fn commit_all(repo: &Repository, message: &str) {
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("test", "test@example.com").unwrap();
+        let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn commits() {
+        let dir = test_dir("git");
+        let repo = Repository::init(&dir).unwrap();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(dir.join("README"), "needle\n").unwrap();
+        commit_all(&repo, "first");
+        std::fs::write(dir.join("big"), vec![b'x'; 1000]).unwrap();
+        std::fs::write(dir.join("README"), "changed\n").unwrap();
+        commit_all(&repo, "second");
+        // uncommitted changes aren't seen
+        std::fs::write(dir.join("README"), "needle, uncommitted\n").unwrap();
+
+        let head = open(&dir, "HEAD").unwrap();
+        assert_eq!(
+            render(&head.tree, "."),
+            ".\n├── README\n├── big\n└── src/\n    └── main.rs"
+        );
+        assert_eq!(
+            head.tree.as_ref().collapse_layers(largest(1)),
+            vec![(PathBuf::from("big"), 1000)]
+        );
+
+        let first = open(&dir.join("src"), "HEAD~1").unwrap();
+        assert_eq!(
+            render(&first.tree, "."),
+            ".\n├── README\n└── src/\n    └── main.rs"
+        );
+        let options = SearchOptions {
+            contents: Arc::new(first.contents),
+            ..SearchOptions::default()
+        };
+        let results = search(first.tree, first.root.clone(), "needle", &options)
+            .await
+            .unwrap();
+        let paths: Vec<PathBuf> = results.matches.into_iter().map(|m| m.path).collect();
+        assert_eq!(paths, vec![first.root.join("README")]);
+
+        assert!(open(&dir, "no-such-branch").is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! Include and exclude filters, written as globs over paths relative to the root of the tree, eg
+//! '**/*.rs' to include only rust sources, or '!**/vendor/**' to exclude anything under a 'vendor'
+//! directory. Patterns are compiled once, up front, and applied to each entry as it's read, so
+//! excluded directories are never expanded.
+
+use crate::filetree::paths::Normalization;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct Globs {
+    include: GlobSet,
+    exclude: GlobSet,
+    // how paths are normalized before being matched
+    normalization: Normalization,
+}
+
+impl Globs {
+    /// Patterns prefixed with '!' exclude, and any others include. Files are kept if they match
+    /// some include pattern, or if there are none, and no exclude pattern. Directories are only
+    /// subject to exclude patterns, as their contents may be included. Paths are matched as
+    /// 'normalization' makes them, eg so that '**/*.RS' matches 'main.rs' if case is folded.
+    pub fn new<S: AsRef<str>>(
+        patterns: &[S],
+        normalization: Normalization,
+    ) -> Result<Self, globset::Error> {
+        let glob = |pattern: &str| {
+            GlobBuilder::new(&normalization.pattern(pattern))
+                .case_insensitive(normalization.fold_case)
+                .build()
+        };
+        let mut include = GlobSetBuilder::new();
+        let mut exclude = GlobSetBuilder::new();
+        for pattern in patterns {
+            match pattern.as_ref().strip_prefix('!') {
+                Some(pattern) => {
+                    exclude.add(glob(pattern)?);
+                    // everything under a directory is excluded, so it needn't be read at all
+                    if let Some(dir) = pattern.strip_suffix("/**") {
+                        exclude.add(glob(dir)?);
+                    }
+                }
+                None => {
+                    include.add(glob(pattern.as_ref())?);
+                }
+            }
+        }
+        Ok(Self {
+            include: include.build()?,
+            exclude: exclude.build()?,
+            normalization,
+        })
+    }
+
+    /// Whether to keep the entry at 'path', relative to the root
+    pub fn keep(&self, path: &Path, is_dir: bool) -> bool {
+        let path = self.normalization.path(path);
+        if self.exclude.is_match(&path) {
+            return false;
+        }
+        is_dir || self.include.is_empty() || self.include.is_match(&path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::paths::Unicode;
+
+    #[test]
+    fn include_and_exclude() {
+        let globs = Globs::new(
+            &["**/*.rs", "!**/vendor/**", "!*.generated.rs"],
+            Normalization::default(),
+        )
+        .unwrap();
+        let keep = |path: &str, is_dir| globs.keep(Path::new(path), is_dir);
+
+        assert!(keep("main.rs", false));
+        assert!(keep("src/lib.rs", false));
+        assert!(!keep("README.md", false));
+        // directories are kept regardless of includes
+        assert!(keep("src", true));
+        assert!(!keep("vendor", true));
+        assert!(!keep("src/vendor", true));
+        assert!(!keep("src/vendor/dep.rs", false));
+        assert!(!keep("src/parser.generated.rs", false));
+
+        let everything = Globs::default();
+        assert!(everything.keep(Path::new("anything"), false));
+        assert!(Globs::new(&["a[b"], Normalization::default()).is_err());
+    }
+
+    #[test]
+    fn normalized() {
+        let normalization = Normalization {
+            unicode: Unicode::Nfc,
+            fold_case: true,
+        };
+        // a composed 'é' in the pattern, and a decomposed one in the path
+        let globs = Globs::new(&["**/CAF\u{e9}/*.RS"], normalization).unwrap();
+        assert!(globs.keep(Path::new("src/Cafe\u{301}/main.rs"), false));
+        assert!(!globs.keep(Path::new("src/cafe/main.rs"), false));
+
+        let exact = Globs::new(&["**/*.RS"], Normalization::default()).unwrap();
+        assert!(!exact.keep(Path::new("main.rs"), false));
+    }
+}
@@ -0,0 +1,228 @@
+//! Merkle hashing for change detection: a hash of the contents of each file, and of the names and
+//! hashes of the entries of each directory. Hashes are stable across runs, so a hashed tree can be
+//! saved and compared against a later one, skipping any subtree whose hash hasn't changed.
+
+use crate::filetree::contents::ContentCache;
+use crate::filetree::error::{Error, WithPath};
+use crate::filetree::{FileTreeRef, RecursiveFileTree};
+use futures::{future::BoxFuture, FutureExt};
+use recursion::recursive::Collapse;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::hash::Hasher;
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+
+// FNV-1a, which unlike the standard library's hashers is fully specified, so its output can be
+// persisted and compared across runs and toolchains
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// each kind of entry hashes with a distinct tag, so eg a file and a link holding the same bytes
+// don't collide
+const FILE: u8 = 0;
+const DIR: u8 = 1;
+const SYMLINK: u8 = 2;
+const TRUNCATED: u8 = 3;
+
+/// A file tree with a hash for every entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HashTree {
+    File {
+        hash: u64,
+    },
+    /// entry names are stored lossily, as utf-8
+    Dir {
+        hash: u64,
+        entries: BTreeMap<String, HashTree>,
+    },
+    Symlink {
+        hash: u64,
+        target: PathBuf,
+    },
+    /// a directory whose entries were left out, which hashes the same whatever they are
+    Truncated {
+        hash: u64,
+    },
+}
+
+impl HashTree {
+    pub fn hash(&self) -> u64 {
+        match self {
+            HashTree::File { hash }
+            | HashTree::Dir { hash, .. }
+            | HashTree::Symlink { hash, .. }
+            | HashTree::Truncated { hash } => *hash,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+// borrowed filetree, with each child hashed lazily given its path
+type LazilyHashedFileTree<'a> = FileTreeRef<
+    'a,
+    Box<dyn FnOnce(PathBuf) -> BoxFuture<'a, Result<HashTree, Error>> + Send + Sync + 'a>,
+>;
+
+/// Hash every file under 'root_dir', which 'tree' must have been built from
+pub fn hash_tree<'a>(
+    tree: &'a RecursiveFileTree,
+    root_dir: PathBuf,
+    contents: &'a ContentCache,
+) -> BoxFuture<'a, Result<HashTree, Error>> {
+    let f = tree.as_ref().collapse_layers(|node| {
+        Box::new(move |path| async move { hash_layer(node, path, contents).await }.boxed())
+    });
+
+    f(root_dir)
+}
+
+async fn hash_layer(
+    node: LazilyHashedFileTree<'_>,
+    path: PathBuf,
+    contents: &ContentCache,
+) -> Result<HashTree, Error> {
+    match node {
+        FileTreeRef::File(metadata) if contents.fits(metadata.len) => {
+            let mut hasher = Fnv::default();
+            hasher.write_u8(FILE);
+            hasher.write(&contents.read(&path, metadata).await.at(&path)?);
+            Ok(HashTree::File {
+                hash: hasher.finish(),
+            })
+        }
+        FileTreeRef::File(_) => {
+            let mut hasher = Fnv::default();
+            hasher.write_u8(FILE);
+            // read in chunks, so files too big to cache needn't fit in memory
+            let mut file = tokio::fs::File::open(&path).await.at(&path)?;
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let n = file.read(&mut buf).await.at(&path)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.write(&buf[..n]);
+            }
+            Ok(HashTree::File {
+                hash: hasher.finish(),
+            })
+        }
+        FileTreeRef::Symlink(target) => {
+            let mut hasher = Fnv::default();
+            hasher.write_u8(SYMLINK);
+            hasher.write(target.to_string_lossy().as_bytes());
+            Ok(HashTree::Symlink {
+                hash: hasher.finish(),
+                target: target.clone(),
+            })
+        }
+        FileTreeRef::Truncated(..) => {
+            let mut hasher = Fnv::default();
+            hasher.write_u8(TRUNCATED);
+            Ok(HashTree::Truncated {
+                hash: hasher.finish(),
+            })
+        }
+        FileTreeRef::Dir(_, children) => {
+            let mut entries = BTreeMap::new();
+            for (name, child) in children.into_iter() {
+                let hashed = child(path.join(name)).await?;
+                entries.insert(name.to_string_lossy().into_owned(), hashed);
+            }
+            // entries are hashed in sorted order, with each name length-prefixed so that
+            // the boundaries between names are unambiguous
+            let mut hasher = Fnv::default();
+            hasher.write_u8(DIR);
+            for (name, entry) in entries.iter() {
+                hasher.write(&(name.len() as u64).to_le_bytes());
+                hasher.write(name.as_bytes());
+                hasher.write(&entry.hash().to_le_bytes());
+            }
+            Ok(HashTree::Dir {
+                hash: hasher.finish(),
+                entries,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::build::{build_file_tree, BuildOptions};
+    use crate::filetree::test_dir;
+    use std::path::Path;
+
+    async fn hash_dir(dir: &Path) -> HashTree {
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap();
+        hash_tree(&tree, dir.to_path_buf(), &ContentCache::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn change_detection() {
+        let dir = test_dir("hash");
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::create_dir_all(dir.join("c")).unwrap();
+        std::fs::write(dir.join("a/x"), "x").unwrap();
+        std::fs::write(dir.join("a/b/y"), "y").unwrap();
+        std::fs::write(dir.join("c/y"), "y").unwrap();
+
+        let before = hash_dir(&dir).await;
+        assert_eq!(hash_dir(&dir).await, before);
+
+        // content addressed: equal files and equal dirs hash the same
+        let HashTree::Dir { entries, .. } = &before else {
+            panic!("root is a dir")
+        };
+        let HashTree::Dir { entries: a, .. } = &entries["a"] else {
+            panic!("'a' is a dir")
+        };
+        assert_eq!(a["b"].hash(), entries["c"].hash());
+
+        // persisted hashes compare equal to freshly computed ones
+        let saved = before.to_json().unwrap();
+        assert_eq!(HashTree::from_json(&saved).unwrap(), before);
+
+        // a change is reflected in the hash of every directory above it, and no others
+        std::fs::write(dir.join("a/b/y"), "changed").unwrap();
+        let after = hash_dir(&dir).await;
+        assert_ne!(after.hash(), before.hash());
+        let HashTree::Dir { entries: after, .. } = &after else {
+            panic!("root is a dir")
+        };
+        assert_ne!(after["a"].hash(), entries["a"].hash());
+        assert_eq!(after["c"].hash(), entries["c"].hash());
+    }
+}
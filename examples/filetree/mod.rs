@@ -1,20 +1,117 @@
+pub mod archive;
+#[cfg(unix)]
+pub mod audit;
 pub mod build;
+pub mod contents;
+pub mod diff;
+pub mod du;
+pub mod error;
+pub mod export;
+#[cfg(feature = "git")]
+pub mod git;
+pub mod globs;
+pub mod hash;
+pub mod paths;
+pub mod query;
+pub mod rank;
 pub mod search;
+#[cfg(feature = "notify")]
+pub mod watch;
 
+use recursion::flamegraph::FoldedStacks;
+use recursion::map_layer::{EdgeLabeled, LayerArity, MapLayer};
 use recursion::recursive::Collapse;
+use recursion::recursive_tree::arena_eval::ArenaIndex;
 use recursion::recursive_tree::RecursiveTree;
-use recursion::{map_layer::MapLayer, recursive_tree::arena_eval::ArenaIndex};
-use std::{collections::HashMap, ffi::OsString};
+use recursion::render::render_tree;
+use std::borrow::Cow;
+use std::fmt;
+use std::time::SystemTime;
+use std::{collections::BTreeMap, ffi::OsString, path::PathBuf};
 
 // structure of the file tree with metadata, no file contents, files do not each own their full path b/c that's too much overhead
+#[derive(Clone)]
 pub enum FileTree<A> {
-    File(std::fs::Metadata),
-    Dir(HashMap<OsString, A>),
+    File(Metadata),
+    /// a directory's own metadata, and its entries by name. Entries are kept in order, so that
+    /// traversals, and so any output, are the same from run to run.
+    Dir(Metadata, BTreeMap<OsString, A>),
+    /// a symbolic link that wasn't followed, with its target as written in the link
+    Symlink(PathBuf),
+    /// a directory's own metadata, with its entries left out to bound the size of the tree
+    Truncated(Metadata, Truncation),
 }
 
 pub enum FileTreeRef<'a, A> {
-    File(&'a std::fs::Metadata),
-    Dir(HashMap<&'a OsString, A>),
+    File(&'a Metadata),
+    Dir(&'a Metadata, BTreeMap<&'a OsString, A>),
+    Symlink(&'a PathBuf),
+    Truncated(&'a Metadata, Truncation),
+}
+
+/// What's recorded of each file and directory. Unlike 'std::fs::Metadata', this can be made for
+/// entries that aren't on disk, eg those of an archive, with whatever isn't known left out.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Metadata {
+    pub is_dir: bool,
+    /// size in bytes
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    pub readonly: bool,
+    /// permission bits, including the setuid, setgid and sticky bits, on unix
+    pub mode: Option<u32>,
+    /// the owning user's id, on unix
+    pub uid: Option<u32>,
+    /// set for files with more than one hard link, which may appear in a tree under several paths
+    pub hardlink: Option<FileId>,
+}
+
+impl From<&std::fs::Metadata> for Metadata {
+    #[cfg(unix)]
+    fn from(metadata: &std::fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            readonly: metadata.permissions().readonly(),
+            mode: Some(metadata.mode()),
+            uid: Some(metadata.uid()),
+            hardlink: (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino())),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn from(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            readonly: metadata.permissions().readonly(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Which limit a directory's entries were left out due to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Truncation {
+    /// it's at 'BuildOptions::max_depth'
+    Depth,
+    /// it has more entries than 'BuildOptions::max_entries_per_dir'
+    Entries,
+    /// it's outside every one of 'BuildOptions::prefixes'
+    Sparse,
+}
+
+impl fmt::Display for Truncation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Truncation::Depth => write!(f, "max depth reached"),
+            Truncation::Entries => write!(f, "too many entries"),
+            Truncation::Sparse => write!(f, "not under a prefix"),
+        }
+    }
 }
 
 impl<A, B> MapLayer<B> for FileTree<A> {
@@ -24,10 +121,12 @@ impl<A, B> MapLayer<B> for FileTree<A> {
     fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
         match self {
             FileTree::File(x) => FileTree::File(x),
-            FileTree::Dir(xs) => {
+            FileTree::Dir(metadata, xs) => {
                 let xs = xs.into_iter().map(|(k, v)| (k, f(v))).collect();
-                FileTree::Dir(xs)
+                FileTree::Dir(metadata, xs)
             }
+            FileTree::Symlink(target) => FileTree::Symlink(target),
+            FileTree::Truncated(metadata, truncation) => FileTree::Truncated(metadata, truncation),
         }
     }
 }
@@ -39,23 +138,119 @@ impl<'a, A: Copy + 'a, B: 'a> MapLayer<B> for &'a FileTree<A> {
     fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
         match self {
             FileTree::File(x) => FileTreeRef::File(x),
-            FileTree::Dir(xs) => {
+            FileTree::Dir(metadata, xs) => {
                 let xs = xs.iter().map(|(k, v)| (k, f(*v))).collect();
-                FileTreeRef::Dir(xs)
+                FileTreeRef::Dir(metadata, xs)
+            }
+            FileTree::Symlink(target) => FileTreeRef::Symlink(target),
+            FileTree::Truncated(metadata, truncation) => {
+                FileTreeRef::Truncated(metadata, *truncation)
             }
         }
     }
 }
 
+/// Directory entries are children in name order, labeled by their names
+impl<A> LayerArity for FileTree<A> {
+    type Child = A;
+
+    fn child_count(&self) -> usize {
+        match self {
+            FileTree::Dir(_, entries) => entries.len(),
+            _ => 0,
+        }
+    }
+
+    fn child(&self, position: usize) -> Option<&A> {
+        self.children().nth(position)
+    }
+
+    fn children(&self) -> impl Iterator<Item = &A> {
+        let entries = match self {
+            FileTree::Dir(_, entries) => Some(entries.values()),
+            _ => None,
+        };
+        entries.into_iter().flatten()
+    }
+}
+
+impl<A> EdgeLabeled for FileTree<A> {
+    fn edge_label(&self, position: usize) -> Option<Cow<'_, str>> {
+        match self {
+            FileTree::Dir(_, entries) => entries
+                .keys()
+                .nth(position)
+                .map(|name| name.to_string_lossy()),
+            _ => None,
+        }
+    }
+
+    fn labeled_children(&self) -> impl Iterator<Item = (Cow<'_, str>, &A)> {
+        let entries = match self {
+            FileTree::Dir(_, entries) => Some(entries.iter()),
+            _ => None,
+        };
+        entries
+            .into_iter()
+            .flatten()
+            .map(|(name, entry)| (name.to_string_lossy(), entry))
+    }
+}
+
 pub type RecursiveFileTree = RecursiveTree<FileTree<ArenaIndex>, ArenaIndex>;
 
+/// Identifies a file regardless of which of its paths it was reached by: its device and inode
+pub type FileId = (u64, u64);
+
 // some utility functions over FileTreeRef, to show how using borrowed data works
 
 /// calculate the depth of a file
 pub fn depth(tree: &RecursiveFileTree) -> usize {
     tree.as_ref()
         .collapse_layers(|node: FileTreeRef<usize>| match node {
-            FileTreeRef::Dir(depths) => depths.into_iter().map(|(_k, v)| v).max().unwrap_or(0) + 1,
+            FileTreeRef::Dir(_, depths) => depths.into_values().max().unwrap_or(0) + 1,
             _ => 1,
         })
 }
+
+/// file sizes as folded stacks, for viewing disk usage as a flamegraph
+pub fn folded_sizes(tree: &RecursiveFileTree) -> FoldedStacks {
+    tree.as_ref()
+        .collapse_layers(|node: FileTreeRef<FoldedStacks>| match node {
+            FileTreeRef::Dir(_, children) => FoldedStacks::merge(
+                children
+                    .into_iter()
+                    .map(|(name, stacks)| stacks.under(name.to_string_lossy())),
+            ),
+            FileTreeRef::File(metadata) => FoldedStacks::weight(metadata.len),
+            FileTreeRef::Symlink(_) | FileTreeRef::Truncated(..) => FoldedStacks::default(),
+        })
+}
+
+/// render 'tree'-command style, with entries sorted by name
+pub fn render(tree: &RecursiveFileTree, root: &str) -> String {
+    // dirs are marked to distinguish them from files, and links are labeled with their
+    // target. truncated dirs are labeled with why.
+    let paths = paths::entry_paths(tree, str::to_string);
+    let name = |idx: ArenaIndex| paths[idx].file_name().unwrap_or_default().to_string_lossy();
+    render_tree(
+        tree,
+        |idx, node| match node {
+            _ if idx == tree.root() => root.to_string(),
+            FileTree::File(_) => name(idx).into_owned(),
+            FileTree::Dir(..) => format!("{}/", name(idx)),
+            FileTree::Symlink(target) => format!("{} -> {}", name(idx), target.display()),
+            FileTree::Truncated(_, truncation) => format!("{}/ [{}]", name(idx), truncation),
+        },
+        |a, b| paths[a].cmp(&paths[b]),
+    )
+}
+
+// a fresh, empty directory for a test to build a tree in
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("filetree-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,130 @@
+//! Path normalization, so that trees compare and filter the same way whichever platform they were
+//! read on, or whichever platform made the archive or commit they were read from.
+//!
+//! Entry names are kept in the tree as they are on disk, so that files can still be opened by
+//! them. They're normalized only where paths are compared: by globs, sparse prefixes and diffs.
+//! Exports always separate components with '/', whatever the platform's separator.
+
+use crate::filetree::RecursiveFileTree;
+use recursion::map_layer::EdgeLabeled;
+use recursion::recursive_tree::Sidecar;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+/// Which unicode normalization form names are put in before being compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Unicode {
+    /// compare names as they are
+    #[default]
+    AsIs,
+    /// composed, as most platforms write names
+    Nfc,
+    /// decomposed, as HFS+ on macOS writes names
+    Nfd,
+}
+
+/// How names are made comparable, eg to diff a tree read on macOS against one read on linux.
+/// The default leaves them as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Normalization {
+    pub unicode: Unicode,
+    /// compare names case-insensitively, as on windows' and macOS' filesystems by default
+    pub fold_case: bool,
+}
+
+impl Normalization {
+    /// A single name, normalized. Names that aren't valid unicode have invalid parts replaced.
+    pub fn name(&self, name: &OsStr) -> String {
+        let name = name.to_string_lossy();
+        let name: String = match self.unicode {
+            Unicode::AsIs => name.into_owned(),
+            Unicode::Nfc => name.nfc().collect(),
+            Unicode::Nfd => name.nfd().collect(),
+        };
+        if self.fold_case {
+            name.to_lowercase()
+        } else {
+            name
+        }
+    }
+
+    /// A relative path, with each of its names normalized
+    pub fn path(&self, path: &Path) -> PathBuf {
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => self.name(name),
+                other => other.as_os_str().to_string_lossy().into_owned(),
+            })
+            .collect()
+    }
+
+    /// A glob pattern, normalized as names it's matched against are, other than their case, which
+    /// is left to the glob
+    pub fn pattern(&self, pattern: &str) -> String {
+        match self.unicode {
+            Unicode::AsIs => pattern.to_string(),
+            Unicode::Nfc => pattern.nfc().collect(),
+            Unicode::Nfd => pattern.nfd().collect(),
+        }
+    }
+}
+
+/// A relative path with its components separated by '/', on every platform
+pub fn portable(path: &Path) -> String {
+    let names: Vec<_> = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    names.join("/")
+}
+
+/// Every entry's path relative to the root, joined from the edge labels leading to it, each passed
+/// through 'name', eg to normalize it. The root's path is empty.
+pub fn entry_paths(
+    tree: &RecursiveFileTree,
+    mut name: impl FnMut(&str) -> String,
+) -> Sidecar<PathBuf> {
+    let mut paths = Sidecar::filled(tree, PathBuf::new());
+    let mut stack = vec![tree.root()];
+    while let Some(idx) = stack.pop() {
+        for (label, entry) in tree.layer(idx).labeled_children() {
+            paths[*entry] = paths[idx].join(name(&label));
+            stack.push(*entry);
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalization() {
+        // 'é' as one code point, and as 'e' followed by a combining accent
+        let (composed, decomposed) = ("caf\u{e9}", "cafe\u{301}");
+        let nfc = Normalization {
+            unicode: Unicode::Nfc,
+            ..Normalization::default()
+        };
+        assert_eq!(nfc.name(OsStr::new(decomposed)), composed);
+        let nfd = Normalization {
+            unicode: Unicode::Nfd,
+            ..Normalization::default()
+        };
+        assert_eq!(nfd.name(OsStr::new(composed)), decomposed);
+        let as_is = Normalization::default();
+        assert_eq!(as_is.name(OsStr::new(decomposed)), decomposed);
+
+        let folded = Normalization {
+            unicode: Unicode::Nfc,
+            fold_case: true,
+        };
+        let path = Path::new("Src").join("CAFE\u{301}.rs");
+        assert_eq!(folded.path(&path), Path::new("src").join("caf\u{e9}.rs"));
+
+        assert_eq!(portable(&Path::new("a").join("b").join("c")), "a/b/c");
+        assert_eq!(portable(Path::new("")), "");
+    }
+}
@@ -0,0 +1,230 @@
+//! Queries over file metadata, each written as an algebra over a single layer so that it can be
+//! used in any collapse of a tree, or run for every directory at once via 'each_dir'.
+//!
+//! Results hold paths relative to the directory the query was run for. Links are never matched,
+//! and neither is anything under a truncated directory.
+
+use crate::filetree::{FileId, FileTreeRef};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Files with their sizes, by path
+pub type FileSizes = Vec<(PathBuf, u64)>;
+
+/// Paths by the file they lead to
+pub type Links = BTreeMap<FileId, Vec<PathBuf>>;
+
+/// A result for each directory, by path
+pub type ByDir<A> = BTreeMap<PathBuf, A>;
+
+/// A result, along with that of each directory it was computed from
+pub type WithDirs<A> = (A, ByDir<A>);
+
+// 'path', relative to an entry named 'name', as a path relative to the entry's parent
+pub(crate) fn under(name: &OsStr, path: PathBuf) -> PathBuf {
+    if path.as_os_str().is_empty() {
+        PathBuf::from(name)
+    } else {
+        Path::new(name).join(path)
+    }
+}
+
+/// The most recently modified file, if any, with ties broken by path
+pub fn newest(node: FileTreeRef<Option<(PathBuf, SystemTime)>>) -> Option<(PathBuf, SystemTime)> {
+    match node {
+        FileTreeRef::File(metadata) => Some((PathBuf::new(), metadata.modified?)),
+        FileTreeRef::Symlink(_) | FileTreeRef::Truncated(..) => None,
+        FileTreeRef::Dir(_, children) => children
+            .into_iter()
+            .filter_map(|(name, newest)| newest.map(|(path, time)| (under(name, path), time)))
+            .max_by(|(a_path, a), (b_path, b)| a.cmp(b).then_with(|| b_path.cmp(a_path))),
+    }
+}
+
+/// Every file modified at or after 'since', sorted by path
+pub fn modified_since(since: SystemTime) -> impl Fn(FileTreeRef<Vec<PathBuf>>) -> Vec<PathBuf> {
+    move |node| match node {
+        FileTreeRef::File(metadata) => match metadata.modified {
+            Some(modified) if modified >= since => vec![PathBuf::new()],
+            _ => Vec::new(),
+        },
+        FileTreeRef::Symlink(_) | FileTreeRef::Truncated(..) => Vec::new(),
+        FileTreeRef::Dir(_, children) => {
+            let mut paths: Vec<PathBuf> = children
+                .into_iter()
+                .flat_map(|(name, paths)| paths.into_iter().map(|path| under(name, path)))
+                .collect();
+            paths.sort();
+            paths
+        }
+    }
+}
+
+/// The 'n' largest files, largest first, with ties broken by path. Only 'n' are kept at each
+/// level, however many files there are.
+pub fn largest(n: usize) -> impl Fn(FileTreeRef<FileSizes>) -> FileSizes {
+    move |node| match node {
+        FileTreeRef::File(metadata) if n > 0 => vec![(PathBuf::new(), metadata.len)],
+        FileTreeRef::File(_) | FileTreeRef::Symlink(_) | FileTreeRef::Truncated(..) => Vec::new(),
+        FileTreeRef::Dir(_, children) => {
+            let mut files: FileSizes = children
+                .into_iter()
+                .flat_map(|(name, files)| {
+                    files
+                        .into_iter()
+                        .map(|(path, size)| (under(name, path), size))
+                })
+                .collect();
+            files.sort_by(|(a_path, a), (b_path, b)| b.cmp(a).then_with(|| a_path.cmp(b_path)));
+            files.truncate(n);
+            files
+        }
+    }
+}
+
+/// Every path of each file with several hard links, sorted. Files whose other links are all outside
+/// the tree have a single path here.
+pub fn hardlinks(node: FileTreeRef<Links>) -> Links {
+    match node {
+        FileTreeRef::File(metadata) => metadata
+            .hardlink
+            .map(|id| Links::from([(id, vec![PathBuf::new()])]))
+            .unwrap_or_default(),
+        FileTreeRef::Symlink(_) | FileTreeRef::Truncated(..) => Links::new(),
+        FileTreeRef::Dir(_, children) => {
+            let mut links = Links::new();
+            for (name, below) in children {
+                for (id, paths) in below {
+                    let paths = paths.into_iter().map(|path| under(name, path));
+                    links.entry(id).or_default().extend(paths);
+                }
+            }
+            links
+        }
+    }
+}
+
+/// Run 'algebra' for every directory at once, collecting each directory's result by its path.
+/// The directory the collapse is run for is included, with an empty path.
+pub fn each_dir<'a, A: Clone>(
+    mut algebra: impl FnMut(FileTreeRef<'a, A>) -> A,
+) -> impl FnMut(FileTreeRef<'a, WithDirs<A>>) -> WithDirs<A> {
+    move |node| match node {
+        FileTreeRef::File(metadata) => (algebra(FileTreeRef::File(metadata)), ByDir::new()),
+        FileTreeRef::Symlink(target) => (algebra(FileTreeRef::Symlink(target)), ByDir::new()),
+        FileTreeRef::Truncated(metadata, truncation) => (
+            algebra(FileTreeRef::Truncated(metadata, truncation)),
+            ByDir::new(),
+        ),
+        FileTreeRef::Dir(metadata, children) => {
+            let mut dirs = ByDir::new();
+            let children = children
+                .into_iter()
+                .map(|(name, (result, below))| {
+                    for (path, result) in below {
+                        dirs.insert(under(name, path), result);
+                    }
+                    (name, result)
+                })
+                .collect();
+            let result = algebra(FileTreeRef::Dir(metadata, children));
+            dirs.insert(PathBuf::new(), result.clone());
+            (result, dirs)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::build::{build_file_tree, BuildOptions};
+    use crate::filetree::{test_dir, FileTree};
+    use recursion::recursive::Collapse;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn queries() {
+        let dir = test_dir("query");
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::create_dir_all(dir.join("empty")).unwrap();
+        let epoch = SystemTime::UNIX_EPOCH;
+        // by path, with size and age
+        for (file, size, secs) in [("top", 10, 100), ("a/x", 300, 300), ("a/b/y", 20, 200)] {
+            std::fs::write(dir.join(file), vec![0; size]).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(dir.join(file))
+                .unwrap()
+                .set_modified(epoch + Duration::from_secs(secs))
+                .unwrap();
+        }
+
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap();
+        let at = |secs| epoch + Duration::from_secs(secs);
+
+        assert_eq!(
+            tree.as_ref().collapse_layers(newest),
+            Some((PathBuf::from("a/x"), at(300)))
+        );
+        let (_, newest_by_dir) = tree.as_ref().collapse_layers(each_dir(newest));
+        assert_eq!(
+            newest_by_dir,
+            ByDir::from([
+                (PathBuf::new(), Some((PathBuf::from("a/x"), at(300)))),
+                (PathBuf::from("a"), Some((PathBuf::from("x"), at(300)))),
+                (PathBuf::from("a/b"), Some((PathBuf::from("y"), at(200)))),
+                (PathBuf::from("empty"), None),
+            ])
+        );
+
+        assert_eq!(
+            tree.as_ref().collapse_layers(modified_since(at(200))),
+            vec![PathBuf::from("a/b/y"), PathBuf::from("a/x")]
+        );
+
+        assert_eq!(
+            tree.as_ref().collapse_layers(largest(2)),
+            vec![(PathBuf::from("a/x"), 300), (PathBuf::from("a/b/y"), 20)]
+        );
+        assert_eq!(tree.as_ref().collapse_layers(largest(0)), Vec::new());
+
+        // entries' edge labels name the same paths as queries report
+        let mut files: Vec<PathBuf> = tree
+            .leaves()
+            .filter(|idx| matches!(tree.layer(*idx), FileTree::File(_)))
+            .map(|idx| {
+                let path = tree.path_to(idx).expect("every entry is named");
+                path.iter().map(|name| name.as_ref()).collect()
+            })
+            .collect();
+        files.sort();
+        assert_eq!(files, ["a/b/y", "a/x", "top"].map(PathBuf::from));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hard_links() {
+        let dir = test_dir("query_hardlinks");
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::write(dir.join("a/x"), "").unwrap();
+        std::fs::write(dir.join("single"), "").unwrap();
+        std::fs::hard_link(dir.join("a/x"), dir.join("b")).unwrap();
+        std::fs::hard_link(dir.join("a/x"), dir.join("a/y")).unwrap();
+
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap();
+        let links: Vec<Vec<PathBuf>> = tree
+            .as_ref()
+            .collapse_layers(hardlinks)
+            .into_values()
+            .collect();
+        assert_eq!(links, vec![["a/x", "a/y", "b"].map(PathBuf::from).to_vec()]);
+    }
+}
@@ -0,0 +1,218 @@
+//! Ranking of search results, for showing the few most relevant of many matches, eg in an editor.
+//!
+//! Each file with matches is scored in three parts, each between 0 and 1: how near it is to a
+//! given directory, how densely it matches, and how recently it was modified. Every match in a
+//! file shares the file's score. Files' metadata is gathered by the 'files' algebra, in the style
+//! of 'query', so the tree searched needn't be read again.
+
+use crate::filetree::query::under;
+use crate::filetree::search::Match;
+use crate::filetree::{FileTreeRef, Metadata, RecursiveFileTree};
+use recursion::recursive::Collapse;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Files with their metadata, by path
+pub type Files<'a> = Vec<(PathBuf, &'a Metadata)>;
+
+/// Every file, by path relative to the directory the collapse is run for. Links aren't included,
+/// and neither is anything under a truncated directory.
+pub fn files<'a>(node: FileTreeRef<'a, Files<'a>>) -> Files<'a> {
+    match node {
+        FileTreeRef::File(metadata) => vec![(PathBuf::new(), metadata)],
+        FileTreeRef::Symlink(_) | FileTreeRef::Truncated(..) => Vec::new(),
+        FileTreeRef::Dir(_, children) => children
+            .into_iter()
+            .flat_map(|(name, files)| {
+                files
+                    .into_iter()
+                    .map(|(path, metadata)| (under(name, path), metadata))
+            })
+            .collect(),
+    }
+}
+
+/// How relevant a file's matches are likely to be, higher being better
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Score {
+    /// 1 for files directly in 'RankOptions::near', falling with each directory between them
+    pub proximity: f64,
+    /// approaches 1 as the number of matching lines per KiB of the file grows
+    pub density: f64,
+    /// 1 for files modified at 'RankOptions::now', 1/2 for those modified a day before, and so on
+    pub recency: f64,
+}
+
+impl Score {
+    pub fn total(&self) -> f64 {
+        self.proximity + self.density + self.recency
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RankOptions {
+    /// the directory files are scored by their distance from, relative to the root searched
+    pub near: PathBuf,
+    /// keep only this many of the best matches
+    pub limit: Option<usize>,
+    /// the time recency is measured from
+    pub now: SystemTime,
+}
+
+impl Default for RankOptions {
+    fn default() -> Self {
+        Self {
+            near: PathBuf::new(),
+            limit: None,
+            now: SystemTime::now(),
+        }
+    }
+}
+
+// the number of directories between 'a' and 'b', by way of their closest common ancestor
+fn distance(a: &Path, b: &Path) -> usize {
+    let common = a
+        .components()
+        .zip(b.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    a.components().count() + b.components().count() - 2 * common
+}
+
+fn score(
+    relative: &Path,
+    metadata: &Metadata,
+    matching_lines: usize,
+    options: &RankOptions,
+) -> Score {
+    let dir = relative.parent().unwrap_or(Path::new(""));
+    let per_kib = matching_lines as f64 * 1024.0 / metadata.len.max(1) as f64;
+    let recency = metadata.modified.map_or(0.0, |modified| {
+        let age = options
+            .now
+            .duration_since(modified)
+            .unwrap_or(Duration::ZERO);
+        1.0 / (1.0 + age.as_secs_f64() / 86400.0)
+    });
+    Score {
+        proximity: 1.0 / (1.0 + distance(&options.near, dir) as f64),
+        density: per_kib / (1.0 + per_kib),
+        recency,
+    }
+}
+
+/// The 'matches' found by searching 'tree' at 'root', best first, with their scores. Ties are
+/// broken by path and then line number, so that matches within a file stay in order.
+pub fn rank(
+    tree: &RecursiveFileTree,
+    root: &Path,
+    matches: Vec<Match>,
+    options: &RankOptions,
+) -> Vec<(Score, Match)> {
+    let files: HashMap<PathBuf, &Metadata> =
+        tree.as_ref().collapse_layers(files).into_iter().collect();
+    let mut matching_lines: HashMap<&Path, usize> = HashMap::new();
+    for m in &matches {
+        *matching_lines.entry(&m.path).or_default() += 1;
+    }
+    let scores: HashMap<PathBuf, Score> = matching_lines
+        .into_iter()
+        .map(|(path, count)| {
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            let score = files.get(relative).map_or_else(Score::default, |metadata| {
+                score(relative, metadata, count, options)
+            });
+            (path.to_path_buf(), score)
+        })
+        .collect();
+
+    let mut ranked: Vec<(Score, Match)> =
+        matches.into_iter().map(|m| (scores[&m.path], m)).collect();
+    ranked.sort_by(|(a, a_match), (b, b_match)| {
+        b.total()
+            .total_cmp(&a.total())
+            .then_with(|| a_match.path.cmp(&b_match.path))
+            .then_with(|| a_match.line_number.cmp(&b_match.line_number))
+    });
+    if let Some(limit) = options.limit {
+        ranked.truncate(limit);
+    }
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::build::{build_file_tree, BuildOptions};
+    use crate::filetree::search::{search, LineNumber, SearchOptions};
+    use crate::filetree::test_dir;
+
+    #[test]
+    fn distances() {
+        assert_eq!(distance(Path::new(""), Path::new("")), 0);
+        assert_eq!(distance(Path::new("a"), Path::new("a/b/c")), 2);
+        assert_eq!(distance(Path::new("a/x"), Path::new("a/b/c")), 3);
+    }
+
+    #[tokio::test]
+    async fn ranking() {
+        let dir = test_dir("rank");
+        std::fs::create_dir_all(dir.join("src/deep")).unwrap();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(100 * 86400);
+        let sparse = format!("needle\n{}\n", "hay ".repeat(500));
+        // by path, with contents and age in days
+        for (file, contents, days) in [
+            ("src/near", "needle\nhay\n", 0),
+            ("src/deep/far", "needle\nhay\n", 0),
+            ("src/old", "needle\nhay\n", 10),
+            ("src/dense", "needle\nneedle\n", 0),
+            ("src/sparse", &sparse, 0),
+        ] {
+            std::fs::write(dir.join(file), contents).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(dir.join(file))
+                .unwrap()
+                .set_modified(now - Duration::from_secs(days * 86400))
+                .unwrap();
+        }
+
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap();
+        let results = search(
+            tree.clone(),
+            dir.clone(),
+            "needle",
+            &SearchOptions::default(),
+        )
+        .await
+        .unwrap();
+        let options = RankOptions {
+            near: PathBuf::from("src"),
+            limit: Some(5),
+            now,
+        };
+        let ranked: Vec<(PathBuf, LineNumber)> = rank(&tree, &dir, results.matches, &options)
+            .into_iter()
+            .map(|(_, m)| {
+                (
+                    m.path.strip_prefix(&dir).unwrap().to_path_buf(),
+                    m.line_number,
+                )
+            })
+            .collect();
+        assert_eq!(
+            ranked,
+            vec![
+                (PathBuf::from("src/dense"), 1),
+                (PathBuf::from("src/dense"), 2),
+                (PathBuf::from("src/near"), 1),
+                (PathBuf::from("src/deep/far"), 1),
+                (PathBuf::from("src/sparse"), 1),
+            ]
+        );
+    }
+}
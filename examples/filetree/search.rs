@@ -1,75 +1,834 @@
-use crate::filetree::{FileTree, RecursiveFileTree};
+use crate::filetree::contents::ContentCache;
+use crate::filetree::error::Error;
+use crate::filetree::query::under;
+use crate::filetree::{FileTree, FileTreeRef, Metadata, RecursiveFileTree};
+use aho_corasick::AhoCorasick;
 use futures::{future::BoxFuture, FutureExt};
+use rayon::prelude::*;
 use recursion::recursive::Collapse;
 use regex::Regex;
-use std::{fs::Metadata, path::PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
+/// A line's position in a file, starting from 1 as in grep and editors
 pub type LineNumber = usize;
 
-#[derive(Debug, Clone)]
-pub struct GrepResult {
+/// Which of a matcher's patterns matched, by the order they were given in
+pub type PatternId = usize;
+
+/// Something to search file contents for, made of one or more patterns
+pub trait Matcher: Sync {
+    /// Byte ranges of each non-overlapping match in 'haystack'
+    fn find_all(&self, haystack: &str) -> Vec<Range<usize>>;
+
+    fn is_match(&self, haystack: &str) -> bool {
+        !self.find_all(haystack).is_empty()
+    }
+
+    /// Byte ranges of each match in 'haystack', along with the pattern matched. Matchers of a
+    /// single pattern only ever match pattern 0.
+    fn find_patterns(&self, haystack: &str) -> Vec<(PatternId, Range<usize>)> {
+        self.find_all(haystack)
+            .into_iter()
+            .map(|range| (0, range))
+            .collect()
+    }
+}
+
+/// How letters' case is matched, for matchers built from patterns as given by a user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Case {
+    #[default]
+    Sensitive,
+    Insensitive,
+    /// insensitive, unless some pattern contains an uppercase letter. Letters escaped with a
+    /// backslash, like the regex class '\W', aren't counted.
+    Smart,
+}
+
+impl Case {
+    /// Whether to match 'patterns' case-insensitively
+    pub fn is_insensitive<S: AsRef<str>>(self, patterns: &[S]) -> bool {
+        let has_uppercase = |pattern: &str| {
+            let mut escaped = false;
+            pattern.chars().any(|c| {
+                let literal = !escaped && c.is_uppercase();
+                escaped = !escaped && c == '\\';
+                literal
+            })
+        };
+        match self {
+            Case::Sensitive => false,
+            Case::Insensitive => true,
+            Case::Smart => !patterns.iter().any(|p| has_uppercase(p.as_ref())),
+        }
+    }
+}
+
+impl Matcher for Regex {
+    fn find_all(&self, haystack: &str) -> Vec<Range<usize>> {
+        self.find_iter(haystack).map(|m| m.range()).collect()
+    }
+
+    fn is_match(&self, haystack: &str) -> bool {
+        Regex::is_match(self, haystack)
+    }
+}
+
+/// A plain substring
+impl Matcher for str {
+    fn find_all(&self, haystack: &str) -> Vec<Range<usize>> {
+        haystack
+            .match_indices(self)
+            .map(|(start, m)| start..start + m.len())
+            .collect()
+    }
+
+    fn is_match(&self, haystack: &str) -> bool {
+        haystack.contains(self)
+    }
+}
+
+/// Many literal strings, all searched for in a single pass. Every pattern found is reported, even
+/// where they overlap, as long as it's built with the default 'MatchKind::Standard'.
+impl Matcher for AhoCorasick {
+    fn find_all(&self, haystack: &str) -> Vec<Range<usize>> {
+        self.find_iter(haystack).map(|m| m.range()).collect()
+    }
+
+    fn is_match(&self, haystack: &str) -> bool {
+        AhoCorasick::is_match(self, haystack)
+    }
+
+    fn find_patterns(&self, haystack: &str) -> Vec<(PatternId, Range<usize>)> {
+        self.find_overlapping_iter(haystack)
+            .map(|m| (m.pattern().as_usize(), m.range()))
+            .collect()
+    }
+}
+
+// each line's patterns and match ranges, sorted
+type LineMatches = (BTreeSet<PatternId>, Vec<Range<usize>>);
+
+// the index of each line touched by some match against the whole of 'contents', so matches may
+// span lines, with the patterns that touched it and the part of the line each match covers
+fn multiline_matches<M: Matcher + ?Sized>(
+    matcher: &M,
+    contents: &str,
+) -> BTreeMap<usize, LineMatches> {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(contents.match_indices('\n').map(|(idx, _)| idx + 1))
+        .collect();
+    let line_of = |offset: usize| line_starts.partition_point(|start| *start <= offset) - 1;
+
+    let mut matched: BTreeMap<usize, LineMatches> = BTreeMap::new();
+    for (pattern, range) in matcher.find_patterns(contents) {
+        let last = range.end.saturating_sub(1).max(range.start);
+        let lines = line_of(range.start)..=line_of(last);
+        for (line, &start) in lines.clone().zip(&line_starts[lines]) {
+            // the line as 'str::lines' splits it, without its terminator
+            let text = contents[start..].lines().next().unwrap_or("");
+            let end = range.end.min(start + text.len()).saturating_sub(start);
+            let begin = range.start.saturating_sub(start).min(end);
+            let (patterns, ranges) = matched.entry(line).or_default();
+            patterns.insert(pattern);
+            ranges.push(begin..end);
+        }
+    }
+    for (_, ranges) in matched.values_mut() {
+        ranges.sort_by_key(|range| (range.start, range.end));
+    }
+    matched
+}
+
+/// A matching line, along with the lines around it. Context may overlap with that of other
+/// matches in the same file, or include other matching lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub path: PathBuf,
+    pub line_number: LineNumber,
+    pub line: String,
+    /// which of the matcher's patterns matched the line, sorted
+    pub patterns: Vec<PatternId>,
+    /// byte ranges of 'line' covered by a match, sorted, for highlighting them. Matches may
+    /// overlap if several patterns match, and those spanning lines are split between them.
+    pub ranges: Vec<Range<usize>>,
+    /// up to 'SearchOptions::before_context' lines immediately before, in order
+    pub before: Vec<String>,
+    /// up to 'SearchOptions::after_context' lines immediately after, in order
+    pub after: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// contains a NUL byte near the start, and binary files weren't searched
+    Binary,
+    /// larger than 'SearchOptions::max_file_size'
+    TooLarge,
+    /// reading it would have gone over 'SearchOptions::max_total_bytes_read'
+    OverBudget,
+    /// couldn't be read, eg for lack of permission, or because it was removed since the tree was
+    /// built
+    Unreadable(std::io::ErrorKind),
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::Binary => write!(f, "binary"),
+            SkipReason::TooLarge => write!(f, "too large"),
+            SkipReason::OverBudget => write!(f, "over the read limit"),
+            SkipReason::Unreadable(kind) => write!(f, "unreadable ({})", kind),
+        }
+    }
+}
+
+/// A file that wasn't searched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
     pub path: PathBuf,
-    pub metadata: Metadata,
-    pub matching_lines: Vec<(LineNumber, String)>,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchResults {
+    pub matches: Vec<Match>,
+    pub skipped: Vec<Skipped>,
+}
+
+impl SearchResults {
+    fn skipped(path: PathBuf, reason: SkipReason) -> Self {
+        Self {
+            matches: Vec::new(),
+            skipped: vec![Skipped { path, reason }],
+        }
+    }
+
+    fn extend(&mut self, other: SearchResults) {
+        self.matches.extend(other.matches);
+        self.skipped.extend(other.skipped);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    /// search each file as a whole, so that matches can span lines, rather than line by line
+    pub multiline: bool,
+    /// search binary files too, with invalid utf-8 replaced
+    pub binary: bool,
+    /// where file contents are read from, to share them with other searches
+    pub contents: Arc<ContentCache>,
+    /// how many lines before each match to include
+    pub before_context: usize,
+    /// how many lines after each match to include
+    pub after_context: usize,
+    /// skip files larger than this many bytes
+    pub max_file_size: Option<u64>,
+    /// stop reading files once this many bytes have been read in all, skipping the rest. Files are
+    /// counted by their size in the tree, and those that would go over are skipped whole.
+    pub max_total_bytes_read: Option<u64>,
+    /// search the files that links in the tree point to, as if they were at the link's path.
+    /// Links to directories are still left out, as their entries aren't in the tree: see
+    /// 'BuildOptions::symlinks' to follow those.
+    pub follow_symlinks: bool,
+}
+
+// the same heuristic as git and grep: text files don't contain NUL bytes, so only the start of
+// the file needs to be checked
+fn is_binary(contents: &[u8]) -> bool {
+    contents[..contents.len().min(8192)].contains(&0)
 }
 
-// return vec of grep results, with short circuit
-pub fn search(
+// return all grep results, with short circuit
+pub fn search<'a, M: Matcher + ?Sized>(
     tree: RecursiveFileTree,
     root_dir: PathBuf,
-    regex: &Regex,
-) -> BoxFuture<std::io::Result<Vec<GrepResult>>> {
-    let f = tree.collapse_layers(move |node| {
-        Box::new(move |path| async move { grep_layer(node, path, regex).await }.boxed())
-    });
+    matcher: &'a M,
+    options: &'a SearchOptions,
+) -> BoxFuture<'a, Result<SearchResults, Error>> {
+    async move {
+        // the bytes read so far, across every file
+        let read = AtomicU64::new(0);
+        let read = &read;
+        let f = tree.collapse_layers(move |node| {
+            Box::new(move |path| {
+                async move { grep_layer(node, path, matcher, options, read).await }.boxed()
+            })
+        });
+        f(root_dir).await
+    }
+    .boxed()
+}
+
+// what 'search_parallel' searches: files, and links if they're followed
+enum Searchable<'a> {
+    File(&'a Metadata),
+    Link(&'a PathBuf),
+}
+
+/// As 'search', but with files read and matched on rayon's thread pool, using 'std::fs' rather
+/// than tokio. Every file is listed up front, so that they can be searched in any order, and the
+/// results are then put back in path order. Which files go over 'max_total_bytes_read' depends on
+/// the order they happen to be read in.
+pub fn search_parallel<M: Matcher + ?Sized>(
+    tree: &RecursiveFileTree,
+    root_dir: &Path,
+    matcher: &M,
+    options: &SearchOptions,
+) -> SearchResults {
+    let searchable =
+        tree.as_ref()
+            .collapse_layers(|node: FileTreeRef<Vec<(PathBuf, Searchable)>>| match node {
+                FileTreeRef::File(metadata) => vec![(PathBuf::new(), Searchable::File(metadata))],
+                FileTreeRef::Symlink(target) if options.follow_symlinks => {
+                    vec![(PathBuf::new(), Searchable::Link(target))]
+                }
+                FileTreeRef::Symlink(_) | FileTreeRef::Truncated(..) => Vec::new(),
+                FileTreeRef::Dir(_, children) => children
+                    .into_iter()
+                    .flat_map(|(name, below)| {
+                        below
+                            .into_iter()
+                            .map(|(path, searchable)| (under(name, path), searchable))
+                    })
+                    .collect(),
+            });
 
-    f(root_dir)
+    let read = AtomicU64::new(0);
+    let results: Vec<SearchResults> = searchable
+        .into_par_iter()
+        .map(|(relative, searchable)| {
+            let path = root_dir.join(relative);
+            match searchable {
+                Searchable::File(metadata) => {
+                    grep_file_blocking(path.clone(), &path, metadata, matcher, options, &read)
+                }
+                Searchable::Link(target) => {
+                    let target = link_target(&path, target);
+                    let metadata = std::fs::metadata(&target)
+                        .map(|metadata| Metadata::from(&metadata))
+                        .unwrap_or_default();
+                    if metadata.is_dir {
+                        return SearchResults::default();
+                    }
+                    grep_file_blocking(path, &target, &metadata, matcher, options, &read)
+                }
+            }
+        })
+        .collect();
+
+    let mut all_results = SearchResults::default();
+    for results in results {
+        all_results.extend(results);
+    }
+    all_results
 }
 
 // lazy traversal of filetree with path component
 type LazilyTraversableFileTree<'a, Res, Err> =
     FileTree<Box<dyn FnOnce(PathBuf) -> BoxFuture<'a, Result<Res, Err>> + Send + Sync + 'a>>;
 
+// why a file with 'metadata' isn't searched, if it isn't, before reading it. Files that are
+// searched count towards 'SearchOptions::max_total_bytes_read'.
+fn skip_reason(
+    metadata: &Metadata,
+    options: &SearchOptions,
+    read: &AtomicU64,
+) -> Option<SkipReason> {
+    if options.max_file_size.is_some_and(|max| metadata.len > max) {
+        return Some(SkipReason::TooLarge);
+    }
+    let max = options.max_total_bytes_read?;
+    read.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |read| {
+        Some(read + metadata.len).filter(|total| *total <= max)
+    })
+    .err()
+    .map(|_| SkipReason::OverBudget)
+}
+
+// where a link at 'path' to 'target' leads
+fn link_target(path: &Path, target: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) => dir.join(target),
+        None => target.to_path_buf(),
+    }
+}
+
+// search a single file, at 'path' in the tree, reading it from 'from', which differs for links.
+// Files that aren't searched are reported as skipped rather than failing the whole search.
+async fn grep_file<M: Matcher + ?Sized>(
+    path: PathBuf,
+    from: &Path,
+    metadata: &Metadata,
+    matcher: &M,
+    options: &SearchOptions,
+    read: &AtomicU64,
+) -> SearchResults {
+    if let Some(reason) = skip_reason(metadata, options, read) {
+        return SearchResults::skipped(path, reason);
+    }
+    let contents = options.contents.read(from, metadata).await;
+    scan(path, contents, matcher, options)
+}
+
+// as 'grep_file', reading on the current thread
+fn grep_file_blocking<M: Matcher + ?Sized>(
+    path: PathBuf,
+    from: &Path,
+    metadata: &Metadata,
+    matcher: &M,
+    options: &SearchOptions,
+    read: &AtomicU64,
+) -> SearchResults {
+    if let Some(reason) = skip_reason(metadata, options, read) {
+        return SearchResults::skipped(path, reason);
+    }
+    let contents = options.contents.read_blocking(from, metadata);
+    scan(path, contents, matcher, options)
+}
+
+// match the contents of the file at 'path', once read
+fn scan<M: Matcher + ?Sized>(
+    path: PathBuf,
+    contents: std::io::Result<Arc<[u8]>>,
+    matcher: &M,
+    options: &SearchOptions,
+) -> SearchResults {
+    let contents = match contents {
+        Err(e) => return SearchResults::skipped(path, SkipReason::Unreadable(e.kind())),
+        Ok(contents) => contents,
+    };
+    if !options.binary && is_binary(&contents) {
+        return SearchResults::skipped(path, SkipReason::Binary);
+    }
+
+    let contents = String::from_utf8_lossy(&contents);
+    let lines: Vec<&str> = contents.lines().collect();
+    let matching_lines: Vec<(usize, LineMatches)> = if options.multiline {
+        multiline_matches(matcher, &contents).into_iter().collect()
+    } else {
+        lines
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, line)| {
+                // most lines don't match, and finding where matches are is slower
+                if !matcher.is_match(line) {
+                    return None;
+                }
+                let found = matcher.find_patterns(line);
+                let mut ranges: Vec<Range<usize>> =
+                    found.iter().map(|(_, range)| range.clone()).collect();
+                ranges.sort_by_key(|range| (range.start, range.end));
+                let patterns = found.into_iter().map(|(pattern, _)| pattern).collect();
+                Some((idx, (patterns, ranges)))
+            })
+            .collect()
+    };
+
+    let to_strings = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect();
+    let matches = matching_lines
+        .into_iter()
+        // a match can end in the newline terminating the last line
+        .filter(|(idx, _)| *idx < lines.len())
+        .map(|(idx, (patterns, ranges))| Match {
+            path: path.clone(),
+            line_number: idx + 1,
+            line: lines[idx].to_string(),
+            patterns: patterns.into_iter().collect(),
+            ranges,
+            before: to_strings(&lines[idx.saturating_sub(options.before_context)..idx]),
+            after: to_strings(&lines[idx + 1..(idx + 1 + options.after_context).min(lines.len())]),
+        })
+        .collect();
+    SearchResults {
+        matches,
+        skipped: Vec::new(),
+    }
+}
+
 // grep a single layer of recursive FileTree structure
-async fn grep_layer<'a>(
-    node: LazilyTraversableFileTree<'a, Vec<GrepResult>, std::io::Error>,
+async fn grep_layer<'a, M: Matcher + ?Sized>(
+    node: LazilyTraversableFileTree<'a, SearchResults, Error>,
     path: PathBuf,
-    regex: &'a Regex,
-) -> std::io::Result<Vec<GrepResult>> {
+    matcher: &'a M,
+    options: &'a SearchOptions,
+    read: &'a AtomicU64,
+) -> Result<SearchResults, Error> {
     match node {
         FileTree::File(metadata) => {
-            let mut matching_lines = Vec::new();
-
-            match tokio::fs::read_to_string(&path).await {
-                Err(_) => {} // binary file or w/e, just skip. TODO: more granular handling
-                Ok(contents) => {
-                    for (line_num, line) in contents.lines().enumerate() {
-                        if regex.is_match(line) {
-                            matching_lines.push((line_num, line.to_string()));
-                        }
-                    }
-                }
+            Ok(grep_file(path.clone(), &path, &metadata, matcher, options, read).await)
+        }
+        FileTree::Symlink(target) if options.follow_symlinks => {
+            let target = link_target(&path, &target);
+            // files from archives and commits aren't on disk, and are read from their source
+            let metadata = tokio::fs::metadata(&target)
+                .await
+                .map(|metadata| Metadata::from(&metadata))
+                .unwrap_or_default();
+            if metadata.is_dir {
+                return Ok(SearchResults::default());
             }
-
-            Ok(if !matching_lines.is_empty() {
-                vec![GrepResult {
-                    path,
-                    metadata,
-                    matching_lines,
-                }]
-            } else {
-                Vec::new()
-            })
+            Ok(grep_file(path, &target, &metadata, matcher, options, read).await)
         }
-        FileTree::Dir(search_results_futs) => {
-            let mut all_results = Vec::new();
+        // links are only present if they weren't followed
+        FileTree::Symlink(_) | FileTree::Truncated(..) => Ok(SearchResults::default()),
+        FileTree::Dir(_, search_results_futs) => {
+            let mut all_results = SearchResults::default();
             for (path_component, search_result_fut) in search_results_futs.into_iter() {
                 let mut child_path = path.clone();
                 child_path.push(path_component);
                 let search_result = search_result_fut(child_path).await?;
-                all_results.extend(search_result.into_iter());
+                all_results.extend(search_result);
             }
             Ok(all_results)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::build::{build_file_tree, BuildOptions};
+    use crate::filetree::test_dir;
+    use regex::RegexBuilder;
+
+    async fn search_dir<M: Matcher + ?Sized>(
+        dir: PathBuf,
+        matcher: &M,
+        options: &SearchOptions,
+    ) -> SearchResults {
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap();
+        search(tree, dir, matcher, options).await.unwrap()
+    }
+
+    // search a single file, in a directory of its own
+    async fn grep<M: Matcher + ?Sized>(
+        name: &str,
+        matcher: &M,
+        multiline: bool,
+    ) -> Vec<(LineNumber, String)> {
+        let dir = test_dir(name);
+        std::fs::write(dir.join("file"), "Foo bar\nfoo\nbaz qux\n").unwrap();
+        let options = SearchOptions {
+            multiline,
+            ..SearchOptions::default()
+        };
+        let results = search_dir(dir, matcher, &options).await;
+        results
+            .matches
+            .into_iter()
+            .map(|m| (m.line_number, m.line))
+            .collect()
+    }
+
+    fn lines(expected: &[(LineNumber, &str)]) -> Vec<(LineNumber, String)> {
+        expected.iter().map(|(n, l)| (*n, l.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn matchers() {
+        assert_eq!(grep("substring", "foo", false).await, lines(&[(2, "foo")]));
+        let regex = RegexBuilder::new("^foo")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            grep("regex", &regex, false).await,
+            lines(&[(1, "Foo bar"), (2, "foo")])
+        );
+    }
+
+    #[tokio::test]
+    async fn multiline() {
+        let regex = Regex::new("foo\\nbaz").unwrap();
+        assert_eq!(grep("lines", &regex, false).await, lines(&[]));
+        assert_eq!(
+            grep("multiline", &regex, true).await,
+            lines(&[(2, "foo"), (3, "baz qux")])
+        );
+    }
+
+    #[tokio::test]
+    async fn binary_files() {
+        let dir = test_dir("binary");
+        std::fs::write(dir.join("text"), "needle\n").unwrap();
+        std::fs::write(dir.join("object"), b"\x7fELF\0\0needle\n\xff").unwrap();
+
+        let results = search_dir(dir.clone(), "needle", &SearchOptions::default()).await;
+        assert_eq!(results.matches.len(), 1);
+        assert_eq!(
+            results.skipped,
+            vec![Skipped {
+                path: dir.join("object"),
+                reason: SkipReason::Binary
+            }]
+        );
+
+        let options = SearchOptions {
+            binary: true,
+            ..SearchOptions::default()
+        };
+        let results = search_dir(dir, "needle", &options).await;
+        assert_eq!(results.matches.len(), 2);
+        assert!(results.skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn context() {
+        let dir = test_dir("context");
+        std::fs::write(dir.join("file"), "a\nmatch 1\nb\nc\nd\nmatch 2\n").unwrap();
+        let options = SearchOptions {
+            before_context: 2,
+            after_context: 1,
+            ..SearchOptions::default()
+        };
+        let results = search_dir(dir.clone(), "match", &options).await;
+        let strings = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect();
+        assert_eq!(
+            results.matches,
+            vec![
+                Match {
+                    path: dir.join("file"),
+                    line_number: 2,
+                    line: "match 1".to_string(),
+                    patterns: vec![0],
+                    ranges: vec![Range { start: 0, end: 5 }],
+                    before: strings(&["a"]),
+                    after: strings(&["b"]),
+                },
+                Match {
+                    path: dir.join("file"),
+                    line_number: 6,
+                    line: "match 2".to_string(),
+                    patterns: vec![0],
+                    ranges: vec![Range { start: 0, end: 5 }],
+                    before: strings(&["c", "d"]),
+                    after: Vec::new(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn many_patterns() {
+        let dir = test_dir("many_patterns");
+        std::fs::write(
+            dir.join("config"),
+            "user = admin\nkey = AKIAIOSFODNN7\ntoken = ghp_abc AKIA\n",
+        )
+        .unwrap();
+        let signatures = AhoCorasick::new(["AKIA", "ghp_", "AKIAIOSFODNN7", "unused"]).unwrap();
+
+        let found = |multiline| {
+            let dir = dir.clone();
+            let signatures = &signatures;
+            async move {
+                let options = SearchOptions {
+                    multiline,
+                    ..SearchOptions::default()
+                };
+                search_dir(dir, signatures, &options)
+                    .await
+                    .matches
+                    .into_iter()
+                    .map(|m| (m.line_number, m.patterns))
+                    .collect::<Vec<_>>()
+            }
+        };
+        // overlapping patterns are all reported
+        let expected = vec![(2, vec![0, 2]), (3, vec![0, 1])];
+        assert_eq!(found(false).await, expected);
+        assert_eq!(found(true).await, expected);
+    }
+
+    #[tokio::test]
+    async fn ordered() {
+        let dir = test_dir("ordered");
+        std::fs::create_dir_all(dir.join("m")).unwrap();
+        for file in ["z", "a", "m/x", "b"] {
+            std::fs::write(dir.join(file), "needle\n").unwrap();
+        }
+        // matches come in path order, however the directory happens to list them
+        let results = search_dir(dir.clone(), "needle", &SearchOptions::default()).await;
+        let paths: Vec<PathBuf> = results.matches.into_iter().map(|m| m.path).collect();
+        assert_eq!(
+            paths,
+            ["a", "b", "m/x", "z"].map(|file| dir.join(file)).to_vec()
+        );
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(!Case::Sensitive.is_insensitive(&["foo"]));
+        assert!(Case::Insensitive.is_insensitive(&["Foo"]));
+        assert!(Case::Smart.is_insensitive(&["foo", "bar"]));
+        assert!(!Case::Smart.is_insensitive(&["foo", "Bar"]));
+        // escaped letters are regex syntax, not text to match
+        assert!(Case::Smart.is_insensitive(&["\\Wfoo\\\\"]));
+        assert!(!Case::Smart.is_insensitive(&["\\\\Foo"]));
+    }
+
+    #[tokio::test]
+    async fn highlighting() {
+        let dir = test_dir("highlighting");
+        std::fs::write(dir.join("file"), "a needle, a needle\nneed\nle\n").unwrap();
+        let ranges = |multiline| {
+            let dir = dir.clone();
+            async move {
+                let options = SearchOptions {
+                    multiline,
+                    ..SearchOptions::default()
+                };
+                let regex = Regex::new("needle|need\\nle").unwrap();
+                search_dir(dir, &regex, &options)
+                    .await
+                    .matches
+                    .into_iter()
+                    .map(|m| {
+                        let ranges: Vec<_> = m.ranges.iter().map(|r| (r.start, r.end)).collect();
+                        (m.line_number, ranges)
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(ranges(false).await, vec![(1, vec![(2, 8), (12, 18)])]);
+        // a match spanning lines is split between them
+        assert_eq!(
+            ranges(true).await,
+            vec![
+                (1, vec![(2, 8), (12, 18)]),
+                (2, vec![(0, 4)]),
+                (3, vec![(0, 2)])
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn limits() {
+        let dir = test_dir("search_limits");
+        std::fs::write(dir.join("a"), "needle\n").unwrap();
+        std::fs::write(dir.join("b"), "needle\n").unwrap();
+        std::fs::write(dir.join("big"), format!("needle\n{}", "x".repeat(100))).unwrap();
+        std::fs::write(dir.join("removed"), "needle\n").unwrap();
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap();
+        std::fs::remove_file(dir.join("removed")).unwrap();
+
+        let search_with = |options: SearchOptions| {
+            let (tree, dir) = (tree.clone(), dir.clone());
+            async move {
+                let results = search(tree, dir.clone(), "needle", &options).await.unwrap();
+                let matched: Vec<PathBuf> = results.matches.into_iter().map(|m| m.path).collect();
+                (matched, results.skipped)
+            }
+        };
+        let skipped = |file: &str, reason| Skipped {
+            path: dir.join(file),
+            reason,
+        };
+        let not_found = SkipReason::Unreadable(std::io::ErrorKind::NotFound);
+
+        let (matched, skips) = search_with(SearchOptions {
+            max_file_size: Some(50),
+            ..SearchOptions::default()
+        })
+        .await;
+        assert_eq!(matched, vec![dir.join("a"), dir.join("b")]);
+        assert_eq!(
+            skips,
+            vec![
+                skipped("big", SkipReason::TooLarge),
+                skipped("removed", not_found)
+            ]
+        );
+
+        // files are read in path order, until the next would go over
+        let (matched, skips) = search_with(SearchOptions {
+            max_total_bytes_read: Some(10),
+            ..SearchOptions::default()
+        })
+        .await;
+        assert_eq!(matched, vec![dir.join("a")]);
+        assert_eq!(
+            skips,
+            vec![
+                skipped("b", SkipReason::OverBudget),
+                skipped("big", SkipReason::OverBudget),
+                skipped("removed", SkipReason::OverBudget),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn links() {
+        let dir = test_dir("search_links");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/file"), "needle\n").unwrap();
+        std::os::unix::fs::symlink("sub/file", dir.join("link")).unwrap();
+        std::os::unix::fs::symlink("sub", dir.join("dir_link")).unwrap();
+        std::os::unix::fs::symlink("missing", dir.join("broken")).unwrap();
+
+        let options = SearchOptions {
+            follow_symlinks: true,
+            ..SearchOptions::default()
+        };
+        let results = search_dir(dir.clone(), "needle", &options).await;
+        let matched: Vec<PathBuf> = results.matches.into_iter().map(|m| m.path).collect();
+        // matches in linked files are at the link's path
+        assert_eq!(matched, vec![dir.join("link"), dir.join("sub/file")]);
+        assert_eq!(
+            results.skipped,
+            vec![Skipped {
+                path: dir.join("broken"),
+                reason: SkipReason::Unreadable(std::io::ErrorKind::NotFound),
+            }]
+        );
+
+        let results = search_dir(dir, "needle", &SearchOptions::default()).await;
+        assert_eq!(results.matches.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn parallel() {
+        let dir = test_dir("search_parallel");
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        for (file, contents) in [
+            ("top", "needle\nhay\nneedle\n"),
+            ("a/x", "hay\n"),
+            ("a/b/y", "a needle\n"),
+            ("a/b/z", "needle\0"),
+        ] {
+            std::fs::write(dir.join(file), contents).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("../top", dir.join("a/link")).unwrap();
+
+        let root = dir.to_string_lossy().to_string();
+        let tree = build_file_tree(root, &BuildOptions::default())
+            .await
+            .unwrap();
+        let options = SearchOptions {
+            follow_symlinks: true,
+            after_context: 1,
+            ..SearchOptions::default()
+        };
+        // the same results, in the same order
+        let results = search(tree.clone(), dir.clone(), "needle", &options)
+            .await
+            .unwrap();
+        let parallel = search_parallel(&tree, &dir, "needle", &options);
+        assert_eq!(parallel.matches, results.matches);
+        assert_eq!(parallel.skipped, results.skipped);
+        assert_eq!(parallel.matches.len(), if cfg!(unix) { 5 } else { 3 });
+        assert_eq!(parallel.skipped.len(), 1);
+    }
+}
@@ -0,0 +1,214 @@
+//! Keeping a built tree up to date as the filesystem changes. Rather than rebuilding from scratch,
+//! only the directories that events were reported in are re-read, and each is grafted in place of
+//! its old subtree.
+//!
+//! With 'Symlinks::Follow', a link within a re-read directory may be expanded even if its target
+//! is already expanded elsewhere in the tree, as only the directories re-read are tracked.
+
+use crate::filetree::build::{build_file_tree, build_subtree, BuildOptions};
+use crate::filetree::error::{Error, WithPath};
+use crate::filetree::{FileTree, RecursiveFileTree};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use recursion::recursive_tree::ArenaIndex;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+/// The directories that 'events' were reported in, relative to 'root', which must be canonical.
+/// Events outside of 'root', and those that only record access, are ignored.
+pub fn affected_dirs(root: &Path, events: &[Event]) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = events
+        .iter()
+        .filter(|event| !matches!(event.kind, EventKind::Access(_)))
+        .flat_map(|event| event.paths.iter())
+        .filter_map(|path| {
+            let path = path.strip_prefix(root).ok()?;
+            // changes to the root itself are recorded in the root
+            Some(path.parent().unwrap_or(path).to_path_buf())
+        })
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+// the index of the directory at 'path', if there's one in the tree
+fn find_dir(tree: &RecursiveFileTree, path: &Path) -> Option<ArenaIndex> {
+    let mut idx = tree.root();
+    for component in path.components() {
+        match tree.layer(idx) {
+            FileTree::Dir(_, entries) => idx = *entries.get(component.as_os_str())?,
+            _ => return None,
+        }
+    }
+    matches!(tree.layer(idx), FileTree::Dir(..)).then_some(idx)
+}
+
+/// Re-read each of 'dirs', relative to 'root_path', and replace its subtree. A directory that
+/// isn't in the tree or is no longer on disk, eg because it was just created or removed, is
+/// handled by re-reading the closest ancestor that's in both instead.
+pub async fn update(
+    tree: &RecursiveFileTree,
+    root_path: &str,
+    dirs: &[PathBuf],
+    options: &BuildOptions,
+) -> Result<RecursiveFileTree, Error> {
+    let mut targets = Vec::new();
+    for dir in dirs {
+        let mut dir = dir.as_path();
+        while let Some(parent) = dir.parent() {
+            let is_dir = tokio::fs::metadata(Path::new(root_path).join(dir))
+                .await
+                .is_ok_and(|metadata| metadata.is_dir());
+            if is_dir && find_dir(tree, dir).is_some() {
+                break;
+            }
+            dir = parent;
+        }
+        targets.push(dir.to_path_buf());
+    }
+
+    // re-reading a directory re-reads everything under it, and sorting puts each directory
+    // before any others under it
+    targets.sort();
+    targets.dedup();
+    let mut outermost: Vec<PathBuf> = Vec::new();
+    for dir in targets {
+        if !outermost.iter().any(|outer| dir.starts_with(outer)) {
+            outermost.push(dir);
+        }
+    }
+
+    let mut tree = tree.clone();
+    for dir in outermost {
+        // indices change with each graft, so each position is looked up as it's needed
+        let idx = find_dir(&tree, &dir).expect("each target is a directory in the tree");
+        let subtree = build_subtree(root_path, &dir, options).await?;
+        tree = tree.graft(idx, &subtree);
+    }
+    Ok(tree)
+}
+
+/// A tree that's kept up to date with changes to the filesystem
+pub struct Watch<'a> {
+    root_path: String,
+    // canonical, to match the paths of events
+    root: PathBuf,
+    options: &'a BuildOptions,
+    tree: RecursiveFileTree,
+    events: UnboundedReceiver<notify::Result<Event>>,
+    // stops watching when dropped
+    _watcher: RecommendedWatcher,
+}
+
+impl<'a> Watch<'a> {
+    /// Start watching 'root_path', then build the tree, so that no changes are missed
+    pub async fn new(root_path: String, options: &'a BuildOptions) -> Result<Watch<'a>, Error> {
+        let root = tokio::fs::canonicalize(&root_path).await.at(&root_path)?;
+        let (sender, events) = unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // the receiver only goes away along with the watcher
+            let _ = sender.send(event);
+        })?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+
+        let tree = build_file_tree(root_path.clone(), options).await?;
+        Ok(Watch {
+            root_path,
+            root,
+            options,
+            tree,
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    pub fn tree(&self) -> &RecursiveFileTree {
+        &self.tree
+    }
+
+    /// Wait for the next change to the tree, and apply it along with any other changes that have
+    /// arrived in the meantime
+    pub async fn changed(&mut self) -> Result<&RecursiveFileTree, Error> {
+        loop {
+            let mut events = match self.events.recv().await {
+                Some(event) => vec![event?],
+                None => return Err(notify::Error::generic("watcher stopped").into()),
+            };
+            while let Ok(event) = self.events.try_recv() {
+                events.push(event?);
+            }
+
+            let dirs = affected_dirs(&self.root, &events);
+            if !dirs.is_empty() {
+                self.tree = update(&self.tree, &self.root_path, &dirs, self.options).await?;
+                return Ok(&self.tree);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetree::{render, test_dir};
+    use notify::event::{AccessKind, CreateKind, ModifyKind, RemoveKind};
+
+    #[tokio::test]
+    async fn incremental() {
+        let dir = test_dir("watch");
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::create_dir_all(dir.join("c")).unwrap();
+        std::fs::write(dir.join("a/b/x"), "").unwrap();
+        std::fs::write(dir.join("c/y"), "").unwrap();
+        std::fs::write(dir.join(".gitignore"), "*.log\n").unwrap();
+
+        let root_path = dir.to_string_lossy().to_string();
+        let options = BuildOptions {
+            respect_ignore_files: true,
+            ..BuildOptions::default()
+        };
+        let tree = build_file_tree(root_path.clone(), &options).await.unwrap();
+
+        let root = dir.canonicalize().unwrap();
+        let event = |kind, path: &str| Event::new(kind).add_path(root.join(path));
+
+        // only the directories that events were reported in are re-read, and with the ignore
+        // rules of their ancestors still applied
+        std::fs::write(dir.join("a/b/new"), "").unwrap();
+        std::fs::write(dir.join("a/b/out.log"), "").unwrap();
+        std::fs::write(dir.join("c/unreported"), "").unwrap();
+        let events = [
+            event(EventKind::Create(CreateKind::File), "a/b/new"),
+            event(EventKind::Create(CreateKind::File), "a/b/out.log"),
+            event(EventKind::Access(AccessKind::Any), "c/y"),
+            Event::new(EventKind::Any).add_path(PathBuf::from("/elsewhere")),
+        ];
+        let dirs = affected_dirs(&root, &events);
+        assert_eq!(dirs, vec![PathBuf::from("a/b")]);
+        let tree = update(&tree, &root_path, &dirs, &options).await.unwrap();
+        assert_eq!(
+            render(&tree, "."),
+            ".\n├── .gitignore\n├── a/\n│   └── b/\n│       ├── new\n│       └── x\n└── c/\n    └── y"
+        );
+
+        // directories that were created or removed are handled by re-reading their parents
+        std::fs::create_dir_all(dir.join("a/d/e")).unwrap();
+        std::fs::write(dir.join("a/d/e/z"), "").unwrap();
+        std::fs::remove_dir_all(dir.join("c")).unwrap();
+        let events = [
+            event(EventKind::Create(CreateKind::Folder), "a/d"),
+            event(EventKind::Create(CreateKind::Folder), "a/d/e"),
+            event(EventKind::Create(CreateKind::File), "a/d/e/z"),
+            event(EventKind::Modify(ModifyKind::Any), "c/y"),
+            event(EventKind::Remove(RemoveKind::Folder), "c"),
+        ];
+        let dirs = affected_dirs(&root, &events);
+        assert_eq!(
+            dirs,
+            ["", "a", "a/d", "a/d/e", "c"].map(PathBuf::from).to_vec()
+        );
+        let tree = update(&tree, &root_path, &dirs, &options).await.unwrap();
+        let rebuilt = build_file_tree(root_path.clone(), &options).await.unwrap();
+        assert_eq!(render(&tree, "."), render(&rebuilt, "."));
+    }
+}
@@ -1,57 +1,715 @@
 mod filetree;
 
-use clap::Parser;
+use aho_corasick::AhoCorasick;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use colored::*;
-use filetree::{build::build_file_tree, search::search};
-use regex::Regex;
-use std::ffi::OsString;
+use filetree::archive::{is_archive, open};
+#[cfg(unix)]
+use filetree::audit::{matching, owned_by, setgid, setuid, world_writable};
+use filetree::build::{build_file_tree, BuildOptions, Symlinks};
+use filetree::contents::ContentCache;
+use filetree::diff::{diff, diff_hashes, Change, Diff};
+use filetree::du::{human, render_sizes, sizes};
+use filetree::error::{Error, WithPath};
+use filetree::export::{to_dot, to_json, to_ndjson};
+use filetree::globs::Globs;
+use filetree::hash::{hash_tree, HashTree};
+use filetree::paths::{Normalization, Unicode};
+use filetree::query::{each_dir, hardlinks, largest, modified_since, newest};
+use filetree::rank::{rank, RankOptions};
+use filetree::search::{
+    search, search_parallel, Case, LineNumber, Match, Matcher, PatternId, SearchOptions, SkipReason,
+};
+use recursion::recursive::Collapse;
+use regex::RegexBuilder;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use crate::filetree::depth;
+use crate::filetree::{depth, folded_sizes, render, RecursiveFileTree};
 
-/// Simple program to greet a person
+/// Search, measure, render and compare file trees, each built once and then traversed by the
+/// subcommand given
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
-struct Args {
-    /// Name of the p&erson to greet
-    #[clap(short, long)]
-    regex: String,
+struct Cli {
+    #[clap(flatten)]
+    source: SourceArgs,
 
-    /// paths to filter out
-    #[clap(short, long)]
-    paths_to_ignore: Vec<OsString>,
+    #[clap(subcommand)]
+    command: Command,
 }
 
-// build a recursive tree of filesystem state (dirs and files with metadata only) then
-// lazily traverse it grep-style to return
+/// Which tree to read, and which of its entries
+#[derive(Args, Debug)]
+struct SourceArgs {
+    /// only include paths matching this glob, or exclude them if it starts with '!', eg
+    /// '**/*.rs' or '!**/vendor/**'. May be given more than once.
+    #[clap(short, long, global = true)]
+    glob: Vec<String>,
+
+    /// don't respect '.gitignore' and '.ignore' files, or git's global excludes
+    #[clap(long, global = true)]
+    no_ignore: bool,
+
+    /// only read entries up to this many directories deep
+    #[clap(long, global = true)]
+    max_depth: Option<usize>,
+
+    /// only read directories under this path, relative to the current directory, along with those
+    /// leading to it. May be given more than once.
+    #[clap(long, global = true)]
+    prefix: Vec<PathBuf>,
+
+    /// leave out the entries of any directory with more than this many
+    #[clap(long, global = true)]
+    max_entries_per_dir: Option<usize>,
+
+    /// whether to ignore, record, or follow symbolic links
+    #[clap(long, value_enum, default_value_t, global = true)]
+    symlinks: Symlinks,
+
+    /// read the contents of this tar, gzipped tar, or zip archive instead of the current
+    /// directory, without extracting it
+    #[clap(long, global = true)]
+    archive: Option<PathBuf>,
+
+    /// read the files of this commit, eg 'HEAD~1', of the git repository containing the current
+    /// directory, rather than the working directory, without checking it out
+    #[cfg(feature = "git")]
+    #[clap(long, global = true)]
+    git_rev: Option<String>,
+
+    /// keep up to this many bytes of file contents in memory once read, so that eg searching
+    /// again in watch mode needn't read every file again
+    #[clap(long, default_value_t = 0, global = true)]
+    cache_bytes: usize,
+
+    /// maximum number of directory entries to read at once
+    #[clap(long, default_value_t = BuildOptions::default().parallelism, global = true)]
+    parallelism: usize,
+
+    /// the unicode normalization form names are put in before they're matched against globs and
+    /// prefixes, or compared by 'diff' and 'hash --since'
+    #[clap(long, value_enum, default_value_t, global = true)]
+    unicode: Unicode,
+
+    /// match names against globs and prefixes, and compare them in 'diff' and 'hash --since',
+    /// case-insensitively
+    #[clap(long, global = true)]
+    fold_case: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// search the contents of every file
+    Grep(GrepArgs),
+
+    /// print the total size, and the sizes of the largest directories and files. Files with
+    /// several hard links are only counted once.
+    Du {
+        /// how many of the largest directories and files to print
+        #[clap(short = 'n', long, default_value_t = 10)]
+        largest: usize,
+    },
+
+    /// print the file tree, 'tree'-command style
+    Tree {
+        /// label each entry with its size
+        #[clap(long)]
+        sizes: bool,
+    },
+
+    /// print every path that differs between two directories, by metadata. Archives may be given
+    /// instead of directories.
+    Diff { old: PathBuf, new: PathBuf },
+
+    /// hash every file and directory, to compare against or save for a later run
+    Hash {
+        /// print every path that changed since the hashes at this path were written
+        #[clap(long)]
+        since: Option<PathBuf>,
+
+        /// write the hashes to this path, for use with '--since'
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+
+    /// write the file tree out, for use by other tools
+    Export {
+        #[clap(value_enum)]
+        format: Format,
+
+        /// write to this path rather than to stdout
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+
+    /// print the most recently modified file under each directory
+    Newest,
+
+    /// print every file modified within this many seconds
+    ModifiedWithin { secs: u64 },
+
+    /// print each group of paths that are hard links to the same file
+    Hardlinks,
+
+    /// print world-writable files and directories, and setuid and setgid files
+    #[cfg(unix)]
+    Audit {
+        /// instead, print every file and directory owned by the user with this id
+        #[clap(long)]
+        owned_by: Option<u32>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    /// a single nested json object
+    Json,
+    /// newline-delimited json, one object per entry
+    Ndjson,
+    /// a graphviz digraph, with an edge from each directory to each of its entries
+    Dot,
+    /// file sizes as folded stacks, for use with flamegraph tooling
+    Folded,
+}
+
+#[derive(Args, Debug)]
+struct GrepArgs {
+    /// the regex to search for
+    #[clap(required_unless_present = "patterns-file")]
+    pattern: Option<String>,
+
+    /// search for each line of this file as a literal string, all in a single pass over each
+    /// file, rather than for a single pattern. With '-i', only ascii letters match
+    /// case-insensitively.
+    #[clap(short = 'f', long)]
+    patterns_file: Option<PathBuf>,
+
+    /// match case-insensitively
+    #[clap(short = 'i', long)]
+    ignore_case: bool,
+
+    /// match case-insensitively, unless a pattern contains an uppercase letter
+    #[clap(short = 'S', long, conflicts_with = "ignore-case")]
+    smart_case: bool,
+
+    /// treat the pattern as a literal string rather than a regex
+    #[clap(short = 'F', long)]
+    fixed_strings: bool,
+
+    /// search each file as a whole, so that matches can span lines
+    #[clap(short = 'U', long)]
+    multiline: bool,
+
+    /// print this many lines of context after each match
+    #[clap(short = 'A', long)]
+    after_context: Option<usize>,
+
+    /// print this many lines of context before each match
+    #[clap(short = 'B', long)]
+    before_context: Option<usize>,
+
+    /// print this many lines of context before and after each match, unless overridden by '-A'
+    /// or '-B'
+    #[clap(short = 'C', long, default_value_t = 0)]
+    context: usize,
+
+    /// search binary files as if they were text
+    #[clap(short = 'a', long)]
+    text: bool,
+
+    /// skip files larger than this many bytes
+    #[clap(long)]
+    max_filesize: Option<u64>,
+
+    /// stop searching once this many bytes of files have been read in all
+    #[clap(long)]
+    max_total_bytes: Option<u64>,
+
+    /// search the files that links point to, when links are recorded rather than followed
+    #[clap(long)]
+    follow_links: bool,
+
+    /// read and match files on a pool of threads, one per core, rather than with async reads
+    #[clap(long)]
+    rayon: bool,
+
+    /// print only this many matches, those most likely to be relevant first, rather than every
+    /// match grouped by file. Matches score higher in files near '--near', in files with many of
+    /// them, and in recently modified files. Context isn't printed.
+    #[clap(long)]
+    top: Option<usize>,
+
+    /// the directory matches are ranked by their distance from, with '--top'
+    #[clap(long, default_value = ".")]
+    near: PathBuf,
+
+    /// keep watching for changes, and search again after each
+    #[cfg(feature = "notify")]
+    #[clap(long)]
+    watch: bool,
+}
+
+// build a recursive tree of filesystem state (dirs and files with metadata only) then traverse it
+// as the subcommand given asks
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    let args = Args::parse();
-    let regex = Regex::new(&args.regex).unwrap();
-
-    let current_dir = std::env::current_dir()?;
-
-    let fs_tree = build_file_tree(".".to_string(), &|path_component| {
-        !args.paths_to_ignore.contains(path_component)
-    })
-    .await?;
-
-    println!("{} {:?}", "sparse filetree depth:".cyan(), depth(&fs_tree));
-
-    // TODO: remove paths to ignore from here entirely and move it to build phase - cleaner that way, runs all the futures in the map, etc
-    let grep_res = search(fs_tree, current_dir, &regex).await?;
-    for elem in grep_res.into_iter() {
-        println!("{} {:?}", "file:".cyan(), elem.path);
-        println!("{} {:?}", "permissions".cyan(), elem.metadata.permissions());
-        println!("{} {:?}", "modified".cyan(), elem.metadata.modified());
-        for (line_num, matching_line) in elem.matching_lines.into_iter() {
-            println!(
+async fn main() {
+    if let Err(e) = run(Cli::parse()).await {
+        eprintln!("{} {}", "error:".red(), e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Error> {
+    let source = &cli.source;
+    let current_dir = std::env::current_dir().at(".")?;
+    let contents = Arc::new(ContentCache::new(source.cache_bytes));
+
+    let normalization = Normalization {
+        unicode: source.unicode,
+        fold_case: source.fold_case,
+    };
+    let options = BuildOptions {
+        symlinks: source.symlinks,
+        respect_ignore_files: !source.no_ignore,
+        parallelism: source.parallelism,
+        globs: Globs::new(&source.glob, normalization)?,
+        max_depth: source.max_depth,
+        max_entries_per_dir: source.max_entries_per_dir,
+        prefixes: source
+            .prefix
+            .iter()
+            .map(|prefix| prefix.strip_prefix(".").unwrap_or(prefix).to_path_buf())
+            .collect(),
+        normalization,
+    };
+
+    // the two trees compared by 'diff' are read from the paths given, rather than from the source
+    if let Command::Diff { old, new } = &cli.command {
+        let old = open_tree(old, &options).await?;
+        let new = open_tree(new, &options).await?;
+        print_diff(diff(&old, &new, &normalization));
+        return Ok(());
+    }
+
+    #[cfg(feature = "notify")]
+    if let Command::Grep(args) = &cli.command {
+        if args.watch {
+            // search again after every change, until interrupted
+            let (matcher, labels) = matcher(args)?;
+            let mut watch = filetree::watch::Watch::new(".".to_string(), &options).await?;
+            let mut fs_tree = watch.tree().clone();
+            loop {
+                grep(
+                    fs_tree,
+                    current_dir.clone(),
+                    matcher.as_ref(),
+                    &labels,
+                    args,
+                    &contents,
+                )
+                .await?;
+                fs_tree = watch.changed().await?.clone();
+            }
+        }
+    }
+
+    let (fs_tree, current_dir, contents) =
+        tree_source(source, &options, current_dir, contents).await?;
+
+    match &cli.command {
+        Command::Grep(args) => {
+            let (matcher, labels) = matcher(args)?;
+            grep(
+                fs_tree,
+                current_dir,
+                matcher.as_ref(),
+                &labels,
+                args,
+                &contents,
+            )
+            .await?;
+        }
+
+        Command::Du { largest: n } => {
+            let sizes = sizes(&fs_tree);
+            println!("{} {}", "total size:".cyan(), human(sizes.total));
+            for (label, entries) in [
+                ("largest dirs:", sizes.largest_dirs(*n)),
+                (
+                    "largest files:",
+                    fs_tree.as_ref().collapse_layers(largest(*n)),
+                ),
+            ] {
+                println!("{}", label.cyan());
+                for (path, size) in entries {
+                    println!("{:>10}  {}", human(size), path.display());
+                }
+            }
+        }
+
+        Command::Tree { sizes } => {
+            if *sizes {
+                println!("{}", render_sizes(&fs_tree, "."));
+            } else {
+                println!("{}", render(&fs_tree, "."));
+            }
+            println!("{} {}", "depth:".cyan(), depth(&fs_tree));
+        }
+
+        Command::Diff { .. } => unreachable!("diffs are handled before the source is read"),
+
+        Command::Hash { since, out } => {
+            let hashes = hash_tree(&fs_tree, current_dir, &contents).await?;
+            println!("{} {:016x}", "hash:".cyan(), hashes.hash());
+            if let Some(path) = since {
+                let saved = std::fs::read_to_string(path).at(path)?;
+                let saved = HashTree::from_json(&saved).map_err(|source| Error::Json {
+                    path: path.clone(),
+                    source,
+                })?;
+                println!("{}", "changed:".cyan());
+                print_diff(diff_hashes(&saved, &hashes, &normalization));
+            }
+            if let Some(path) = out {
+                let json = hashes.to_json().map_err(|source| Error::Json {
+                    path: path.clone(),
+                    source,
+                })?;
+                std::fs::write(path, json).at(path)?;
+            }
+        }
+
+        Command::Export { format, out } => {
+            let exported = match format {
+                Format::Json => to_json(&fs_tree).to_string(),
+                Format::Ndjson => to_ndjson(&fs_tree),
+                Format::Dot => to_dot(&fs_tree, "."),
+                Format::Folded => {
+                    let mut folded = Vec::new();
+                    folded_sizes(&fs_tree)
+                        .write_to(&mut folded)
+                        .expect("writing to a Vec doesn't fail");
+                    String::from_utf8_lossy(&folded).into_owned()
+                }
+            };
+            match out {
+                Some(path) => std::fs::write(path, exported).at(path)?,
+                None => print!("{}", exported),
+            }
+        }
+
+        Command::Newest => {
+            let (_, newest_by_dir) = fs_tree.as_ref().collapse_layers(each_dir(newest));
+            for (dir, newest) in newest_by_dir {
+                if let Some((path, _)) = newest {
+                    println!("{}", Path::new(".").join(dir).join(path).display());
+                }
+            }
+        }
+
+        Command::ModifiedWithin { secs } => {
+            let since = SystemTime::now() - Duration::from_secs(*secs);
+            for path in fs_tree.as_ref().collapse_layers(modified_since(since)) {
+                println!("{}", path.display());
+            }
+        }
+
+        Command::Hardlinks => {
+            for paths in fs_tree.as_ref().collapse_layers(hardlinks).into_values() {
+                if paths.len() > 1 {
+                    let paths: Vec<String> = paths
+                        .iter()
+                        .map(|path| Path::new(".").join(path).display().to_string())
+                        .collect();
+                    println!("{}", paths.join(" = "));
+                }
+            }
+        }
+
+        #[cfg(unix)]
+        Command::Audit {
+            owned_by: Some(uid),
+        } => {
+            let found = fs_tree.as_ref().collapse_layers(matching(owned_by(*uid)));
+            for path in found {
+                println!("{}", Path::new(".").join(path).display());
+            }
+        }
+
+        #[cfg(unix)]
+        Command::Audit { owned_by: None } => {
+            for (label, found) in [
+                (
+                    "world-writable:",
+                    fs_tree.as_ref().collapse_layers(matching(world_writable)),
+                ),
+                (
+                    "setuid:",
+                    fs_tree.as_ref().collapse_layers(matching(setuid)),
+                ),
+                (
+                    "setgid:",
+                    fs_tree.as_ref().collapse_layers(matching(setgid)),
+                ),
+            ] {
+                println!("{}", label.cyan());
+                for path in found {
+                    println!("{}", Path::new(".").join(path).display());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// the matcher for the pattern or patterns given, along with the patterns to label matches with, if
+// there's more than one
+fn matcher(args: &GrepArgs) -> Result<(Box<dyn Matcher>, Vec<String>), Error> {
+    let case = if args.ignore_case {
+        Case::Insensitive
+    } else if args.smart_case {
+        Case::Smart
+    } else {
+        Case::Sensitive
+    };
+    let matcher: Box<dyn Matcher> = match (&args.patterns_file, &args.pattern) {
+        (Some(path), _) => {
+            let labels: Vec<String> = std::fs::read_to_string(path)
+                .at(path)?
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(|line| line.to_string())
+                .collect();
+            let patterns = AhoCorasick::builder()
+                .ascii_case_insensitive(case.is_insensitive(&labels))
+                .build(&labels)?;
+            return Ok((Box::new(patterns), labels));
+        }
+        (None, Some(pattern)) => {
+            let ignore_case = case.is_insensitive(&[pattern]);
+            if args.fixed_strings && !ignore_case {
+                Box::new(AhoCorasick::new([pattern])?)
+            } else {
+                // case-insensitive literals are matched as escaped regexes, for unicode case
+                // folding
+                let pattern = if args.fixed_strings {
+                    regex::escape(pattern)
+                } else {
+                    pattern.clone()
+                };
+                let regex = RegexBuilder::new(&pattern)
+                    .case_insensitive(ignore_case)
+                    .multi_line(args.multiline)
+                    .build()?;
+                Box::new(regex)
+            }
+        }
+        (None, None) => unreachable!("clap requires a pattern without a patterns file"),
+    };
+    Ok((matcher, Vec::new()))
+}
+
+// a directory, or an archive, as a tree
+async fn open_tree(path: &Path, options: &BuildOptions) -> Result<RecursiveFileTree, Error> {
+    if is_archive(path) {
+        Ok(open(path)?.tree)
+    } else {
+        build_file_tree(path.to_string_lossy().to_string(), options).await
+    }
+}
+
+// the tree to search and analyse, where its files are or would be on disk, and where to read their
+// contents from
+async fn tree_source(
+    args: &SourceArgs,
+    options: &BuildOptions,
+    current_dir: PathBuf,
+    contents: Arc<ContentCache>,
+) -> Result<(RecursiveFileTree, PathBuf, Arc<ContentCache>), Error> {
+    if let Some(path) = &args.archive {
+        let archive = open(path)?;
+        return Ok((archive.tree, path.clone(), Arc::new(archive.contents)));
+    }
+    #[cfg(feature = "git")]
+    if let Some(rev) = &args.git_rev {
+        let revision = filetree::git::open(&current_dir, rev)?;
+        return Ok((revision.tree, revision.root, Arc::new(revision.contents)));
+    }
+    let fs_tree = build_file_tree(".".to_string(), options).await?;
+    Ok((fs_tree, current_dir, contents))
+}
+
+// search the tree, and print the results
+async fn grep(
+    fs_tree: RecursiveFileTree,
+    current_dir: PathBuf,
+    matcher: &dyn Matcher,
+    labels: &[String],
+    args: &GrepArgs,
+    contents: &Arc<ContentCache>,
+) -> Result<(), Error> {
+    let search_options = SearchOptions {
+        multiline: args.multiline,
+        binary: args.text,
+        contents: contents.clone(),
+        before_context: args.before_context.unwrap_or(args.context),
+        after_context: args.after_context.unwrap_or(args.context),
+        max_file_size: args.max_filesize,
+        max_total_bytes_read: args.max_total_bytes,
+        follow_symlinks: args.follow_links,
+    };
+    let grep_res = if args.rayon {
+        search_parallel(&fs_tree, &current_dir, matcher, &search_options)
+    } else {
+        search(
+            fs_tree.clone(),
+            current_dir.clone(),
+            matcher,
+            &search_options,
+        )
+        .await?
+    };
+    match args.top {
+        Some(top) => {
+            let options = RankOptions {
+                near: args
+                    .near
+                    .strip_prefix(".")
+                    .unwrap_or(&args.near)
+                    .to_path_buf(),
+                limit: Some(top),
+                ..RankOptions::default()
+            };
+            for (score, m) in rank(&fs_tree, &current_dir, grep_res.matches, &options) {
+                println!(
+                    "{}\t{}{}\t{}{}",
+                    format!("{:.2}", score.total()).yellow(),
+                    m.path.display(),
+                    format!(":{}:", m.line_number).magenta(),
+                    highlight(&m.line, &m.ranges),
+                    pattern_labels(&m.patterns, labels),
+                );
+            }
+        }
+        None => print_matches(grep_res.matches, labels),
+    }
+
+    // the number of files skipped for each reason
+    let mut skipped: BTreeMap<String, usize> = BTreeMap::new();
+    for skip in &grep_res.skipped {
+        *skipped.entry(skip.reason.to_string()).or_default() += 1;
+    }
+    if !skipped.is_empty() {
+        let counts: Vec<String> = skipped
+            .into_iter()
+            .map(|(reason, count)| format!("{} {}", count, reason))
+            .collect();
+        println!("{} {}", "skipped files:".cyan(), counts.join(", "));
+    }
+    if grep_res
+        .skipped
+        .iter()
+        .any(|skip| skip.reason == SkipReason::Binary)
+    {
+        println!("(use --text to search binary files)");
+    }
+
+    if contents.cached_bytes() > 0 {
+        println!(
+            "{} {}",
+            "cached contents:".cyan(),
+            human(contents.cached_bytes() as u64)
+        );
+    }
+
+    Ok(())
+}
+
+// print matches grep-style, grouped by file
+fn print_matches(matches: Vec<Match>, labels: &[String]) {
+    // matches are grouped by file, in order, and printed grep-style: each line once, whether
+    // it's a match or context for one, with '--' between runs of lines that aren't adjacent.
+    // each line to print, by number, with the patterns it matched if it's a match
+    type Lines = BTreeMap<LineNumber, (String, Option<Vec<PatternId>>)>;
+    let mut by_file: Vec<(PathBuf, Lines)> = Vec::new();
+    for m in matches {
+        if by_file.last().is_none_or(|(path, _)| *path != m.path) {
+            by_file.push((m.path.clone(), BTreeMap::new()));
+        }
+        let (_, lines) = by_file.last_mut().expect("just pushed");
+        let first = m.line_number - m.before.len();
+        for (idx, line) in m.before.into_iter().enumerate() {
+            lines.entry(first + idx).or_insert((line, None));
+        }
+        for (idx, line) in m.after.into_iter().enumerate() {
+            lines.entry(m.line_number + 1 + idx).or_insert((line, None));
+        }
+        let line = highlight(&m.line, &m.ranges);
+        lines.insert(m.line_number, (line, Some(m.patterns)));
+    }
+    for (path, lines) in by_file {
+        println!("{} {:?}", "file:".cyan(), path);
+        let mut previous = None;
+        for (line_number, (line, patterns)) in lines {
+            if previous.is_some_and(|previous| previous + 1 != line_number) {
+                println!("{}", "--".cyan());
+            }
+            previous = Some(line_number);
+            let separator = if patterns.is_some() { ":" } else { "-" };
+            print!(
                 "{}\t{}",
-                format!("{:?}::", line_num).magenta(),
-                matching_line
+                format!("{}{}", line_number, separator).magenta(),
+                line
             );
+            match patterns {
+                Some(patterns) => println!("{}", pattern_labels(&patterns, labels)),
+                None => println!(),
+            }
         }
-        println!("\n");
+        println!();
     }
+}
 
-    Ok(())
+// the patterns a line matched, to print after it, if there's more than one pattern
+fn pattern_labels(patterns: &[PatternId], labels: &[String]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let matched: Vec<&str> = patterns.iter().map(|p| labels[*p].as_str()).collect();
+    format!("\t{}", format!("[{}]", matched.join(", ")).yellow())
+}
+
+// 'line' with the parts covered by 'ranges', which are sorted but may overlap, in color
+fn highlight(line: &str, ranges: &[Range<usize>]) -> String {
+    let mut highlighted = String::new();
+    let mut printed = 0;
+    for range in ranges {
+        let start = range.start.max(printed);
+        if range.end <= start {
+            continue;
+        }
+        highlighted.push_str(&line[printed..start]);
+        highlighted.push_str(&line[start..range.end].red().bold().to_string());
+        printed = range.end;
+    }
+    highlighted.push_str(&line[printed..]);
+    highlighted
+}
+
+fn print_diff(diff: Diff) {
+    for (path, change) in diff {
+        let marker = match change {
+            Change::Added => "+".green(),
+            Change::Removed => "-".red(),
+            Change::Modified => "~".yellow(),
+            Change::Ambiguous => "?".magenta(),
+        };
+        println!("{} {}", marker, path.display());
+    }
 }
@@ -0,0 +1,130 @@
+//! Interactive loop for the expression language: each line is parsed, typechecked, optimized and
+//! evaluated. Commands:
+//!
+//! - ':type <expr>' prints the static type of an expression, if it can be determined
+//! - ':trace <expr>' prints every step of small-step evaluation
+//! - ':dot <expr>' prints the expression tree as a graphviz digraph
+//! - ':quit' exits, as does end of input
+//!
+//! Run with 'cargo run --example repl --features expr_example'.
+
+use std::io::{self, BufRead, Write};
+
+use recursion::examples::expr::lang::dot::to_dot;
+use recursion::examples::expr::lang::eval::eval_arena;
+use recursion::examples::expr::lang::optimize::optimize;
+use recursion::examples::expr::lang::parse::parse_spanned;
+use recursion::examples::expr::lang::pretty::pretty;
+use recursion::examples::expr::lang::step::trace;
+use recursion::examples::expr::lang::typecheck::typecheck_spanned;
+use recursion::examples::expr::lang::{RecursiveExpr, Value};
+use recursion::spanned::strip_spans;
+
+fn show(value: &Value) -> String {
+    match value {
+        Value::Int(x) => x.to_string(),
+        Value::Float(x) => format!("{:?}", x),
+        Value::Bool(x) => x.to_string(),
+        Value::Closure { param, .. } => format!("<function of {}>", param),
+    }
+}
+
+/// Parse and typecheck, rendering any error along with the part of the input it refers to
+fn check(input: &str) -> Result<(RecursiveExpr, Option<String>), String> {
+    let expr = parse_spanned(input).map_err(|e| format!("parse error: {}", e))?;
+    let t = typecheck_spanned(&expr).map_err(|e| {
+        format!(
+            "type error: expected {:?}, found {:?} in '{}'",
+            e.value.error.expected, e.value.error.found, &input[e.span]
+        )
+    })?;
+    Ok((strip_spans(expr), t.map(|t| format!("{:?}", t))))
+}
+
+/// Output for a single line of input, or 'None' to exit
+fn respond(line: &str) -> Option<String> {
+    let line = line.trim();
+    let (command, input) = match line.strip_prefix(':') {
+        Some(command) => command.split_once(' ').unwrap_or((command, "")),
+        None => ("", line),
+    };
+
+    let output = match command {
+        "quit" => return None,
+        "type" => check(input).map(|(_, t)| t.unwrap_or_else(|| "unknown".to_string())),
+        "trace" => check(input).map(|(expr, _)| {
+            trace(expr)
+                .map(|step| pretty(&step))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }),
+        "dot" => check(input).map(|(expr, _)| to_dot(&expr).trim_end().to_string()),
+        "" if input.is_empty() => Ok(String::new()),
+        "" => check(input).and_then(|(expr, t)| {
+            let value =
+                eval_arena(&optimize(&expr)).map_err(|e| format!("runtime error: {:?}", e.kind))?;
+            Ok(match t {
+                Some(t) => format!("{} : {}", show(&value), t),
+                None => show(&value),
+            })
+        }),
+        other => Err(format!("unknown command ':{}'", other)),
+    };
+    Some(output.unwrap_or_else(|e| e))
+}
+
+fn main() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            return Ok(());
+        };
+        match respond(&line) {
+            Some(output) if output.is_empty() => {}
+            Some(output) => println!("{}", output),
+            None => return Ok(()),
+        }
+    }
+}
+
+// run with 'cargo test --example repl --features expr_example'
+#[cfg(test)]
+mod tests {
+    fn respond(line: &str) -> String {
+        super::respond(line).expect("not a quit command")
+    }
+
+    #[test]
+    fn evaluates() {
+        assert_eq!(
+            respond("let twice = \\f -> \\x -> f (f x) in twice (\\y -> y * 2) 5"),
+            "20"
+        );
+        assert_eq!(respond("1 + 2.5"), "3.5 : Float");
+        assert_eq!(respond("\\x -> x"), "<function of x> : Fn");
+        assert_eq!(respond(""), "");
+        assert_eq!(super::respond(":quit"), None);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(respond("1 +"), "parse error: expected expression at 3..3");
+        assert_eq!(
+            respond("1 + (2 < true)"),
+            "type error: expected Int, found Bool in '2 < true'"
+        );
+        assert_eq!(respond("7 / (3 - 3)"), "runtime error: DivideByZero");
+        assert_eq!(respond(":nope 1"), "unknown command ':nope'");
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(respond(":type 1 < 2"), "Bool");
+        assert_eq!(respond(":type \\x -> x"), "Fn");
+        assert_eq!(respond(":trace (1 + 2) * 3"), "(1 + 2) * 3\n3 * 3\n9");
+        assert!(respond(":dot 1 + x").starts_with("digraph expr {\n  n0 [label=\"+\"];"));
+    }
+}
@@ -0,0 +1,72 @@
+//! The expression language in the browser: parsing, evaluation and pretty printing exposed to
+//! javascript via wasm-bindgen, driven by the page in 'examples/wasm/index.html'. Build with
+//!
+//! cargo build --release --example wasm --target wasm32-unknown-unknown \
+//!     --no-default-features --features expr_example
+//! wasm-bindgen --target web --out-dir examples/wasm/pkg \
+//!     target/wasm32-unknown-unknown/release/examples/wasm.wasm
+//!
+//! and then serve 'examples/wasm', eg with 'python3 -m http.server -d examples/wasm', and open it.
+//! Each function returns an error message, thrown as an exception in javascript, for input that
+//! doesn't parse or evaluate.
+
+use wasm_bindgen::prelude::*;
+
+use recursion::examples::expr::lang::eval::eval_arena;
+use recursion::examples::expr::lang::optimize::optimize;
+use recursion::examples::expr::lang::parse::parse;
+use recursion::examples::expr::lang::pretty;
+use recursion::examples::expr::lang::{RecursiveExpr, Value};
+
+fn parsed(source: &str) -> Result<RecursiveExpr, String> {
+    parse(source).map_err(|e| format!("parse error: {}", e))
+}
+
+/// The parse tree of 'source', as an outline of its layers
+#[wasm_bindgen]
+pub fn parse_tree(source: &str) -> Result<String, String> {
+    Ok(format!("{:?}", parsed(source)?.debug_truncated(500, 50)))
+}
+
+/// The value of 'source', after optimizing it
+#[wasm_bindgen]
+pub fn evaluate(source: &str) -> Result<String, String> {
+    let value = eval_arena(&optimize(&parsed(source)?))
+        .map_err(|e| format!("runtime error: {:?}", e.kind))?;
+    Ok(match value {
+        Value::Int(x) => x.to_string(),
+        Value::Float(x) => format!("{:?}", x),
+        Value::Bool(x) => x.to_string(),
+        Value::Closure { param, .. } => format!("<function of {}>", param),
+    })
+}
+
+/// 'source' pretty printed, with minimal parentheses
+#[wasm_bindgen]
+pub fn pretty(source: &str) -> Result<String, String> {
+    Ok(pretty::pretty(&parsed(source)?))
+}
+
+// run with 'cargo test --example wasm --features expr_example'
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports() {
+        assert_eq!(evaluate("let x = 2 in x * (3 + 4)"), Ok("14".to_string()));
+        assert_eq!(pretty("((1 + 2)) * 3"), Ok("(1 + 2) * 3".to_string()));
+        assert_eq!(
+            parse_tree("1 + 2"),
+            Ok("Add(_, _)\n├── LiteralInt(1)\n└── LiteralInt(2)".to_string())
+        );
+        assert_eq!(
+            evaluate("1 +"),
+            Err("parse error: expected expression at 3..3".to_string())
+        );
+        assert_eq!(
+            evaluate("7 / (3 - 3)"),
+            Err("runtime error: DivideByZero".to_string())
+        );
+    }
+}
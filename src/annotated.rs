@@ -0,0 +1,248 @@
+//! Trees where every node carries an annotation alongside its layer: the arena-backed counterpart
+//! to the cofree comonad. An annotated tree is what a scan gives, with each node annotated with
+//! the result of collapsing the subtree below it, and what a history-based collapse works over,
+//! with each layer able to look at the annotations of any of its descendants, not just those of
+//! its children.
+//!
+//! As with 'Spanned', only the owned 'MapLayer' impl is generic, and by-reference impls should be
+//! written for each concrete layer type.
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree, Sidecar};
+
+/// A layer along with its annotation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotated<A, L> {
+    pub annotation: A,
+    pub layer: L,
+}
+
+impl<A, L> Annotated<A, L> {
+    pub fn new(annotation: A, layer: L) -> Self {
+        Self { annotation, layer }
+    }
+}
+
+impl<B, A, L: MapLayer<B>> MapLayer<B> for Annotated<A, L> {
+    type To = Annotated<A, L::To>;
+    type Unwrapped = L::Unwrapped;
+
+    #[inline(always)]
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, f: F) -> Self::To {
+        Annotated {
+            annotation: self.annotation,
+            layer: self.layer.map_layer(f),
+        }
+    }
+}
+
+/// The part of a tree below the layer being collapsed, as seen during a history-based collapse:
+/// every descendant's layer, along with the annotation already computed for it
+pub struct History<'a, A, U> {
+    elems: &'a [U],
+    annotations: &'a [Option<A>],
+}
+
+impl<'a, A, U> History<'a, A, U> {
+    /// The annotation of the node at 'idx', which must be below the layer being collapsed
+    pub fn annotation(&self, idx: ArenaIndex) -> &'a A {
+        self.annotations[idx.0]
+            .as_ref()
+            .expect("only descendants are annotated")
+    }
+
+    /// The layer of the node at 'idx', eg to look at the annotations of its own children
+    pub fn layer(&self, idx: ArenaIndex) -> &'a U {
+        &self.elems[idx.0]
+    }
+}
+
+impl<U> RecursiveTree<U, ArenaIndex> {
+    /// Annotate each layer with its value in 'annotations', which must have been made for this tree
+    pub fn annotate<A>(
+        self,
+        annotations: Sidecar<A>,
+    ) -> RecursiveTree<Annotated<A, U>, ArenaIndex> {
+        assert_eq!(
+            annotations.values.len(),
+            self.elems.len(),
+            "annotations must be made for the tree they annotate"
+        );
+        RecursiveTree {
+            elems: annotations
+                .values
+                .into_iter()
+                .zip(self.elems)
+                .map(|(annotation, layer)| Annotated::new(annotation, layer))
+                .collect(),
+            _underlying: std::marker::PhantomData,
+        }
+    }
+
+    /// Collapse the tree bottom-up, with each layer given the history of the collapse below it,
+    /// ie the result for every one of its descendants rather than only for its children, and
+    /// giving the tree annotated with the result for each node
+    pub fn collapse_layers_with_history<A>(
+        self,
+        mut collapse_layer: impl FnMut(&U, &History<'_, A, U>) -> A,
+    ) -> RecursiveTree<Annotated<A, U>, ArenaIndex> {
+        let mut annotations: Vec<Option<A>> = std::iter::repeat_with(|| None)
+            .take(self.elems.len())
+            .collect();
+        // children always have higher indices than their parents, so every descendant of a node
+        // is annotated before it is
+        for (idx, layer) in self.elems.iter().enumerate().rev() {
+            let history = History {
+                elems: &self.elems,
+                annotations: &annotations,
+            };
+            let annotation = collapse_layer(layer, &history);
+            annotations[idx] = Some(annotation);
+        }
+        self.annotate(Sidecar {
+            values: annotations.into_iter().flatten().collect(),
+        })
+    }
+}
+
+impl<A, U, Index> RecursiveTree<Annotated<A, U>, Index> {
+    /// Drop the annotation from every layer
+    pub fn strip(self) -> RecursiveTree<U, Index> {
+        RecursiveTree {
+            elems: self.elems.into_iter().map(|node| node.layer).collect(),
+            _underlying: std::marker::PhantomData,
+        }
+    }
+
+    /// Transform every annotation, keeping the tree's shape
+    pub fn map_annotations<B>(
+        self,
+        mut f: impl FnMut(A) -> B,
+    ) -> RecursiveTree<Annotated<B, U>, Index> {
+        RecursiveTree {
+            elems: self
+                .elems
+                .into_iter()
+                .map(|node| Annotated::new(f(node.annotation), node.layer))
+                .collect(),
+            _underlying: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<A, U> RecursiveTree<Annotated<A, U>, ArenaIndex> {
+    /// The annotation of the node at 'idx'
+    pub fn annotation(&self, idx: ArenaIndex) -> &A {
+        &self.elems[idx.0].annotation
+    }
+
+    /// Split the tree into its plain layers and a sidecar of their annotations
+    pub fn unzip(self) -> (RecursiveTree<U, ArenaIndex>, Sidecar<A>) {
+        let (values, elems) = self
+            .elems
+            .into_iter()
+            .map(|node| (node.annotation, node.layer))
+            .unzip();
+        (
+            RecursiveTree {
+                elems,
+                _underlying: std::marker::PhantomData,
+            },
+            Sidecar { values },
+        )
+    }
+
+    /// Replace every annotation with the result of a history-based collapse, as in
+    /// 'collapse_layers_with_history', with each layer also given its previous annotation
+    pub fn reannotate<B>(
+        self,
+        mut collapse_layer: impl FnMut(A, &U, &History<'_, B, U>) -> B,
+    ) -> RecursiveTree<Annotated<B, U>, ArenaIndex> {
+        let (tree, previous) = self.unzip();
+        let mut previous = previous.values.into_iter().map(Some).collect::<Vec<_>>();
+        let mut idx = previous.len();
+        // layers are collapsed in reverse arena order
+        tree.collapse_layers_with_history(|layer, history| {
+            idx -= 1;
+            let annotation = previous[idx].take().expect("each layer is collapsed once");
+            collapse_layer(annotation, layer, history)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{eval::eval_layer, Expr};
+    use crate::recursive::Collapse;
+    use crate::recursive_tree::fixtures::tree;
+
+    #[test]
+    fn scan_and_strip() {
+        let tree = tree();
+        // each node annotated with the value of its subexpression
+        let values = Sidecar::collapse_layers(&tree, |layer: Expr<i64>| eval_layer(layer));
+        let annotated = tree.clone().annotate(values.clone());
+        assert_eq!(*annotated.annotation(annotated.root()), 2);
+        assert_eq!(*annotated.annotation(ArenaIndex(4)), -1);
+
+        // annotations are carried along by collapse, which sees them on every layer
+        let largest =
+            annotated
+                .clone()
+                .collapse_layers(|node: Annotated<i64, Expr<i64>>| match node.layer {
+                    Expr::LiteralInt(_) => node.annotation,
+                    Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) => {
+                        node.annotation.max(a).max(b)
+                    }
+                });
+        assert_eq!(largest, 5);
+
+        let doubled = annotated.clone().map_annotations(|x| x * 2);
+        assert_eq!(*doubled.annotation(ArenaIndex(1)), -6);
+
+        let (layers, unzipped) = annotated.clone().unzip();
+        assert_eq!(layers.elems, tree.elems);
+        assert_eq!(unzipped, values);
+        assert_eq!(annotated.strip().collapse_layers(eval_layer), 2);
+    }
+
+    #[test]
+    fn history() {
+        // the number of literals within two levels of each node, which needs grandchildren
+        let within_two = |layer: &Expr<ArenaIndex>,
+                          history: &History<'_, usize, Expr<ArenaIndex>>| {
+            let literals = |idx| match history.layer(idx) {
+                Expr::LiteralInt(_) => 1,
+                Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) => [a, b]
+                    .into_iter()
+                    .filter(|child| matches!(history.layer(**child), Expr::LiteralInt(_)))
+                    .count(),
+            };
+            match layer {
+                Expr::LiteralInt(_) => 0,
+                Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) => literals(*a) + literals(*b),
+            }
+        };
+        let annotated = tree().collapse_layers_with_history(within_two);
+        let counts: Vec<usize> = (0..9)
+            .map(|idx| *annotated.annotation(ArenaIndex(idx)))
+            .collect();
+        assert_eq!(counts, [1, 4, 0, 2, 2, 0, 0, 0, 0]);
+
+        // the sum of the children's annotations, seen through the history, along with the old one
+        let reannotated = annotated.reannotate(|old, layer, history: &History<'_, usize, _>| {
+            let below: usize = match layer {
+                Expr::LiteralInt(_) => 0,
+                Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) => {
+                    history.annotation(*a) + history.annotation(*b)
+                }
+            };
+            old + below
+        });
+        let counts: Vec<usize> = (0..9)
+            .map(|idx| *reannotated.annotation(ArenaIndex(idx)))
+            .collect();
+        assert_eq!(counts, [9, 8, 0, 2, 2, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,179 @@
+//! Language-neutral encoding of arena-backed trees, for exchanging trees with non-Rust tooling.
+//!
+//! Layers are converted to and from a generic 'WireNode' (a kind tag, opaque payload bytes, and a list
+//! of labeled child edges) via 'WireLayer', and whole trees are encoded as a flat list of wire nodes in
+//! the same topological order as the arena, with child edges referring to positions in that list.
+//! 'TreeCodec' implementations decide how that list is laid out on the wire.
+
+#[cfg(any(test, feature = "protobuf"))]
+pub mod protobuf;
+
+use std::fmt;
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// A single layer in language-neutral form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireNode<A> {
+    /// identifies the layer variant, eg "add" or "literal"
+    pub kind: String,
+    /// layer-specific data that isn't a child, eg a literal value
+    pub payload: Vec<u8>,
+    pub children: Vec<WireEdge<A>>,
+}
+
+/// A labeled edge to a child node, eg "lhs" or a file name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireEdge<A> {
+    pub label: String,
+    pub child: A,
+}
+
+/// Conversion between some layer type and 'WireNode'. Children must appear in 'to_wire' output
+/// in the same order they are visited by 'map_layer', and a layer returned by 'from_wire' must have
+/// exactly the children of the node it was given, in order, or decoding fails.
+pub trait WireLayer<A>: Sized {
+    fn to_wire(&self) -> WireNode<A>;
+    fn from_wire(node: WireNode<A>) -> Result<Self, CodecError>;
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    /// bytes could not be parsed by the codec
+    Decode(String),
+    /// a wire node could not be converted back into a layer
+    InvalidLayer(String),
+    /// child edges don't describe a tree in topological order
+    InvalidTree(String),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Decode(msg) => write!(f, "decode error: {}", msg),
+            CodecError::InvalidLayer(msg) => write!(f, "invalid layer: {}", msg),
+            CodecError::InvalidTree(msg) => write!(f, "invalid tree: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// A wire format for whole trees
+pub trait TreeCodec {
+    fn encode_nodes(&self, nodes: Vec<WireNode<u64>>) -> Vec<u8>;
+    fn decode_nodes(&self, bytes: &[u8]) -> Result<Vec<WireNode<u64>>, CodecError>;
+
+    fn encode<L: WireLayer<ArenaIndex>>(&self, tree: &RecursiveTree<L, ArenaIndex>) -> Vec<u8> {
+        let nodes = tree
+            .elems
+            .iter()
+            .map(|layer| {
+                let node = layer.to_wire();
+                WireNode {
+                    kind: node.kind,
+                    payload: node.payload,
+                    children: node
+                        .children
+                        .into_iter()
+                        .map(|edge| WireEdge {
+                            label: edge.label,
+                            child: edge.child.0 as u64,
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        self.encode_nodes(nodes)
+    }
+
+    fn decode<L: WireLayer<ArenaIndex>>(
+        &self,
+        bytes: &[u8],
+    ) -> Result<RecursiveTree<L, ArenaIndex>, CodecError>
+    where
+        for<'a> &'a L: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        let nodes = self.decode_nodes(bytes)?;
+        check_topo_order(&nodes)?;
+
+        let elems = nodes
+            .into_iter()
+            .enumerate()
+            .map(|(idx, node)| {
+                let expected: Vec<_> = node
+                    .children
+                    .iter()
+                    .map(|edge| ArenaIndex(edge.child as usize))
+                    .collect();
+                let layer = L::from_wire(WireNode {
+                    kind: node.kind,
+                    payload: node.payload,
+                    children: node
+                        .children
+                        .into_iter()
+                        .map(|edge| WireEdge {
+                            label: edge.label,
+                            // checked to be in range above
+                            child: ArenaIndex(edge.child as usize),
+                        })
+                        .collect(),
+                })?;
+                // 'from_wire' could drop, duplicate or reorder children, which collapse relies on
+                // being exactly those checked above
+                let mut children = Vec::with_capacity(expected.len());
+                layer.map_layer(|child| children.push(child));
+                if children != expected {
+                    return Err(CodecError::InvalidTree(format!(
+                        "node {} was decoded with children {:?} rather than {:?}",
+                        idx, children, expected
+                    )));
+                }
+                Ok(layer)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(RecursiveTree {
+            elems,
+            _underlying: std::marker::PhantomData,
+        })
+    }
+}
+
+// decoded bytes are untrusted, and collapse relies on every node being referenced exactly once by a preceding node
+fn check_topo_order(nodes: &[WireNode<u64>]) -> Result<(), CodecError> {
+    if nodes.is_empty() {
+        return Err(CodecError::InvalidTree("no nodes".to_string()));
+    }
+
+    let mut referenced = vec![false; nodes.len()];
+    for (idx, node) in nodes.iter().enumerate() {
+        for edge in node.children.iter() {
+            // an index too big for this platform is out of range, rather than truncated
+            let child = usize::try_from(edge.child).unwrap_or(usize::MAX);
+            if child <= idx || child >= nodes.len() {
+                return Err(CodecError::InvalidTree(format!(
+                    "node {} has out of order child {}",
+                    idx, child
+                )));
+            }
+            if referenced[child] {
+                return Err(CodecError::InvalidTree(format!(
+                    "node {} has multiple parents",
+                    child
+                )));
+            }
+            referenced[child] = true;
+        }
+    }
+
+    match referenced.iter().skip(1).position(|r| !r) {
+        Some(idx) => Err(CodecError::InvalidTree(format!(
+            "node {} is unreachable",
+            idx + 1
+        ))),
+        None => Ok(()),
+    }
+}
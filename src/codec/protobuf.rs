@@ -0,0 +1,205 @@
+//! Protobuf implementation of 'TreeCodec'. The schema lives in 'proto/tree.proto', for generating
+//! bindings in other languages.
+
+use prost::Message;
+
+use crate::codec::{CodecError, TreeCodec, WireEdge, WireNode};
+
+#[derive(Clone, PartialEq, Message)]
+struct Tree {
+    #[prost(message, repeated, tag = "1")]
+    nodes: Vec<Node>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Node {
+    #[prost(string, tag = "1")]
+    kind: String,
+    #[prost(bytes = "vec", tag = "2")]
+    payload: Vec<u8>,
+    #[prost(message, repeated, tag = "3")]
+    children: Vec<Edge>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Edge {
+    #[prost(string, tag = "1")]
+    label: String,
+    #[prost(uint64, tag = "2")]
+    node: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProtobufCodec;
+
+impl TreeCodec for ProtobufCodec {
+    fn encode_nodes(&self, nodes: Vec<WireNode<u64>>) -> Vec<u8> {
+        let tree = Tree {
+            nodes: nodes
+                .into_iter()
+                .map(|node| Node {
+                    kind: node.kind,
+                    payload: node.payload,
+                    children: node
+                        .children
+                        .into_iter()
+                        .map(|edge| Edge {
+                            label: edge.label,
+                            node: edge.child,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+
+        tree.encode_to_vec()
+    }
+
+    fn decode_nodes(&self, bytes: &[u8]) -> Result<Vec<WireNode<u64>>, CodecError> {
+        let tree = Tree::decode(bytes).map_err(|e| CodecError::Decode(e.to_string()))?;
+
+        Ok(tree
+            .nodes
+            .into_iter()
+            .map(|node| WireNode {
+                kind: node.kind,
+                payload: node.payload,
+                children: node
+                    .children
+                    .into_iter()
+                    .map(|edge| WireEdge {
+                        label: edge.label,
+                        child: edge.node,
+                    })
+                    .collect(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::WireLayer;
+    use crate::examples::expr::{eval::eval_layer, BlocAllocExpr, Expr};
+    use crate::recursive::{Collapse, Expand};
+
+    impl<A: Clone> WireLayer<A> for Expr<A> {
+        fn to_wire(&self) -> WireNode<A> {
+            let (kind, payload, children) = match self {
+                Expr::Add(a, b) => ("add", vec![], vec![a, b]),
+                Expr::Sub(a, b) => ("sub", vec![], vec![a, b]),
+                Expr::Mul(a, b) => ("mul", vec![], vec![a, b]),
+                Expr::LiteralInt(x) => ("literal", x.to_le_bytes().to_vec(), vec![]),
+            };
+            WireNode {
+                kind: kind.to_string(),
+                payload,
+                children: children
+                    .into_iter()
+                    .zip(["lhs", "rhs"])
+                    .map(|(child, label)| WireEdge {
+                        label: label.to_string(),
+                        child: child.clone(),
+                    })
+                    .collect(),
+            }
+        }
+
+        fn from_wire(node: WireNode<A>) -> Result<Self, CodecError> {
+            let mut children = node.children.into_iter().map(|edge| edge.child);
+            let layer = match (node.kind.as_str(), children.next(), children.next()) {
+                ("add", Some(a), Some(b)) => Expr::Add(a, b),
+                ("sub", Some(a), Some(b)) => Expr::Sub(a, b),
+                ("mul", Some(a), Some(b)) => Expr::Mul(a, b),
+                ("literal", None, None) => {
+                    let bytes = node.payload.try_into().map_err(|_| {
+                        CodecError::InvalidLayer("literal payload must be 8 bytes".to_string())
+                    })?;
+                    Expr::LiteralInt(i64::from_le_bytes(bytes))
+                }
+                (kind, _, _) => return Err(CodecError::InvalidLayer(kind.to_string())),
+            };
+            Ok(layer)
+        }
+    }
+
+    fn example() -> BlocAllocExpr {
+        BlocAllocExpr::expand_layers(4, |depth| match depth {
+            0 => Expr::LiteralInt(3),
+            x if x % 2 == 0 => Expr::Mul(depth - 1, depth - 1),
+            _ => Expr::Sub(depth - 1, 0),
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        let tree = example();
+        let bytes = ProtobufCodec.encode(&tree);
+        let decoded: BlocAllocExpr = ProtobufCodec.decode(&bytes).unwrap();
+
+        assert_eq!(
+            tree.as_ref().collapse_layers(eval_layer),
+            decoded.collapse_layers(eval_layer)
+        );
+    }
+
+    #[test]
+    fn edge_labels_on_the_wire() {
+        let nodes = ProtobufCodec
+            .decode_nodes(&ProtobufCodec.encode(&example()))
+            .unwrap();
+        let labels: Vec<_> = nodes[0].children.iter().map(|e| e.label.as_str()).collect();
+        assert_eq!(labels, vec!["lhs", "rhs"]);
+    }
+
+    #[test]
+    fn reject_cycles() {
+        let nodes = vec![WireNode {
+            kind: "add".to_string(),
+            payload: vec![],
+            children: vec![
+                WireEdge {
+                    label: "lhs".to_string(),
+                    child: 0,
+                },
+                WireEdge {
+                    label: "rhs".to_string(),
+                    child: 0,
+                },
+            ],
+        }];
+
+        let res: Result<BlocAllocExpr, _> =
+            ProtobufCodec.decode(&ProtobufCodec.encode_nodes(nodes));
+        assert!(matches!(res, Err(CodecError::InvalidTree(_))));
+    }
+
+    #[test]
+    fn reject_dropped_children() {
+        // a valid tree on the wire, but 'from_wire' ignores an 'add' node's third child
+        let literal = |x: i64| WireNode {
+            kind: "literal".to_string(),
+            payload: x.to_le_bytes().to_vec(),
+            children: vec![],
+        };
+        let edge = |child| WireEdge {
+            label: String::new(),
+            child,
+        };
+        let nodes = vec![
+            WireNode {
+                kind: "add".to_string(),
+                payload: vec![],
+                children: vec![edge(1), edge(2), edge(3)],
+            },
+            literal(1),
+            literal(2),
+            literal(3),
+        ];
+
+        let res: Result<BlocAllocExpr, _> =
+            ProtobufCodec.decode(&ProtobufCodec.encode_nodes(nodes));
+        assert!(matches!(res, Err(CodecError::InvalidTree(_))));
+    }
+}
@@ -0,0 +1,115 @@
+//! Directed acyclic graphs: recursive structures in which structurally identical subtrees are
+//! stored once and shared by every parent that refers to them. Built by hash-consing an existing
+//! tree bottom-up, and collapsed in a single pass that visits each shared node once.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::map_layer::MapLayer;
+use crate::recursive::{Collapse, Expand};
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// A recursive structure with shared subtrees, with layers of type `Wrapped`, which is some
+/// `Layer<ArenaIndex>`.
+///
+/// Unlike 'RecursiveTree', layers are stored children-first, with the root last.
+#[derive(Debug, Clone)]
+pub struct RecursiveDag<Wrapped> {
+    // nonempty, each layer only refers to layers that precede it
+    pub(crate) elems: Vec<Wrapped>,
+}
+
+impl<L> RecursiveDag<L> {
+    /// Hash-cons a tree, such that each distinct subtree is stored exactly once
+    pub fn from_tree<'a>(tree: &'a RecursiveTree<L, ArenaIndex>) -> Self
+    where
+        &'a L: MapLayer<ArenaIndex, Unwrapped = ArenaIndex, To = L>,
+        L: Eq + Hash + Clone,
+    {
+        let mut interned: HashMap<L, ArenaIndex> = HashMap::new();
+        let mut elems = Vec::new();
+        // position of each tree node in the dag
+        let mut remap = vec![ArenaIndex(0); tree.elems.len()];
+
+        // children always have higher indices than their parents, so they're interned first
+        for (idx, layer) in tree.elems.iter().enumerate().rev() {
+            let layer = layer.map_layer(|ArenaIndex(child)| remap[child]);
+            remap[idx] = *interned.entry(layer).or_insert_with_key(|layer| {
+                elems.push(layer.clone());
+                ArenaIndex(elems.len() - 1)
+            });
+        }
+
+        // the root is larger than any of its subtrees, so it can't have been shared
+        Self { elems }
+    }
+
+    /// Number of distinct subtrees
+    pub fn len(&self) -> usize {
+        self.elems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elems.is_empty()
+    }
+
+    /// Expand back out into a tree, duplicating shared subtrees
+    pub fn to_tree(&self) -> RecursiveTree<L, ArenaIndex>
+    where
+        L: MapLayer<ArenaIndex, Unwrapped = ArenaIndex, To = L> + Clone,
+    {
+        RecursiveTree::expand_layers(ArenaIndex(self.elems.len() - 1), |ArenaIndex(idx)| {
+            self.elems[idx].clone()
+        })
+    }
+}
+
+/// Each shared subtree is collapsed once, and its result is cloned for each parent.
+impl<'a, A, U, O> Collapse<A, O> for &'a RecursiveDag<U>
+where
+    A: Clone,
+    &'a U: MapLayer<A, To = O, Unwrapped = ArenaIndex>,
+{
+    fn collapse_layers<F: FnMut(O) -> A>(self, mut collapse_layer: F) -> A {
+        let mut results: Vec<A> = Vec::with_capacity(self.elems.len());
+
+        for layer in self.elems.iter() {
+            let node = layer.map_layer(|ArenaIndex(child)| results[child].clone());
+            results.push(collapse_layer(node));
+        }
+
+        results.pop().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{eval::eval_layer, BlocAllocExpr, Expr};
+
+    #[test]
+    fn shares_identical_subtrees() {
+        // (1 + 2) * (1 + 2)
+        let tree = BlocAllocExpr::expand_layers(0, |depth| match depth {
+            0 => Expr::Mul(1, 1),
+            1 => Expr::Add(2, 3),
+            2 => Expr::LiteralInt(1),
+            _ => Expr::LiteralInt(2),
+        });
+        assert_eq!(tree.elems.len(), 7);
+
+        let dag = RecursiveDag::from_tree(&tree);
+        assert_eq!(dag.len(), 4);
+
+        let mut visited = 0;
+        let result = dag.collapse_layers(|layer| {
+            visited += 1;
+            eval_layer(layer)
+        });
+        assert_eq!(result, 9);
+        assert_eq!(visited, 4);
+
+        assert_eq!(dag.to_tree().elems.len(), 7);
+        assert_eq!(dag.to_tree().as_ref().collapse_layers(eval_layer), 9);
+    }
+}
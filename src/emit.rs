@@ -0,0 +1,209 @@
+//! Streaming serialization of recursive structures.
+//!
+//! Instead of collapsing a tree into one large in-memory value and then serializing it,
+//! each layer is written to a sink as soon as it is collapsed, as a self-contained record.
+//! Records are written in postorder (children before parents) and refer to their children by
+//! the 'RecordId' of previously written records, so memory use is independent of output size.
+
+use std::io;
+
+#[cfg(any(test, feature = "async"))]
+use futures::io::{AsyncWrite, AsyncWriteExt};
+
+#[cfg(any(test, feature = "async"))]
+use crate::map_layer::MapLayer;
+use crate::recursive::Collapse;
+#[cfg(any(test, feature = "async"))]
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTreeRef};
+
+/// Position of a record in the output stream, starting at 0 for the first record written.
+/// The root is always the last record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    any(test, feature = "json", feature = "cbor"),
+    derive(serde::Serialize, serde::Deserialize)
+)]
+#[cfg_attr(any(test, feature = "json", feature = "cbor"), serde(transparent))]
+pub struct RecordId(pub usize);
+
+/// Collapse some structure by writing each layer to 'writer' via 'emit', which receives the id of the
+/// record being written and a layer in which each child has been replaced by the id of its record.
+/// Returns the id of the root record. Stops writing after the first error, which is returned.
+pub fn emit_layers<Tree, Wrapped, W, F>(
+    tree: Tree,
+    writer: &mut W,
+    mut emit: F,
+) -> io::Result<RecordId>
+where
+    Tree: Collapse<RecordId, Wrapped>,
+    F: FnMut(&mut W, RecordId, Wrapped) -> io::Result<()>,
+{
+    let mut next_id = 0;
+    let mut error = None;
+
+    // collapse can't short circuit, so keep assigning ids but skip writes after a failure
+    let root = tree.collapse_layers(|layer| {
+        let id = RecordId(next_id);
+        next_id += 1;
+        if error.is_none() {
+            if let Err(e) = emit(writer, id, layer) {
+                error = Some(e);
+            }
+        }
+        id
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(root),
+    }
+}
+
+/// Async version of 'emit_layers' for borrowed arena-backed trees. Each record is serialized into a
+/// reusable buffer by 'emit' and then written to the async sink before moving on to the next layer.
+#[cfg(any(test, feature = "async"))]
+pub async fn emit_layers_async<'a, U, O, W, F>(
+    tree: RecursiveTreeRef<'a, U, ArenaIndex>,
+    writer: &mut W,
+    mut emit: F,
+) -> io::Result<RecordId>
+where
+    &'a U: MapLayer<RecordId, To = O, Unwrapped = ArenaIndex>,
+    W: AsyncWrite + Unpin,
+    F: FnMut(&mut Vec<u8>, RecordId, O) -> io::Result<()>,
+{
+    let len = tree.elems.len();
+    let mut buf = Vec::new();
+
+    // visiting nodes in reverse arena order writes all children before their parents,
+    // so the node at idx 'i' is always the record with id 'len - 1 - i'
+    for (idx, layer) in tree.elems.iter().enumerate().rev() {
+        let layer = layer.map_layer(|ArenaIndex(child)| RecordId(len - 1 - child));
+        buf.clear();
+        emit(&mut buf, RecordId(len - 1 - idx), layer)?;
+        writer.write_all(&buf).await?;
+    }
+    writer.flush().await?;
+
+    Ok(RecordId(len - 1))
+}
+
+#[cfg(any(test, feature = "json", feature = "cbor"))]
+#[derive(serde::Serialize)]
+struct Record<L> {
+    id: RecordId,
+    layer: L,
+}
+
+/// Write a single record as a line of json, for use with 'emit_layers'
+#[cfg(any(test, feature = "json"))]
+pub fn ndjson_record<W: io::Write, L: serde::Serialize>(
+    writer: &mut W,
+    id: RecordId,
+    layer: L,
+) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, &Record { id, layer })?;
+    writer.write_all(b"\n")
+}
+
+/// Write a single record as a cbor map, for use with 'emit_layers'
+#[cfg(any(test, feature = "cbor"))]
+pub fn cbor_record<W: io::Write, L: serde::Serialize>(
+    writer: &mut W,
+    id: RecordId,
+    layer: L,
+) -> io::Result<()> {
+    ciborium::into_writer(&Record { id, layer }, writer).map_err(|e| match e {
+        ciborium::ser::Error::Io(e) => e,
+        ciborium::ser::Error::Value(msg) => io::Error::new(io::ErrorKind::InvalidData, msg),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{BlocAllocExpr, Expr};
+    use crate::recursive::Expand;
+    use crate::recursive_tree::fixtures::complete_layer;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct OwnedRecord {
+        id: RecordId,
+        layer: Expr<RecordId>,
+    }
+
+    fn example() -> BlocAllocExpr {
+        BlocAllocExpr::expand_layers(3, complete_layer)
+    }
+
+    // records must be numbered sequentially and only refer back to records already written
+    fn check_postorder(records: &[OwnedRecord]) {
+        for (idx, record) in records.iter().enumerate() {
+            assert_eq!(record.id, RecordId(idx));
+            record.layer.map_layer(|child| assert!(child < record.id));
+        }
+    }
+
+    #[test]
+    fn ndjson() {
+        let mut out = Vec::new();
+        let root = emit_layers(example().as_ref(), &mut out, ndjson_record).unwrap();
+
+        let records: Vec<OwnedRecord> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(records.len(), 15);
+        assert_eq!(root, RecordId(14));
+        check_postorder(&records);
+    }
+
+    #[test]
+    fn cbor() {
+        let mut out = Vec::new();
+        emit_layers(example(), &mut out, cbor_record).unwrap();
+
+        let mut reader = &out[..];
+        let mut records = Vec::new();
+        while !reader.is_empty() {
+            records.push(ciborium::from_reader::<OwnedRecord, _>(&mut reader).unwrap());
+        }
+
+        assert_eq!(records.len(), 15);
+        check_postorder(&records);
+    }
+
+    #[test]
+    fn async_matches_sync() {
+        let tree = example();
+
+        let mut sync_out = Vec::new();
+        emit_layers(tree.as_ref(), &mut sync_out, ndjson_record).unwrap();
+
+        let mut async_out = Vec::new();
+        let root = futures::executor::block_on(emit_layers_async(
+            tree.as_ref(),
+            &mut async_out,
+            ndjson_record,
+        ))
+        .unwrap();
+
+        assert_eq!(root, RecordId(14));
+        assert_eq!(sync_out, async_out);
+    }
+
+    #[test]
+    fn stops_after_error() {
+        let mut writes = 0;
+        let res = emit_layers(example(), &mut Vec::<u8>::new(), |_w, _id, _layer| {
+            writes += 1;
+            Err(io::Error::other("sink closed"))
+        });
+
+        assert!(res.is_err());
+        assert_eq!(writes, 1);
+    }
+}
@@ -0,0 +1,159 @@
+//! AVL trees: binary search trees in which the heights of each node's subtrees differ by at most
+//! one, so that lookups take logarithmic time. Insertion and deletion are those of 'bst', followed
+//! by a collapse that rebalances the tree, rotating each layer whose subtrees' heights differ by
+//! two. Rotations are local rewrites of a single layer whose children have already been balanced,
+//! so the tree is balanced from the bottom up, as a recursive implementation would do on the way
+//! back up from the changed node.
+
+use std::rc::Rc;
+
+use crate::examples::bst::{self, BstLayer, RecursiveBst};
+use crate::recursive::{Collapse, Expand};
+
+/// A tree with shared subtrees, each with its height, as built by rebalancing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Avl {
+    Empty,
+    Node {
+        left: Rc<Avl>,
+        value: i64,
+        right: Rc<Avl>,
+        height: usize,
+    },
+}
+
+fn height(tree: &Avl) -> usize {
+    match tree {
+        Avl::Empty => 0,
+        Avl::Node { height, .. } => *height,
+    }
+}
+
+fn node(left: Rc<Avl>, value: i64, right: Rc<Avl>) -> Rc<Avl> {
+    let height = 1 + height(&left).max(height(&right));
+    Rc::new(Avl::Node {
+        left,
+        value,
+        right,
+        height,
+    })
+}
+
+// the children of a subtree taller than its sibling, which can't be empty
+fn children(tree: &Avl) -> (Rc<Avl>, i64, Rc<Avl>) {
+    match tree {
+        Avl::Node {
+            left, value, right, ..
+        } => (left.clone(), *value, right.clone()),
+        Avl::Empty => unreachable!("a subtree taller than its sibling isn't empty"),
+    }
+}
+
+/// A single layer, rotated if the heights of its subtrees differ by two. If the taller subtree's
+/// inner child is the taller of its two, it's rotated up to the top (a double rotation), and
+/// otherwise the taller subtree is.
+pub fn balance_layer(layer: BstLayer<Rc<Avl>>) -> Rc<Avl> {
+    let (l, x, r) = match layer {
+        BstLayer::Empty => return Rc::new(Avl::Empty),
+        BstLayer::Node(l, x, r) => (l, x, r),
+    };
+    if height(&l) > height(&r) + 1 {
+        let (a, y, b) = children(&l);
+        if height(&b) > height(&a) {
+            let (b1, z, b2) = children(&b);
+            node(node(a, y, b1), z, node(b2, x, r))
+        } else {
+            node(a, y, node(b, x, r))
+        }
+    } else if height(&r) > height(&l) + 1 {
+        let (a, y, b) = children(&r);
+        if height(&a) > height(&b) {
+            let (a1, z, a2) = children(&a);
+            node(node(l, x, a1), z, node(a2, y, b))
+        } else {
+            node(node(l, x, a), y, b)
+        }
+    } else {
+        node(l, x, r)
+    }
+}
+
+/// Restore the balance of a tree that was balanced before a single insertion or deletion
+pub fn rebalance(tree: &RecursiveBst) -> RecursiveBst {
+    let balanced = tree.as_ref().collapse_layers(balance_layer);
+    RecursiveBst::expand_layers(balanced, |tree| match &*tree {
+        Avl::Empty => BstLayer::Empty,
+        Avl::Node {
+            left, value, right, ..
+        } => BstLayer::Node(left.clone(), *value, right.clone()),
+    })
+}
+
+pub fn insert(tree: &RecursiveBst, value: i64) -> RecursiveBst {
+    rebalance(&bst::insert(tree, value))
+}
+
+pub fn delete(tree: &RecursiveBst, value: i64) -> RecursiveBst {
+    rebalance(&bst::delete(tree, value))
+}
+
+pub fn from_values(values: impl IntoIterator<Item = i64>) -> RecursiveBst {
+    values
+        .into_iter()
+        .fold(bst::empty(), |tree, value| insert(&tree, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::bst::to_vec;
+    use proptest::prelude::*;
+    use std::collections::BTreeSet;
+
+    // the height of the tree, if the heights of every node's subtrees differ by at most one
+    fn balanced_height(tree: &RecursiveBst) -> Option<usize> {
+        tree.as_ref()
+            .collapse_layers(|layer: BstLayer<Option<usize>>| match layer {
+                BstLayer::Empty => Some(0),
+                BstLayer::Node(l, _, r) => {
+                    let (l, r) = (l?, r?);
+                    (l.abs_diff(r) <= 1).then_some(1 + l.max(r))
+                }
+            })
+    }
+
+    #[test]
+    fn rotations() {
+        // ascending values would make a list of a plain search tree
+        let tree = from_values(1..=127);
+        assert_eq!(balanced_height(&tree), Some(7));
+        assert_eq!(to_vec(&tree), (1..=127).collect::<Vec<_>>());
+        assert!(matches!(tree.layer(tree.root()), BstLayer::Node(_, 64, _)));
+
+        // each double rotation
+        for values in [[3, 1, 2], [1, 3, 2]] {
+            let tree = from_values(values);
+            assert!(matches!(tree.layer(tree.root()), BstLayer::Node(_, 2, _)));
+            assert_eq!(balanced_height(&tree), Some(2));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn invariants(
+            values in prop::collection::vec(-50i64..50, 0..60),
+            deleted in prop::collection::vec(-50i64..50, 0..40),
+        ) {
+            let mut tree = from_values(values.iter().copied());
+            let mut expected: BTreeSet<i64> = values.into_iter().collect();
+            prop_assert!(balanced_height(&tree).is_some());
+
+            for value in deleted {
+                tree = delete(&tree, value);
+                expected.remove(&value);
+                prop_assert!(balanced_height(&tree).is_some());
+            }
+            prop_assert_eq!(to_vec(&tree), expected.into_iter().collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! A binary search tree of integers. Insertion is an apomorphism: only the search path for the new
+//! value is expanded, and every subtree off that path is kept as it is. Deletion is a paramorphism:
+//! removing a node with two children replaces it with the smallest value of its right subtree, so
+//! each step needs its children's original subtrees as well as the results of deleting from them.
+
+use std::borrow::Cow;
+use std::rc::Rc;
+
+use crate::map_layer::{EdgeLabeled, LayerArity, MapLayer};
+use crate::recursive::{collapse_layers_with_subtrees, Collapse, Expand};
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree, Sharing};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BstLayer<A> {
+    Empty,
+    Node(A, i64, A),
+}
+
+impl<A, B> MapLayer<B> for BstLayer<A> {
+    type To = BstLayer<B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            BstLayer::Empty => BstLayer::Empty,
+            BstLayer::Node(l, x, r) => {
+                let l = f(l);
+                BstLayer::Node(l, x, f(r))
+            }
+        }
+    }
+}
+
+impl<A> LayerArity for BstLayer<A> {
+    type Child = A;
+
+    fn child_count(&self) -> usize {
+        match self {
+            BstLayer::Empty => 0,
+            BstLayer::Node(..) => 2,
+        }
+    }
+
+    fn child(&self, position: usize) -> Option<&A> {
+        match (self, position) {
+            (BstLayer::Node(l, _, _), 0) => Some(l),
+            (BstLayer::Node(_, _, r), 1) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+impl<A> EdgeLabeled for BstLayer<A> {
+    fn edge_label(&self, position: usize) -> Option<Cow<'_, str>> {
+        match (self.child(position), position) {
+            (Some(_), 0) => Some("left".into()),
+            (Some(_), _) => Some("right".into()),
+            (None, _) => None,
+        }
+    }
+}
+
+impl<A: Copy, B> MapLayer<B> for &BstLayer<A> {
+    type To = BstLayer<B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            BstLayer::Empty => BstLayer::Empty,
+            BstLayer::Node(l, x, r) => {
+                let l = f(*l);
+                BstLayer::Node(l, *x, f(*r))
+            }
+        }
+    }
+}
+
+pub type RecursiveBst = RecursiveTree<BstLayer<ArenaIndex>, ArenaIndex>;
+
+/// A tree with shared subtrees, as rebuilt by deletion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bst {
+    Empty,
+    Node(Rc<Bst>, i64, Rc<Bst>),
+}
+
+fn embed(layer: BstLayer<Rc<Bst>>) -> Rc<Bst> {
+    Rc::new(match layer {
+        BstLayer::Empty => Bst::Empty,
+        BstLayer::Node(l, x, r) => Bst::Node(l, x, r),
+    })
+}
+
+fn from_shared(tree: Rc<Bst>) -> RecursiveBst {
+    RecursiveBst::expand_layers(tree, |tree| match &*tree {
+        Bst::Empty => BstLayer::Empty,
+        Bst::Node(l, x, r) => BstLayer::Node(l.clone(), *x, r.clone()),
+    })
+}
+
+pub fn empty() -> RecursiveBst {
+    RecursiveBst::expand_layers((), |()| BstLayer::Empty)
+}
+
+pub fn from_values(values: impl IntoIterator<Item = i64>) -> RecursiveBst {
+    values
+        .into_iter()
+        .fold(empty(), |tree, value| insert(&tree, value))
+}
+
+/// The tree with 'value' added, if it isn't there already. Each layer on the search path is
+/// expanded from its index in 'tree', or from nothing for the new node's empty children.
+pub fn insert(tree: &RecursiveBst, value: i64) -> RecursiveBst {
+    tree.expand_layers_sharing(Some(tree.root()), |idx| {
+        match idx.map(|idx| tree.layer(idx)) {
+            None => BstLayer::Empty,
+            Some(BstLayer::Empty) => {
+                BstLayer::Node(Sharing::Expand(None), value, Sharing::Expand(None))
+            }
+            Some(BstLayer::Node(l, x, r)) if value < *x => {
+                BstLayer::Node(Sharing::Expand(Some(*l)), *x, Sharing::Keep(*r))
+            }
+            Some(BstLayer::Node(l, x, r)) if value > *x => {
+                BstLayer::Node(Sharing::Keep(*l), *x, Sharing::Expand(Some(*r)))
+            }
+            Some(BstLayer::Node(l, x, r)) => {
+                BstLayer::Node(Sharing::Keep(*l), *x, Sharing::Keep(*r))
+            }
+        }
+    })
+}
+
+/// The tree with 'value' removed, if it's there
+pub fn delete(tree: &RecursiveBst, value: i64) -> RecursiveBst {
+    // each subtree with 'value' deleted, and with its smallest value popped off, if it has one
+    type Deleted = (Rc<Bst>, Option<(i64, Rc<Bst>)>);
+    let (deleted, _) = collapse_layers_with_subtrees(
+        tree.as_ref(),
+        embed,
+        |layer: BstLayer<(Rc<Bst>, Deleted)>| match layer {
+            BstLayer::Empty => (Rc::new(Bst::Empty), None),
+            BstLayer::Node((l, (l_deleted, l_popped)), x, (r, (r_deleted, r_popped))) => {
+                let popped = match l_popped {
+                    None => (x, r.clone()),
+                    Some((min, l)) => (min, embed(BstLayer::Node(l, x, r.clone()))),
+                };
+                let deleted = if value < x {
+                    embed(BstLayer::Node(l_deleted, x, r))
+                } else if value > x {
+                    embed(BstLayer::Node(l, x, r_deleted))
+                } else {
+                    match (&*l, r_popped) {
+                        (_, None) => l,
+                        (Bst::Empty, Some(_)) => r,
+                        (_, Some((min, r))) => embed(BstLayer::Node(l, min, r)),
+                    }
+                };
+                (deleted, Some(popped))
+            }
+        },
+    );
+    from_shared(deleted)
+}
+
+pub fn contains(tree: &RecursiveBst, value: i64) -> bool {
+    let mut idx = tree.root();
+    loop {
+        match tree.layer(idx) {
+            BstLayer::Empty => return false,
+            BstLayer::Node(l, x, _) if value < *x => idx = *l,
+            BstLayer::Node(_, x, r) if value > *x => idx = *r,
+            BstLayer::Node(..) => return true,
+        }
+    }
+}
+
+/// Every value, in order
+pub fn to_vec(tree: &RecursiveBst) -> Vec<i64> {
+    tree.as_ref()
+        .collapse_layers(|layer: BstLayer<Vec<i64>>| match layer {
+            BstLayer::Empty => Vec::new(),
+            BstLayer::Node(mut l, x, r) => {
+                l.push(x);
+                l.extend(r);
+                l
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::BTreeSet;
+
+    // whether every node's value is between those of its left and right subtrees, along with the
+    // smallest and largest values of each subtree
+    fn is_ordered(tree: &RecursiveBst) -> bool {
+        tree.as_ref()
+            .collapse_layers(|layer: BstLayer<Option<Option<(i64, i64)>>>| match layer {
+                BstLayer::Empty => Some(None),
+                BstLayer::Node(l, x, r) => {
+                    let (l, r) = (l?, r?);
+                    let min = match l {
+                        Some((l_min, l_max)) if l_max < x => l_min,
+                        Some(_) => return None,
+                        None => x,
+                    };
+                    let max = match r {
+                        Some((r_min, r_max)) if r_min > x => r_max,
+                        Some(_) => return None,
+                        None => x,
+                    };
+                    Some(Some((min, max)))
+                }
+            })
+            .is_some()
+    }
+
+    #[test]
+    fn insert_and_delete() {
+        let tree = from_values([5, 3, 8, 1, 4, 7, 9, 3]);
+        assert_eq!(to_vec(&tree), vec![1, 3, 4, 5, 7, 8, 9]);
+        assert!(contains(&tree, 4) && !contains(&tree, 6));
+
+        // a leaf, an inner node, and the root, which is replaced by the smallest value to its right
+        assert_eq!(to_vec(&delete(&tree, 1)), vec![3, 4, 5, 7, 8, 9]);
+        assert_eq!(to_vec(&delete(&tree, 3)), vec![1, 4, 5, 7, 8, 9]);
+        let without_root = delete(&tree, 5);
+        assert_eq!(to_vec(&without_root), vec![1, 3, 4, 7, 8, 9]);
+        assert!(matches!(
+            without_root.layer(without_root.root()),
+            BstLayer::Node(_, 7, _)
+        ));
+        assert!(is_ordered(&without_root));
+        assert_eq!(to_vec(&delete(&tree, 6)), to_vec(&tree));
+        assert_eq!(to_vec(&delete(&empty(), 1)), Vec::<i64>::new());
+    }
+
+    proptest! {
+        #[test]
+        fn invariants(
+            values in prop::collection::vec(-50i64..50, 0..40),
+            deleted in prop::collection::vec(-50i64..50, 0..20),
+        ) {
+            let mut tree = from_values(values.iter().copied());
+            let mut expected: BTreeSet<i64> = values.into_iter().collect();
+            prop_assert!(is_ordered(&tree));
+            prop_assert_eq!(to_vec(&tree), expected.iter().copied().collect::<Vec<_>>());
+
+            for value in deleted {
+                tree = delete(&tree, value);
+                expected.remove(&value);
+                prop_assert!(is_ordered(&tree));
+                prop_assert!(!contains(&tree, value));
+            }
+            prop_assert_eq!(to_vec(&tree), expected.into_iter().collect::<Vec<_>>());
+        }
+    }
+}
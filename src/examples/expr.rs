@@ -1,17 +1,34 @@
+pub mod bytecode;
+pub mod cse;
 pub mod eval;
+pub mod lang;
 #[cfg(test)]
 pub mod monomorphic;
 pub mod naive;
+pub mod numeric;
+pub mod pretty;
 #[cfg(test)]
 pub mod typed_eval;
 
+use std::borrow::Cow;
+use std::hash::Hasher;
+
 use crate::{
-    map_layer::MapLayer,
+    map_layer::{EdgeLabeled, LayerArity, MapLayer},
+    merkle::HashLayer,
     recursive_tree::{arena_eval::ArenaIndex, stack_machine_eval::StackMarker, RecursiveTree},
 };
 
 /// Simple expression language with some operations on integers
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    any(test, feature = "json", feature = "cbor"),
+    derive(serde::Serialize, serde::Deserialize)
+)]
+#[cfg_attr(
+    any(test, feature = "rkyv"),
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)
+)]
 pub enum Expr<A> {
     Add(A, A),
     Sub(A, A),
@@ -35,6 +52,35 @@ impl<A, B> MapLayer<B> for Expr<A> {
 }
 
 // this is, like, basically fine? - just usize and ()
+impl<A> LayerArity for Expr<A> {
+    type Child = A;
+
+    fn child_count(&self) -> usize {
+        match self {
+            Expr::Add(..) | Expr::Sub(..) | Expr::Mul(..) => 2,
+            Expr::LiteralInt(_) => 0,
+        }
+    }
+
+    fn child(&self, position: usize) -> Option<&A> {
+        match (self, position) {
+            (Expr::Add(a, _) | Expr::Sub(a, _) | Expr::Mul(a, _), 0) => Some(a),
+            (Expr::Add(_, b) | Expr::Sub(_, b) | Expr::Mul(_, b), 1) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+impl<A> EdgeLabeled for Expr<A> {
+    fn edge_label(&self, position: usize) -> Option<Cow<'_, str>> {
+        match (self.child(position), position) {
+            (Some(_), 0) => Some("lhs".into()),
+            (Some(_), _) => Some("rhs".into()),
+            (None, _) => None,
+        }
+    }
+}
+
 impl<'a, A: Copy, B: 'a> MapLayer<B> for &'a Expr<A> {
     type To = Expr<B>;
     type Unwrapped = A;
@@ -50,5 +96,47 @@ impl<'a, A: Copy, B: 'a> MapLayer<B> for &'a Expr<A> {
     }
 }
 
+// archived layers are collapsed in place, with their children read as they're visited
+#[cfg(any(test, feature = "rkyv"))]
+impl<B> MapLayer<B> for &ArchivedExpr<ArenaIndex> {
+    type To = Expr<B>;
+    type Unwrapped = ArenaIndex;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            ArchivedExpr::Add(a, b) => Expr::Add(f(a.into()), f(b.into())),
+            ArchivedExpr::Sub(a, b) => Expr::Sub(f(a.into()), f(b.into())),
+            ArchivedExpr::Mul(a, b) => Expr::Mul(f(a.into()), f(b.into())),
+            ArchivedExpr::LiteralInt(x) => Expr::LiteralInt(x.to_native()),
+        }
+    }
+}
+
+impl HashLayer for Expr<u64> {
+    fn hash_layer<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Expr::Add(a, b) => {
+                state.write_u8(0);
+                state.write_u64(*a);
+                state.write_u64(*b);
+            }
+            Expr::Sub(a, b) => {
+                state.write_u8(1);
+                state.write_u64(*a);
+                state.write_u64(*b);
+            }
+            Expr::Mul(a, b) => {
+                state.write_u8(2);
+                state.write_u64(*a);
+                state.write_u64(*b);
+            }
+            Expr::LiteralInt(x) => {
+                state.write_u8(3);
+                state.write(&x.to_le_bytes());
+            }
+        }
+    }
+}
+
 pub type DFSStackExpr = RecursiveTree<Expr<StackMarker>, StackMarker>;
 pub type BlocAllocExpr = RecursiveTree<Expr<ArenaIndex>, ArenaIndex>;
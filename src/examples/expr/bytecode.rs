@@ -0,0 +1,96 @@
+//! Code generation as a collapse: each subtree compiles to a sequence of instructions for a small
+//! stack machine that leaves the subtree's value on top of the stack.
+
+use crate::examples::expr::Expr;
+use crate::recursive::Collapse;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    Push(i64),
+    /// pop two operands and push the result, with the first operand being the deeper of the two
+    Add,
+    Sub,
+    Mul,
+}
+
+pub fn compile_layer(layer: Expr<Vec<Instr>>) -> Vec<Instr> {
+    let (mut a, b, op) = match layer {
+        Expr::Add(a, b) => (a, b, Instr::Add),
+        Expr::Sub(a, b) => (a, b, Instr::Sub),
+        Expr::Mul(a, b) => (a, b, Instr::Mul),
+        Expr::LiteralInt(x) => return vec![Instr::Push(x)],
+    };
+    // reuse the left operand's buffer
+    a.extend(b);
+    a.push(op);
+    a
+}
+
+pub fn compile<T: Collapse<Vec<Instr>, Expr<Vec<Instr>>>>(expr: T) -> Vec<Instr> {
+    expr.collapse_layers(compile_layer)
+}
+
+/// Run a program produced by 'compile', returning the value left on the stack
+pub fn run(program: &[Instr]) -> i64 {
+    let mut stack = Vec::new();
+    for instr in program {
+        let x = match instr {
+            Instr::Push(x) => *x,
+            op => {
+                let b = stack.pop().expect("stack underflow");
+                let a = stack.pop().expect("stack underflow");
+                match op {
+                    Instr::Add => a + b,
+                    Instr::Sub => a - b,
+                    _ => a * b,
+                }
+            }
+        };
+        stack.push(x);
+    }
+    stack.pop().expect("empty program")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::eval::naive_eval;
+    use crate::examples::expr::naive::{arb_expr, generate_layer};
+    use crate::examples::expr::BlocAllocExpr;
+    use crate::recursive::Expand;
+    use proptest::prelude::*;
+
+    #[test]
+    fn postfix() {
+        // (1 - 2) * 3
+        let expr = BlocAllocExpr::expand_layers(0, |n| match n {
+            0 => Expr::Mul(1, 2),
+            1 => Expr::Sub(3, 4),
+            2 => Expr::LiteralInt(3),
+            3 => Expr::LiteralInt(1),
+            _ => Expr::LiteralInt(2),
+        });
+        let program = compile(expr.as_ref());
+        assert_eq!(
+            program,
+            [
+                Instr::Push(1),
+                Instr::Push(2),
+                Instr::Sub,
+                Instr::Push(3),
+                Instr::Mul
+            ]
+        );
+        assert_eq!(run(&program), -3);
+    }
+
+    proptest! {
+        #[test]
+        fn matches_eval(expr in arb_expr()) {
+            let expected = naive_eval(&expr);
+            prop_assert_eq!(run(&compile(&expr)), expected);
+            let arena = BlocAllocExpr::expand_layers(&expr, generate_layer);
+            prop_assert_eq!(run(&compile(arena.as_ref())), expected);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Common subexpression elimination, via hash-consing into a 'RecursiveDag'.
+
+use crate::dag::RecursiveDag;
+use crate::examples::expr::eval::eval_layer;
+use crate::examples::expr::{BlocAllocExpr, Expr};
+use crate::recursive::Collapse;
+use crate::recursive_tree::arena_eval::ArenaIndex;
+
+pub type ExprDag = RecursiveDag<Expr<ArenaIndex>>;
+
+/// Share structurally identical subexpressions, returning the resulting DAG along with the number
+/// of nodes eliminated.
+pub fn cse(expr: &BlocAllocExpr) -> (ExprDag, usize) {
+    let dag = RecursiveDag::from_tree(expr);
+    let eliminated = expr.elems.len() - dag.len();
+    (dag, eliminated)
+}
+
+/// Evaluate each shared subexpression once. Evaluating the tree instead takes time proportional to
+/// its size, which can be exponential in the size of the DAG.
+pub fn eval_shared(expr: &ExprDag) -> i64 {
+    expr.collapse_layers(eval_layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::eval::naive_eval;
+    use crate::examples::expr::naive::arb_expr;
+    use crate::map_layer::Project;
+    use crate::recursive::Expand;
+    use proptest::prelude::*;
+
+    #[test]
+    fn duplicated_subtrees() {
+        // x_0 = 1, x_n = x_(n-1) * 1 + x_(n-1), doubling in size at each level
+        let expr = BlocAllocExpr::expand_layers((10, 0), |(depth, n)| match (depth, n) {
+            (0, _) | (_, 2) => Expr::LiteralInt(1),
+            (_, 0) => Expr::Add((depth, 1), (depth - 1, 0)),
+            _ => Expr::Mul((depth - 1, 0), (depth, 2)),
+        });
+        assert_eq!(expr.elems.len(), 4 * 1024 - 3);
+
+        let (dag, eliminated) = cse(&expr);
+        // an add and a mul for each level, plus the literal
+        assert_eq!(dag.len(), 21);
+        assert_eq!(eliminated, expr.elems.len() - 21);
+        assert_eq!(dag.collapse_layers(eval_layer), 1024);
+    }
+
+    #[test]
+    fn fibonacci() {
+        // fib(n) = fib(n - 1) + fib(n - 2), where each fib(k) appears fib(n - k + 1) times
+        let expr = BlocAllocExpr::expand_layers(20, |n| match n {
+            0 | 1 => Expr::LiteralInt(n),
+            _ => Expr::Add(n - 1, n - 2),
+        });
+        assert_eq!(expr.elems.len(), 21891);
+
+        let (dag, _) = cse(&expr);
+        assert_eq!(dag.len(), 21);
+        assert_eq!(eval_shared(&dag), 6765);
+        assert_eq!(expr.as_ref().collapse_layers(eval_layer), 6765);
+    }
+
+    proptest! {
+        #[test]
+        fn preserves_meaning(expr in arb_expr()) {
+            let expected = naive_eval(&expr);
+            let tree = BlocAllocExpr::expand_layers(&expr, Project::project);
+            let (dag, eliminated) = cse(&tree);
+
+            prop_assert_eq!(dag.len() + eliminated, tree.elems.len());
+            prop_assert_eq!(eval_shared(&dag), expected);
+            prop_assert_eq!(dag.to_tree().as_ref().collapse_layers(eval_layer), expected);
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! A slightly larger expression language than 'Expr', with booleans, variables, let-bindings
+//! and first-class functions.
+//! 'Expr' itself stays as-is, as it's used for benchmarking.
+
+pub mod anf;
+pub mod differentiate;
+pub mod dot;
+pub mod eval;
+pub mod free_vars;
+pub mod generate;
+pub mod interval;
+#[cfg(any(test, feature = "json"))]
+pub mod json;
+pub mod metrics;
+pub mod normalize;
+pub mod optimize;
+pub mod parse;
+pub mod partial;
+pub mod pretty;
+pub mod rewrite;
+pub mod step;
+pub mod substitute;
+pub mod typecheck;
+
+use std::fmt;
+use std::rc::Rc;
+
+use crate::examples::expr::lang::eval::Eval;
+use crate::map_layer::{MapLayer, Project};
+use crate::recursive::{Collapse, Expand};
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+use crate::spanned::Spanned;
+#[cfg(test)]
+use proptest::prelude::*;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    any(test, feature = "json", feature = "cbor"),
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub enum Expr<A> {
+    Add(A, A),
+    Sub(A, A),
+    Mul(A, A),
+    /// integer division truncates towards zero
+    Div(A, A),
+    /// remainder of 'Div', with the sign of the dividend
+    Mod(A, A),
+    Eq(A, A),
+    Lt(A, A),
+    /// short-circuiting: 'b' is only evaluated if 'a' is true
+    And(A, A),
+    /// short-circuiting: 'b' is only evaluated if 'a' is false
+    Or(A, A),
+    If(A, A, A),
+    LiteralInt(i64),
+    LiteralFloat(f64),
+    LiteralBool(bool),
+    Var(String),
+    /// 'let name = a in b'
+    Let(String, A, A),
+    /// '\\param -> body'
+    Lambda(String, A),
+    /// 'f arg'
+    App(A, A),
+}
+
+impl<A, B> MapLayer<B> for Expr<A> {
+    type To = Expr<B>;
+    type Unwrapped = A;
+
+    #[inline(always)]
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            Expr::Add(a, b) => Expr::Add(f(a), f(b)),
+            Expr::Sub(a, b) => Expr::Sub(f(a), f(b)),
+            Expr::Mul(a, b) => Expr::Mul(f(a), f(b)),
+            Expr::Div(a, b) => Expr::Div(f(a), f(b)),
+            Expr::Mod(a, b) => Expr::Mod(f(a), f(b)),
+            Expr::Eq(a, b) => Expr::Eq(f(a), f(b)),
+            Expr::Lt(a, b) => Expr::Lt(f(a), f(b)),
+            Expr::And(a, b) => Expr::And(f(a), f(b)),
+            Expr::Or(a, b) => Expr::Or(f(a), f(b)),
+            Expr::If(a, b, c) => Expr::If(f(a), f(b), f(c)),
+            Expr::LiteralInt(x) => Expr::LiteralInt(x),
+            Expr::LiteralFloat(x) => Expr::LiteralFloat(x),
+            Expr::LiteralBool(x) => Expr::LiteralBool(x),
+            Expr::Var(name) => Expr::Var(name),
+            Expr::Let(name, a, b) => Expr::Let(name, f(a), f(b)),
+            Expr::Lambda(param, body) => Expr::Lambda(param, f(body)),
+            Expr::App(g, arg) => Expr::App(f(g), f(arg)),
+        }
+    }
+}
+
+// names are cloned, which is fine for an example
+impl<'a, A: Copy, B: 'a> MapLayer<B> for &'a Expr<A> {
+    type To = Expr<B>;
+    type Unwrapped = A;
+
+    #[inline(always)]
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            Expr::Add(a, b) => Expr::Add(f(*a), f(*b)),
+            Expr::Sub(a, b) => Expr::Sub(f(*a), f(*b)),
+            Expr::Mul(a, b) => Expr::Mul(f(*a), f(*b)),
+            Expr::Div(a, b) => Expr::Div(f(*a), f(*b)),
+            Expr::Mod(a, b) => Expr::Mod(f(*a), f(*b)),
+            Expr::Eq(a, b) => Expr::Eq(f(*a), f(*b)),
+            Expr::Lt(a, b) => Expr::Lt(f(*a), f(*b)),
+            Expr::And(a, b) => Expr::And(f(*a), f(*b)),
+            Expr::Or(a, b) => Expr::Or(f(*a), f(*b)),
+            Expr::If(a, b, c) => Expr::If(f(*a), f(*b), f(*c)),
+            Expr::LiteralInt(x) => Expr::LiteralInt(*x),
+            Expr::LiteralFloat(x) => Expr::LiteralFloat(*x),
+            Expr::LiteralBool(x) => Expr::LiteralBool(*x),
+            Expr::Var(name) => Expr::Var(name.clone()),
+            Expr::Let(name, a, b) => Expr::Let(name.clone(), f(*a), f(*b)),
+            Expr::Lambda(param, body) => Expr::Lambda(param.clone(), f(*body)),
+            Expr::App(g, arg) => Expr::App(f(*g), f(*arg)),
+        }
+    }
+}
+
+impl<'a, A: Copy, B: 'a> MapLayer<B> for &'a Spanned<Expr<A>> {
+    type To = Spanned<Expr<B>>;
+    type Unwrapped = A;
+
+    #[inline(always)]
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, f: F) -> Self::To {
+        Spanned::new(self.span.clone(), (&self.value).map_layer(f))
+    }
+}
+
+/// boxed representation, for building expressions by hand
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprAST {
+    Add(Box<ExprAST>, Box<ExprAST>),
+    Sub(Box<ExprAST>, Box<ExprAST>),
+    Mul(Box<ExprAST>, Box<ExprAST>),
+    Div(Box<ExprAST>, Box<ExprAST>),
+    Mod(Box<ExprAST>, Box<ExprAST>),
+    Eq(Box<ExprAST>, Box<ExprAST>),
+    Lt(Box<ExprAST>, Box<ExprAST>),
+    And(Box<ExprAST>, Box<ExprAST>),
+    Or(Box<ExprAST>, Box<ExprAST>),
+    If(Box<ExprAST>, Box<ExprAST>, Box<ExprAST>),
+    LiteralInt(i64),
+    LiteralFloat(f64),
+    LiteralBool(bool),
+    Var(String),
+    Let(String, Box<ExprAST>, Box<ExprAST>),
+    Lambda(String, Box<ExprAST>),
+    App(Box<ExprAST>, Box<ExprAST>),
+}
+
+impl<'a> Project for &'a ExprAST {
+    type To = Expr<&'a ExprAST>;
+
+    fn project(self) -> Self::To {
+        match self {
+            ExprAST::Add(a, b) => Expr::Add(a, b),
+            ExprAST::Sub(a, b) => Expr::Sub(a, b),
+            ExprAST::Mul(a, b) => Expr::Mul(a, b),
+            ExprAST::Div(a, b) => Expr::Div(a, b),
+            ExprAST::Mod(a, b) => Expr::Mod(a, b),
+            ExprAST::Eq(a, b) => Expr::Eq(a, b),
+            ExprAST::Lt(a, b) => Expr::Lt(a, b),
+            ExprAST::And(a, b) => Expr::And(a, b),
+            ExprAST::Or(a, b) => Expr::Or(a, b),
+            ExprAST::If(a, b, c) => Expr::If(a, b, c),
+            ExprAST::LiteralInt(x) => Expr::LiteralInt(*x),
+            ExprAST::LiteralFloat(x) => Expr::LiteralFloat(*x),
+            ExprAST::LiteralBool(x) => Expr::LiteralBool(*x),
+            ExprAST::Var(name) => Expr::Var(name.clone()),
+            ExprAST::Let(name, a, b) => Expr::Let(name.clone(), a, b),
+            ExprAST::Lambda(param, body) => Expr::Lambda(param.clone(), body),
+            ExprAST::App(f, arg) => Expr::App(f, arg),
+        }
+    }
+}
+
+pub type RecursiveExpr = RecursiveTree<Expr<ArenaIndex>, ArenaIndex>;
+
+/// An expression along with the source location of each node, as produced by 'parse_spanned'
+pub type SpannedExpr = RecursiveTree<Spanned<Expr<ArenaIndex>>, ArenaIndex>;
+
+pub fn from_ast(expr: &ExprAST) -> RecursiveExpr {
+    RecursiveExpr::expand_layers(expr, Project::project)
+}
+
+pub fn to_ast(expr: &RecursiveExpr) -> ExprAST {
+    expr.as_ref().collapse_layers(embed)
+}
+
+/// Rebuild a single boxed node from a layer of boxed subtrees
+pub fn embed(layer: Expr<ExprAST>) -> ExprAST {
+    match layer {
+        Expr::Add(a, b) => ExprAST::Add(Box::new(a), Box::new(b)),
+        Expr::Sub(a, b) => ExprAST::Sub(Box::new(a), Box::new(b)),
+        Expr::Mul(a, b) => ExprAST::Mul(Box::new(a), Box::new(b)),
+        Expr::Div(a, b) => ExprAST::Div(Box::new(a), Box::new(b)),
+        Expr::Mod(a, b) => ExprAST::Mod(Box::new(a), Box::new(b)),
+        Expr::Eq(a, b) => ExprAST::Eq(Box::new(a), Box::new(b)),
+        Expr::Lt(a, b) => ExprAST::Lt(Box::new(a), Box::new(b)),
+        Expr::And(a, b) => ExprAST::And(Box::new(a), Box::new(b)),
+        Expr::Or(a, b) => ExprAST::Or(Box::new(a), Box::new(b)),
+        Expr::If(a, b, c) => ExprAST::If(Box::new(a), Box::new(b), Box::new(c)),
+        Expr::LiteralInt(x) => ExprAST::LiteralInt(x),
+        Expr::LiteralFloat(x) => ExprAST::LiteralFloat(x),
+        Expr::LiteralBool(x) => ExprAST::LiteralBool(x),
+        Expr::Var(name) => ExprAST::Var(name),
+        Expr::Let(name, a, b) => ExprAST::Let(name, Box::new(a), Box::new(b)),
+        Expr::Lambda(param, body) => ExprAST::Lambda(param, Box::new(body)),
+        Expr::App(f, arg) => ExprAST::App(Box::new(f), Box::new(arg)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Fn,
+}
+
+#[derive(Clone)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// a function, along with the environment it was defined in
+    Closure {
+        param: String,
+        body: Eval,
+        env: Env<Value>,
+    },
+}
+
+impl Value {
+    pub fn type_of(&self) -> Type {
+        match self {
+            Value::Int(_) => Type::Int,
+            Value::Float(_) => Type::Float,
+            Value::Bool(_) => Type::Bool,
+            Value::Closure { .. } => Type::Fn,
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(x) => write!(f, "Int({})", x),
+            Value::Float(x) => write!(f, "Float({:?})", x),
+            Value::Bool(x) => write!(f, "Bool({})", x),
+            Value::Closure { param, .. } => write!(f, "Closure({})", param),
+        }
+    }
+}
+
+// closures are never equal, as there's no way to compare function bodies
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// Arbitrary expressions, not necessarily well-typed or closed
+#[cfg(test)]
+pub fn arb_expr() -> impl Strategy<Value = ExprAST> {
+    let name = prop_oneof![Just("x"), Just("y"), Just("f")].prop_map(|s| s.to_string());
+    let leaf = prop_oneof![
+        any::<i64>().prop_map(ExprAST::LiteralInt),
+        // parsing and printing only round-trips finite floats
+        (-1e9..1e9f64).prop_map(ExprAST::LiteralFloat),
+        any::<bool>().prop_map(ExprAST::LiteralBool),
+        name.clone().prop_map(ExprAST::Var),
+    ];
+    leaf.prop_recursive(6, 64, 3, move |inner| {
+        let bin = (inner.clone(), inner.clone());
+        prop_oneof![
+            bin.clone()
+                .prop_map(|(a, b)| ExprAST::Add(Box::new(a), Box::new(b))),
+            bin.clone()
+                .prop_map(|(a, b)| ExprAST::Sub(Box::new(a), Box::new(b))),
+            bin.clone()
+                .prop_map(|(a, b)| ExprAST::Mul(Box::new(a), Box::new(b))),
+            bin.clone()
+                .prop_map(|(a, b)| ExprAST::Div(Box::new(a), Box::new(b))),
+            bin.clone()
+                .prop_map(|(a, b)| ExprAST::Mod(Box::new(a), Box::new(b))),
+            bin.clone()
+                .prop_map(|(a, b)| ExprAST::Eq(Box::new(a), Box::new(b))),
+            bin.clone()
+                .prop_map(|(a, b)| ExprAST::Lt(Box::new(a), Box::new(b))),
+            bin.clone()
+                .prop_map(|(a, b)| ExprAST::And(Box::new(a), Box::new(b))),
+            bin.clone()
+                .prop_map(|(a, b)| ExprAST::Or(Box::new(a), Box::new(b))),
+            bin.clone()
+                .prop_map(|(a, b)| ExprAST::App(Box::new(a), Box::new(b))),
+            (inner.clone(), inner.clone(), inner.clone()).prop_map(|(a, b, c)| ExprAST::If(
+                Box::new(a),
+                Box::new(b),
+                Box::new(c)
+            )),
+            (name.clone(), inner.clone(), inner.clone()).prop_map(|(n, a, b)| ExprAST::Let(
+                n,
+                Box::new(a),
+                Box::new(b)
+            )),
+            (name.clone(), inner).prop_map(|(n, a)| ExprAST::Lambda(n, Box::new(a))),
+        ]
+    })
+}
+
+/// A scoped environment. Binding a name returns a new environment and leaves the old one unchanged,
+/// so environments can be cheaply captured and shared.
+#[derive(Debug)]
+pub struct Env<V>(Option<Rc<(String, V, Env<V>)>>);
+
+impl<V> Clone for Env<V> {
+    fn clone(&self) -> Self {
+        Env(self.0.clone())
+    }
+}
+
+impl<V> Default for Env<V> {
+    fn default() -> Self {
+        Env(None)
+    }
+}
+
+impl<V> Env<V> {
+    pub fn bind(&self, name: impl Into<String>, value: V) -> Self {
+        Env(Some(Rc::new((name.into(), value, self.clone()))))
+    }
+
+    /// innermost binding for 'name'
+    pub fn lookup(&self, name: &str) -> Option<&V> {
+        let mut env = self;
+        while let Some(node) = &env.0 {
+            let (bound, value, parent) = &**node;
+            if bound == name {
+                return Some(value);
+            }
+            env = parent;
+        }
+        None
+    }
+}
+
+// helpers for building expressions by hand
+pub mod build {
+    use super::ExprAST;
+
+    pub fn lit(x: i64) -> ExprAST {
+        ExprAST::LiteralInt(x)
+    }
+
+    pub fn float(x: f64) -> ExprAST {
+        ExprAST::LiteralFloat(x)
+    }
+
+    pub fn bool_(x: bool) -> ExprAST {
+        ExprAST::LiteralBool(x)
+    }
+
+    pub fn var(name: &str) -> ExprAST {
+        ExprAST::Var(name.to_string())
+    }
+
+    pub fn add(a: ExprAST, b: ExprAST) -> ExprAST {
+        ExprAST::Add(Box::new(a), Box::new(b))
+    }
+
+    pub fn sub(a: ExprAST, b: ExprAST) -> ExprAST {
+        ExprAST::Sub(Box::new(a), Box::new(b))
+    }
+
+    pub fn mul(a: ExprAST, b: ExprAST) -> ExprAST {
+        ExprAST::Mul(Box::new(a), Box::new(b))
+    }
+
+    pub fn div(a: ExprAST, b: ExprAST) -> ExprAST {
+        ExprAST::Div(Box::new(a), Box::new(b))
+    }
+
+    pub fn mod_(a: ExprAST, b: ExprAST) -> ExprAST {
+        ExprAST::Mod(Box::new(a), Box::new(b))
+    }
+
+    pub fn eq(a: ExprAST, b: ExprAST) -> ExprAST {
+        ExprAST::Eq(Box::new(a), Box::new(b))
+    }
+
+    pub fn lt(a: ExprAST, b: ExprAST) -> ExprAST {
+        ExprAST::Lt(Box::new(a), Box::new(b))
+    }
+
+    pub fn and(a: ExprAST, b: ExprAST) -> ExprAST {
+        ExprAST::And(Box::new(a), Box::new(b))
+    }
+
+    pub fn or(a: ExprAST, b: ExprAST) -> ExprAST {
+        ExprAST::Or(Box::new(a), Box::new(b))
+    }
+
+    pub fn if_(cond: ExprAST, a: ExprAST, b: ExprAST) -> ExprAST {
+        ExprAST::If(Box::new(cond), Box::new(a), Box::new(b))
+    }
+
+    pub fn let_(name: &str, value: ExprAST, body: ExprAST) -> ExprAST {
+        ExprAST::Let(name.to_string(), Box::new(value), Box::new(body))
+    }
+
+    pub fn lambda(param: &str, body: ExprAST) -> ExprAST {
+        ExprAST::Lambda(param.to_string(), Box::new(body))
+    }
+
+    pub fn app(f: ExprAST, arg: ExprAST) -> ExprAST {
+        ExprAST::App(Box::new(f), Box::new(arg))
+    }
+}
@@ -0,0 +1,309 @@
+//! Lowering to A-normal form: a flat sequence of named bindings, each applying a single operation
+//! to atoms (literals and variables), as a compiler middle-end might consume. Only the branches of
+//! an 'if' and the bodies of lambdas are nested, as they aren't always evaluated.
+//!
+//! Each subtree is collapsed into a function that emits its bindings into a shared builder, which
+//! also holds the fresh name counter, and returns the atom holding its result. Let-bound variables
+//! are resolved to atoms as the builder descends, so they never need a binding of their own.
+
+use std::fmt;
+
+use crate::examples::expr::lang::{Env, Expr, RecursiveExpr};
+use crate::recursive::Collapse;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Atom {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Var(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Add(Atom, Atom),
+    Sub(Atom, Atom),
+    Mul(Atom, Atom),
+    Div(Atom, Atom),
+    Mod(Atom, Atom),
+    Eq(Atom, Atom),
+    Lt(Atom, Atom),
+    If(Atom, Block, Block),
+    Lambda(String, Block),
+    App(Atom, Atom),
+}
+
+/// A sequence of bindings, each of which may refer to those before it, followed by a result
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub bindings: Vec<(String, Op)>,
+    pub result: Atom,
+}
+
+#[derive(Default)]
+pub struct Builder {
+    bindings: Vec<(String, Op)>,
+    next: usize,
+}
+
+impl Builder {
+    // names can't clash with variables in the source, which are always identifiers
+    fn emit(&mut self, op: Op) -> Atom {
+        let name = format!("%{}", self.next);
+        self.next += 1;
+        self.bindings.push((name.clone(), op));
+        Atom::Var(name)
+    }
+
+    // lower a subtree into a separate block, sharing the fresh name counter
+    fn block(&mut self, lower: Lower, env: &Env<Atom>) -> Block {
+        let outer = std::mem::take(&mut self.bindings);
+        let result = lower(self, env);
+        let bindings = std::mem::replace(&mut self.bindings, outer);
+        Block { bindings, result }
+    }
+}
+
+/// A subtree, awaiting the builder to emit its bindings into and the atoms that variables in scope
+/// have been resolved to
+pub type Lower = Box<dyn FnOnce(&mut Builder, &Env<Atom>) -> Atom>;
+
+fn binary(a: Lower, b: Lower, op: fn(Atom, Atom) -> Op) -> Lower {
+    Box::new(move |builder, env| {
+        let a = a(builder, env);
+        let b = b(builder, env);
+        builder.emit(op(a, b))
+    })
+}
+
+pub fn lower_layer(layer: Expr<Lower>) -> Lower {
+    match layer {
+        Expr::Add(a, b) => binary(a, b, Op::Add),
+        Expr::Sub(a, b) => binary(a, b, Op::Sub),
+        Expr::Mul(a, b) => binary(a, b, Op::Mul),
+        Expr::Div(a, b) => binary(a, b, Op::Div),
+        Expr::Mod(a, b) => binary(a, b, Op::Mod),
+        Expr::Eq(a, b) => binary(a, b, Op::Eq),
+        Expr::Lt(a, b) => binary(a, b, Op::Lt),
+        Expr::App(f, arg) => binary(f, arg, Op::App),
+        Expr::If(cond, a, b) => Box::new(move |builder, env| {
+            let cond = cond(builder, env);
+            let a = builder.block(a, env);
+            let b = builder.block(b, env);
+            builder.emit(Op::If(cond, a, b))
+        }),
+        // 'a && b' is 'if a then b else false', and 'a || b' is 'if a then true else b'
+        Expr::And(a, b) => Box::new(move |builder, env| {
+            let a = a(builder, env);
+            let b = builder.block(b, env);
+            builder.emit(Op::If(a, b, Block::atom(Atom::Bool(false))))
+        }),
+        Expr::Or(a, b) => Box::new(move |builder, env| {
+            let a = a(builder, env);
+            let b = builder.block(b, env);
+            builder.emit(Op::If(a, Block::atom(Atom::Bool(true)), b))
+        }),
+        Expr::LiteralInt(x) => Box::new(move |_, _| Atom::Int(x)),
+        Expr::LiteralFloat(x) => Box::new(move |_, _| Atom::Float(x)),
+        Expr::LiteralBool(x) => Box::new(move |_, _| Atom::Bool(x)),
+        // free variables are left as-is
+        Expr::Var(name) => {
+            Box::new(move |_, env| env.lookup(&name).cloned().unwrap_or(Atom::Var(name)))
+        }
+        Expr::Let(name, value, body) => Box::new(move |builder, env| {
+            let value = value(builder, env);
+            body(builder, &env.bind(name, value))
+        }),
+        Expr::Lambda(param, body) => Box::new(move |builder, env| {
+            let body = builder.block(body, &env.bind(param.clone(), Atom::Var(param.clone())));
+            builder.emit(Op::Lambda(param, body))
+        }),
+    }
+}
+
+pub fn lower(expr: &RecursiveExpr) -> Block {
+    let root = expr.as_ref().collapse_layers(lower_layer);
+    Builder::default().block(root, &Env::default())
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Atom::Int(x) => write!(f, "{}", x),
+            Atom::Float(x) => write!(f, "{:?}", x),
+            Atom::Bool(x) => write!(f, "{}", x),
+            Atom::Var(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl Block {
+    fn atom(result: Atom) -> Self {
+        Block {
+            bindings: Vec::new(),
+            result,
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        let pad = "  ".repeat(indent);
+        for (name, op) in &self.bindings {
+            write!(f, "{}{} = ", pad, name)?;
+            let (symbol, a, b) = match op {
+                Op::Add(a, b) => ("+", a, b),
+                Op::Sub(a, b) => ("-", a, b),
+                Op::Mul(a, b) => ("*", a, b),
+                Op::Div(a, b) => ("/", a, b),
+                Op::Mod(a, b) => ("%", a, b),
+                Op::Eq(a, b) => ("==", a, b),
+                Op::Lt(a, b) => ("<", a, b),
+                Op::App(g, arg) => {
+                    writeln!(f, "{} {}", g, arg)?;
+                    continue;
+                }
+                Op::If(cond, a, b) => {
+                    writeln!(f, "if {} then", cond)?;
+                    a.write(f, indent + 1)?;
+                    writeln!(f, "{}else", pad)?;
+                    b.write(f, indent + 1)?;
+                    continue;
+                }
+                Op::Lambda(param, body) => {
+                    writeln!(f, "\\{} ->", param)?;
+                    body.write(f, indent + 1)?;
+                    continue;
+                }
+            };
+            writeln!(f, "{} {} {}", a, symbol, b)?;
+        }
+        writeln!(f, "{}{}", pad, self.result)
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::eval::{
+        arithmetic, equal, eval_arena, less_than, ArithOp, EvalErrorKind,
+    };
+    use crate::examples::expr::lang::generate::{gen_expr, Rng};
+    use crate::examples::expr::lang::parse::parse;
+    use crate::examples::expr::lang::Value;
+
+    fn lowered(input: &str) -> String {
+        lower(&parse(input).unwrap()).to_string()
+    }
+
+    #[test]
+    fn flattens() {
+        assert_eq!(
+            lowered("let y = x * 2 + 1 in y * (y - 3)"),
+            "%0 = x * 2\n%1 = %0 + 1\n%2 = %1 - 3\n%3 = %1 * %2\n%3\n"
+        );
+        // shadowing is resolved during lowering
+        assert_eq!(
+            lowered("let x = 1 in (let x = 2 in x) + x"),
+            "%0 = 2 + 1\n%0\n"
+        );
+        assert_eq!(lowered("let a = 1 in a"), "1\n");
+    }
+
+    #[test]
+    fn nested_blocks() {
+        assert_eq!(
+            lowered("let f = \\x -> x * 2 in if f 1 < 3 then f 2 else 0"),
+            [
+                "%1 = \\x ->",
+                "  %0 = x * 2",
+                "  %0",
+                "%2 = %1 1",
+                "%3 = %2 < 3",
+                "%5 = if %3 then",
+                "  %4 = %1 2",
+                "  %4",
+                "else",
+                "  0",
+                "%5",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[derive(Clone)]
+    enum AnfValue {
+        Value(Value),
+        Closure(String, Block, Env<AnfValue>),
+    }
+
+    fn run(block: &Block, env: &Env<AnfValue>) -> Result<AnfValue, EvalErrorKind> {
+        let mut env = env.clone();
+        let atom = |env: &Env<AnfValue>, atom: &Atom| match atom {
+            Atom::Int(x) => AnfValue::Value(Value::Int(*x)),
+            Atom::Float(x) => AnfValue::Value(Value::Float(*x)),
+            Atom::Bool(x) => AnfValue::Value(Value::Bool(*x)),
+            Atom::Var(name) => env.lookup(name).expect("closed expression").clone(),
+        };
+        let value = |env: &Env<AnfValue>, a: &Atom| match atom(env, a) {
+            AnfValue::Value(v) => v,
+            AnfValue::Closure(..) => panic!("well-typed expression"),
+        };
+        for (name, op) in &block.bindings {
+            let arith = |op, a, b| arithmetic(op, value(&env, a), value(&env, b));
+            let result = match op {
+                Op::Add(a, b) => arith(ArithOp::Add, a, b),
+                Op::Sub(a, b) => arith(ArithOp::Sub, a, b),
+                Op::Mul(a, b) => arith(ArithOp::Mul, a, b),
+                Op::Div(a, b) => arith(ArithOp::Div, a, b),
+                Op::Mod(a, b) => arith(ArithOp::Mod, a, b),
+                Op::Eq(a, b) => equal(value(&env, a), value(&env, b)).map(Value::Bool),
+                Op::Lt(a, b) => less_than(value(&env, a), value(&env, b)).map(Value::Bool),
+                Op::If(cond, a, b) => {
+                    let branch = if value(&env, cond) == Value::Bool(true) {
+                        a
+                    } else {
+                        b
+                    };
+                    let result = run(branch, &env)?;
+                    env = env.bind(name.clone(), result);
+                    continue;
+                }
+                Op::Lambda(param, body) => {
+                    let closure = AnfValue::Closure(param.clone(), body.clone(), env.clone());
+                    env = env.bind(name.clone(), closure);
+                    continue;
+                }
+                Op::App(f, arg) => match atom(&env, f) {
+                    AnfValue::Closure(param, body, captured) => {
+                        let result = run(&body, &captured.bind(param, atom(&env, arg)))?;
+                        env = env.bind(name.clone(), result);
+                        continue;
+                    }
+                    AnfValue::Value(_) => panic!("well-typed expression"),
+                },
+            };
+            let result = result.map_err(|e| e.kind)?;
+            env = env.bind(name.clone(), AnfValue::Value(result));
+        }
+        Ok(atom(&env, &block.result))
+    }
+
+    #[test]
+    fn preserves_meaning() {
+        let mut rng = Rng::new(3);
+        for _ in 0..1000 {
+            let expr = gen_expr(6, &mut rng);
+            let result = run(&lower(&expr), &Env::default()).map(|v| match v {
+                AnfValue::Value(v) => v,
+                AnfValue::Closure(..) => panic!("generated expressions aren't functions"),
+            });
+            assert_eq!(result, eval_arena(&expr).map_err(|e| e.kind));
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Symbolic differentiation, as a paramorphism: the product rule needs the derivative of each
+//! operand, but also each operand itself.
+
+use crate::examples::expr::lang::{embed, from_ast, Expr, ExprAST, RecursiveExpr};
+use crate::recursive::collapse_layers_with_subtrees;
+
+// constructors that skip trivial terms, to keep derivatives readable
+fn sum(a: ExprAST, b: ExprAST) -> ExprAST {
+    match (a, b) {
+        (ExprAST::LiteralInt(0), x) | (x, ExprAST::LiteralInt(0)) => x,
+        (a, b) => ExprAST::Add(Box::new(a), Box::new(b)),
+    }
+}
+
+fn difference(a: ExprAST, b: ExprAST) -> ExprAST {
+    match (a, b) {
+        (a, ExprAST::LiteralInt(0)) => a,
+        (a, b) => ExprAST::Sub(Box::new(a), Box::new(b)),
+    }
+}
+
+fn product(a: ExprAST, b: ExprAST) -> ExprAST {
+    match (a, b) {
+        (ExprAST::LiteralInt(0), _) | (_, ExprAST::LiteralInt(0)) => ExprAST::LiteralInt(0),
+        (ExprAST::LiteralInt(1), x) | (x, ExprAST::LiteralInt(1)) => x,
+        (a, b) => ExprAST::Mul(Box::new(a), Box::new(b)),
+    }
+}
+
+/// Derivative of a single layer, given each child's original subtree and derivative
+fn derivative_layer(layer: Expr<(ExprAST, Option<ExprAST>)>, var: &str) -> Option<ExprAST> {
+    Some(match layer {
+        Expr::LiteralInt(_) | Expr::LiteralFloat(_) => ExprAST::LiteralInt(0),
+        Expr::Var(x) => ExprAST::LiteralInt(if x == var { 1 } else { 0 }),
+        Expr::Add((_, da), (_, db)) => sum(da?, db?),
+        Expr::Sub((_, da), (_, db)) => difference(da?, db?),
+        Expr::Mul((a, da), (b, db)) => sum(product(da?, b), product(a, db?)),
+        // only arithmetic is differentiable
+        _ => return None,
+    })
+}
+
+/// Derivative of 'expr' with respect to 'var', if 'expr' only uses arithmetic
+pub fn differentiate(expr: &RecursiveExpr, var: &str) -> Option<RecursiveExpr> {
+    collapse_layers_with_subtrees(expr.as_ref(), embed, |layer| derivative_layer(layer, var))
+        .map(|derivative| from_ast(&derivative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::eval::eval;
+    use crate::examples::expr::lang::parse::parse;
+    use crate::examples::expr::lang::pretty::pretty;
+    use crate::examples::expr::lang::{build::*, to_ast, Value};
+
+    fn derivative(input: &str, var: &str) -> Option<String> {
+        differentiate(&parse(input).unwrap(), var).map(|d| pretty(&d))
+    }
+
+    #[test]
+    fn rules() {
+        assert_eq!(derivative("x * x + 3 * x - 7", "x").unwrap(), "x + x + 3");
+        assert_eq!(derivative("x * y", "x").unwrap(), "y");
+        assert_eq!(derivative("x * y", "z").unwrap(), "0");
+        assert_eq!(
+            derivative("(x - 1) * (x + 1)", "x").unwrap(),
+            "x + 1 + (x - 1)"
+        );
+        assert_eq!(derivative("if x < 1 then x else 1", "x"), None);
+    }
+
+    #[test]
+    fn evaluates() {
+        // d/dx x^3 = 3x^2
+        let d = differentiate(&parse("x * x * x").unwrap(), "x").unwrap();
+        for x in -3..4 {
+            let at_x = let_("x", lit(x), to_ast(&d));
+            assert_eq!(eval(&at_x), Ok(Value::Int(3 * x * x)));
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Graphviz output, for visualizing the structure of an expression. Arena indices double as node
+//! ids, so no traversal is needed: each layer is just visited in place.
+
+use std::fmt::Write;
+
+use crate::examples::expr::lang::{Expr, RecursiveExpr};
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::arena_eval::ArenaIndex;
+
+fn label(layer: &Expr<ArenaIndex>) -> String {
+    match layer {
+        Expr::Add(_, _) => "+".to_string(),
+        Expr::Sub(_, _) => "-".to_string(),
+        Expr::Mul(_, _) => "*".to_string(),
+        Expr::Div(_, _) => "/".to_string(),
+        Expr::Mod(_, _) => "%".to_string(),
+        Expr::Eq(_, _) => "==".to_string(),
+        Expr::Lt(_, _) => "<".to_string(),
+        Expr::And(_, _) => "&&".to_string(),
+        Expr::Or(_, _) => "||".to_string(),
+        Expr::If(_, _, _) => "if".to_string(),
+        Expr::LiteralInt(x) => x.to_string(),
+        Expr::LiteralFloat(x) => format!("{:?}", x),
+        Expr::LiteralBool(x) => x.to_string(),
+        Expr::Var(name) => name.clone(),
+        Expr::Let(name, _, _) => format!("let {}", name),
+        Expr::Lambda(param, _) => format!("\\{}", param),
+        Expr::App(_, _) => "app".to_string(),
+    }
+}
+
+/// A 'digraph' with a node per layer and edges from each layer to its children, in order
+pub fn to_dot(expr: &RecursiveExpr) -> String {
+    let mut out = String::from("digraph expr {\n");
+    for (idx, layer) in expr.elems.iter().enumerate() {
+        let label = label(layer).replace('\\', "\\\\").replace('"', "\\\"");
+        writeln!(out, "  n{} [label=\"{}\"];", idx, label).unwrap();
+        layer.map_layer(|ArenaIndex(child)| {
+            writeln!(out, "  n{} -> n{};", idx, child).unwrap();
+        });
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::parse::parse;
+
+    #[test]
+    fn graph() {
+        let expr = parse("\\x -> x * 2").unwrap();
+        assert_eq!(
+            to_dot(&expr),
+            [
+                "digraph expr {",
+                "  n0 [label=\"\\\\x\"];",
+                "  n0 -> n1;",
+                "  n1 [label=\"*\"];",
+                "  n1 -> n2;",
+                "  n1 -> n3;",
+                "  n2 [label=\"x\"];",
+                "  n3 [label=\"2\"];",
+                "}",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
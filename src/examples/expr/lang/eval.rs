@@ -0,0 +1,549 @@
+//! Evaluation with variables requires information to flow top-down (which bindings are in scope)
+//! as well as bottom-up (values). This is done by collapsing each subtree into a function from
+//! environment to value - an inherited attribute - and applying the root function to an empty
+//! environment.
+//!
+//! Runtime errors report the path to the subexpression that failed. Paths are inherited attributes
+//! too: each subtree is collapsed into a function that's given its own path and builds its
+//! evaluator, so errors within a function body are located within the body, wherever the
+//! function happens to be called from.
+
+use crate::examples::expr::lang::typecheck::{typecheck, typecheck_spanned, Located, TypeError};
+use crate::examples::expr::lang::{Env, Expr, ExprAST, RecursiveExpr, SpannedExpr, Type, Value};
+use crate::map_layer::MapLayer;
+use crate::recursive::Collapse;
+use crate::spanned::{span_at, Span, Spanned};
+
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalErrorKind {
+    UnboundVar(String),
+    /// Integer arithmetic overflowed
+    Overflow,
+    /// The divisor of a division or remainder was zero
+    DivideByZero,
+    /// Rejected before evaluation started
+    TypeCheck(Located<TypeError>),
+    /// Only detected during evaluation
+    Type(TypeError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalError {
+    pub kind: EvalErrorKind,
+    /// Path from the root to the subexpression that failed, where each step is the position of a
+    /// child within its parent layer. Only unset for errors that haven't yet been located.
+    pub path: Option<Vec<usize>>,
+    /// Source location of the subexpression that failed, when evaluating a 'SpannedExpr'
+    pub span: Option<Span>,
+}
+
+impl EvalError {
+    // errors are located at the innermost node they pass through
+    fn or_at(mut self, span: &Span) -> Self {
+        self.span.get_or_insert_with(|| span.clone());
+        self
+    }
+
+    fn or_within(mut self, path: &[usize]) -> Self {
+        self.path.get_or_insert_with(|| path.to_vec());
+        self
+    }
+}
+
+impl From<EvalErrorKind> for EvalError {
+    fn from(kind: EvalErrorKind) -> Self {
+        EvalError {
+            kind,
+            path: None,
+            span: None,
+        }
+    }
+}
+
+impl From<TypeError> for EvalError {
+    fn from(e: TypeError) -> Self {
+        EvalErrorKind::Type(e).into()
+    }
+}
+
+impl From<Located<TypeError>> for EvalError {
+    fn from(e: Located<TypeError>) -> Self {
+        let path = e.path.clone();
+        EvalError::from(EvalErrorKind::TypeCheck(e)).or_within(&path)
+    }
+}
+
+/// A subtree, awaiting an environment. Shared, as function bodies are captured by every
+/// closure value created from them.
+pub type Eval = Rc<dyn Fn(&Env<Value>) -> Result<Value, EvalError>>;
+
+/// Any number, promoted to a float
+fn number(v: Value) -> Result<f64, EvalError> {
+    match v {
+        Value::Int(x) => Ok(x as f64),
+        Value::Float(x) => Ok(x),
+        v => Err(EvalError::from(TypeError {
+            expected: Type::Int,
+            found: v.type_of(),
+        })),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+/// Integer arithmetic is checked, failing on overflow. If either operand is a float, the other is
+/// promoted to a float as well. Dividing by zero fails for floats as well as ints.
+pub fn arithmetic(op: ArithOp, a: Value, b: Value) -> Result<Value, EvalError> {
+    let division = matches!(op, ArithOp::Div | ArithOp::Mod);
+    if let (Value::Int(a), Value::Int(b)) = (&a, &b) {
+        if division && *b == 0 {
+            return Err(EvalErrorKind::DivideByZero.into());
+        }
+        let result = match op {
+            ArithOp::Add => a.checked_add(*b),
+            ArithOp::Sub => a.checked_sub(*b),
+            ArithOp::Mul => a.checked_mul(*b),
+            // only 'i64::MIN / -1' overflows
+            ArithOp::Div => a.checked_div(*b),
+            ArithOp::Mod => a.checked_rem(*b),
+        };
+        return result
+            .map(Value::Int)
+            .ok_or_else(|| EvalErrorKind::Overflow.into());
+    }
+    let (a, b) = (number(a)?, number(b)?);
+    if division && b == 0.0 {
+        return Err(EvalErrorKind::DivideByZero.into());
+    }
+    Ok(Value::Float(match op {
+        ArithOp::Add => a + b,
+        ArithOp::Sub => a - b,
+        ArithOp::Mul => a * b,
+        ArithOp::Div => a / b,
+        ArithOp::Mod => a % b,
+    }))
+}
+
+pub fn less_than(a: Value, b: Value) -> Result<bool, EvalError> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Ok(a < b),
+        (a, b) => Ok(number(a)? < number(b)?),
+    }
+}
+
+/// Numbers are compared after promotion, other values must be of the same type
+pub fn equal(a: Value, b: Value) -> Result<bool, EvalError> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Ok(a == b),
+        (a @ (Value::Int(_) | Value::Float(_)), b @ (Value::Int(_) | Value::Float(_))) => {
+            Ok(number(a)? == number(b)?)
+        }
+        (a, b) => {
+            // functions can't be compared
+            let expected = match a.type_of() {
+                Type::Fn => Type::Int,
+                t => t,
+            };
+            if b.type_of() != expected {
+                return Err(EvalError::from(TypeError {
+                    expected,
+                    found: b.type_of(),
+                }));
+            }
+            Ok(a == b)
+        }
+    }
+}
+
+fn bool(v: Value) -> Result<bool, EvalError> {
+    match v {
+        Value::Bool(x) => Ok(x),
+        v => Err(EvalError::from(TypeError {
+            expected: Type::Bool,
+            found: v.type_of(),
+        })),
+    }
+}
+
+// type errors not caught by 'typecheck' (due to variables of unknown type) are caught here
+pub fn eval_layer(layer: Expr<Eval>) -> Eval {
+    match layer {
+        Expr::Add(a, b) => Rc::new(move |env| arithmetic(ArithOp::Add, a(env)?, b(env)?)),
+        Expr::Sub(a, b) => Rc::new(move |env| arithmetic(ArithOp::Sub, a(env)?, b(env)?)),
+        Expr::Mul(a, b) => Rc::new(move |env| arithmetic(ArithOp::Mul, a(env)?, b(env)?)),
+        Expr::Div(a, b) => Rc::new(move |env| arithmetic(ArithOp::Div, a(env)?, b(env)?)),
+        Expr::Mod(a, b) => Rc::new(move |env| arithmetic(ArithOp::Mod, a(env)?, b(env)?)),
+        Expr::Lt(a, b) => Rc::new(move |env| Ok(Value::Bool(less_than(a(env)?, b(env)?)?))),
+        Expr::Eq(a, b) => Rc::new(move |env| Ok(Value::Bool(equal(a(env)?, b(env)?)?))),
+        // only the branch that's taken is evaluated
+        Expr::If(cond, a, b) => Rc::new(move |env| if bool(cond(env)?)? { a(env) } else { b(env) }),
+        // as with 'if', the right hand side is only evaluated if the left doesn't decide the result
+        Expr::And(a, b) => Rc::new(move |env| Ok(Value::Bool(bool(a(env)?)? && bool(b(env)?)?))),
+        Expr::Or(a, b) => Rc::new(move |env| Ok(Value::Bool(bool(a(env)?)? || bool(b(env)?)?))),
+        Expr::LiteralInt(x) => Rc::new(move |_| Ok(Value::Int(x))),
+        Expr::LiteralFloat(x) => Rc::new(move |_| Ok(Value::Float(x))),
+        Expr::LiteralBool(x) => Rc::new(move |_| Ok(Value::Bool(x))),
+        Expr::Var(name) => Rc::new(move |env| {
+            env.lookup(&name)
+                .cloned()
+                .ok_or_else(|| EvalErrorKind::UnboundVar(name.clone()).into())
+        }),
+        Expr::Let(name, value, body) => Rc::new(move |env| {
+            let value = value(env)?;
+            body(&env.bind(name.clone(), value))
+        }),
+        Expr::Lambda(param, body) => Rc::new(move |env| {
+            Ok(Value::Closure {
+                param: param.clone(),
+                body: body.clone(),
+                env: env.clone(),
+            })
+        }),
+        Expr::App(f, arg) => Rc::new(move |env| match f(env)? {
+            // the body is evaluated in the closure's environment, not the caller's
+            Value::Closure {
+                param,
+                body,
+                env: captured,
+            } => body(&captured.bind(param, arg(env)?)),
+            v => Err(EvalError::from(TypeError {
+                expected: Type::Fn,
+                found: v.type_of(),
+            })),
+        }),
+    }
+}
+
+/// A subtree awaiting its own path, which is only known once the layers above it are collapsed
+pub type Locate = Box<dyn FnOnce(&mut Vec<usize>) -> Eval>;
+
+/// Evaluate a single layer, with runtime errors located at the innermost subexpression they pass
+/// through
+pub fn locate_layer(layer: Expr<Locate>) -> Locate {
+    Box::new(move |path| {
+        let here = path.clone();
+        let mut position = 0;
+        let eval = eval_layer(layer.map_layer(|child| {
+            path.push(position);
+            position += 1;
+            let child = child(path);
+            path.pop();
+            child
+        }));
+        Rc::new(move |env: &Env<Value>| eval(env).map_err(|e| e.or_within(&here)))
+    })
+}
+
+fn run(root: Locate) -> Result<Value, EvalError> {
+    root(&mut Vec::new())(&Env::default())
+}
+
+/// Typecheck, then evaluate
+pub fn eval(expr: &ExprAST) -> Result<Value, EvalError> {
+    typecheck(expr)?;
+    run(expr.collapse_layers(locate_layer))
+}
+
+pub fn eval_arena(expr: &RecursiveExpr) -> Result<Value, EvalError> {
+    // both passes run over the same arena
+    typecheck(expr.as_ref())?;
+    run(expr.as_ref().collapse_layers(locate_layer))
+}
+
+/// Typecheck, then evaluate, with errors reporting the span of the subexpression that failed
+pub fn eval_spanned(expr: &SpannedExpr) -> Result<Value, EvalError> {
+    typecheck_spanned(expr).map_err(|e| EvalError::from(e.value).or_at(&e.span))?;
+    run(expr
+        .as_ref()
+        .collapse_layers(|layer: Spanned<Expr<Locate>>| locate_layer(layer.value)))
+    .map_err(|e| {
+        let span = e.path.as_ref().and_then(|path| span_at(expr, path));
+        EvalError { span, ..e }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::{build::*, from_ast};
+    use crate::recursive::TryCollapse;
+
+    fn error_at(path: &[usize], kind: EvalErrorKind) -> Result<Value, EvalError> {
+        Err(EvalError {
+            kind,
+            path: Some(path.to_vec()),
+            span: None,
+        })
+    }
+
+    #[test]
+    fn scoping() {
+        // let x = 2 in let y = x * 3 in (let x = 10 in x + y) - x
+        let expr = let_(
+            "x",
+            lit(2),
+            let_(
+                "y",
+                mul(var("x"), lit(3)),
+                sub(let_("x", lit(10), add(var("x"), var("y"))), var("x")),
+            ),
+        );
+
+        assert_eq!(eval(&expr), Ok(Value::Int(14)));
+        assert_eq!(eval_arena(&from_ast(&expr)), Ok(Value::Int(14)));
+    }
+
+    #[test]
+    fn unbound() {
+        // the binding is only in scope within the body
+        let expr = add(let_("x", lit(1), var("x")), var("x"));
+        assert_eq!(
+            eval(&expr),
+            error_at(&[1], EvalErrorKind::UnboundVar("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn conditionals() {
+        // let x = 3 in if x < 5 then x == 3 else false
+        let expr = let_(
+            "x",
+            lit(3),
+            if_(lt(var("x"), lit(5)), eq(var("x"), lit(3)), bool_(false)),
+        );
+        assert_eq!(eval(&expr), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn short_circuit() {
+        // 1 / 0 == 1
+        let fails = || eq(div(lit(1), lit(0)), lit(1));
+        assert_eq!(eval(&and(bool_(false), fails())), Ok(Value::Bool(false)));
+        assert_eq!(eval(&or(bool_(true), fails())), Ok(Value::Bool(true)));
+        assert_eq!(
+            eval_arena(&from_ast(&and(bool_(true), fails()))),
+            error_at(&[1, 0], EvalErrorKind::DivideByZero)
+        );
+        assert_eq!(
+            eval(&or(lt(lit(2), lit(1)), bool_(true))),
+            Ok(Value::Bool(true))
+        );
+    }
+
+    #[test]
+    fn numeric_tower() {
+        // ints are promoted to floats when mixed with them
+        assert_eq!(eval(&add(lit(1), float(2.5))), Ok(Value::Float(3.5)));
+        assert_eq!(eval(&mul(lit(3), lit(4))), Ok(Value::Int(12)));
+        assert_eq!(eval(&lt(lit(1), float(1.5))), Ok(Value::Bool(true)));
+        assert_eq!(eval(&eq(lit(2), float(2.0))), Ok(Value::Bool(true)));
+        // float arithmetic doesn't overflow, it saturates to infinity
+        let expr = mul(float(f64::MAX), lit(2));
+        assert_eq!(eval(&expr), Ok(Value::Float(f64::INFINITY)));
+    }
+
+    #[test]
+    fn overflow() {
+        let expr = let_("max", lit(i64::MAX), add(var("max"), lit(1)));
+        assert_eq!(eval(&expr), error_at(&[1], EvalErrorKind::Overflow));
+        assert_eq!(
+            eval_arena(&from_ast(&expr)),
+            error_at(&[1], EvalErrorKind::Overflow)
+        );
+        assert_eq!(
+            eval(&sub(lit(i64::MIN), lit(1))),
+            error_at(&[], EvalErrorKind::Overflow)
+        );
+        assert_eq!(
+            eval(&div(lit(i64::MIN), lit(-1))),
+            error_at(&[], EvalErrorKind::Overflow)
+        );
+        // promoting to a float first avoids the overflow
+        assert_eq!(
+            eval(&add(lit(i64::MAX), float(1.0))),
+            Ok(Value::Float(i64::MAX as f64 + 1.0))
+        );
+    }
+
+    #[test]
+    fn division() {
+        assert_eq!(eval(&div(lit(7), lit(2))), Ok(Value::Int(3)));
+        assert_eq!(eval(&mod_(lit(-7), lit(2))), Ok(Value::Int(-1)));
+        assert_eq!(eval(&div(lit(7), float(2.0))), Ok(Value::Float(3.5)));
+
+        // let f = \x -> 10 / x in f 5 + (if f 0 < 1 then 1 else 0)
+        let expr = let_(
+            "f",
+            lambda("x", div(lit(10), var("x"))),
+            add(
+                app(var("f"), lit(5)),
+                if_(lt(app(var("f"), lit(0)), lit(1)), lit(1), lit(0)),
+            ),
+        );
+        // the error is located at the division within the function body, not the call site
+        let expected = error_at(&[0, 0], EvalErrorKind::DivideByZero);
+        assert_eq!(eval(&expr), expected);
+        assert_eq!(eval_arena(&from_ast(&expr)), expected);
+
+        assert_eq!(
+            eval(&mod_(float(1.5), sub(lit(2), lit(2)))),
+            error_at(&[], EvalErrorKind::DivideByZero)
+        );
+    }
+
+    #[test]
+    fn fallible_collapse() {
+        // without variables, evaluation needs no environment and can be a single fallible collapse
+        // that stops at the first error
+        let mut visited = 0;
+        let mut constant = |layer: Expr<Value>| {
+            visited += 1;
+            match layer {
+                Expr::LiteralInt(x) => Ok(Value::Int(x)),
+                Expr::Add(a, b) => arithmetic(ArithOp::Add, a, b),
+                Expr::Div(a, b) => arithmetic(ArithOp::Div, a, b),
+                _ => unimplemented!("not used below"),
+            }
+        };
+        // (2 + 3) + (1 / 0)
+        let expr = from_ast(&add(add(lit(2), lit(3)), div(lit(1), lit(0))));
+        let result = expr.as_ref().try_collapse_layers(&mut constant);
+        assert_eq!(result.map_err(|e| e.kind), Err(EvalErrorKind::DivideByZero));
+        // layers are visited in reverse bfs order, so neither the root nor '2 + 3' is reached
+        assert_eq!(visited, 5);
+    }
+
+    #[test]
+    fn static_type_error() {
+        // if true then 2 else (true + 1): the add is rejected before evaluation starts,
+        // even though it's in the branch that would never be taken
+        let expr = if_(bool_(true), lit(2), add(bool_(true), lit(1)));
+        let expected = Err(EvalError::from(Located {
+            path: vec![2],
+            error: TypeError {
+                expected: Type::Int,
+                found: Type::Bool,
+            },
+        }));
+        assert_eq!(eval(&expr), expected);
+        assert_eq!(eval_arena(&from_ast(&expr)), expected);
+    }
+
+    #[test]
+    fn branch_type_mismatch() {
+        let expr = if_(bool_(true), lit(2), bool_(false));
+        assert_eq!(
+            eval(&expr),
+            Err(EvalError::from(Located {
+                path: vec![],
+                error: TypeError {
+                    expected: Type::Int,
+                    found: Type::Bool,
+                },
+            }))
+        );
+    }
+
+    #[test]
+    fn dynamic_type_error() {
+        // the type of 'x' isn't known statically
+        let expr = let_("x", bool_(true), add(var("x"), lit(1)));
+        assert_eq!(
+            eval(&expr),
+            error_at(
+                &[1],
+                EvalErrorKind::Type(TypeError {
+                    expected: Type::Int,
+                    found: Type::Bool,
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn higher_order() {
+        // let twice = \f -> \x -> f (f x) in twice (\y -> y * 2) 5
+        let expr = let_(
+            "twice",
+            lambda("f", lambda("x", app(var("f"), app(var("f"), var("x"))))),
+            app(
+                app(var("twice"), lambda("y", mul(var("y"), lit(2)))),
+                lit(5),
+            ),
+        );
+        assert_eq!(eval(&expr), Ok(Value::Int(20)));
+        assert_eq!(eval_arena(&from_ast(&expr)), Ok(Value::Int(20)));
+    }
+
+    #[test]
+    fn lexical_scope() {
+        // let a = 10 in let f = \x -> x + a in let a = 100 in f 1
+        let expr = let_(
+            "a",
+            lit(10),
+            let_(
+                "f",
+                lambda("x", add(var("x"), var("a"))),
+                let_("a", lit(100), app(var("f"), lit(1))),
+            ),
+        );
+        assert_eq!(eval(&expr), Ok(Value::Int(11)));
+    }
+
+    #[test]
+    fn apply_non_function() {
+        let error = TypeError {
+            expected: Type::Fn,
+            found: Type::Int,
+        };
+        // caught statically
+        assert_eq!(
+            eval(&app(lit(1), lit(2))),
+            Err(EvalError::from(Located {
+                path: vec![],
+                error: error.clone(),
+            }))
+        );
+        // caught at runtime
+        assert_eq!(
+            eval(&let_("f", lit(1), app(var("f"), lit(2)))),
+            error_at(&[1], EvalErrorKind::Type(error))
+        );
+    }
+
+    #[test]
+    fn spans() {
+        use crate::examples::expr::lang::parse::parse_spanned;
+        let eval = |input: &str| eval_spanned(&parse_spanned(input).unwrap());
+
+        // runtime errors are located at the innermost failing subexpression
+        let err = eval("let x = true in 1 + (x * 2)").unwrap_err();
+        assert_eq!(err.span, Some(21..26));
+        assert_eq!(
+            err.kind,
+            EvalErrorKind::Type(TypeError {
+                expected: Type::Int,
+                found: Type::Bool,
+            })
+        );
+        let err = eval("let f = \\x -> x + y in f 1").unwrap_err();
+        assert_eq!(err.span, Some(18..19));
+        assert_eq!(err.path, Some(vec![0, 0, 1]));
+        assert_eq!(err.kind, EvalErrorKind::UnboundVar("y".to_string()));
+
+        // as are static errors
+        let err = eval("1 + (2 < true)").unwrap_err();
+        assert_eq!(err.span, Some(5..13));
+
+        assert_eq!(eval("let x = 2 in x * 3"), Ok(Value::Int(6)));
+    }
+}
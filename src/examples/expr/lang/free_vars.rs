@@ -0,0 +1,210 @@
+//! Free variable analysis. Free variables are collapsed bottom-up as a set union, with binders
+//! removing the variable they bind from their body's set.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::examples::expr::lang::{Expr, RecursiveExpr};
+use crate::map_layer::MapLayer;
+use crate::recursive::{Collapse, Expand};
+use crate::recursive_tree::{arena_eval::ArenaIndex, Sidecar};
+
+pub fn free_vars_layer(layer: Expr<HashSet<String>>) -> HashSet<String> {
+    match layer {
+        Expr::Var(name) => HashSet::from([name]),
+        Expr::Let(name, mut value, mut body) => {
+            body.remove(&name);
+            value.extend(body);
+            value
+        }
+        Expr::Lambda(param, mut body) => {
+            body.remove(&param);
+            body
+        }
+        Expr::Add(mut a, b)
+        | Expr::Sub(mut a, b)
+        | Expr::Mul(mut a, b)
+        | Expr::Div(mut a, b)
+        | Expr::Mod(mut a, b)
+        | Expr::Eq(mut a, b)
+        | Expr::Lt(mut a, b)
+        | Expr::And(mut a, b)
+        | Expr::Or(mut a, b)
+        | Expr::App(mut a, b) => {
+            a.extend(b);
+            a
+        }
+        Expr::If(mut a, b, c) => {
+            a.extend(b);
+            a.extend(c);
+            a
+        }
+        Expr::LiteralInt(_) | Expr::LiteralFloat(_) | Expr::LiteralBool(_) => HashSet::new(),
+    }
+}
+
+pub fn free_vars(expr: &RecursiveExpr) -> HashSet<String> {
+    expr.as_ref().collapse_layers(free_vars_layer)
+}
+
+/// Number of free occurrences of each variable
+pub type Usage = HashMap<String, usize>;
+
+fn merge(mut a: Usage, b: &Usage) -> Usage {
+    for (name, count) in b {
+        *a.entry(name.clone()).or_default() += count;
+    }
+    a
+}
+
+/// Variable usage for every node in the tree, in arena order (so the root's usage is first)
+pub fn annotate_usage(expr: &RecursiveExpr) -> Vec<Usage> {
+    let mut usage = vec![Usage::new(); expr.elems.len()];
+    // children always have higher indices than their parents
+    for (idx, layer) in expr.elems.iter().enumerate().rev() {
+        let layer = layer.map_layer(|ArenaIndex(child)| &usage[child]);
+        usage[idx] = match layer {
+            Expr::Var(name) => Usage::from([(name, 1)]),
+            Expr::Let(name, value, body) => {
+                let mut body = body.clone();
+                body.remove(&name);
+                merge(body, value)
+            }
+            Expr::Lambda(param, body) => {
+                let mut body = body.clone();
+                body.remove(&param);
+                body
+            }
+            Expr::Add(a, b)
+            | Expr::Sub(a, b)
+            | Expr::Mul(a, b)
+            | Expr::Div(a, b)
+            | Expr::Mod(a, b)
+            | Expr::Eq(a, b)
+            | Expr::Lt(a, b)
+            | Expr::And(a, b)
+            | Expr::Or(a, b)
+            | Expr::App(a, b) => merge(a.clone(), b),
+            Expr::If(a, b, c) => merge(merge(a.clone(), b), c),
+            Expr::LiteralInt(_) | Expr::LiteralFloat(_) | Expr::LiteralBool(_) => Usage::new(),
+        };
+    }
+    usage
+}
+
+// whether 'name' is bound where the node at 'idx' is, by an enclosing 'let' body or lambda
+fn in_scope(
+    expr: &RecursiveExpr,
+    parents: &Sidecar<Option<ArenaIndex>>,
+    idx: ArenaIndex,
+    name: &str,
+) -> bool {
+    let mut child = idx;
+    while let Some(parent) = parents[child] {
+        match &expr.elems[parent.0] {
+            Expr::Let(bound, _, body) | Expr::Lambda(bound, body)
+                if *body == child && bound == name =>
+            {
+                return true
+            }
+            _ => child = parent,
+        }
+    }
+    false
+}
+
+// whether evaluating the node at 'idx' can't fail: literals, lambdas, and variables in scope
+fn cannot_fail(
+    expr: &RecursiveExpr,
+    parents: &Sidecar<Option<ArenaIndex>>,
+    idx: ArenaIndex,
+) -> bool {
+    match &expr.elems[idx.0] {
+        Expr::LiteralInt(_) | Expr::LiteralFloat(_) | Expr::LiteralBool(_) | Expr::Lambda(..) => {
+            true
+        }
+        Expr::Var(name) => in_scope(expr, parents, idx, name),
+        _ => false,
+    }
+}
+
+/// Remove 'let' bindings that are never used. Bound expressions are evaluated whether or not
+/// they're used, so only bindings whose expression can't fail are removed, to keep any errors.
+pub fn eliminate_dead_lets(expr: &RecursiveExpr) -> RecursiveExpr {
+    let usage = annotate_usage(expr);
+    let parents = expr.parents();
+    RecursiveExpr::expand_layers(ArenaIndex(0), |ArenaIndex(mut idx)| loop {
+        match &expr.elems[idx] {
+            Expr::Let(name, value, ArenaIndex(body))
+                if !usage[*body].contains_key(name) && cannot_fail(expr, &parents, *value) =>
+            {
+                idx = *body
+            }
+            layer => break layer.map_layer(|child| child),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::eval::eval_arena;
+    use crate::examples::expr::lang::parse::parse;
+    use crate::examples::expr::lang::pretty::pretty;
+
+    fn set(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn free() {
+        let expr = parse("let x = y + 1 in \\z -> x * z * w").unwrap();
+        assert_eq!(free_vars(&expr), set(&["y", "w"]));
+        // bindings aren't recursive
+        let expr = parse("let x = x in x").unwrap();
+        assert_eq!(free_vars(&expr), set(&["x"]));
+    }
+
+    #[test]
+    fn usage() {
+        let expr = parse("let x = y in x + x * y").unwrap();
+        let usage = annotate_usage(&expr);
+        assert_eq!(usage[0], Usage::from([("y".to_string(), 2)]));
+        // the body, 'x + x * y'
+        assert_eq!(
+            usage[2],
+            Usage::from([("x".to_string(), 2), ("y".to_string(), 1)])
+        );
+    }
+
+    #[test]
+    fn dead_lets() {
+        let expr = parse("let a = 1 in let b = a in let c = a in \\x -> x + b").unwrap();
+        assert_eq!(
+            pretty(&eliminate_dead_lets(&expr)),
+            "let a = 1 in let b = a in \\x -> x + b"
+        );
+        let expr = parse("f (let unused = 2 in 3)").unwrap();
+        assert_eq!(pretty(&eliminate_dead_lets(&expr)), "f 3");
+        let expr = parse("\\x -> let f = \\y -> y in let y = x in x").unwrap();
+        assert_eq!(pretty(&eliminate_dead_lets(&expr)), "\\x -> x");
+
+        // unused bindings that fail when they're evaluated are kept, including those of free
+        // variables, and of variables bound only later
+        for input in [
+            "let y = 1 / 0 in 3",
+            "let y = true + 1 in 3",
+            "let y = z in 3",
+            "let y = y in 3",
+            "(\\x -> let y = x in 3) 1 + (let x = 1 in x)",
+        ] {
+            let expr = parse(input).unwrap();
+            let eliminated = eliminate_dead_lets(&expr);
+            assert_eq!(eval_arena(&eliminated), eval_arena(&expr), "{}", input);
+        }
+        let expr = parse("let y = x in let x = 1 in x").unwrap();
+        assert_eq!(
+            pretty(&eliminate_dead_lets(&expr)),
+            "let y = x in let x = 1 in x"
+        );
+    }
+}
@@ -0,0 +1,283 @@
+//! Random generation of well-typed, closed expressions, as an anamorphism. Each seed describes the
+//! node to generate - its type, the variables in scope and its remaining depth - along with its own
+//! random number generator, so generation is deterministic and each node's choices are independent
+//! of the order in which nodes are expanded.
+//!
+//! Generated expressions always typecheck, but may still fail at runtime via overflow or division
+//! by zero.
+
+use crate::examples::expr::lang::{Expr, RecursiveExpr, Type};
+use crate::recursive::Expand;
+
+/// A small, seedable pseudorandom number generator (splitmix64). Good enough for generating test
+/// inputs, and doesn't require any dependencies.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed in '0..n'
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// A new generator, independent of this one
+    pub fn split(&mut self) -> Self {
+        Rng(self.next_u64())
+    }
+
+    fn pick<T: Copy>(&mut self, options: &[T]) -> T {
+        options[self.below(options.len())]
+    }
+}
+
+const NAMES: [&str; 3] = ["a", "b", "c"];
+const TYPES: [Type; 3] = [Type::Int, Type::Float, Type::Bool];
+
+#[derive(Clone)]
+enum Shape {
+    /// any expression of the given type
+    Of(Type),
+    /// a lambda, of the given parameter and result types
+    Lambda(&'static str, Type, Type),
+}
+
+#[derive(Clone)]
+struct Seed {
+    shape: Shape,
+    depth: usize,
+    /// innermost binding last
+    scope: Vec<(&'static str, Type)>,
+    rng: Rng,
+}
+
+impl Seed {
+    fn child(&mut self, shape: Shape) -> Seed {
+        Seed {
+            shape,
+            depth: self.depth.saturating_sub(1),
+            scope: self.scope.clone(),
+            rng: self.rng.split(),
+        }
+    }
+
+    fn binding(&mut self, name: &'static str, t: Type, shape: Shape) -> Seed {
+        let mut child = self.child(shape);
+        child.scope.retain(|(bound, _)| *bound != name);
+        child.scope.push((name, t));
+        child
+    }
+
+    fn of(&mut self, t: Type) -> Seed {
+        self.child(Shape::Of(t))
+    }
+
+    // a number, which must be a float if 'float' is set
+    fn number(&mut self, float: bool) -> Seed {
+        let t = if float {
+            Type::Float
+        } else {
+            self.rng.pick(&[Type::Int, Type::Float])
+        };
+        self.of(t)
+    }
+}
+
+fn expand_layer(mut seed: Seed) -> Expr<Seed> {
+    let t = match seed.shape {
+        Shape::Lambda(param, param_type, result) => {
+            let body = seed.binding(param, param_type, Shape::Of(result));
+            return Expr::Lambda(param.to_string(), body);
+        }
+        Shape::Of(t) => t,
+    };
+
+    let in_scope: Vec<&str> = seed
+        .scope
+        .iter()
+        .filter(|(_, bound)| *bound == t)
+        .map(|(name, _)| *name)
+        .collect();
+    // leaves become more likely the deeper we go, and are the only option at depth zero
+    if seed.depth == 0 || seed.rng.below(seed.depth + 1) == 0 {
+        if !in_scope.is_empty() && seed.rng.below(2) == 0 {
+            return Expr::Var(seed.rng.pick(&in_scope).to_string());
+        }
+        return match t {
+            Type::Int => Expr::LiteralInt(seed.rng.below(201) as i64 - 100),
+            Type::Float => Expr::LiteralFloat((seed.rng.below(2001) as f64 - 1000.0) / 100.0),
+            _ => Expr::LiteralBool(seed.rng.below(2) == 0),
+        };
+    }
+
+    match seed.rng.below(6) {
+        // let and application work the same way for every type
+        0 => {
+            let name = seed.rng.pick(&NAMES);
+            let bound = seed.rng.pick(&TYPES);
+            let value = seed.of(bound);
+            Expr::Let(
+                name.to_string(),
+                value,
+                seed.binding(name, bound, Shape::Of(t)),
+            )
+        }
+        1 => {
+            let param = seed.rng.pick(&NAMES);
+            let param_type = seed.rng.pick(&TYPES);
+            let f = seed.child(Shape::Lambda(param, param_type, t));
+            Expr::App(f, seed.of(param_type))
+        }
+        2 => Expr::If(seed.of(Type::Bool), seed.of(t), seed.of(t)),
+        _ if t == Type::Bool => match seed.rng.below(5) {
+            0 => Expr::Lt(seed.number(false), seed.number(false)),
+            1 => Expr::Eq(seed.number(false), seed.number(false)),
+            2 => Expr::And(seed.of(Type::Bool), seed.of(Type::Bool)),
+            3 => Expr::Or(seed.of(Type::Bool), seed.of(Type::Bool)),
+            _ => Expr::Eq(seed.of(Type::Bool), seed.of(Type::Bool)),
+        },
+        _ => {
+            let (a, b) = if t == Type::Float {
+                // at least one operand must be a float for the result to be one
+                let a = seed.number(false);
+                let b = seed.number(matches!(a.shape, Shape::Of(Type::Int)));
+                (a, b)
+            } else {
+                (seed.of(t), seed.of(t))
+            };
+            match seed.rng.below(5) {
+                0 => Expr::Add(a, b),
+                1 => Expr::Sub(a, b),
+                2 => Expr::Mul(a, b),
+                3 => Expr::Div(a, b),
+                _ => Expr::Mod(a, b),
+            }
+        }
+    }
+}
+
+/// A random closed expression of type 'Int', 'Float' or 'Bool', with at most 'depth' layers
+/// between the root and any leaf, plus one for each lambda along the way
+pub fn gen_expr(depth: usize, rng: &mut Rng) -> RecursiveExpr {
+    let root = Seed {
+        shape: Shape::Of(rng.pick(&TYPES)),
+        depth,
+        scope: Vec::new(),
+        rng: rng.split(),
+    };
+    RecursiveExpr::expand_layers(root, expand_layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::eval::{
+        arithmetic, equal, eval, eval_arena, less_than, ArithOp, EvalErrorKind,
+    };
+    use crate::examples::expr::lang::typecheck::typecheck;
+    use crate::examples::expr::lang::{to_ast, Env, ExprAST, Value};
+
+    #[derive(Clone)]
+    enum RefValue<'a> {
+        Value(Value),
+        Closure(&'a str, &'a ExprAST, Env<RefValue<'a>>),
+    }
+
+    /// Reference evaluator: plain recursion over the boxed AST, sharing only the arithmetic
+    /// and comparison rules with 'eval'
+    fn reference_eval<'a>(
+        expr: &'a ExprAST,
+        env: &Env<RefValue<'a>>,
+    ) -> Result<RefValue<'a>, EvalErrorKind> {
+        let value = |expr: &'a ExprAST| match reference_eval(expr, env)? {
+            RefValue::Value(v) => Ok(v),
+            RefValue::Closure(..) => panic!("generated expressions are well-typed"),
+        };
+        let arith = |op, a, b| {
+            arithmetic(op, value(a)?, value(b)?)
+                .map(RefValue::Value)
+                .map_err(|e| e.kind)
+        };
+        let bool = |v: bool| Ok(RefValue::Value(Value::Bool(v)));
+
+        match expr {
+            ExprAST::Add(a, b) => arith(ArithOp::Add, a, b),
+            ExprAST::Sub(a, b) => arith(ArithOp::Sub, a, b),
+            ExprAST::Mul(a, b) => arith(ArithOp::Mul, a, b),
+            ExprAST::Div(a, b) => arith(ArithOp::Div, a, b),
+            ExprAST::Mod(a, b) => arith(ArithOp::Mod, a, b),
+            ExprAST::Lt(a, b) => bool(less_than(value(a)?, value(b)?).map_err(|e| e.kind)?),
+            ExprAST::Eq(a, b) => bool(equal(value(a)?, value(b)?).map_err(|e| e.kind)?),
+            ExprAST::And(a, b) => match value(a)? {
+                Value::Bool(false) => bool(false),
+                _ => reference_eval(b, env),
+            },
+            ExprAST::Or(a, b) => match value(a)? {
+                Value::Bool(true) => bool(true),
+                _ => reference_eval(b, env),
+            },
+            ExprAST::If(cond, a, b) => match value(cond)? {
+                Value::Bool(true) => reference_eval(a, env),
+                _ => reference_eval(b, env),
+            },
+            ExprAST::LiteralInt(x) => Ok(RefValue::Value(Value::Int(*x))),
+            ExprAST::LiteralFloat(x) => Ok(RefValue::Value(Value::Float(*x))),
+            ExprAST::LiteralBool(x) => bool(*x),
+            ExprAST::Var(name) => Ok(env
+                .lookup(name)
+                .expect("generated expressions are closed")
+                .clone()),
+            ExprAST::Let(name, bound, body) => {
+                let bound = reference_eval(bound, env)?;
+                reference_eval(body, &env.bind(name.clone(), bound))
+            }
+            ExprAST::Lambda(param, body) => Ok(RefValue::Closure(param, body, env.clone())),
+            ExprAST::App(f, arg) => match reference_eval(f, env)? {
+                RefValue::Closure(param, body, captured) => {
+                    let arg = reference_eval(arg, env)?;
+                    reference_eval(body, &captured.bind(param, arg))
+                }
+                RefValue::Value(_) => panic!("generated expressions are well-typed"),
+            },
+        }
+    }
+
+    #[test]
+    fn deterministic() {
+        let generate = |seed| to_ast(&gen_expr(5, &mut Rng::new(seed)));
+        assert_eq!(generate(7), generate(7));
+        assert_ne!(generate(7), generate(8));
+    }
+
+    #[test]
+    fn matches_reference() {
+        let mut rng = Rng::new(0);
+        let mut failures = 0;
+        for _ in 0..5000 {
+            let expr = gen_expr(6, &mut rng);
+            let ast = to_ast(&expr);
+            assert!(typecheck(&ast).is_ok(), "ill-typed: {:?}", ast);
+
+            let expected = reference_eval(&ast, &Env::default()).map(|v| match v {
+                RefValue::Value(v) => v,
+                RefValue::Closure(..) => panic!("generated expressions aren't functions"),
+            });
+            failures += expected.is_err() as usize;
+            assert_eq!(eval_arena(&expr).map_err(|e| e.kind), expected, "{:?}", ast);
+            assert_eq!(eval(&ast).map_err(|e| e.kind), expected, "{:?}", ast);
+        }
+        // runtime errors should be exercised too, but shouldn't dominate
+        assert!(0 < failures && failures < 2500, "{} failures", failures);
+    }
+}
@@ -0,0 +1,267 @@
+//! Interval analysis, as an example of abstract interpretation: the same layers are given a
+//! non-standard semantics, where each subexpression is collapsed into an approximation of every
+//! value it could take rather than a single value. The approximation is sound - every value
+//! evaluation could produce is within it - but not necessarily precise.
+//!
+//! As with 'eval', what's known about variables is inherited top-down, so each subtree is first
+//! collapsed into a function from the abstract environment to its abstract value. Static analyses
+//! over other domains (signs, constants, nullability) can follow the same shape.
+
+use crate::examples::expr::lang::{Env, Expr, RecursiveExpr, Value};
+use crate::recursive::Collapse;
+
+/// Every number between 'lo' and 'hi' inclusive. Ints and floats share the same representation,
+/// so bounds on ints beyond 2^53 are approximate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub lo: f64,
+    pub hi: f64,
+}
+
+impl Interval {
+    pub fn new(lo: f64, hi: f64) -> Self {
+        Interval { lo, hi }
+    }
+
+    pub fn point(x: f64) -> Self {
+        Interval { lo: x, hi: x }
+    }
+
+    /// Any number at all
+    pub fn any() -> Self {
+        Interval::new(f64::NEG_INFINITY, f64::INFINITY)
+    }
+
+    pub fn contains(&self, x: f64) -> bool {
+        self.lo <= x && x <= self.hi
+    }
+
+    fn hull(self, other: Interval) -> Interval {
+        Interval::new(self.lo.min(other.lo), self.hi.max(other.hi))
+    }
+
+    // the smallest interval containing all four combinations of bounds
+    fn combine(self, other: Interval, op: impl Fn(f64, f64) -> f64) -> Interval {
+        let bounds = [
+            op(self.lo, other.lo),
+            op(self.lo, other.hi),
+            op(self.hi, other.lo),
+            op(self.hi, other.hi),
+        ]
+        // only '0 * inf' is undefined, and zero is the limit as either bound approaches it
+        .map(|x| if x.is_nan() { 0.0 } else { x });
+        Interval::new(
+            bounds.into_iter().fold(f64::INFINITY, f64::min),
+            bounds.into_iter().fold(f64::NEG_INFINITY, f64::max),
+        )
+    }
+}
+
+/// An approximation of the values some subexpression could take
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Abstract {
+    Number(Interval),
+    /// 'None' if it could be either
+    Bool(Option<bool>),
+    /// Nothing is known, eg for functions and unbound variables
+    Unknown,
+}
+
+impl Abstract {
+    fn join(self, other: Abstract) -> Abstract {
+        match (self, other) {
+            (Abstract::Number(a), Abstract::Number(b)) => Abstract::Number(a.hull(b)),
+            (Abstract::Bool(a), Abstract::Bool(b)) if a == b => Abstract::Bool(a),
+            (Abstract::Bool(_), Abstract::Bool(_)) => Abstract::Bool(None),
+            _ => Abstract::Unknown,
+        }
+    }
+
+    /// Whether 'value' is one of the values approximated
+    pub fn contains(&self, value: &Value) -> bool {
+        match (self, value) {
+            (Abstract::Number(i), Value::Int(x)) => i.contains(*x as f64),
+            (Abstract::Number(i), Value::Float(x)) => i.contains(*x),
+            (Abstract::Bool(b), Value::Bool(x)) => b.is_none_or(|b| b == *x),
+            (Abstract::Unknown, _) => true,
+            _ => false,
+        }
+    }
+}
+
+fn numbers(a: Abstract, b: Abstract, op: impl FnOnce(Interval, Interval) -> Interval) -> Abstract {
+    match (a, b) {
+        (Abstract::Number(a), Abstract::Number(b)) => Abstract::Number(op(a, b)),
+        // a type error, which evaluation will catch
+        _ => Abstract::Unknown,
+    }
+}
+
+fn divide(a: Interval, b: Interval) -> Interval {
+    if b.contains(0.0) {
+        return Interval::any();
+    }
+    let quotient = a.combine(b, |x, y| x / y);
+    // integer division truncates, which can move the result outwards to the next integer
+    Interval::new(quotient.lo.floor(), quotient.hi.ceil())
+}
+
+fn remainder(a: Interval, b: Interval) -> Interval {
+    if b.contains(0.0) {
+        return Interval::any();
+    }
+    // the remainder has the sign of the dividend, and is smaller in magnitude than the divisor
+    let max = b.lo.abs().max(b.hi.abs());
+    Interval::new(a.lo.min(0.0).max(-max), a.hi.max(0.0).min(max))
+}
+
+fn less_than(a: Interval, b: Interval) -> Option<bool> {
+    if a.hi < b.lo {
+        Some(true)
+    } else if a.lo >= b.hi {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn equal(a: Abstract, b: Abstract) -> Abstract {
+    Abstract::Bool(match (a, b) {
+        (Abstract::Number(a), Abstract::Number(b)) if a.hi < b.lo || b.hi < a.lo => Some(false),
+        (Abstract::Number(a), Abstract::Number(b)) if a.lo == a.hi && a == b => Some(true),
+        (Abstract::Bool(Some(a)), Abstract::Bool(Some(b))) => Some(a == b),
+        _ => None,
+    })
+}
+
+// 'a && b' is 'if a then b else false', and 'a || b' is 'if a then true else b', where 'decisive'
+// is the value of 'a' that decides the result without evaluating 'b'
+fn short_circuit(a: Abstract, b: impl FnOnce() -> Abstract, decisive: bool) -> Abstract {
+    let rhs = || match b() {
+        b @ Abstract::Bool(_) => b,
+        // a type error, which evaluation will catch
+        _ => Abstract::Unknown,
+    };
+    match a {
+        Abstract::Bool(Some(x)) if x == decisive => Abstract::Bool(Some(decisive)),
+        Abstract::Bool(Some(_)) => rhs(),
+        Abstract::Bool(None) => rhs().join(Abstract::Bool(Some(decisive))),
+        _ => Abstract::Unknown,
+    }
+}
+
+/// A subtree, awaiting what's known about the variables in scope
+pub type Analysis = Box<dyn FnOnce(&Env<Abstract>) -> Abstract>;
+
+pub fn interval_layer(layer: Expr<Analysis>) -> Analysis {
+    match layer {
+        Expr::Add(a, b) => {
+            Box::new(move |env| numbers(a(env), b(env), |a, b| a.combine(b, |x, y| x + y)))
+        }
+        Expr::Sub(a, b) => Box::new(move |env| {
+            numbers(a(env), b(env), |a, b| {
+                Interval::new(a.lo - b.hi, a.hi - b.lo)
+            })
+        }),
+        Expr::Mul(a, b) => {
+            Box::new(move |env| numbers(a(env), b(env), |a, b| a.combine(b, |x, y| x * y)))
+        }
+        Expr::Div(a, b) => Box::new(move |env| numbers(a(env), b(env), divide)),
+        Expr::Mod(a, b) => Box::new(move |env| numbers(a(env), b(env), remainder)),
+        Expr::Lt(a, b) => Box::new(move |env| match (a(env), b(env)) {
+            (Abstract::Number(a), Abstract::Number(b)) => Abstract::Bool(less_than(a, b)),
+            _ => Abstract::Unknown,
+        }),
+        Expr::Eq(a, b) => Box::new(move |env| equal(a(env), b(env))),
+        Expr::And(a, b) => Box::new(move |env| short_circuit(a(env), || b(env), false)),
+        Expr::Or(a, b) => Box::new(move |env| short_circuit(a(env), || b(env), true)),
+        // if the condition is known, the other branch can't contribute
+        Expr::If(cond, a, b) => Box::new(move |env| match cond(env) {
+            Abstract::Bool(Some(true)) => a(env),
+            Abstract::Bool(Some(false)) => b(env),
+            _ => a(env).join(b(env)),
+        }),
+        Expr::LiteralInt(x) => Box::new(move |_| Abstract::Number(Interval::point(x as f64))),
+        Expr::LiteralFloat(x) => Box::new(move |_| Abstract::Number(Interval::point(x))),
+        Expr::LiteralBool(x) => Box::new(move |_| Abstract::Bool(Some(x))),
+        Expr::Var(name) => Box::new(move |env| *env.lookup(&name).unwrap_or(&Abstract::Unknown)),
+        Expr::Let(name, value, body) => Box::new(move |env| {
+            let value = value(env);
+            body(&env.bind(name, value))
+        }),
+        // functions aren't tracked, so neither are the results of calling them
+        Expr::Lambda(_, _) | Expr::App(_, _) => Box::new(|_| Abstract::Unknown),
+    }
+}
+
+/// Approximate the values 'expr' could evaluate to, given approximations of its free variables
+pub fn analyze(expr: &RecursiveExpr, env: &Env<Abstract>) -> Abstract {
+    expr.as_ref().collapse_layers(interval_layer)(env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::eval::eval_arena;
+    use crate::examples::expr::lang::generate::{gen_expr, Rng};
+    use crate::examples::expr::lang::parse::parse;
+
+    fn range(input: &str, lo: f64, hi: f64) -> Abstract {
+        let env = Env::default().bind("x", Abstract::Number(Interval::new(lo, hi)));
+        analyze(&parse(input).unwrap(), &env)
+    }
+
+    fn number(lo: f64, hi: f64) -> Abstract {
+        Abstract::Number(Interval::new(lo, hi))
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(range("x * 2 + 1", 0.0, 10.0), number(1.0, 21.0));
+        assert_eq!(
+            range("if x < 5 then x else 10 - x", 0.0, 10.0),
+            number(0.0, 10.0)
+        );
+        assert_eq!(range("x < 20", 0.0, 10.0), Abstract::Bool(Some(true)));
+        assert_eq!(range("x == 20", 0.0, 10.0), Abstract::Bool(Some(false)));
+        assert_eq!(range("x == 5", 0.0, 10.0), Abstract::Bool(None));
+        assert_eq!(
+            range("x < 5 && 20 < x", 0.0, 10.0),
+            Abstract::Bool(Some(false))
+        );
+        assert_eq!(range("x < 20 || y", 0.0, 10.0), Abstract::Bool(Some(true)));
+        // the analysis isn't relational, so 'y * y' could be negative as far as it knows
+        assert_eq!(
+            range("let y = x - 5 in y * y", 0.0, 10.0),
+            number(-25.0, 25.0)
+        );
+    }
+
+    #[test]
+    fn division() {
+        assert_eq!(
+            range("10 / x", 0.0, 10.0),
+            Abstract::Number(Interval::any())
+        );
+        assert_eq!(range("10 / x", 1.0, 10.0), number(1.0, 10.0));
+        assert_eq!(range("10 / x", 3.0, 4.0), number(2.0, 4.0));
+        assert_eq!(range("x % 3", -10.0, 2.0), number(-3.0, 2.0));
+    }
+
+    #[test]
+    fn sound() {
+        let mut rng = Rng::new(2);
+        for _ in 0..1000 {
+            let expr = gen_expr(6, &mut rng);
+            let approximation = analyze(&expr, &Env::default());
+            if let Ok(value) = eval_arena(&expr) {
+                assert!(
+                    approximation.contains(&value),
+                    "{:?} not in {:?}",
+                    value,
+                    approximation
+                );
+            }
+        }
+    }
+}
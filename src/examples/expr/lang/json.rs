@@ -0,0 +1,51 @@
+//! Persisting expressions as json. 'Expr' derives serde's traits generically over its child type,
+//! so an arena-backed 'RecursiveExpr' serializes as a flat array of layers with children referred
+//! to by position, eg '1 + x' is `[{"Add":[1,2]},{"LiteralInt":1},{"Var":"x"}]`. The same pattern
+//! works for any layer type, and for any serde format.
+
+use crate::examples::expr::lang::RecursiveExpr;
+
+pub fn to_json(expr: &RecursiveExpr) -> String {
+    serde_json::to_string(expr).expect("layers always serialize")
+}
+
+/// Fails on malformed json, and on layers that don't describe a tree
+pub fn from_json(json: &str) -> serde_json::Result<RecursiveExpr> {
+    serde_json::from_str(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::parse::parse;
+    use crate::examples::expr::lang::{arb_expr, from_ast, to_ast};
+    use proptest::prelude::*;
+
+    #[test]
+    fn format() {
+        let expr = parse("let f = \\x -> x * 2.5 in f 1").unwrap();
+        let json = to_json(&expr);
+        assert_eq!(
+            json,
+            concat!(
+                r#"[{"Let":["f",1,2]},{"Lambda":["x",3]},{"App":[4,5]},{"Mul":[6,7]},"#,
+                r#"{"Var":"f"},{"LiteralInt":1},{"Var":"x"},{"LiteralFloat":2.5}]"#
+            )
+        );
+        assert_eq!(to_ast(&from_json(&json).unwrap()), to_ast(&expr));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(from_json(r#"[{"Add":[1,2]},{"LiteralInt":1}]"#).is_err());
+        assert!(from_json(r#"[{"Mul":[1,2]},{"LiteralInt":1},{"Var":3}]"#).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(expr in arb_expr()) {
+            let json = to_json(&from_ast(&expr));
+            prop_assert_eq!(to_ast(&from_json(&json).unwrap()), expr);
+        }
+    }
+}
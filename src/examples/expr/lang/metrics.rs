@@ -0,0 +1,178 @@
+//! Size and complexity metrics: node count, depth, and how often each operator and operand occurs,
+//! from which Halstead's measures are derived. Each metric is its own collapse function, and they're
+//! combined via 'product' so that all of them are computed in a single pass over the tree.
+//!
+//! Operators are every non-leaf layer, including binders and application. Operands are literals and
+//! names, with the name introduced by a binder counted as an occurrence of that name.
+
+use std::collections::BTreeMap;
+
+use crate::examples::expr::lang::{Expr, RecursiveExpr};
+use crate::map_layer::MapLayer;
+use crate::recursive::{product, Collapse};
+
+/// Number of occurrences of each distinct operator or operand
+pub type Histogram<K> = BTreeMap<K, usize>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    pub nodes: usize,
+    /// number of layers on the longest path from the root to a leaf
+    pub depth: usize,
+    pub operators: Histogram<&'static str>,
+    pub operands: Histogram<String>,
+}
+
+impl Metrics {
+    /// Number of distinct operators and operands
+    pub fn vocabulary(&self) -> usize {
+        self.operators.len() + self.operands.len()
+    }
+
+    /// Total occurrences of operators and operands
+    pub fn length(&self) -> usize {
+        self.operators.values().sum::<usize>() + self.operands.values().sum::<usize>()
+    }
+
+    /// Bits needed to write the program down, given its vocabulary
+    pub fn volume(&self) -> f64 {
+        self.length() as f64 * (self.vocabulary() as f64).log2()
+    }
+
+    /// Grows with the number of distinct operators, and with how often operands are reused
+    pub fn difficulty(&self) -> f64 {
+        let total_operands = self.operands.values().sum::<usize>();
+        self.operators.len() as f64 / 2.0 * total_operands as f64 / self.operands.len() as f64
+    }
+
+    pub fn effort(&self) -> f64 {
+        self.difficulty() * self.volume()
+    }
+}
+
+fn operator<A>(layer: &Expr<A>) -> Option<&'static str> {
+    Some(match layer {
+        Expr::Add(_, _) => "+",
+        Expr::Sub(_, _) => "-",
+        Expr::Mul(_, _) => "*",
+        Expr::Div(_, _) => "/",
+        Expr::Mod(_, _) => "%",
+        Expr::Eq(_, _) => "==",
+        Expr::Lt(_, _) => "<",
+        Expr::And(_, _) => "&&",
+        Expr::Or(_, _) => "||",
+        Expr::If(_, _, _) => "if",
+        Expr::Let(_, _, _) => "let",
+        Expr::Lambda(_, _) => "\\",
+        Expr::App(_, _) => "app",
+        Expr::LiteralInt(_) | Expr::LiteralFloat(_) | Expr::LiteralBool(_) | Expr::Var(_) => {
+            return None
+        }
+    })
+}
+
+fn operand<A>(layer: &Expr<A>) -> Option<String> {
+    match layer {
+        Expr::LiteralInt(x) => Some(x.to_string()),
+        Expr::LiteralFloat(x) => Some(format!("{:?}", x)),
+        Expr::LiteralBool(x) => Some(x.to_string()),
+        Expr::Var(name) | Expr::Let(name, _, _) | Expr::Lambda(name, _) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+fn count_nodes(layer: Expr<usize>) -> usize {
+    let mut nodes = 1;
+    layer.map_layer(|child| nodes += child);
+    nodes
+}
+
+fn depth(layer: Expr<usize>) -> usize {
+    let mut deepest = 0;
+    layer.map_layer(|child| deepest = deepest.max(child));
+    deepest + 1
+}
+
+// merge the children's histograms, then add this layer's entry, if any
+fn tally<K: Ord>(
+    key: impl Fn(&Expr<Histogram<K>>) -> Option<K>,
+) -> impl Fn(Expr<Histogram<K>>) -> Histogram<K> {
+    move |layer| {
+        let own = key(&layer);
+        let mut histogram = Histogram::new();
+        layer.map_layer(|child| {
+            for (k, n) in child {
+                *histogram.entry(k).or_insert(0) += n;
+            }
+        });
+        if let Some(k) = own {
+            *histogram.entry(k).or_insert(0) += 1;
+        }
+        histogram
+    }
+}
+
+/// Compute every metric in a single pass
+pub fn metrics(expr: &RecursiveExpr) -> Metrics {
+    let ((nodes, depth), (operators, operands)) = expr.as_ref().collapse_layers(product(
+        product(count_nodes, depth),
+        product(tally(operator), tally(operand)),
+    ));
+    Metrics {
+        nodes,
+        depth,
+        operators,
+        operands,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::generate::{gen_expr, Rng};
+    use crate::examples::expr::lang::parse::parse;
+
+    #[test]
+    fn counts() {
+        let m = metrics(&parse("let x = 1 in x + x * 2").unwrap());
+        assert_eq!(m.nodes, 7);
+        // let, +, *, x
+        assert_eq!(m.depth, 4);
+        assert_eq!(
+            m.operators,
+            Histogram::from([("let", 1), ("+", 1), ("*", 1)])
+        );
+        assert_eq!(
+            m.operands,
+            Histogram::from([
+                ("x".to_string(), 3),
+                ("1".to_string(), 1),
+                ("2".to_string(), 1)
+            ])
+        );
+
+        assert_eq!(m.vocabulary(), 6);
+        assert_eq!(m.length(), 8);
+        assert!((m.volume() - 8.0 * 6f64.log2()).abs() < 1e-9);
+        // 3 distinct operators, 5 occurrences of 3 distinct operands
+        assert!((m.difficulty() - 2.5).abs() < 1e-9);
+        assert!((m.effort() - 2.5 * m.volume()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn every_node_is_counted() {
+        let mut rng = Rng::new(7);
+        for _ in 0..200 {
+            let expr = gen_expr(6, &mut rng);
+            let m = metrics(&expr);
+            assert_eq!(m.nodes, expr.elems.len());
+            // each node is either an operator or an operand, and binders are both
+            let binders = expr
+                .elems
+                .iter()
+                .filter(|layer| matches!(layer, Expr::Let(..) | Expr::Lambda(..)))
+                .count();
+            assert_eq!(m.length(), m.nodes + binders);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Normalization into a canonical form, such that expressions that differ only by the order and
+//! grouping of additions and multiplications, or of the operands of '==', normalize to the same
+//! thing.
+//!
+//! Nested sums and products are flattened into a single variadic layer, integer constants are
+//! folded together and moved to the end, and the remaining operands are sorted. This is exact for
+//! integer arithmetic that doesn't overflow, but reassociating may change whether evaluation
+//! overflows, or how float results are rounded.
+
+use crate::examples::expr::lang::{embed, from_ast, Expr, ExprAST, RecursiveExpr};
+use crate::map_layer::MapLayer;
+use crate::recursive::{Collapse, Expand};
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// A layer of a normalized expression, where sums and products may have any number of operands
+#[derive(Debug, Clone, PartialEq)]
+pub enum Norm<A> {
+    Sum(Vec<A>),
+    Product(Vec<A>),
+    /// Any other layer
+    Other(Expr<A>),
+}
+
+impl<A, B> MapLayer<B> for Norm<A> {
+    type To = Norm<B>;
+    type Unwrapped = A;
+
+    #[inline(always)]
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, f: F) -> Self::To {
+        match self {
+            Norm::Sum(xs) => Norm::Sum(xs.into_iter().map(f).collect()),
+            Norm::Product(xs) => Norm::Product(xs.into_iter().map(f).collect()),
+            Norm::Other(layer) => Norm::Other(layer.map_layer(f)),
+        }
+    }
+}
+
+impl<'a, A: Copy, B: 'a> MapLayer<B> for &'a Norm<A> {
+    type To = Norm<B>;
+    type Unwrapped = A;
+
+    #[inline(always)]
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            Norm::Sum(xs) => Norm::Sum(xs.iter().map(|x| f(*x)).collect()),
+            Norm::Product(xs) => Norm::Product(xs.iter().map(|x| f(*x)).collect()),
+            Norm::Other(layer) => Norm::Other(layer.map_layer(f)),
+        }
+    }
+}
+
+pub type NormalExpr = RecursiveTree<Norm<ArenaIndex>, ArenaIndex>;
+
+/// A normalized subtree, boxed so that subtrees can be compared and sorted while normalizing
+#[derive(Debug, Clone, PartialEq)]
+pub struct Canonical(Norm<Box<Canonical>>);
+
+impl Canonical {
+    fn other(layer: Expr<Canonical>) -> Self {
+        Canonical(Norm::Other(layer.map_layer(Box::new)))
+    }
+
+    fn int(&self) -> Option<i64> {
+        match self.0 {
+            Norm::Other(Expr::LiteralInt(x)) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+// any total order will do, as long as it only depends on structure
+fn sort(operands: &mut [Canonical]) {
+    operands.sort_by_cached_key(|operand| format!("{:?}", operand));
+}
+
+/// Flatten a sum or product of two normalized operands into a single layer
+fn variadic(sum: bool, operands: [Canonical; 2]) -> Canonical {
+    let identity = if sum { 0 } else { 1 };
+    let fold = |a: i64, b: i64| {
+        if sum {
+            a.checked_add(b)
+        } else {
+            a.checked_mul(b)
+        }
+    };
+
+    let mut terms = Vec::new();
+    for operand in operands {
+        match operand.0 {
+            Norm::Sum(xs) if sum => terms.extend(xs.into_iter().map(|x| *x)),
+            Norm::Product(xs) if !sum => terms.extend(xs.into_iter().map(|x| *x)),
+            other => terms.push(Canonical(other)),
+        }
+    }
+
+    // constants that would overflow if folded are left as separate terms
+    let mut constant = identity;
+    terms.retain(|term| match term.int().and_then(|x| fold(constant, x)) {
+        Some(folded) => {
+            constant = folded;
+            false
+        }
+        None => true,
+    });
+    sort(&mut terms);
+    if constant != identity || terms.is_empty() {
+        terms.push(Canonical::other(Expr::LiteralInt(constant)));
+    }
+
+    if terms.len() == 1 {
+        return terms.pop().unwrap();
+    }
+    let terms = terms.into_iter().map(Box::new).collect();
+    Canonical(if sum {
+        Norm::Sum(terms)
+    } else {
+        Norm::Product(terms)
+    })
+}
+
+pub fn normalize_layer(layer: Expr<Canonical>) -> Canonical {
+    match layer {
+        Expr::Add(a, b) => variadic(true, [a, b]),
+        Expr::Mul(a, b) => variadic(false, [a, b]),
+        Expr::Eq(a, b) => {
+            let mut operands = [a, b];
+            sort(&mut operands);
+            let [a, b] = operands;
+            Canonical::other(Expr::Eq(a, b))
+        }
+        layer => Canonical::other(layer),
+    }
+}
+
+fn canonical(expr: &RecursiveExpr) -> Canonical {
+    expr.as_ref().collapse_layers(normalize_layer)
+}
+
+pub fn normalize(expr: &RecursiveExpr) -> NormalExpr {
+    NormalExpr::expand_layers(canonical(expr), |Canonical(layer)| {
+        layer.map_layer(|operand| *operand)
+    })
+}
+
+/// Convert back to binary operators, grouping sums and products to the left
+pub fn denormalize(expr: &NormalExpr) -> RecursiveExpr {
+    let ast = expr.as_ref().collapse_layers(|layer: Norm<ExprAST>| {
+        let nest = |xs: Vec<ExprAST>, op: fn(Box<ExprAST>, Box<ExprAST>) -> ExprAST| {
+            xs.into_iter()
+                .reduce(|a, b| op(Box::new(a), Box::new(b)))
+                .expect("sums and products have at least two operands")
+        };
+        match layer {
+            Norm::Sum(xs) => nest(xs, ExprAST::Add),
+            Norm::Product(xs) => nest(xs, ExprAST::Mul),
+            Norm::Other(layer) => embed(layer),
+        }
+    });
+    from_ast(&ast)
+}
+
+/// Whether two expressions are equal up to reordering and regrouping sums and products, and
+/// folding integer constants
+pub fn equivalent(a: &RecursiveExpr, b: &RecursiveExpr) -> bool {
+    canonical(a) == canonical(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::parse::parse;
+    use crate::examples::expr::lang::pretty::pretty;
+
+    fn normalized(input: &str) -> String {
+        pretty(&denormalize(&normalize(&parse(input).unwrap())))
+    }
+
+    fn equiv(a: &str, b: &str) -> bool {
+        equivalent(&parse(a).unwrap(), &parse(b).unwrap())
+    }
+
+    #[test]
+    fn flattens() {
+        let expr = normalize(&parse("x * (2 * y) + 3 + (z + 1)").unwrap());
+        match &expr.elems[0] {
+            Norm::Sum(terms) => assert_eq!(terms.len(), 3),
+            layer => panic!("expected a sum, got {:?}", layer),
+        }
+        assert_eq!(normalized("x * (2 * y) + 3 + (z + 1)"), "z + x * y * 2 + 4");
+        // constants are folded away entirely where possible
+        assert_eq!(normalized("(1 + x) + -1"), "x");
+        assert_eq!(normalized("2 * 3 + 4"), "10");
+        // other layers are left alone, apart from their children
+        assert_eq!(
+            normalized("\\f -> f (b - (a + 1))"),
+            "\\f -> f (b - (a + 1))"
+        );
+    }
+
+    #[test]
+    fn semantic_equality() {
+        assert!(equiv("a + (b + c)", "(c + a) + b"));
+        assert!(equiv("2 * x + 3", "3 + x * 2"));
+        assert!(equiv("1 + 2 == x", "x == 3"));
+        assert!(equiv("let y = a * b in y", "let y = b * a in y"));
+        assert!(!equiv("x - y", "y - x"));
+        assert!(!equiv("x * (y + 1)", "x * y + x"));
+    }
+}
@@ -0,0 +1,108 @@
+//! Constant folding. Each subtree is collapsed into an optimized boxed expression - either a
+//! literal, if everything below it was constant, or the same layer rebuilt around its optimized
+//! children - which is then expanded back out into a new arena. Other rewrite passes can follow
+//! the same shape by swapping out 'fold_layer'.
+
+use crate::examples::expr::lang::{embed, from_ast, Expr, ExprAST, RecursiveExpr};
+use crate::recursive::Collapse;
+
+use ExprAST::{LiteralBool as Bool, LiteralInt as Int};
+
+/// Fold a single layer whose children have already been folded. Arithmetic that would overflow or
+/// divide by zero is left as-is, so that the failure (if any) still happens at evaluation time.
+pub fn fold_layer(layer: Expr<ExprAST>) -> ExprAST {
+    match layer {
+        Expr::Add(Int(a), Int(b)) if a.checked_add(b).is_some() => Int(a + b),
+        Expr::Sub(Int(a), Int(b)) if a.checked_sub(b).is_some() => Int(a - b),
+        Expr::Mul(Int(a), Int(b)) if a.checked_mul(b).is_some() => Int(a * b),
+        Expr::Div(Int(a), Int(b)) if a.checked_div(b).is_some() => Int(a / b),
+        Expr::Mod(Int(a), Int(b)) if a.checked_rem(b).is_some() => Int(a % b),
+        Expr::Lt(Int(a), Int(b)) => Bool(a < b),
+        Expr::Eq(Int(a), Int(b)) => Bool(a == b),
+        Expr::Eq(Bool(a), Bool(b)) => Bool(a == b),
+        // the right hand side isn't evaluated, so it can be dropped
+        Expr::And(Bool(false), _) => Bool(false),
+        Expr::Or(Bool(true), _) => Bool(true),
+        Expr::And(Bool(a), Bool(b)) => Bool(a && b),
+        Expr::Or(Bool(a), Bool(b)) => Bool(a || b),
+        // the branch not taken is dropped, along with any type errors it contains
+        Expr::If(Bool(cond), a, b) => {
+            if cond {
+                a
+            } else {
+                b
+            }
+        }
+        layer => embed(layer),
+    }
+}
+
+pub fn optimize(expr: &RecursiveExpr) -> RecursiveExpr {
+    from_ast(&expr.as_ref().collapse_layers(fold_layer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::eval::eval_arena;
+    use crate::examples::expr::lang::{build::*, to_ast};
+
+    fn optimized(expr: ExprAST) -> ExprAST {
+        to_ast(&optimize(&from_ast(&expr)))
+    }
+
+    #[test]
+    fn folds_constants() {
+        // (1 + 2) * 3 < 10
+        assert_eq!(
+            optimized(lt(mul(add(lit(1), lit(2)), lit(3)), lit(10))),
+            bool_(true)
+        );
+        // if 1 == 2 then x else y + (2 - 3)
+        assert_eq!(
+            optimized(if_(
+                eq(lit(1), lit(2)),
+                var("x"),
+                add(var("y"), sub(lit(2), lit(3)))
+            )),
+            add(var("y"), lit(-1))
+        );
+        // the right hand side of '&&' and '||' is dropped if it wouldn't be evaluated, but is
+        // otherwise kept, to preserve any type errors
+        assert_eq!(optimized(and(bool_(false), var("x"))), bool_(false));
+        assert_eq!(
+            optimized(and(bool_(true), var("x"))),
+            and(bool_(true), var("x"))
+        );
+        assert_eq!(
+            optimized(or(lt(lit(2), lit(1)), eq(lit(1), lit(1)))),
+            bool_(true)
+        );
+    }
+
+    #[test]
+    fn keeps_non_constant_structure() {
+        // \x -> x + (4 * 5), with the constant part folded under the binder
+        let expr = lambda("x", add(var("x"), mul(lit(4), lit(5))));
+        assert_eq!(optimized(expr), lambda("x", add(var("x"), lit(20))));
+
+        // type errors and overflow are left to evaluation
+        let expr = add(bool_(true), add(lit(i64::MAX), lit(1)));
+        assert_eq!(optimized(expr.clone()), expr);
+        let expr = add(div(lit(6), lit(3)), mod_(lit(1), lit(0)));
+        assert_eq!(optimized(expr), add(lit(2), mod_(lit(1), lit(0))));
+    }
+
+    #[test]
+    fn preserves_meaning() {
+        // let f = \x -> x * (2 + 3) in f (10 - 4)
+        let expr = from_ast(&let_(
+            "f",
+            lambda("x", mul(var("x"), add(lit(2), lit(3)))),
+            app(var("f"), sub(lit(10), lit(4))),
+        ));
+        let optimized = optimize(&expr);
+        assert!(optimized.elems.len() < expr.elems.len());
+        assert_eq!(eval_arena(&optimized), eval_arena(&expr));
+    }
+}
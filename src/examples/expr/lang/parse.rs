@@ -0,0 +1,552 @@
+//! Parsing as an anamorphism: the seed is a range of tokens, and each expand step picks the operator
+//! that binds most loosely within that range, splitting it into sub-ranges for its operands.
+//!
+//! Grammar, from loosest to tightest binding:
+//!
+//! ```text
+//! expr := 'let' ident '=' expr 'in' expr
+//!       | 'if' expr 'then' expr 'else' expr
+//!       | '\' ident '->' expr
+//!       | or
+//! or   := and ('||' and)*
+//! and  := cmp ('&&' cmp)*
+//! cmp  := sum (('==' | '<') sum)?
+//! sum  := prod (('+' | '-') prod)*
+//! prod := app (('*' | '/' | '%') app)*
+//! app  := atom atom*
+//! atom := int | float | 'true' | 'false' | ident | '(' expr ')'
+//! ```
+//!
+//! 'let', 'if' and lambdas extend as far to the right as possible. There is no unary minus, but
+//! a '-' immediately followed by a digit is part of a numeric literal unless it directly follows
+//! an operand (eg '1 - -2' or 'f (-2)', but not 'f -2'). Float literals have digits on both sides
+//! of the decimal point, eg '1.0' but not '1.' or '.5'.
+
+use std::fmt;
+
+use crate::examples::expr::lang::{Expr, RecursiveExpr, SpannedExpr};
+use crate::recursive::TryExpand;
+use crate::spanned::{strip_spans, Span, Spanned};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// bytes of the input at which the error was found
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}..{}",
+            self.message, self.span.start, self.span.end
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(i64),
+    Float(f64),
+    Ident(String),
+    Let,
+    In,
+    If,
+    Then,
+    Else,
+    True,
+    False,
+    LParen,
+    RParen,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    EqEq,
+    Lt,
+    AndAnd,
+    OrOr,
+    Assign,
+    Backslash,
+    Arrow,
+}
+
+fn tokenize(input: &str) -> Result<Vec<(Span, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '+' => Token::Plus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '%' => Token::Percent,
+            '<' => Token::Lt,
+            '\\' => Token::Backslash,
+            '-' if chars.next_if(|(_, c)| *c == '>').is_some() => Token::Arrow,
+            '-' if !follows_operand(&tokens)
+                && chars.peek().is_some_and(|(_, c)| c.is_ascii_digit()) =>
+            {
+                numeric_literal(input, offset, &mut chars)?
+            }
+            '-' => Token::Minus,
+            '=' if chars.next_if(|(_, c)| *c == '=').is_some() => Token::EqEq,
+            '=' => Token::Assign,
+            '&' if chars.next_if(|(_, c)| *c == '&').is_some() => Token::AndAnd,
+            '|' if chars.next_if(|(_, c)| *c == '|').is_some() => Token::OrOr,
+            c if c.is_ascii_digit() => numeric_literal(input, offset, &mut chars)?,
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = offset + c.len_utf8();
+                while let Some((idx, c)) = chars.next_if(|(_, c)| c.is_alphanumeric() || *c == '_')
+                {
+                    end = idx + c.len_utf8();
+                }
+                match &input[offset..end] {
+                    "let" => Token::Let,
+                    "in" => Token::In,
+                    "if" => Token::If,
+                    "then" => Token::Then,
+                    "else" => Token::Else,
+                    "true" => Token::True,
+                    "false" => Token::False,
+                    ident => Token::Ident(ident.to_string()),
+                }
+            }
+            c => {
+                return Err(ParseError {
+                    span: offset..offset + c.len_utf8(),
+                    message: format!("unexpected character '{}'", c),
+                })
+            }
+        };
+        // tokens end where the next character starts
+        let end = chars.peek().map_or(input.len(), |(idx, _)| *idx);
+        tokens.push((offset..end, token));
+    }
+
+    Ok(tokens)
+}
+
+fn follows_operand(tokens: &[(Span, Token)]) -> bool {
+    matches!(
+        tokens.last(),
+        Some((
+            _,
+            Token::Int(_)
+                | Token::Float(_)
+                | Token::Ident(_)
+                | Token::True
+                | Token::False
+                | Token::RParen
+        ))
+    )
+}
+
+// consume an integer or float literal starting at 'offset', which may include a leading '-'
+fn numeric_literal(
+    input: &str,
+    offset: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+) -> Result<Token, ParseError> {
+    let mut end = offset + 1;
+    while let Some((idx, _)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+        end = idx + 1;
+    }
+
+    if chars.next_if(|(_, c)| *c == '.').is_none() {
+        return input[offset..end]
+            .parse()
+            .map(Token::Int)
+            .map_err(|_| ParseError {
+                span: offset..end,
+                message: "integer literal out of range".to_string(),
+            });
+    }
+
+    let point = end;
+    while let Some((idx, _)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+        end = idx + 1;
+    }
+    if end == point {
+        return Err(ParseError {
+            span: point..point + 1,
+            message: "expected digits after '.'".to_string(),
+        });
+    }
+    // digits with a single decimal point always parse as a float
+    Ok(Token::Float(input[offset..end].parse().unwrap()))
+}
+
+/// A range of tokens, 'start..end'
+type Tokens = (usize, usize);
+
+struct Parser {
+    tokens: Vec<(Span, Token)>,
+    // for each '(', the index of the matching ')'
+    close: Vec<usize>,
+    input_len: usize,
+}
+
+impl Parser {
+    fn new(input: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(input)?;
+
+        let mut close = vec![0; tokens.len()];
+        let mut open = Vec::new();
+        for (idx, (span, token)) in tokens.iter().enumerate() {
+            match token {
+                Token::LParen => open.push(idx),
+                Token::RParen => match open.pop() {
+                    Some(o) => close[o] = idx,
+                    None => return Err(Self::error_at(span.clone(), "unmatched ')'")),
+                },
+                _ => {}
+            }
+        }
+        if let Some(o) = open.pop() {
+            return Err(Self::error_at(tokens[o].0.clone(), "unmatched '('"));
+        }
+
+        Ok(Self {
+            tokens,
+            close,
+            input_len: input.len(),
+        })
+    }
+
+    fn error_at(span: Span, message: &str) -> ParseError {
+        ParseError {
+            span,
+            message: message.to_string(),
+        }
+    }
+
+    fn error(&self, idx: usize, message: &str) -> ParseError {
+        let span = self
+            .tokens
+            .get(idx)
+            .map_or(self.input_len..self.input_len, |(span, _)| span.clone());
+        Self::error_at(span, message)
+    }
+
+    fn token(&self, idx: usize) -> &Token {
+        &self.tokens[idx].1
+    }
+
+    fn ident(&self, idx: usize, end: usize) -> Result<String, ParseError> {
+        match self.tokens.get(idx).filter(|_| idx < end) {
+            Some((_, Token::Ident(name))) => Ok(name.clone()),
+            _ => Err(self.error(idx, "expected identifier")),
+        }
+    }
+
+    fn expect(
+        &self,
+        idx: usize,
+        end: usize,
+        expected: Token,
+        message: &str,
+    ) -> Result<(), ParseError> {
+        match self.tokens.get(idx).filter(|_| idx < end) {
+            Some((_, t)) if *t == expected => Ok(()),
+            _ => Err(self.error(idx, message)),
+        }
+    }
+
+    // index of the keyword closing a 'let' or 'if' opened just before 'start', skipping over nested ones
+    fn find_closing(&self, start: usize, end: usize, opens: Token, closes: Token) -> Option<usize> {
+        let mut depth = 0;
+        let mut idx = start;
+        while idx < end {
+            match self.token(idx) {
+                Token::LParen => idx = self.close[idx],
+                t if *t == opens => depth += 1,
+                t if *t == closes && depth == 0 => return Some(idx),
+                t if *t == closes => depth -= 1,
+                _ => {}
+            }
+            idx += 1;
+        }
+        None
+    }
+
+    fn expand_layer(&self, tokens: Tokens) -> Result<Spanned<Expr<Tokens>>, ParseError> {
+        let (start, end) = self.strip_parens(tokens);
+        if start == end {
+            return Err(self.error(start, "expected expression"));
+        }
+        let span = self.tokens[start].0.start..self.tokens[end - 1].0.end;
+        Ok(Spanned::new(span, self.expand_tokens(start, end)?))
+    }
+
+    fn strip_parens(&self, (mut start, mut end): Tokens) -> Tokens {
+        // parens only group, they don't correspond to any layer
+        while end - start >= 2
+            && *self.token(start) == Token::LParen
+            && self.close[start] == end - 1
+        {
+            start += 1;
+            end -= 1;
+        }
+        (start, end)
+    }
+
+    fn expand_tokens(&self, start: usize, end: usize) -> Result<Expr<Tokens>, ParseError> {
+        match self.token(start) {
+            Token::Let => {
+                let name = self.ident(start + 1, end)?;
+                self.expect(start + 2, end, Token::Assign, "expected '='")?;
+                let in_idx = self
+                    .find_closing(start + 3, end, Token::Let, Token::In)
+                    .ok_or_else(|| self.error(end, "expected 'in'"))?;
+                return Ok(Expr::Let(name, (start + 3, in_idx), (in_idx + 1, end)));
+            }
+            Token::If => {
+                let then_idx = self
+                    .find_closing(start + 1, end, Token::If, Token::Then)
+                    .ok_or_else(|| self.error(end, "expected 'then'"))?;
+                let else_idx = self
+                    .find_closing(then_idx + 1, end, Token::If, Token::Else)
+                    .ok_or_else(|| self.error(end, "expected 'else'"))?;
+                return Ok(Expr::If(
+                    (start + 1, then_idx),
+                    (then_idx + 1, else_idx),
+                    (else_idx + 1, end),
+                ));
+            }
+            Token::Backslash => {
+                let param = self.ident(start + 1, end)?;
+                self.expect(start + 2, end, Token::Arrow, "expected '->'")?;
+                return Ok(Expr::Lambda(param, (start + 3, end)));
+            }
+            _ => {}
+        }
+
+        // find the loosest-binding operator outside of parens, preferring the rightmost for left
+        // associativity. stops at 'let', 'if' or a lambda, which extend to the end of the range.
+        let mut split: Option<(u8, usize)> = None;
+        let mut comparison = None;
+        // start of each operand of an application
+        let mut atoms = Vec::new();
+        let mut idx = start;
+        while idx < end {
+            let precedence = match self.token(idx) {
+                Token::EqEq | Token::Lt => {
+                    if comparison.is_some() {
+                        return Err(self.error(idx, "comparisons can't be chained"));
+                    }
+                    comparison = Some(idx);
+                    Some(2)
+                }
+                // each operand of '&&' and '||' may have its own comparison
+                Token::OrOr => {
+                    comparison = None;
+                    Some(0)
+                }
+                Token::AndAnd => {
+                    comparison = None;
+                    Some(1)
+                }
+                Token::Plus | Token::Minus => Some(3),
+                Token::Star | Token::Slash | Token::Percent => Some(4),
+                Token::Let | Token::If | Token::Backslash => {
+                    atoms.push(idx);
+                    break;
+                }
+                Token::LParen => {
+                    atoms.push(idx);
+                    idx = self.close[idx];
+                    None
+                }
+                Token::In
+                | Token::Then
+                | Token::Else
+                | Token::Assign
+                | Token::Arrow
+                | Token::RParen => return Err(self.error(idx, "unexpected token")),
+                Token::Int(_) | Token::Float(_) | Token::Ident(_) | Token::True | Token::False => {
+                    atoms.push(idx);
+                    None
+                }
+            };
+            if let Some(precedence) = precedence {
+                if split.is_none_or(|(p, _)| precedence <= p) {
+                    split = Some((precedence, idx));
+                }
+                atoms.clear();
+            }
+            idx += 1;
+        }
+
+        if let Some((_, op)) = split {
+            let (a, b) = ((start, op), (op + 1, end));
+            return Ok(match self.token(op) {
+                Token::EqEq => Expr::Eq(a, b),
+                Token::Lt => Expr::Lt(a, b),
+                Token::AndAnd => Expr::And(a, b),
+                Token::OrOr => Expr::Or(a, b),
+                Token::Plus => Expr::Add(a, b),
+                Token::Minus => Expr::Sub(a, b),
+                Token::Star => Expr::Mul(a, b),
+                Token::Slash => Expr::Div(a, b),
+                Token::Percent => Expr::Mod(a, b),
+                _ => unreachable!("only operators are split on"),
+            });
+        }
+
+        // application is left associative, so the last operand is applied to everything before it
+        if atoms.len() > 1 {
+            let last = atoms[atoms.len() - 1];
+            return Ok(Expr::App((start, last), (last, end)));
+        }
+
+        match self.token(start) {
+            Token::Int(x) => Ok(Expr::LiteralInt(*x)),
+            Token::Float(x) => Ok(Expr::LiteralFloat(*x)),
+            Token::True => Ok(Expr::LiteralBool(true)),
+            Token::False => Ok(Expr::LiteralBool(false)),
+            Token::Ident(name) => Ok(Expr::Var(name.clone())),
+            _ => Err(self.error(start, "unexpected token")),
+        }
+    }
+}
+
+/// Parse, keeping the span of source that each node was parsed from
+pub fn parse_spanned(input: &str) -> Result<SpannedExpr, ParseError> {
+    let parser = Parser::new(input)?;
+    SpannedExpr::try_expand_layers((0, parser.tokens.len()), |tokens| {
+        parser.expand_layer(tokens)
+    })
+}
+
+pub fn parse(input: &str) -> Result<RecursiveExpr, ParseError> {
+    parse_spanned(input).map(strip_spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::eval::eval_arena;
+    use crate::examples::expr::lang::{build::*, to_ast, Value};
+
+    fn parse_ast(input: &str) -> crate::examples::expr::lang::ExprAST {
+        to_ast(&parse(input).unwrap())
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            parse_ast("1 + 2 * 3 < 10 - 3 - 2"),
+            lt(
+                add(lit(1), mul(lit(2), lit(3))),
+                sub(sub(lit(10), lit(3)), lit(2))
+            )
+        );
+        assert_eq!(parse_ast("(1 + 2) * 3"), mul(add(lit(1), lit(2)), lit(3)));
+        // division binds like multiplication, and also associates to the left
+        assert_eq!(
+            parse_ast("1 + 12 / 3 % 2 * 5"),
+            add(lit(1), mul(mod_(div(lit(12), lit(3)), lit(2)), lit(5)))
+        );
+        // '&&' binds tighter than '||', and each operand may be a comparison
+        assert_eq!(
+            parse_ast("a || x < 1 && y == 2 || b"),
+            or(
+                or(var("a"), and(lt(var("x"), lit(1)), eq(var("y"), lit(2)))),
+                var("b")
+            )
+        );
+    }
+
+    #[test]
+    fn application() {
+        assert_eq!(
+            parse_ast("f x (g y) + 1"),
+            add(
+                app(app(var("f"), var("x")), app(var("g"), var("y"))),
+                lit(1)
+            )
+        );
+        // lambdas extend to the right
+        assert_eq!(
+            parse_ast("f \\x -> x + 1"),
+            app(var("f"), lambda("x", add(var("x"), lit(1))))
+        );
+    }
+
+    #[test]
+    fn binders() {
+        assert_eq!(
+            parse_ast("let x = let y = 1 in y in if x == 1 then x else 0"),
+            let_(
+                "x",
+                let_("y", lit(1), var("y")),
+                if_(eq(var("x"), lit(1)), var("x"), lit(0))
+            )
+        );
+        assert_eq!(
+            parse_ast("if if true then false else true then 1 else 2"),
+            if_(if_(bool_(true), bool_(false), bool_(true)), lit(1), lit(2))
+        );
+    }
+
+    #[test]
+    fn parse_and_eval() {
+        let expr = parse("let twice = \\f -> \\x -> f (f x) in twice (\\y -> y * 2) 5").unwrap();
+        assert_eq!(eval_arena(&expr), Ok(Value::Int(20)));
+    }
+
+    #[test]
+    fn negative_literals() {
+        assert_eq!(parse_ast("1 - -2"), sub(lit(1), lit(-2)));
+        assert_eq!(parse_ast("1-2"), sub(lit(1), lit(2)));
+        assert_eq!(parse_ast("f (-2)"), app(var("f"), lit(-2)));
+        assert_eq!(parse_ast("f -2"), sub(var("f"), lit(2)));
+        assert_eq!(parse_ast("-9223372036854775808"), lit(i64::MIN));
+    }
+
+    #[test]
+    fn float_literals() {
+        assert_eq!(parse_ast("1.5 * x"), mul(float(1.5), var("x")));
+        assert_eq!(parse_ast("2 - -0.25"), sub(lit(2), float(-0.25)));
+        assert_eq!(parse_ast("f 10.0"), app(var("f"), float(10.0)));
+    }
+
+    #[test]
+    fn errors() {
+        let err = |input: &str| parse(input).err().map(|e| (e.span.start, e.message));
+        assert_eq!(err("1 + (2"), Some((4, "unmatched '('".to_string())));
+        assert_eq!(err("1 + 2)"), Some((5, "unmatched ')'".to_string())));
+        assert_eq!(err("1 +"), Some((3, "expected expression".to_string())));
+        assert_eq!(
+            err("1 < 2 < 3"),
+            Some((6, "comparisons can't be chained".to_string()))
+        );
+        assert_eq!(err("let x = 1 x"), Some((11, "expected 'in'".to_string())));
+        assert_eq!(
+            err("let 1 = 1 in 1"),
+            Some((4, "expected identifier".to_string()))
+        );
+        assert_eq!(
+            err("1 $ 2"),
+            Some((2, "unexpected character '$'".to_string()))
+        );
+        assert_eq!(
+            err("a & b"),
+            Some((2, "unexpected character '&'".to_string()))
+        );
+        assert_eq!(
+            err("1. + 2"),
+            Some((1, "expected digits after '.'".to_string()))
+        );
+        assert_eq!(err("1 in 2"), Some((2, "unexpected token".to_string())));
+        assert_eq!(err(""), Some((0, "expected expression".to_string())));
+    }
+}
@@ -0,0 +1,213 @@
+//! Partial evaluation: given values for some free variables, reduce everything that only depends
+//! on known values and leave a residual expression for the rest.
+//!
+//! Each subtree is collapsed into either a known value or a residual layer around its partially
+//! evaluated children, and the result is then expanded back out into an arena. As with 'eval',
+//! which variables are known is inherited top-down, so subtrees are first collapsed into functions
+//! from scope to result.
+
+use crate::examples::expr::lang::eval::{arithmetic, equal, less_than, ArithOp};
+use crate::examples::expr::lang::{Env, Expr, RecursiveExpr, Value};
+use crate::recursive::{Collapse, Expand};
+
+/// A partially evaluated subtree
+#[derive(Debug, Clone)]
+pub enum Partial {
+    Known(Value),
+    /// A layer that couldn't be reduced, around its partially evaluated children
+    Residual(Box<Expr<Partial>>),
+}
+
+impl Partial {
+    fn known(&self) -> Option<Value> {
+        match self {
+            Partial::Known(v) => Some(v.clone()),
+            Partial::Residual(_) => None,
+        }
+    }
+}
+
+/// Variables known from the start, and bindings within the expression. The latter are unknown
+/// (and shadow any outer binding) if the value they're bound to can't be fully reduced.
+pub struct Scope<'a> {
+    known: &'a Env<Value>,
+    local: Env<Option<Value>>,
+}
+
+impl<'a> Scope<'a> {
+    fn lookup(&self, name: &str) -> Option<Value> {
+        match self.local.lookup(name) {
+            Some(local) => local.clone(),
+            None => self.known.lookup(name).cloned(),
+        }
+    }
+
+    fn bind(&self, name: &str, value: Option<Value>) -> Scope<'a> {
+        Scope {
+            known: self.known,
+            local: self.local.bind(name, value),
+        }
+    }
+}
+
+/// A subtree, awaiting the variables in scope
+pub type PartialEval = Box<dyn FnOnce(&Scope<'_>) -> Partial>;
+
+fn residual(layer: Expr<Partial>) -> Partial {
+    Partial::Residual(Box::new(layer))
+}
+
+// operations that would fail are left for evaluation to report
+fn binary(
+    a: Partial,
+    b: Partial,
+    op: impl FnOnce(Value, Value) -> Option<Value>,
+    rebuild: impl FnOnce(Partial, Partial) -> Expr<Partial>,
+) -> Partial {
+    match (a.known(), b.known()) {
+        (Some(x), Some(y)) => op(x, y).map_or_else(|| residual(rebuild(a, b)), Partial::Known),
+        _ => residual(rebuild(a, b)),
+    }
+}
+
+fn arith(
+    op: ArithOp,
+    a: Partial,
+    b: Partial,
+    rebuild: fn(Partial, Partial) -> Expr<Partial>,
+) -> Partial {
+    binary(a, b, |x, y| arithmetic(op, x, y).ok(), rebuild)
+}
+
+pub fn partial_eval_layer(layer: Expr<PartialEval>) -> PartialEval {
+    match layer {
+        Expr::Add(a, b) => Box::new(move |s| arith(ArithOp::Add, a(s), b(s), Expr::Add)),
+        Expr::Sub(a, b) => Box::new(move |s| arith(ArithOp::Sub, a(s), b(s), Expr::Sub)),
+        Expr::Mul(a, b) => Box::new(move |s| arith(ArithOp::Mul, a(s), b(s), Expr::Mul)),
+        Expr::Div(a, b) => Box::new(move |s| arith(ArithOp::Div, a(s), b(s), Expr::Div)),
+        Expr::Mod(a, b) => Box::new(move |s| arith(ArithOp::Mod, a(s), b(s), Expr::Mod)),
+        Expr::Lt(a, b) => Box::new(move |s| {
+            let lt = |x, y| less_than(x, y).ok().map(Value::Bool);
+            binary(a(s), b(s), lt, Expr::Lt)
+        }),
+        Expr::Eq(a, b) => Box::new(move |s| {
+            let eq = |x, y| equal(x, y).ok().map(Value::Bool);
+            binary(a(s), b(s), eq, Expr::Eq)
+        }),
+        // a right hand side that isn't evaluated is dropped
+        Expr::And(a, b) => Box::new(move |s| match a(s) {
+            Partial::Known(Value::Bool(false)) => Partial::Known(Value::Bool(false)),
+            a => match (a.known(), b(s)) {
+                (Some(Value::Bool(true)), Partial::Known(Value::Bool(x))) => {
+                    Partial::Known(Value::Bool(x))
+                }
+                (_, b) => residual(Expr::And(a, b)),
+            },
+        }),
+        Expr::Or(a, b) => Box::new(move |s| match a(s) {
+            Partial::Known(Value::Bool(true)) => Partial::Known(Value::Bool(true)),
+            a => match (a.known(), b(s)) {
+                (Some(Value::Bool(false)), Partial::Known(Value::Bool(x))) => {
+                    Partial::Known(Value::Bool(x))
+                }
+                (_, b) => residual(Expr::Or(a, b)),
+            },
+        }),
+        // the branch not taken is dropped
+        Expr::If(cond, a, b) => Box::new(move |s| match cond(s) {
+            Partial::Known(Value::Bool(true)) => a(s),
+            Partial::Known(Value::Bool(false)) => b(s),
+            cond => residual(Expr::If(cond, a(s), b(s))),
+        }),
+        Expr::LiteralInt(x) => Box::new(move |_| Partial::Known(Value::Int(x))),
+        Expr::LiteralFloat(x) => Box::new(move |_| Partial::Known(Value::Float(x))),
+        Expr::LiteralBool(x) => Box::new(move |_| Partial::Known(Value::Bool(x))),
+        Expr::Var(name) => Box::new(move |s| match s.lookup(&name) {
+            Some(v) => Partial::Known(v),
+            None => residual(Expr::Var(name)),
+        }),
+        // bindings to known values are substituted into the body
+        Expr::Let(name, value, body) => Box::new(move |s| match value(s) {
+            Partial::Known(v) => body(&s.bind(&name, Some(v))),
+            value => {
+                let body = body(&s.bind(&name, None));
+                residual(Expr::Let(name, value, body))
+            }
+        }),
+        // nothing is known about a function's parameter, but its body can still be reduced
+        Expr::Lambda(param, body) => Box::new(move |s| {
+            let body = body(&s.bind(&param, None));
+            residual(Expr::Lambda(param, body))
+        }),
+        Expr::App(f, arg) => Box::new(move |s| residual(Expr::App(f(s), arg(s)))),
+    }
+}
+
+/// Reduce 'expr' as far as possible given the values of some of its free variables
+pub fn partial_eval(expr: &RecursiveExpr, known: &Env<Value>) -> RecursiveExpr {
+    let scope = Scope {
+        known,
+        local: Env::default(),
+    };
+    let partial = expr.as_ref().collapse_layers(partial_eval_layer)(&scope);
+
+    RecursiveExpr::expand_layers(partial, |partial| match partial {
+        Partial::Known(Value::Int(x)) => Expr::LiteralInt(x),
+        Partial::Known(Value::Float(x)) => Expr::LiteralFloat(x),
+        Partial::Known(Value::Bool(x)) => Expr::LiteralBool(x),
+        Partial::Known(Value::Closure { .. }) => unreachable!("lambdas are always residual"),
+        Partial::Residual(layer) => *layer,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::eval::eval_arena;
+    use crate::examples::expr::lang::generate::{gen_expr, Rng};
+    use crate::examples::expr::lang::parse::parse;
+    use crate::examples::expr::lang::pretty::pretty;
+
+    fn residual(input: &str, known: &Env<Value>) -> String {
+        pretty(&partial_eval(&parse(input).unwrap(), known))
+    }
+
+    #[test]
+    fn residuals() {
+        let input = "let y = x * 2 in if flag then y + 3 * 4 else y";
+        let known = Env::default().bind("flag", Value::Bool(true));
+        assert_eq!(residual(input, &known), "let y = x * 2 in y + 12");
+        let known = known.bind("x", Value::Int(6));
+        assert_eq!(residual(input, &known), "24");
+
+        // parameters shadow known variables
+        let known = Env::default()
+            .bind("x", Value::Int(1))
+            .bind("n", Value::Int(2));
+        assert_eq!(residual("\\x -> x + n * n", &known), "\\x -> x + 4");
+        assert_eq!(residual("let x = y in x + n", &known), "let x = y in x + 2");
+
+        // failures are left for evaluation
+        assert_eq!(residual("1 / 0 + (2 + 3)", &Env::default()), "1 / 0 + 5");
+
+        // the right hand side of '&&' is dropped once the left is known to be false
+        let known = Env::default().bind("x", Value::Int(3));
+        assert_eq!(residual("x < 2 && f 1", &known), "false");
+        assert_eq!(residual("x < 5 && y", &known), "true && y");
+        assert_eq!(residual("y || x == 3", &known), "y || true");
+    }
+
+    #[test]
+    fn preserves_meaning() {
+        let mut rng = Rng::new(1);
+        for _ in 0..1000 {
+            let expr = gen_expr(6, &mut rng);
+            let reduced = partial_eval(&expr, &Env::default());
+            assert!(reduced.elems.len() <= expr.elems.len());
+            assert_eq!(
+                eval_arena(&reduced).map_err(|e| e.kind),
+                eval_arena(&expr).map_err(|e| e.kind)
+            );
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Pretty printing with only the parentheses the parser needs. Each subtree is collapsed into its
+//! precedence along with its doc, so the parent can decide whether each operand needs wrapping
+//! based on the operand's position (eg the right operand of a left-associative operator).
+
+use crate::examples::expr::lang::{Expr, RecursiveExpr};
+use crate::pretty::Doc;
+use crate::recursive::Collapse;
+
+/// Binding strength, loosest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Prec {
+    /// 'let', 'if' and lambdas, which extend as far right as possible
+    Binder,
+    Or,
+    And,
+    Compare,
+    Sum,
+    Product,
+    App,
+    Atom,
+}
+
+fn parens(doc: Doc) -> Doc {
+    Doc::text("(").append(doc.nest(1)).append(Doc::text(")"))
+}
+
+// wrap 'doc' in parens unless it binds at least as tightly as 'min'
+fn operand((prec, doc): (Prec, Doc), min: Prec) -> Doc {
+    // binders swallow everything to their right, so they're wrapped wherever they're an operand
+    if prec < min || prec == Prec::Binder {
+        parens(doc)
+    } else {
+        doc
+    }
+}
+
+fn binary(prec: Prec, op: &str, a: (Prec, Doc), b: (Prec, Doc), left_assoc: bool) -> (Prec, Doc) {
+    // for left-associative operators the left operand may be at the same level, eg 'a - b - c'
+    let (left_min, right_min) = match prec {
+        Prec::Sum => (Prec::Sum, Prec::Product),
+        Prec::Product => (Prec::Product, Prec::App),
+        Prec::Or => (Prec::Or, Prec::And),
+        Prec::And => (Prec::And, Prec::Compare),
+        _ => (Prec::Sum, Prec::Sum),
+    };
+    let left_min = if left_assoc { left_min } else { right_min };
+    let doc = Doc::concat([
+        operand(a, left_min),
+        Doc::line(),
+        Doc::text(format!("{} ", op)),
+        operand(b, right_min),
+    ])
+    .group();
+    (prec, doc)
+}
+
+pub fn pretty_layer(layer: Expr<(Prec, Doc)>) -> (Prec, Doc) {
+    match layer {
+        Expr::Add(a, b) => binary(Prec::Sum, "+", a, b, true),
+        Expr::Sub(a, b) => binary(Prec::Sum, "-", a, b, true),
+        Expr::Mul(a, b) => binary(Prec::Product, "*", a, b, true),
+        Expr::Div(a, b) => binary(Prec::Product, "/", a, b, true),
+        Expr::Mod(a, b) => binary(Prec::Product, "%", a, b, true),
+        // comparisons can't be chained
+        Expr::Eq(a, b) => binary(Prec::Compare, "==", a, b, false),
+        Expr::Lt(a, b) => binary(Prec::Compare, "<", a, b, false),
+        Expr::And(a, b) => binary(Prec::And, "&&", a, b, true),
+        Expr::Or(a, b) => binary(Prec::Or, "||", a, b, true),
+        Expr::App(f, arg) => {
+            let doc = Doc::concat([
+                operand(f, Prec::App),
+                Doc::line().append(operand(arg, Prec::Atom)).nest(2),
+            ])
+            .group();
+            (Prec::App, doc)
+        }
+        Expr::If(cond, a, b) => {
+            let doc = Doc::concat([
+                Doc::text("if "),
+                cond.1.nest(3),
+                Doc::line(),
+                Doc::text("then "),
+                a.1.nest(5),
+                Doc::line(),
+                Doc::text("else "),
+                b.1.nest(5),
+            ])
+            .group();
+            (Prec::Binder, doc)
+        }
+        Expr::Let(name, value, body) => {
+            let doc = Doc::concat([
+                Doc::text(format!("let {} =", name)),
+                Doc::line().append(value.1).nest(2),
+                Doc::line(),
+                Doc::text("in"),
+                Doc::line(),
+                body.1,
+            ])
+            .group();
+            (Prec::Binder, doc)
+        }
+        Expr::Lambda(param, body) => {
+            let doc = Doc::text(format!("\\{} ->", param))
+                .append(Doc::line().append(body.1).nest(2))
+                .group();
+            (Prec::Binder, doc)
+        }
+        // '-' following an operand is subtraction, so negative literals can't be arguments as-is
+        Expr::LiteralInt(x) if x < 0 => (Prec::App, Doc::text(x.to_string())),
+        Expr::LiteralInt(x) => (Prec::Atom, Doc::text(x.to_string())),
+        Expr::LiteralFloat(x) => {
+            // floats always have a decimal point, to distinguish them from ints
+            let mut text = x.to_string();
+            if !text.contains('.') {
+                text.push_str(".0");
+            }
+            let prec = if x.is_sign_negative() {
+                Prec::App
+            } else {
+                Prec::Atom
+            };
+            (prec, Doc::text(text))
+        }
+        Expr::LiteralBool(x) => (Prec::Atom, Doc::text(x.to_string())),
+        Expr::Var(name) => (Prec::Atom, Doc::text(name)),
+    }
+}
+
+pub fn pretty_width(expr: &RecursiveExpr, width: usize) -> String {
+    expr.as_ref().collapse_layers(pretty_layer).1.render(width)
+}
+
+pub fn pretty(expr: &RecursiveExpr) -> String {
+    pretty_width(expr, 80)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::parse::parse;
+    use crate::examples::expr::lang::{arb_expr, build::*, from_ast, to_ast};
+    use proptest::prelude::*;
+
+    fn print(expr: crate::examples::expr::lang::ExprAST) -> String {
+        pretty(&from_ast(&expr))
+    }
+
+    #[test]
+    fn minimal_parens() {
+        assert_eq!(print(mul(add(lit(1), lit(2)), lit(3))), "(1 + 2) * 3");
+        assert_eq!(print(add(lit(1), mul(lit(2), lit(3)))), "1 + 2 * 3");
+        assert_eq!(print(sub(sub(lit(10), lit(3)), lit(2))), "10 - 3 - 2");
+        assert_eq!(print(sub(lit(10), sub(lit(3), lit(2)))), "10 - (3 - 2)");
+        assert_eq!(
+            print(eq(lt(lit(1), lit(2)), bool_(true))),
+            "(1 < 2) == true"
+        );
+        assert_eq!(
+            print(app(app(var("f"), var("x")), app(var("g"), lit(-1)))),
+            "f x (g (-1))"
+        );
+        assert_eq!(print(app(lambda("x", var("x")), lit(1))), "(\\x -> x) 1");
+        assert_eq!(
+            print(add(let_("x", lit(1), var("x")), lit(2))),
+            "(let x = 1 in x) + 2"
+        );
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(print(add(float(1.5), float(2.0))), "1.5 + 2.0");
+        assert_eq!(print(app(var("f"), float(-0.5))), "f (-0.5)");
+    }
+
+    #[test]
+    fn breaks_long_lines() {
+        let expr = parse("let double = \\x -> x + x in if double 21 == 42 then double 100 else 0")
+            .unwrap();
+        assert_eq!(
+            pretty_width(&expr, 30),
+            "let double =\n  \\x -> x + x\nin\nif double 21 == 42\nthen double 100\nelse 0"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(expr in arb_expr()) {
+            for width in [10, 80] {
+                let printed = pretty_width(&from_ast(&expr), width);
+                let parsed = parse(&printed).map(|e| to_ast(&e));
+                prop_assert_eq!(parsed, Ok(expr.clone()), "printed: {}", printed);
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! A small term rewriting engine. Rules match a single layer, whose children have already been
+//! rewritten, and build a replacement for it. Rules are applied bottom-up via collapse, repeatedly
+//! at each node until none match, and whole passes are repeated until the tree stops changing, as
+//! a replacement may contain new nodes that are themselves rewritable. Rules can be written by hand
+//! or via 'rewrite!'.
+
+use crate::examples::expr::lang::typecheck::typecheck;
+use crate::examples::expr::lang::{embed, from_ast, to_ast, Expr, ExprAST, RecursiveExpr, Type};
+use crate::recursive::Collapse;
+use crate::rewrite;
+
+pub type Rule = Box<dyn Fn(&Expr<ExprAST>) -> Option<ExprAST>>;
+
+/// An ordered set of rules. Rules should not undo each others' work (eg 'a + b -> b + a'), as
+/// rewriting only stops once no rule matches.
+#[derive(Default)]
+pub struct Rewriter {
+    rules: Vec<Rule>,
+}
+
+// whether 'x' is known to evaluate to an integer, if it evaluates at all
+fn is_int(x: &ExprAST) -> bool {
+    typecheck(x) == Ok(Some(Type::Int))
+}
+
+// the inverse of 'embed'
+fn project(expr: ExprAST) -> Expr<ExprAST> {
+    match expr {
+        ExprAST::Add(a, b) => Expr::Add(*a, *b),
+        ExprAST::Sub(a, b) => Expr::Sub(*a, *b),
+        ExprAST::Mul(a, b) => Expr::Mul(*a, *b),
+        ExprAST::Div(a, b) => Expr::Div(*a, *b),
+        ExprAST::Mod(a, b) => Expr::Mod(*a, *b),
+        ExprAST::Eq(a, b) => Expr::Eq(*a, *b),
+        ExprAST::Lt(a, b) => Expr::Lt(*a, *b),
+        ExprAST::And(a, b) => Expr::And(*a, *b),
+        ExprAST::Or(a, b) => Expr::Or(*a, *b),
+        ExprAST::If(a, b, c) => Expr::If(*a, *b, *c),
+        ExprAST::LiteralInt(x) => Expr::LiteralInt(x),
+        ExprAST::LiteralFloat(x) => Expr::LiteralFloat(x),
+        ExprAST::LiteralBool(x) => Expr::LiteralBool(x),
+        ExprAST::Var(name) => Expr::Var(name),
+        ExprAST::Let(name, a, b) => Expr::Let(name, *a, *b),
+        ExprAST::Lambda(param, body) => Expr::Lambda(param, *body),
+        ExprAST::App(f, arg) => Expr::App(*f, *arg),
+    }
+}
+
+impl Rewriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a rule, to be tried after all previously added rules
+    pub fn rule(mut self, rule: impl Fn(&Expr<ExprAST>) -> Option<ExprAST> + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Identities that hold for any integer 'x': 'x + 0', 'x - 0' and 'x * 1' (and their mirror
+    /// images, where they hold). They're only applied where 'x' is known to be an integer, ie it
+    /// typechecks as one, as otherwise they'd turn a type error, eg 'true + 0', into a value. The
+    /// types of variables aren't known, so eg 'x + 0' is left alone. 'x * 0' and 'x - x' are only
+    /// simplified when 'x' is an integer literal, as otherwise they'd drop any failure in
+    /// evaluating 'x', and give an integer zero where 'x' is a float.
+    pub fn simplifications() -> Self {
+        Self::new().rule(rewrite! { Expr, ExprAST;
+            Add(x, LiteralInt(0)) | Add(LiteralInt(0), x) | Sub(x, LiteralInt(0)) if is_int(x) =>
+                x,
+            Mul(x, LiteralInt(1)) | Mul(LiteralInt(1), x) if is_int(x) => x,
+            Mul(LiteralInt(_), LiteralInt(0)) | Mul(LiteralInt(0), LiteralInt(_)) =>
+                ExprAST::LiteralInt(0),
+            Sub(LiteralInt(a), LiteralInt(b)) if a == b => ExprAST::LiteralInt(0),
+        })
+    }
+
+    // rewrite a single layer until no rule matches
+    fn rewrite_layer(&self, mut layer: Expr<ExprAST>) -> ExprAST {
+        while let Some(rewritten) = self.rules.iter().find_map(|rule| rule(&layer)) {
+            layer = project(rewritten);
+        }
+        embed(layer)
+    }
+
+    /// Apply rules until none match anywhere in the tree
+    pub fn rewrite(&self, expr: &RecursiveExpr) -> RecursiveExpr {
+        let mut current = to_ast(expr);
+        loop {
+            let next = (&current).collapse_layers(|layer| self.rewrite_layer(layer));
+            if next == current {
+                return from_ast(&next);
+            }
+            current = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::eval::eval_arena;
+    use crate::examples::expr::lang::parse::parse;
+    use crate::examples::expr::lang::pretty::pretty;
+
+    fn rewrite(rewriter: &Rewriter, input: &str) -> String {
+        pretty(&rewriter.rewrite(&parse(input).unwrap()))
+    }
+
+    #[test]
+    fn simplify() {
+        let rewriter = Rewriter::simplifications();
+        assert_eq!(rewrite(&rewriter, "(2 * 3 + 0) * 1 + z"), "2 * 3 + z");
+        assert_eq!(
+            rewrite(&rewriter, "((1 + 2) * 1 + 0) * (y - y)"),
+            "(1 + 2) * (y - y)"
+        );
+        assert_eq!(rewrite(&rewriter, "(2 - 2) * 1 + 3 * 0"), "0");
+        assert_eq!(
+            rewrite(&rewriter, "\\x -> f (1 + 2 - 0)"),
+            "\\x -> f (1 + 2)"
+        );
+        // only identities that hold for every 'x'
+        assert_eq!(rewrite(&rewriter, "0 - 2"), "0 - 2");
+        // nor for anything not known to be an integer
+        assert_eq!(rewrite(&rewriter, "(x + 0) * 1"), "(x + 0) * 1");
+        assert_eq!(rewrite(&rewriter, "1.5 + 0"), "1.5 + 0");
+    }
+
+    #[test]
+    fn preserves_failures() {
+        let rewriter = Rewriter::simplifications();
+        for input in [
+            "(1 / 0) * 0",
+            "true * 0",
+            "let x = 1.5 in x - x",
+            "let x = 1.5 in x * 0",
+            "let x = 1 / 0 in x - x",
+            "true + 0",
+            "let x = true in x + 0",
+            "(\\y -> y) * 1",
+            "false + 0 == false",
+        ] {
+            let expr = parse(input).unwrap();
+            assert_eq!(
+                eval_arena(&rewriter.rewrite(&expr)),
+                eval_arena(&expr),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn user_rules() {
+        // distribute multiplication over addition, which creates new 'x * 1' terms
+        let rewriter = Rewriter::simplifications().rule(rewrite! { Expr, ExprAST;
+            Mul(x, Add(a, b)) => ExprAST::Add(
+                Box::new(ExprAST::Mul(Box::new(x.clone()), a.clone())),
+                Box::new(ExprAST::Mul(Box::new(x.clone()), b.clone())),
+            ),
+        });
+        assert_eq!(rewrite(&rewriter, "2 * (y + 1)"), "2 * y + 2");
+        assert_eq!(rewrite(&rewriter, "2 * (a + (3 + 0))"), "2 * a + 2 * 3");
+    }
+}
@@ -0,0 +1,241 @@
+//! Small-step evaluation: a single reduction at a time, by substitution, with every intermediate
+//! expression available for inspection.
+//!
+//! Evaluation is call-by-value and left-to-right. Nothing is reduced under a lambda, in the body of
+//! a 'let', or in the branches of an 'if', so for closed expressions every value substituted in is
+//! itself closed and substitution doesn't need to worry about variable capture.
+
+use std::iter;
+
+use crate::examples::expr::lang::eval::{arithmetic, equal, less_than, ArithOp};
+use crate::examples::expr::lang::{Expr, RecursiveExpr, Value};
+use crate::map_layer::MapLayer;
+use crate::recursive::Expand;
+use crate::recursive_tree::arena_eval::ArenaIndex;
+
+fn is_value(layer: &Expr<ArenaIndex>) -> bool {
+    matches!(
+        layer,
+        Expr::LiteralInt(_) | Expr::LiteralFloat(_) | Expr::LiteralBool(_) | Expr::Lambda(_, _)
+    )
+}
+
+enum Next {
+    Reduce,
+    Descend(ArenaIndex),
+    /// a value, or an unbound variable
+    Done,
+}
+
+fn next(expr: &RecursiveExpr, layer: &Expr<ArenaIndex>) -> Next {
+    let value = |idx: &ArenaIndex| is_value(&expr.elems[idx.0]);
+    // operands are evaluated left to right
+    let operands = |a: &ArenaIndex, b: &ArenaIndex| match [a, b].into_iter().find(|x| !value(x)) {
+        Some(operand) => Next::Descend(*operand),
+        None => Next::Reduce,
+    };
+
+    match layer {
+        Expr::Add(a, b)
+        | Expr::Sub(a, b)
+        | Expr::Mul(a, b)
+        | Expr::Div(a, b)
+        | Expr::Mod(a, b)
+        | Expr::Eq(a, b)
+        | Expr::Lt(a, b)
+        | Expr::App(a, b) => operands(a, b),
+        Expr::If(x, _, _) | Expr::Let(_, x, _) if !value(x) => Next::Descend(*x),
+        Expr::And(a, b) | Expr::Or(a, b) => {
+            // 'false && b' and 'true || b' reduce without evaluating 'b'
+            let decided = matches!(
+                (layer, &expr.elems[a.0]),
+                (Expr::And(_, _), Expr::LiteralBool(false))
+                    | (Expr::Or(_, _), Expr::LiteralBool(true))
+            );
+            if !value(a) {
+                Next::Descend(*a)
+            } else if decided || value(b) {
+                Next::Reduce
+            } else {
+                Next::Descend(*b)
+            }
+        }
+        Expr::If(_, _, _) | Expr::Let(_, _, _) => Next::Reduce,
+        Expr::LiteralInt(_)
+        | Expr::LiteralFloat(_)
+        | Expr::LiteralBool(_)
+        | Expr::Var(_)
+        | Expr::Lambda(_, _) => Next::Done,
+    }
+}
+
+fn leaf(layer: Expr<()>) -> RecursiveExpr {
+    RecursiveExpr::expand_layers((), |()| layer.clone())
+}
+
+#[derive(Clone, Copy)]
+enum Seed {
+    Substitute(ArenaIndex),
+    Copy(ArenaIndex),
+}
+
+/// Copy of 'body' with free occurrences of 'name' replaced by 'value'
+fn substitute(
+    expr: &RecursiveExpr,
+    body: ArenaIndex,
+    name: &str,
+    value: ArenaIndex,
+) -> RecursiveExpr {
+    RecursiveExpr::expand_layers(Seed::Substitute(body), |seed| match seed {
+        Seed::Copy(idx) => expr.elems[idx.0].clone().map_layer(Seed::Copy),
+        Seed::Substitute(idx) => match &expr.elems[idx.0] {
+            Expr::Var(x) if x == name => expr.elems[value.0].clone().map_layer(Seed::Copy),
+            // shadowed by an inner binding
+            Expr::Lambda(param, body) if param == name => {
+                Expr::Lambda(param.clone(), Seed::Copy(*body))
+            }
+            Expr::Let(x, bound, body) if x == name => {
+                Expr::Let(x.clone(), Seed::Substitute(*bound), Seed::Copy(*body))
+            }
+            layer => layer.map_layer(Seed::Substitute),
+        },
+    })
+}
+
+/// The expression that the redex at 'idx' reduces to, if it isn't stuck on a type error or on
+/// integer overflow. Arithmetic and comparisons follow the same rules as 'eval'.
+fn reduce(expr: &RecursiveExpr, idx: ArenaIndex) -> Option<RecursiveExpr> {
+    let layer = |idx: &ArenaIndex| &expr.elems[idx.0];
+    // lambdas are values too, but there's nothing to be done with them here
+    let value = |idx: &ArenaIndex| match layer(idx) {
+        Expr::LiteralInt(x) => Some(Value::Int(*x)),
+        Expr::LiteralFloat(x) => Some(Value::Float(*x)),
+        Expr::LiteralBool(x) => Some(Value::Bool(*x)),
+        _ => None,
+    };
+    let arith = |op, a, b| match arithmetic(op, value(a)?, value(b)?).ok()? {
+        Value::Int(x) => Some(leaf(Expr::LiteralInt(x))),
+        Value::Float(x) => Some(leaf(Expr::LiteralFloat(x))),
+        _ => None,
+    };
+
+    let reduced = match layer(&idx) {
+        Expr::Add(a, b) => arith(ArithOp::Add, a, b)?,
+        Expr::Sub(a, b) => arith(ArithOp::Sub, a, b)?,
+        Expr::Mul(a, b) => arith(ArithOp::Mul, a, b)?,
+        Expr::Div(a, b) => arith(ArithOp::Div, a, b)?,
+        Expr::Mod(a, b) => arith(ArithOp::Mod, a, b)?,
+        Expr::Lt(a, b) => leaf(Expr::LiteralBool(less_than(value(a)?, value(b)?).ok()?)),
+        Expr::Eq(a, b) => leaf(Expr::LiteralBool(equal(value(a)?, value(b)?).ok()?)),
+        Expr::And(a, b) => match (layer(a), layer(b)) {
+            (Expr::LiteralBool(false), _) => leaf(Expr::LiteralBool(false)),
+            (Expr::LiteralBool(true), Expr::LiteralBool(x)) => leaf(Expr::LiteralBool(*x)),
+            _ => return None,
+        },
+        Expr::Or(a, b) => match (layer(a), layer(b)) {
+            (Expr::LiteralBool(true), _) => leaf(Expr::LiteralBool(true)),
+            (Expr::LiteralBool(false), Expr::LiteralBool(x)) => leaf(Expr::LiteralBool(*x)),
+            _ => return None,
+        },
+        Expr::If(cond, a, b) => match layer(cond) {
+            Expr::LiteralBool(true) => expr.subtree(*a),
+            Expr::LiteralBool(false) => expr.subtree(*b),
+            _ => return None,
+        },
+        Expr::Let(name, value, body) => substitute(expr, *body, name, *value),
+        Expr::App(f, arg) => match layer(f) {
+            Expr::Lambda(param, body) => substitute(expr, *body, param, *arg),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(reduced)
+}
+
+/// Perform a single reduction, or return 'None' if the expression is a value or is stuck
+pub fn step(expr: &RecursiveExpr) -> Option<RecursiveExpr> {
+    let mut idx = ArenaIndex(0);
+    loop {
+        match next(expr, &expr.elems[idx.0]) {
+            Next::Reduce => return reduce(expr, idx).map(|reduced| expr.graft(idx, &reduced)),
+            Next::Descend(child) => idx = child,
+            Next::Done => return None,
+        }
+    }
+}
+
+/// Every intermediate expression, starting with 'expr' itself and ending with either a value or a
+/// stuck expression
+pub fn trace(expr: RecursiveExpr) -> impl Iterator<Item = RecursiveExpr> {
+    iter::successors(Some(expr), step)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::eval::eval_arena;
+    use crate::examples::expr::lang::parse::parse;
+    use crate::examples::expr::lang::pretty::pretty;
+
+    fn steps(input: &str) -> Vec<String> {
+        trace(parse(input).unwrap()).map(|e| pretty(&e)).collect()
+    }
+
+    #[test]
+    fn reduction_sequence() {
+        assert_eq!(
+            steps("let x = 1 + 2 in x * (let x = 4 in x)"),
+            [
+                "let x = 1 + 2 in x * (let x = 4 in x)",
+                "let x = 3 in x * (let x = 4 in x)",
+                "3 * (let x = 4 in x)",
+                "3 * 4",
+                "12",
+            ]
+        );
+        assert_eq!(
+            steps("(\\f -> f (f 1)) (\\x -> x + x)"),
+            [
+                "(\\f -> f (f 1)) (\\x -> x + x)",
+                "(\\x -> x + x) ((\\x -> x + x) 1)",
+                "(\\x -> x + x) (1 + 1)",
+                "(\\x -> x + x) 2",
+                "2 + 2",
+                "4",
+            ]
+        );
+    }
+
+    #[test]
+    fn agrees_with_eval() {
+        let input = "let twice = \\f -> \\x -> f (f x) in \
+                     let a = 10 in \
+                     if twice (\\y -> y * a) 2 < 100 then 0 else twice (\\y -> y - a) 25";
+        let expr = parse(input).unwrap();
+        let result = trace(expr.clone()).last().unwrap();
+        assert_eq!(pretty(&result), "5");
+        assert_eq!(eval_arena(&expr).unwrap(), eval_arena(&result).unwrap());
+    }
+
+    #[test]
+    fn stuck() {
+        // only the branch taken is evaluated
+        assert_eq!(steps("if 1 < 2 then 3 else true + 1").last().unwrap(), "3");
+        assert_eq!(steps("1 + (true + 1)").last().unwrap(), "1 + (true + 1)");
+        assert_eq!(steps("(\\x -> y) 1").last().unwrap(), "y");
+    }
+
+    #[test]
+    fn short_circuit() {
+        assert_eq!(
+            steps("1 < 2 && (2 < 1 || 1 / 0 == 1)"),
+            [
+                "1 < 2 && (2 < 1 || 1 / 0 == 1)",
+                "true && (2 < 1 || 1 / 0 == 1)",
+                "true && (false || 1 / 0 == 1)",
+            ]
+        );
+        assert_eq!(steps("2 < 1 && 1 / 0 == 1").last().unwrap(), "false");
+        assert_eq!(steps("true || 1 / 0 == 1").last().unwrap(), "true");
+    }
+}
@@ -0,0 +1,206 @@
+//! Capture-avoiding substitution and alpha-equivalence.
+//!
+//! Substitution happens in two passes over the arena. First, any binder that would capture a free
+//! variable of the replacement is renamed, by re-expanding the tree top-down with the renames in
+//! scope. Then the free occurrences of the variable are found with a single forward pass over the
+//! arena (parents always precede their children) and the replacement is grafted in at each of them.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use crate::examples::expr::lang::free_vars::{annotate_usage, free_vars};
+use crate::examples::expr::lang::{Env, Expr, RecursiveExpr};
+use crate::map_layer::MapLayer;
+use crate::recursive::Expand;
+use crate::recursive_tree::arena_eval::ArenaIndex;
+
+// every name used anywhere in the tree, bound or free
+fn names(expr: &RecursiveExpr) -> impl Iterator<Item = &String> {
+    expr.elems.iter().filter_map(|layer| match layer {
+        Expr::Var(name) | Expr::Let(name, _, _) | Expr::Lambda(name, _) => Some(name),
+        _ => None,
+    })
+}
+
+/// Rename binders named in 'avoid' wherever they'd capture a free occurrence of 'var'
+fn avoid_capture(expr: &RecursiveExpr, var: &str, avoid: &HashSet<String>) -> RecursiveExpr {
+    let usage = annotate_usage(expr);
+    // expansion only borrows its closure, so names handed out are tracked via a 'RefCell'
+    let taken: RefCell<HashSet<String>> = RefCell::new(names(expr).chain(avoid).cloned().collect());
+    let fresh = |name: &str| {
+        (1..)
+            .map(|n| format!("{}{}", name, n))
+            .find(|candidate| taken.borrow_mut().insert(candidate.clone()))
+            .expect("some name is unused")
+    };
+
+    // each seed carries the renames in scope, and whether 'var' is still free at that point
+    type Seed = (ArenaIndex, Env<String>, bool);
+    RecursiveExpr::expand_layers(
+        (ArenaIndex(0), Env::default(), true),
+        |(ArenaIndex(idx), renames, free): Seed| {
+            let binder = |name: &String, ArenaIndex(body): ArenaIndex| {
+                if free && avoid.contains(name) && usage[body].contains_key(var) {
+                    let renamed = fresh(name);
+                    (renamed.clone(), renames.bind(name.clone(), renamed), true)
+                } else {
+                    // a binder for 'var' itself shadows it, so there's nothing to capture within
+                    (
+                        name.clone(),
+                        renames.bind(name.clone(), name.clone()),
+                        free && name != var,
+                    )
+                }
+            };
+            match &expr.elems[idx] {
+                Expr::Var(name) => Expr::Var(renames.lookup(name).unwrap_or(name).clone()),
+                Expr::Let(name, value, body) => {
+                    let (name, inner, body_free) = binder(name, *body);
+                    Expr::Let(
+                        name,
+                        (*value, renames.clone(), free),
+                        (*body, inner, body_free),
+                    )
+                }
+                Expr::Lambda(param, body) => {
+                    let (param, inner, body_free) = binder(param, *body);
+                    Expr::Lambda(param, (*body, inner, body_free))
+                }
+                layer => layer.map_layer(|child| (child, renames.clone(), free)),
+            }
+        },
+    )
+}
+
+/// Positions of every free occurrence of 'var'
+fn occurrences(expr: &RecursiveExpr, var: &str) -> Vec<ArenaIndex> {
+    let mut shadowed = vec![false; expr.elems.len()];
+    let mut found = Vec::new();
+    for (idx, layer) in expr.elems.iter().enumerate() {
+        let here = shadowed[idx];
+        match layer {
+            Expr::Var(name) if name == var && !here => found.push(ArenaIndex(idx)),
+            Expr::Let(name, ArenaIndex(value), ArenaIndex(body)) => {
+                shadowed[*value] = here;
+                shadowed[*body] = here || name == var;
+            }
+            Expr::Lambda(param, ArenaIndex(body)) => shadowed[*body] = here || param == var,
+            layer => {
+                layer.map_layer(|ArenaIndex(child)| shadowed[child] = here);
+            }
+        }
+    }
+    found
+}
+
+/// Replace every free occurrence of 'var' in 'expr' with 'replacement', renaming binders in 'expr'
+/// as needed so that free variables of 'replacement' aren't captured
+pub fn substitute(expr: &RecursiveExpr, var: &str, replacement: &RecursiveExpr) -> RecursiveExpr {
+    let renamed = avoid_capture(expr, var, &free_vars(replacement));
+    renamed.graft_many(&occurrences(&renamed, var), replacement)
+}
+
+// bound variables are renamed by binding depth, to names that can't appear in parsed source
+fn canonical(expr: &RecursiveExpr) -> RecursiveExpr {
+    RecursiveExpr::expand_layers(
+        (ArenaIndex(0), Env::default(), 0),
+        |(ArenaIndex(idx), names, depth): (ArenaIndex, Env<String>, usize)| {
+            let bound = format!("#{}", depth);
+            match &expr.elems[idx] {
+                Expr::Var(name) => Expr::Var(names.lookup(name).unwrap_or(name).clone()),
+                Expr::Let(name, value, body) => Expr::Let(
+                    bound.clone(),
+                    (*value, names.clone(), depth),
+                    (*body, names.bind(name.clone(), bound), depth + 1),
+                ),
+                Expr::Lambda(param, body) => Expr::Lambda(
+                    bound.clone(),
+                    (*body, names.bind(param.clone(), bound), depth + 1),
+                ),
+                layer => layer.map_layer(|child| (child, names.clone(), depth)),
+            }
+        },
+    )
+}
+
+/// Whether two expressions are the same up to the names of bound variables
+pub fn alpha_eq(a: &RecursiveExpr, b: &RecursiveExpr) -> bool {
+    canonical(a).elems == canonical(b).elems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::eval::eval_arena;
+    use crate::examples::expr::lang::generate::{gen_expr, Rng};
+    use crate::examples::expr::lang::parse::parse;
+    use crate::examples::expr::lang::pretty::pretty;
+
+    fn subst(input: &str, var: &str, replacement: &str) -> String {
+        let expr = parse(input).unwrap();
+        pretty(&substitute(&expr, var, &parse(replacement).unwrap()))
+    }
+
+    fn alpha(a: &str, b: &str) -> bool {
+        alpha_eq(&parse(a).unwrap(), &parse(b).unwrap())
+    }
+
+    #[test]
+    fn substitution() {
+        assert_eq!(subst("x + y * x", "x", "2 * z"), "2 * z + y * (2 * z)");
+        assert_eq!(subst("f x", "y", "1"), "f x");
+        // only free occurrences are replaced
+        assert_eq!(subst("let x = x in x", "x", "1"), "let x = 1 in x");
+        assert_eq!(subst("(\\x -> x) x", "x", "1"), "(\\x -> x) 1");
+    }
+
+    #[test]
+    fn capture_avoiding() {
+        assert_eq!(subst("\\y -> x + y", "x", "y"), "\\y1 -> y + y1");
+        assert_eq!(
+            subst("let y = 1 in let y1 = y in x * y1", "x", "y"),
+            "let y2 = 1 in let y1 = y2 in y * y1"
+        );
+        // binders are only renamed where they would capture something
+        assert_eq!(subst("\\y -> y", "x", "y"), "\\y -> y");
+        assert_eq!(subst("(\\y -> y) x", "x", "y"), "(\\y -> y) y");
+        assert_eq!(subst("\\x -> \\y -> x", "x", "y"), "\\x -> \\y -> x");
+    }
+
+    #[test]
+    fn alpha_equivalence() {
+        assert!(alpha("\\x -> x", "\\y -> y"));
+        assert!(alpha(
+            "let a = 1 in \\b -> a + b",
+            "let b = 1 in \\a -> b + a"
+        ));
+        assert!(!alpha("\\x -> y", "\\y -> y"));
+        assert!(!alpha("\\x -> \\y -> x", "\\x -> \\y -> y"));
+        // free variables must match exactly
+        assert!(!alpha("x", "y"));
+        assert!(alpha("\\y -> x + y", "\\z -> x + z"));
+    }
+
+    #[test]
+    fn preserves_meaning() {
+        // 'let x = v in body' means the same as 'body' with 'v' substituted for 'x', when evaluating
+        // 'v' succeeds
+        let mut rng = Rng::new(4);
+        let mut checked = 0;
+        for _ in 0..2000 {
+            let expr = gen_expr(6, &mut rng);
+            if let Expr::Let(name, value, body) = &expr.elems[0] {
+                let value = expr.subtree(*value);
+                if eval_arena(&value).is_ok() {
+                    let substituted = substitute(&expr.subtree(*body), name, &value);
+                    assert_eq!(
+                        eval_arena(&substituted).map_err(|e| e.kind),
+                        eval_arena(&expr).map_err(|e| e.kind)
+                    );
+                    checked += 1;
+                }
+            }
+        }
+        assert!(checked > 100, "only {} lets checked", checked);
+    }
+}
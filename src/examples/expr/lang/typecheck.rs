@@ -0,0 +1,223 @@
+//! Static type checking, as a fallible collapse. The types of variables depend on their bindings,
+//! which flow top-down, so here they're treated as unknown and any resulting type errors are only
+//! caught at evaluation time.
+//!
+//! 'typecheck' reports where in the tree an error occurred. Children can't know their own
+//! position, so the path is built as the error propagates upwards, with each parent recording which
+//! of its children it came from.
+
+use crate::examples::expr::lang::{Expr, SpannedExpr, Type};
+use crate::map_layer::MapLayer;
+use crate::recursive::Collapse;
+use crate::spanned::{span_at, Spanned};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeError {
+    pub expected: Type,
+    pub found: Type,
+}
+
+/// An error along with the path from the root to the subexpression it occurred in. Each step is the
+/// position of a child within its parent layer, in field order (eg for 'Let', 0 is the bound value
+/// and 1 is the body).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Located<E> {
+    pub path: Vec<usize>,
+    pub error: E,
+}
+
+fn numeric(found: Option<Type>) -> Result<(), TypeError> {
+    match found {
+        Some(found @ (Type::Bool | Type::Fn)) => Err(TypeError {
+            expected: Type::Int,
+            found,
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn is_numeric(t: Option<Type>) -> bool {
+    matches!(t, Some(Type::Int | Type::Float))
+}
+
+fn expect(expected: Type, found: Option<Type>) -> Result<(), TypeError> {
+    match found {
+        Some(found) if found != expected => Err(TypeError { expected, found }),
+        _ => Ok(()),
+    }
+}
+
+/// Type of some subtree, if known
+pub fn typecheck_layer(layer: Expr<Option<Type>>) -> Result<Option<Type>, TypeError> {
+    match layer {
+        Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) | Expr::Div(a, b) | Expr::Mod(a, b) => {
+            numeric(a)?;
+            numeric(b)?;
+            // ints are promoted to floats when mixed
+            Ok(match (a, b) {
+                (Some(Type::Float), _) | (_, Some(Type::Float)) => Some(Type::Float),
+                (Some(Type::Int), Some(Type::Int)) => Some(Type::Int),
+                _ => None,
+            })
+        }
+        Expr::Lt(a, b) => {
+            numeric(a)?;
+            numeric(b)?;
+            Ok(Some(Type::Bool))
+        }
+        Expr::Eq(a, b) => {
+            match a {
+                Some(_) if is_numeric(a) => numeric(b)?,
+                Some(a) => expect(a, b)?,
+                None => {}
+            }
+            if a == Some(Type::Fn) || b == Some(Type::Fn) {
+                return Err(TypeError {
+                    expected: Type::Int,
+                    found: Type::Fn,
+                });
+            }
+            Ok(Some(Type::Bool))
+        }
+        Expr::And(a, b) | Expr::Or(a, b) => {
+            expect(Type::Bool, a)?;
+            expect(Type::Bool, b)?;
+            Ok(Some(Type::Bool))
+        }
+        Expr::If(cond, a, b) => {
+            expect(Type::Bool, cond)?;
+            if let Some(a) = a {
+                expect(a, b)?;
+            }
+            Ok(a.or(b))
+        }
+        Expr::LiteralInt(_) => Ok(Some(Type::Int)),
+        Expr::LiteralFloat(_) => Ok(Some(Type::Float)),
+        Expr::LiteralBool(_) => Ok(Some(Type::Bool)),
+        Expr::Var(_) => Ok(None),
+        Expr::Let(_, _, body) => Ok(body),
+        // parameter and return types aren't tracked
+        Expr::Lambda(_, _) => Ok(Some(Type::Fn)),
+        Expr::App(f, _) => {
+            expect(Type::Fn, f)?;
+            Ok(None)
+        }
+    }
+}
+
+/// Type of some subtree, with the path to any type error stored leaf-first
+pub type CheckedType = Result<Option<Type>, Located<TypeError>>;
+
+/// Typecheck the whole tree, reporting the location of the first type error found
+pub fn typecheck<T>(expr: T) -> Result<Option<Type>, Located<TypeError>>
+where
+    T: Collapse<CheckedType, Expr<CheckedType>>,
+{
+    expr.collapse_layers(locate_layer).map_err(root_first)
+}
+
+/// Typecheck a tree with source locations, reporting the span of the first type error found as well
+/// as its path
+pub fn typecheck_spanned(expr: &SpannedExpr) -> Result<Option<Type>, Spanned<Located<TypeError>>> {
+    expr.as_ref()
+        .collapse_layers(|layer: Spanned<Expr<CheckedType>>| locate_layer(layer.value))
+        .map_err(|e| {
+            let e = root_first(e);
+            let span = span_at(expr, &e.path).expect("path is within the tree");
+            Spanned::new(span, e)
+        })
+}
+
+// paths are built leaf-first
+fn root_first(mut e: Located<TypeError>) -> Located<TypeError> {
+    e.path.reverse();
+    e
+}
+
+// record which child each error came from, and check layers without errors
+fn locate_layer(layer: Expr<CheckedType>) -> CheckedType {
+    let mut position = 0;
+    let mut failed = None;
+    let layer = layer.map_layer(|child| {
+        position += 1;
+        match child {
+            Ok(t) => t,
+            Err(mut e) => {
+                if failed.is_none() {
+                    e.path.push(position - 1);
+                    failed = Some(e);
+                }
+                None
+            }
+        }
+    });
+
+    match failed {
+        Some(e) => Err(e),
+        None => typecheck_layer(layer).map_err(|error| Located {
+            path: Vec::new(),
+            error,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::lang::{build::*, from_ast};
+
+    #[test]
+    fn error_paths() {
+        // let x = 1 in if x < 2 then x else (3 + (true * 4))
+        let expr = let_(
+            "x",
+            lit(1),
+            if_(
+                lt(var("x"), lit(2)),
+                var("x"),
+                add(lit(3), mul(bool_(true), lit(4))),
+            ),
+        );
+        let expected = Err(Located {
+            path: vec![1, 2, 1],
+            error: TypeError {
+                expected: Type::Int,
+                found: Type::Bool,
+            },
+        });
+        assert_eq!(typecheck(&expr), expected);
+        // the same algebra, run over the arena
+        assert_eq!(typecheck(from_ast(&expr).as_ref()), expected);
+
+        // errors at the root have an empty path
+        let expr = app(lit(1), lit(2));
+        assert_eq!(typecheck(&expr).unwrap_err().path, Vec::<usize>::new());
+
+        assert_eq!(typecheck(&lambda("x", var("x"))), Ok(Some(Type::Fn)));
+    }
+
+    #[test]
+    fn promotion() {
+        assert_eq!(typecheck(&add(lit(1), lit(2))), Ok(Some(Type::Int)));
+        assert_eq!(typecheck(&add(lit(1), float(2.0))), Ok(Some(Type::Float)));
+        assert_eq!(typecheck(&lt(float(1.0), lit(2))), Ok(Some(Type::Bool)));
+        assert_eq!(typecheck(&eq(lit(1), float(1.0))), Ok(Some(Type::Bool)));
+        // ints and floats are both numbers, but bools aren't
+        assert_eq!(
+            typecheck(&mul(float(1.0), bool_(true))).map_err(|e| e.error),
+            Err(TypeError {
+                expected: Type::Int,
+                found: Type::Bool,
+            })
+        );
+    }
+
+    #[test]
+    fn spanned() {
+        use crate::examples::expr::lang::parse::parse_spanned;
+        let expr = parse_spanned("let f = \\x -> x in f (1 + (true < 2))").unwrap();
+        let err = typecheck_spanned(&expr).unwrap_err();
+        assert_eq!(err.span, 27..35);
+        assert_eq!(err.value.path, vec![1, 1, 1]);
+    }
+}
@@ -90,7 +90,7 @@ impl Expr {
         let mut elems = vec![];
 
         fn push_to_frontier<'a>(
-            elems: &Vec<ExprLayer<ExprIdx>>,
+            elems: &[ExprLayer<ExprIdx>],
             frontier: &mut VecDeque<&'a ExprBoxed>,
             a: &'a ExprBoxed,
         ) -> ExprIdx {
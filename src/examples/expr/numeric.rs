@@ -0,0 +1,154 @@
+//! Evaluation generic over the integer representation. 'eval_layer' uses plain 'i64', which panics
+//! on overflow in debug builds and wraps silently in release builds. 'Numeric' lets the caller pick
+//! the semantics instead: checked 'i64', which fails on overflow, `Wrapping<i64>`, or (with the
+//! 'bigint' feature) arbitrary precision 'BigInt', which never overflows.
+
+use std::convert::Infallible;
+use std::num::Wrapping;
+
+use crate::examples::expr::Expr;
+use crate::recursive::TryCollapse;
+
+/// Integer arithmetic that may fail, eg on overflow
+pub trait Numeric: Sized {
+    type Error;
+
+    fn literal(x: i64) -> Self;
+    fn add(self, other: Self) -> Result<Self, Self::Error>;
+    fn sub(self, other: Self) -> Result<Self, Self::Error>;
+    fn mul(self, other: Self) -> Result<Self, Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflow;
+
+/// Checked arithmetic
+impl Numeric for i64 {
+    type Error = Overflow;
+
+    fn literal(x: i64) -> Self {
+        x
+    }
+
+    fn add(self, other: Self) -> Result<Self, Overflow> {
+        self.checked_add(other).ok_or(Overflow)
+    }
+
+    fn sub(self, other: Self) -> Result<Self, Overflow> {
+        self.checked_sub(other).ok_or(Overflow)
+    }
+
+    fn mul(self, other: Self) -> Result<Self, Overflow> {
+        self.checked_mul(other).ok_or(Overflow)
+    }
+}
+
+impl Numeric for Wrapping<i64> {
+    type Error = Infallible;
+
+    fn literal(x: i64) -> Self {
+        Wrapping(x)
+    }
+
+    fn add(self, other: Self) -> Result<Self, Infallible> {
+        Ok(self + other)
+    }
+
+    fn sub(self, other: Self) -> Result<Self, Infallible> {
+        Ok(self - other)
+    }
+
+    fn mul(self, other: Self) -> Result<Self, Infallible> {
+        Ok(self * other)
+    }
+}
+
+#[cfg(any(test, feature = "bigint"))]
+impl Numeric for num_bigint::BigInt {
+    type Error = Infallible;
+
+    fn literal(x: i64) -> Self {
+        x.into()
+    }
+
+    fn add(self, other: Self) -> Result<Self, Infallible> {
+        Ok(self + other)
+    }
+
+    fn sub(self, other: Self) -> Result<Self, Infallible> {
+        Ok(self - other)
+    }
+
+    fn mul(self, other: Self) -> Result<Self, Infallible> {
+        Ok(self * other)
+    }
+}
+
+pub fn eval_numeric_layer<N: Numeric>(node: Expr<N>) -> Result<N, N::Error> {
+    match node {
+        Expr::Add(a, b) => a.add(b),
+        Expr::Sub(a, b) => a.sub(b),
+        Expr::Mul(a, b) => a.mul(b),
+        Expr::LiteralInt(x) => Ok(N::literal(x)),
+    }
+}
+
+/// Evaluate using the arithmetic of 'N', stopping at the first error
+pub fn eval_numeric<N: Numeric, T: TryCollapse<N, Expr<N>>>(expr: T) -> Result<N, N::Error> {
+    expr.try_collapse_layers(eval_numeric_layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::naive::{arb_expr, generate_layer};
+    use crate::examples::expr::BlocAllocExpr;
+    use crate::recursive::Expand;
+    use num_bigint::BigInt;
+    use proptest::prelude::*;
+
+    fn unwrap<T>(result: Result<T, Infallible>) -> T {
+        match result {
+            Ok(x) => x,
+            Err(never) => match never {},
+        }
+    }
+
+    #[test]
+    fn overflow() {
+        // 2^62 * 4
+        let expr = BlocAllocExpr::expand_layers(0, |n| match n {
+            0 => Expr::Mul(1, 2),
+            1 => Expr::LiteralInt(1 << 62),
+            _ => Expr::LiteralInt(4),
+        });
+        assert_eq!(eval_numeric::<i64, _>(expr.as_ref()), Err(Overflow));
+        assert_eq!(
+            unwrap(eval_numeric::<Wrapping<i64>, _>(expr.as_ref())),
+            Wrapping(0)
+        );
+        assert_eq!(
+            unwrap(eval_numeric::<BigInt, _>(expr.as_ref())),
+            BigInt::from(1) << 64
+        );
+    }
+
+    proptest! {
+        // bigints are exact, so the other representations can be checked against them
+        #[test]
+        fn agree_with_bigint(expr in arb_expr()) {
+            let expr = BlocAllocExpr::expand_layers(&expr, generate_layer);
+            let exact = unwrap(eval_numeric::<BigInt, _>(expr.as_ref()));
+
+            // intermediate results may overflow even if the final result fits, so an overflow
+            // doesn't say much about the final result
+            if let Ok(x) = eval_numeric::<i64, _>(expr.as_ref()) {
+                prop_assert_eq!(BigInt::from(x), exact.clone());
+            }
+
+            let wrapped = unwrap(eval_numeric::<Wrapping<i64>, _>(expr.as_ref()));
+            let truncated = exact & BigInt::from(u64::MAX);
+            prop_assert_eq!(wrapped.0 as u64, u64::try_from(truncated).unwrap());
+        }
+    }
+}
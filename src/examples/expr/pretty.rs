@@ -0,0 +1,79 @@
+use std::cmp::Ordering;
+
+use crate::examples::expr::{BlocAllocExpr, Expr};
+use crate::pretty::Doc;
+use crate::render::render_tree;
+
+// fully parenthesized, with operands on separate lines if they don't fit
+pub fn pretty_layer(layer: Expr<Doc>) -> Doc {
+    let (op, a, b) = match layer {
+        Expr::Add(a, b) => ("+", a, b),
+        Expr::Sub(a, b) => ("-", a, b),
+        Expr::Mul(a, b) => ("*", a, b),
+        Expr::LiteralInt(x) => return Doc::text(x.to_string()),
+    };
+
+    Doc::text("(")
+        .append(
+            a.append(Doc::line())
+                .append(Doc::text(op))
+                .append(Doc::text(" "))
+                .append(b)
+                .nest(1),
+        )
+        .append(Doc::text(")"))
+        .group()
+}
+
+// AST structure, for debugging
+pub fn render(expr: &BlocAllocExpr) -> String {
+    render_tree(
+        expr,
+        |_, layer| match layer {
+            Expr::Add(..) => "Add".to_string(),
+            Expr::Sub(..) => "Sub".to_string(),
+            Expr::Mul(..) => "Mul".to_string(),
+            Expr::LiteralInt(x) => x.to_string(),
+        },
+        |_, _| Ordering::Equal,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pretty::pretty;
+    use crate::recursive::Expand;
+
+    fn example() -> BlocAllocExpr {
+        BlocAllocExpr::expand_layers(2, |depth| match depth {
+            0 => Expr::LiteralInt(12345),
+            1 => Expr::Mul(0, 0),
+            _ => Expr::Sub(1, 0),
+        })
+    }
+
+    #[test]
+    fn wide() {
+        assert_eq!(
+            pretty(example().as_ref(), 80, pretty_layer),
+            "((12345 * 12345) - 12345)"
+        );
+    }
+
+    #[test]
+    fn narrow() {
+        assert_eq!(
+            pretty(example().as_ref(), 20, pretty_layer),
+            "((12345 * 12345)\n - 12345)"
+        );
+    }
+
+    #[test]
+    fn ast() {
+        assert_eq!(
+            render(&example()),
+            "Sub\n├── Mul\n│   ├── 12345\n│   └── 12345\n└── 12345"
+        );
+    }
+}
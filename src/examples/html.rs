@@ -0,0 +1,420 @@
+//! A small HTML DOM. Parsing is done in two passes: a lenient tokenizer produces a flat list of tags
+//! and text, and the tree is then expanded from that list one node at a time. Queries and
+//! serialization are collapses over the resulting tree.
+
+use crate::map_layer::MapLayer;
+use crate::recursive::{Collapse, Expand};
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node<A> {
+    Element {
+        tag: String,
+        attrs: Vec<(String, String)>,
+        children: Vec<A>,
+    },
+    Text(String),
+}
+
+/// Borrowed version of 'Node', produced by mapping over a '&Node'
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeRef<'a, A> {
+    Element {
+        tag: &'a str,
+        attrs: &'a [(String, String)],
+        children: Vec<A>,
+    },
+    Text(&'a str),
+}
+
+impl<A, B> MapLayer<B> for Node<A> {
+    type To = Node<B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, f: F) -> Self::To {
+        match self {
+            Node::Element {
+                tag,
+                attrs,
+                children,
+            } => Node::Element {
+                tag,
+                attrs,
+                children: children.into_iter().map(f).collect(),
+            },
+            Node::Text(text) => Node::Text(text),
+        }
+    }
+}
+
+impl<'a, A: Copy, B: 'a> MapLayer<B> for &'a Node<A> {
+    type To = NodeRef<'a, B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            Node::Element {
+                tag,
+                attrs,
+                children,
+            } => NodeRef::Element {
+                tag,
+                attrs,
+                children: children.iter().map(|x| f(*x)).collect(),
+            },
+            Node::Text(text) => NodeRef::Text(text),
+        }
+    }
+}
+
+pub type RecursiveHtml = RecursiveTree<Node<ArenaIndex>, ArenaIndex>;
+
+// elements that never have children or a closing tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+// elements whose contents are a single text node, not markup
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open {
+        tag: String,
+        attrs: Vec<(String, String)>,
+        self_closing: bool,
+    },
+    Close(String),
+    Text(String),
+}
+
+/// Never fails: unknown constructs are treated as text, comments and doctypes are dropped, and
+/// whitespace-only text is skipped.
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = input;
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+        } else if let Some(after) = rest.strip_prefix("<!") {
+            rest = after.find('>').map_or("", |end| &after[end + 1..]);
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>').unwrap_or(after.len());
+            tokens.push(Token::Close(after[..end].trim().to_ascii_lowercase()));
+            rest = after.get(end + 1..).unwrap_or("");
+        } else if rest.starts_with('<') && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic())
+        {
+            let (token, after) = open_tag(&rest[1..]);
+            rest = after;
+            if let Token::Open { tag, .. } = &token {
+                if RAW_TEXT_ELEMENTS.contains(&tag.as_str()) {
+                    let close = format!("</{}", tag);
+                    let end = rest.to_ascii_lowercase().find(&close).unwrap_or(rest.len());
+                    let (text, after) = rest.split_at(end);
+                    tokens.push(token);
+                    if !text.trim().is_empty() {
+                        tokens.push(Token::Text(text.to_string()));
+                    }
+                    rest = after;
+                    continue;
+                }
+            }
+            tokens.push(token);
+        } else {
+            // a '<' that doesn't start a tag is just text
+            let end = rest
+                .char_indices()
+                .skip(1)
+                .find(|(_, c)| *c == '<')
+                .map_or(rest.len(), |(idx, _)| idx);
+            let text = &rest[..end];
+            if !text.trim().is_empty() {
+                tokens.push(Token::Text(decode_entities(text)));
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    tokens
+}
+
+// parse the tag name and attributes following a '<'
+fn open_tag(input: &str) -> (Token, &str) {
+    let name_end = input
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .unwrap_or(input.len());
+    let tag = input[..name_end].to_ascii_lowercase();
+    let mut rest = &input[name_end..];
+    let mut attrs = Vec::new();
+    let mut self_closing = false;
+
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("/>") {
+            self_closing = true;
+            rest = after;
+            break;
+        } else if let Some(after) = rest.strip_prefix('>') {
+            rest = after;
+            break;
+        } else if let Some(after) = rest.strip_prefix('/') {
+            rest = after;
+            continue;
+        } else if rest.is_empty() {
+            break;
+        }
+
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let value = match rest.strip_prefix('=') {
+            Some(after) => {
+                let after = after.trim_start();
+                let (value, after) = match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let end = after[1..].find(quote).map_or(after.len(), |idx| idx + 1);
+                        (&after[1..end], after.get(end + 1..).unwrap_or(""))
+                    }
+                    _ => {
+                        let end = after
+                            .find(|c: char| c.is_whitespace() || c == '>')
+                            .unwrap_or(after.len());
+                        after.split_at(end)
+                    }
+                };
+                rest = after;
+                decode_entities(value)
+            }
+            None => String::new(),
+        };
+        attrs.push((name, value));
+    }
+
+    (
+        Token::Open {
+            tag,
+            attrs,
+            self_closing,
+        },
+        rest,
+    )
+}
+
+fn decode_entities(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&amp;", "&")
+}
+
+fn encode_entities(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Parse a (possibly malformed) HTML fragment into its top-level nodes. Unclosed elements are
+/// closed by the closing tag of an ancestor or by the end of input, and stray closing tags are ignored.
+pub fn parse(input: &str) -> Vec<RecursiveHtml> {
+    let tokens = tokenize(input);
+
+    // for each open tag: the index of the token ending its contents, and the index just past the element
+    let mut contents_end = vec![0; tokens.len()];
+    let mut end = vec![0; tokens.len()];
+    let mut open: Vec<usize> = Vec::new();
+    for (idx, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Open {
+                tag, self_closing, ..
+            } if *self_closing || VOID_ELEMENTS.contains(&tag.as_str()) => {
+                contents_end[idx] = idx + 1;
+                end[idx] = idx + 1;
+            }
+            Token::Open { .. } => open.push(idx),
+            Token::Close(tag) => {
+                let matching = open
+                    .iter()
+                    .rposition(|&o| matches!(&tokens[o], Token::Open { tag: t, .. } if t == tag));
+                if let Some(pos) = matching {
+                    // everything opened after the matching element is implicitly closed here
+                    for o in open.drain(pos + 1..) {
+                        contents_end[o] = idx;
+                        end[o] = idx;
+                    }
+                    let o = open.pop().expect("matching element is on the stack");
+                    contents_end[o] = idx;
+                    end[o] = idx + 1;
+                }
+            }
+            Token::Text(_) => {
+                contents_end[idx] = idx + 1;
+                end[idx] = idx + 1;
+            }
+        }
+    }
+    for o in open {
+        contents_end[o] = tokens.len();
+        end[o] = tokens.len();
+    }
+
+    let is_node = |idx: usize| !matches!(tokens[idx], Token::Close(_));
+    // nodes starting in the token range [start, stop), skipping over the contents of each
+    let nodes_in = |mut start: usize, stop: usize| {
+        let mut nodes = Vec::new();
+        while start < stop {
+            if is_node(start) {
+                nodes.push(start);
+                start = end[start];
+            } else {
+                start += 1;
+            }
+        }
+        nodes
+    };
+
+    nodes_in(0, tokens.len())
+        .into_iter()
+        .map(|root| {
+            RecursiveHtml::expand_layers(root, |idx| match &tokens[idx] {
+                Token::Open { tag, attrs, .. } => Node::Element {
+                    tag: tag.clone(),
+                    attrs: attrs.clone(),
+                    children: nodes_in(idx + 1, contents_end[idx]),
+                },
+                Token::Text(text) => Node::Text(text.clone()),
+                Token::Close(_) => unreachable!("closing tags are never nodes"),
+            })
+        })
+        .collect()
+}
+
+/// Serialize back to HTML, with all elements explicitly closed
+pub fn to_html(tree: &RecursiveHtml) -> String {
+    // text is only escaped once we know it isn't the contents of a script or style,
+    // so each node produces both its escaped and raw serialization
+    let (escaped, _raw) =
+        tree.as_ref()
+            .collapse_layers(|node: NodeRef<(String, String)>| match node {
+                NodeRef::Element {
+                    tag,
+                    attrs,
+                    children,
+                } => {
+                    let mut res = format!("<{}", tag);
+                    for (name, value) in attrs {
+                        res.push_str(&format!(" {}=\"{}\"", name, encode_entities(value)));
+                    }
+                    res.push('>');
+                    if !VOID_ELEMENTS.contains(&tag) {
+                        for (escaped, raw) in children {
+                            if RAW_TEXT_ELEMENTS.contains(&tag) {
+                                res.push_str(&raw);
+                            } else {
+                                res.push_str(&escaped);
+                            }
+                        }
+                        res.push_str(&format!("</{}>", tag));
+                    }
+                    (res.clone(), res)
+                }
+                NodeRef::Text(text) => (encode_entities(text), text.to_string()),
+            });
+    escaped
+}
+
+/// Concatenated text of all text nodes, excluding scripts and styles
+pub fn text_content(tree: &RecursiveHtml) -> String {
+    tree.as_ref()
+        .collapse_layers(|node: NodeRef<String>| match node {
+            NodeRef::Element { tag, .. } if RAW_TEXT_ELEMENTS.contains(&tag) => String::new(),
+            NodeRef::Element { children, .. } => children.concat(),
+            NodeRef::Text(text) => text.to_string(),
+        })
+}
+
+/// The value of some attribute for every element with the given tag, in document order
+pub fn select_attr(tree: &RecursiveHtml, tag: &str, attr: &str) -> Vec<String> {
+    tree.as_ref()
+        .collapse_layers(|node: NodeRef<Vec<String>>| match node {
+            NodeRef::Element {
+                tag: t,
+                attrs,
+                children,
+            } => {
+                let mut res: Vec<String> = attrs
+                    .iter()
+                    .filter(|(name, _)| t == tag && name == attr)
+                    .map(|(_, value)| value.clone())
+                    .collect();
+                res.extend(children.into_iter().flatten());
+                res
+            }
+            NodeRef::Text(_) => Vec::new(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_one(input: &str) -> RecursiveHtml {
+        let mut nodes = parse(input);
+        assert_eq!(nodes.len(), 1);
+        nodes.remove(0)
+    }
+
+    #[test]
+    fn round_trip() {
+        let html = "<div class=\"a\"><p>hello <b>world</b></p><br><img src=\"x.png\"></div>";
+        assert_eq!(to_html(&parse_one(html)), html);
+    }
+
+    #[test]
+    fn lenient() {
+        let html = "<!DOCTYPE html><ul><li>one<li>two</span></ul><!-- comment -->";
+        assert_eq!(
+            to_html(&parse_one(html)),
+            "<ul><li>one<li>two</li></li></ul>"
+        );
+    }
+
+    #[test]
+    fn unclosed_at_end() {
+        assert_eq!(to_html(&parse_one("<p>a <i>b")), "<p>a <i>b</i></p>");
+    }
+
+    #[test]
+    fn fragments() {
+        let nodes = parse("a <b>b</b> <!-- c --> d");
+        let texts: Vec<_> = nodes.iter().map(text_content).collect();
+        assert_eq!(texts, vec!["a ", "b", " d"]);
+    }
+
+    #[test]
+    fn attributes_and_entities() {
+        let tree = parse_one(
+            "<p title='x &amp; y' hidden>1 &lt; 2 <a href=/one>one</a><a HREF=\"/two\">two</a></p>",
+        );
+        assert_eq!(select_attr(&tree, "a", "href"), vec!["/one", "/two"]);
+        assert_eq!(select_attr(&tree, "p", "title"), vec!["x & y"]);
+        assert_eq!(text_content(&tree), "1 < 2 onetwo");
+    }
+
+    #[test]
+    fn raw_text() {
+        let tree = parse_one("<div><script>if (a < b) {}</script>text — ünïcode</div>");
+        assert_eq!(text_content(&tree), "text — ünïcode");
+        assert_eq!(
+            to_html(&tree),
+            "<div><script>if (a < b) {}</script>text — ünïcode</div>"
+        );
+    }
+}
@@ -1,5 +1,6 @@
+use crate::list::{ListLayer, RecursiveList};
 use crate::map_layer::MapLayer;
-use crate::recursive::{Collapse, Expand};
+use crate::recursive::{collapse_layers_into, Collapse};
 use crate::recursive_tree::arena_eval::ArenaIndex;
 use crate::recursive_tree::RecursiveTree;
 
@@ -31,40 +32,22 @@ pub fn max<V: Ord>(r: RecursiveNTree<V>) -> Option<V> {
     r.collapse_layers(|layer| layer.children.into_iter().flatten().max())
 }
 
-/// A linked list of characters. Not good or idiomatic, but it provides a nice minimal example
-#[derive(Debug, Clone, Copy)]
-pub enum CharLinkedList<A> {
-    Cons(char, A),
-    Nil,
-}
-
-impl<A, B> MapLayer<B> for CharLinkedList<A> {
-    type To = CharLinkedList<B>;
-    type Unwrapped = A;
-
-    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
-        match self {
-            CharLinkedList::Cons(c, a) => CharLinkedList::Cons(c, f(a)),
-            CharLinkedList::Nil => CharLinkedList::Nil,
-        }
-    }
-}
-
-pub type RecursiveString = RecursiveTree<CharLinkedList<ArenaIndex>, ArenaIndex>;
+/// A string as a linked list of characters. Not good or idiomatic, but it provides a nice minimal
+/// example
+pub type RecursiveString = RecursiveList<char>;
 
 pub fn from_str(s: &str) -> RecursiveString {
-    RecursiveString::expand_layers(s.chars(), |mut it| {
-        if let Some(c) = it.next() {
-            CharLinkedList::Cons(c, it)
-        } else {
-            CharLinkedList::Nil
-        }
-    })
+    s.chars().collect()
 }
 
+/// Each char is pushed onto one buffer as it's visited, from the last to the first, rather than
+/// copying the rest of the string into a new one for each char
 pub fn to_str(r: RecursiveString) -> String {
-    r.collapse_layers(|cll| match cll {
-        CharLinkedList::Cons(c, s) => format!("{}{}", c, s),
-        CharLinkedList::Nil => String::new(),
-    })
+    let mut reversed = String::new();
+    collapse_layers_into(r, &mut reversed, |out, layer| {
+        if let ListLayer::Cons(c, ()) = layer {
+            out.push(c)
+        }
+    });
+    reversed.chars().rev().collect()
 }
@@ -0,0 +1,234 @@
+//! Game tree search, for tic-tac-toe. The tree of positions reachable from some board is expanded
+//! up to a given depth, and scored by negamax: each position's score, for the player to move, is
+//! the best of the negated scores of the positions its moves lead to.
+//!
+//! Scoring every position is a plain collapse. Alpha-beta pruning skips the moves that can't
+//! change the result, which requires choosing which subtrees to visit, so it's a lazy collapse:
+//! each position evaluates to a function of the bounds (alpha and beta) its score is needed
+//! within, and forces the thunks for its moves one at a time, stopping once the bounds meet.
+
+use std::cell::Cell;
+
+use crate::map_layer::MapLayer;
+use crate::recursive::{Collapse, Expand};
+use crate::recursive_tree::lazy::Thunk;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Player {
+    X,
+    O,
+}
+
+const LINES: [[usize; 3]; 8] = [
+    [0, 1, 2],
+    [3, 4, 5],
+    [6, 7, 8],
+    [0, 3, 6],
+    [1, 4, 7],
+    [2, 5, 8],
+    [0, 4, 8],
+    [2, 4, 6],
+];
+
+/// A tic-tac-toe board, with cells numbered left to right and top to bottom. X moves first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Board {
+    cells: [Option<Player>; 9],
+}
+
+impl Board {
+    /// A board written as its nine cells, eg "x.o......", ignoring whitespace
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut cells = [None; 9];
+        let mut chars = s.chars().filter(|c| !c.is_whitespace());
+        for cell in cells.iter_mut() {
+            *cell = match chars.next()? {
+                'x' | 'X' => Some(Player::X),
+                'o' | 'O' => Some(Player::O),
+                '.' => None,
+                _ => return None,
+            };
+        }
+        chars.next().is_none().then_some(Self { cells })
+    }
+
+    pub fn to_move(&self) -> Player {
+        let xs = self.cells.iter().filter(|c| **c == Some(Player::X)).count();
+        let os = self.cells.iter().filter(|c| **c == Some(Player::O)).count();
+        if xs > os {
+            Player::O
+        } else {
+            Player::X
+        }
+    }
+
+    pub fn winner(&self) -> Option<Player> {
+        LINES.iter().find_map(|[a, b, c]| {
+            let player = self.cells[*a]?;
+            (self.cells[*b] == Some(player) && self.cells[*c] == Some(player)).then_some(player)
+        })
+    }
+
+    /// The empty cells, unless the game is over
+    pub fn moves(&self) -> Vec<usize> {
+        if self.winner().is_some() {
+            return Vec::new();
+        }
+        (0..9).filter(|idx| self.cells[*idx].is_none()).collect()
+    }
+
+    pub fn play(&self, cell: usize) -> Self {
+        let mut cells = self.cells;
+        cells[cell] = Some(self.to_move());
+        Self { cells }
+    }
+
+    /// For the player to move: -1 if they've lost, and otherwise 0, as the game is either drawn or
+    /// not yet decided
+    fn score(&self) -> i32 {
+        if self.winner().is_some() {
+            -1
+        } else {
+            0
+        }
+    }
+}
+
+/// A single position in a game tree, with a child for each move considered from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position<A> {
+    pub board: Board,
+    pub moves: Vec<A>,
+}
+
+impl<A, B> MapLayer<B> for Position<A> {
+    type To = Position<B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, f: F) -> Self::To {
+        Position {
+            board: self.board,
+            moves: self.moves.into_iter().map(f).collect(),
+        }
+    }
+}
+
+impl<A: Copy, B> MapLayer<B> for &Position<A> {
+    type To = Position<B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        Position {
+            board: self.board,
+            moves: self.moves.iter().map(|a| f(*a)).collect(),
+        }
+    }
+}
+
+pub type GameTree = RecursiveTree<Position<ArenaIndex>, ArenaIndex>;
+
+/// Every position reachable from 'board' in at most 'depth' moves
+pub fn game_tree(board: Board, depth: usize) -> GameTree {
+    GameTree::expand_layers((board, depth), |(board, depth)| Position {
+        board,
+        moves: match depth {
+            0 => Vec::new(),
+            _ => board
+                .moves()
+                .into_iter()
+                .map(|cell| (board.play(cell), depth - 1))
+                .collect(),
+        },
+    })
+}
+
+/// The score of the root position for the player to move, found by scoring every position
+pub fn minimax(tree: &GameTree) -> i32 {
+    tree.as_ref().collapse_layers(|position: Position<i32>| {
+        position
+            .moves
+            .into_iter()
+            .map(|score| -score)
+            .max()
+            .unwrap_or_else(|| position.board.score())
+    })
+}
+
+// a position's score, given the bounds it's needed within
+type Bounded<'a> = Box<dyn FnOnce(i32, i32) -> i32 + 'a>;
+
+/// The score of the root position for the player to move, as 'minimax' finds it, along with the
+/// number of positions visited to find it
+pub fn alpha_beta(tree: &GameTree) -> (i32, usize) {
+    let visited = Cell::new(0);
+    let score =
+        tree.as_ref()
+            .collapse_layers_lazy(|position: Position<Thunk<Bounded>>| -> Bounded {
+                visited.set(visited.get() + 1);
+                Box::new(move |mut alpha, beta| {
+                    if position.moves.is_empty() {
+                        return position.board.score();
+                    }
+                    let mut best = i32::MIN;
+                    for next in position.moves {
+                        let score = -next.force()(-beta, -alpha);
+                        best = best.max(score);
+                        alpha = alpha.max(score);
+                        // the opponent has a better option elsewhere, so won't allow this position
+                        if alpha >= beta {
+                            break;
+                        }
+                    }
+                    best
+                })
+            })(-1, 1);
+    (score, visited.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(tree: &GameTree) -> usize {
+        tree.as_ref()
+            .collapse_layers(|position: Position<usize>| 1 + position.moves.iter().sum::<usize>())
+    }
+
+    #[test]
+    fn scores() {
+        // x to move, and can win at once
+        let winning = Board::parse("xx. oo. ...").unwrap();
+        // o to move, and can only block one of x's two threats
+        let losing = Board::parse("x.x .o. x.o").unwrap();
+        // the whole game, which is drawn with perfect play
+        let empty = Board::default();
+        for (board, expected) in [(winning, 1), (losing, -1), (empty, 0)] {
+            let tree = game_tree(board, 9);
+            assert_eq!(minimax(&tree), expected);
+            let (score, visited) = alpha_beta(&tree);
+            assert_eq!(score, expected);
+            if board == empty {
+                assert_eq!(size(&tree), 549946);
+                assert!(visited < 549946 / 10);
+            } else {
+                assert!(visited < size(&tree));
+            }
+        }
+    }
+
+    #[test]
+    fn depth_limited() {
+        let board = Board::parse("x.. ... ...").unwrap();
+        assert_eq!(board.to_move(), Player::O);
+        let tree = game_tree(board, 2);
+        assert_eq!(size(&tree), 1 + 8 + 8 * 7);
+        // no game ends within two moves, so every position is undecided
+        assert_eq!(alpha_beta(&tree).0, 0);
+        assert_eq!(
+            Board::parse("xxx oo. ...").unwrap().winner(),
+            Some(Player::X)
+        );
+        assert_eq!(Board::parse("xxx oo."), None);
+    }
+}
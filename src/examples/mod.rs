@@ -1,3 +1,9 @@
+pub mod avl;
+pub mod bst;
 pub mod expr;
-#[cfg(test)]
+pub mod html;
 pub mod linked_list;
+pub mod minimax;
+pub mod quadtree;
+pub mod rope;
+pub mod trie;
@@ -0,0 +1,222 @@
+//! A quadtree: a spatial index of points in the plane. Each node covers a rectangle, and is split
+//! into four equal quadrants while it holds more than a given number of points. The tree is
+//! expanded from a point set one quadrant at a time.
+//!
+//! Range queries are a lazy collapse, pruned to the quadrants that overlap the range: a split
+//! node only forces the thunks for those quadrants, so the rest of the tree is never visited.
+
+use std::cell::Cell;
+
+use crate::map_layer::MapLayer;
+use crate::recursive::Expand;
+use crate::recursive_tree::lazy::Thunk;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// A rectangle, including its minimum edges but not its maximum ones, so that quadrants don't
+/// overlap
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Rect {
+    pub fn contains(&self, p: Point) -> bool {
+        self.min.x <= p.x && p.x < self.max.x && self.min.y <= p.y && p.y < self.max.y
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.min.x < other.max.x
+            && other.min.x < self.max.x
+            && self.min.y < other.max.y
+            && other.min.y < self.max.y
+    }
+
+    /// The four equal quarters of the rectangle, by increasing y then x
+    pub fn quadrants(&self) -> [Rect; 4] {
+        let mid = Point {
+            x: (self.min.x + self.max.x) / 2.0,
+            y: (self.min.y + self.max.y) / 2.0,
+        };
+        let rect = |min_x, min_y, max_x, max_y| Rect {
+            min: Point { x: min_x, y: min_y },
+            max: Point { x: max_x, y: max_y },
+        };
+        [
+            rect(self.min.x, self.min.y, mid.x, mid.y),
+            rect(mid.x, self.min.y, self.max.x, mid.y),
+            rect(self.min.x, mid.y, mid.x, self.max.y),
+            rect(mid.x, mid.y, self.max.x, self.max.y),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuadLayer<A> {
+    Leaf(Rect, Vec<Point>),
+    Split(Rect, [A; 4]),
+}
+
+impl<A, B> MapLayer<B> for QuadLayer<A> {
+    type To = QuadLayer<B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, f: F) -> Self::To {
+        match self {
+            QuadLayer::Leaf(bounds, points) => QuadLayer::Leaf(bounds, points),
+            QuadLayer::Split(bounds, quadrants) => QuadLayer::Split(bounds, quadrants.map(f)),
+        }
+    }
+}
+
+/// Borrowed version of 'QuadLayer', produced by mapping over a '&QuadLayer'
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuadLayerRef<'a, A> {
+    Leaf(Rect, &'a [Point]),
+    Split(Rect, [A; 4]),
+}
+
+impl<'a, A: Copy, B: 'a> MapLayer<B> for &'a QuadLayer<A> {
+    type To = QuadLayerRef<'a, B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            QuadLayer::Leaf(bounds, points) => QuadLayerRef::Leaf(*bounds, points),
+            QuadLayer::Split(bounds, quadrants) => {
+                QuadLayerRef::Split(*bounds, quadrants.map(&mut f))
+            }
+        }
+    }
+}
+
+pub type QuadTree = RecursiveTree<QuadLayer<ArenaIndex>, ArenaIndex>;
+
+// deep enough for any distinct points of a reasonable scale, but bounded so that many copies of the
+// same point don't split forever
+const MAX_DEPTH: usize = 32;
+
+/// Index the points within 'bounds', splitting any quadrant with more than 'leaf_size' of them.
+/// Points outside 'bounds' are left out.
+pub fn build(bounds: Rect, points: &[Point], leaf_size: usize) -> QuadTree {
+    let points: Vec<Point> = points
+        .iter()
+        .copied()
+        .filter(|p| bounds.contains(*p))
+        .collect();
+    QuadTree::expand_layers((bounds, points, 0), |(bounds, points, depth)| {
+        if points.len() <= leaf_size || depth == MAX_DEPTH {
+            return QuadLayer::Leaf(bounds, points);
+        }
+        let quadrants = bounds.quadrants().map(|quadrant| {
+            let within = points.iter().copied().filter(|p| quadrant.contains(*p));
+            (quadrant, within.collect(), depth + 1)
+        });
+        QuadLayer::Split(bounds, quadrants)
+    })
+}
+
+/// Every point within 'range', along with the number of nodes visited to find them
+pub fn query(tree: &QuadTree, range: Rect) -> (Vec<Point>, usize) {
+    let visited = Cell::new(0);
+    let points = tree
+        .as_ref()
+        .collapse_layers_lazy(|layer: QuadLayerRef<Thunk<Vec<Point>>>| {
+            visited.set(visited.get() + 1);
+            match layer {
+                QuadLayerRef::Leaf(_, points) => points
+                    .iter()
+                    .copied()
+                    .filter(|p| range.contains(*p))
+                    .collect(),
+                QuadLayerRef::Split(bounds, quadrants) => bounds
+                    .quadrants()
+                    .into_iter()
+                    .zip(quadrants)
+                    .filter(|(quadrant, _)| quadrant.intersects(&range))
+                    .flat_map(|(_, points)| points.force())
+                    .collect(),
+            }
+        });
+    (points, visited.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recursive::Collapse;
+    use proptest::prelude::*;
+
+    fn rect(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Rect {
+        Rect {
+            min: Point { x: min_x, y: min_y },
+            max: Point { x: max_x, y: max_y },
+        }
+    }
+
+    fn sorted(mut points: Vec<Point>) -> Vec<(f64, f64)> {
+        points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+        points.into_iter().map(|p| (p.x, p.y)).collect()
+    }
+
+    #[test]
+    fn pruning() {
+        // a 64 by 64 grid of points
+        let points: Vec<Point> = (0..64 * 64)
+            .map(|idx| Point {
+                x: (idx % 64) as f64,
+                y: (idx / 64) as f64,
+            })
+            .collect();
+        let tree = build(rect(0.0, 0.0, 64.0, 64.0), &points, 4);
+        let nodes = tree
+            .as_ref()
+            .collapse_layers(|layer: QuadLayerRef<usize>| match layer {
+                QuadLayerRef::Leaf(..) => 1,
+                QuadLayerRef::Split(_, quadrants) => 1 + quadrants.iter().sum::<usize>(),
+            });
+
+        let (found, visited) = query(&tree, rect(10.0, 10.0, 12.0, 13.0));
+        assert_eq!(
+            sorted(found),
+            vec![
+                (10.0, 10.0),
+                (10.0, 11.0),
+                (10.0, 12.0),
+                (11.0, 10.0),
+                (11.0, 11.0),
+                (11.0, 12.0)
+            ]
+        );
+        assert!(visited * 20 < nodes);
+
+        let (all, visited) = query(&tree, rect(-1.0, -1.0, 100.0, 100.0));
+        assert_eq!(all.len(), 64 * 64);
+        assert_eq!(visited, nodes);
+
+        // copies of one point are kept in a single leaf once the tree is deep enough
+        let same = vec![Point { x: 1.0, y: 1.0 }; 10];
+        let tree = build(rect(0.0, 0.0, 2.0, 2.0), &same, 1);
+        assert_eq!(query(&tree, rect(0.0, 0.0, 2.0, 2.0)).0.len(), 10);
+    }
+
+    proptest! {
+        #[test]
+        fn matches_filter(
+            points in prop::collection::vec((0.0..100.0, 0.0..100.0), 0..200),
+            (x, y, w, h) in (0.0..100.0, 0.0..100.0, 0.0..50.0, 0.0..50.0),
+        ) {
+            let points: Vec<Point> = points.into_iter().map(|(x, y)| Point { x, y }).collect();
+            let tree = build(rect(0.0, 0.0, 100.0, 100.0), &points, 3);
+            let range = rect(x, y, x + w, y + h);
+            let expected = points.iter().copied().filter(|p| range.contains(*p)).collect();
+            prop_assert_eq!(sorted(query(&tree, range).0), sorted(expected));
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! A rope: a string stored as a tree of leaf strings joined by concat nodes, so that slicing and
+//! concatenation needn't copy the whole string. Each node's length is cached alongside the arena,
+//! annotated bottom-up in the style of 'MerkleTree', so a concat node's weight (the length of its
+//! left side) is known without visiting it.
+//!
+//! Lengths and positions are counted in chars. Slicing is an expand that descends through concat
+//! nodes by weight, only visiting those on either edge of the slice, and indexing is a slice of a
+//! single char.
+
+use std::ops::Range;
+
+use crate::map_layer::MapLayer;
+use crate::recursive::{Collapse, Expand};
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RopeLayer<A> {
+    Leaf(String),
+    Concat(A, A),
+}
+
+impl<A, B> MapLayer<B> for RopeLayer<A> {
+    type To = RopeLayer<B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            RopeLayer::Leaf(s) => RopeLayer::Leaf(s),
+            RopeLayer::Concat(a, b) => {
+                let a = f(a);
+                RopeLayer::Concat(a, f(b))
+            }
+        }
+    }
+}
+
+impl<A: Copy, B> MapLayer<B> for &RopeLayer<A> {
+    type To = RopeLayer<B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            RopeLayer::Leaf(s) => RopeLayer::Leaf(s.clone()),
+            RopeLayer::Concat(a, b) => {
+                let a = f(*a);
+                RopeLayer::Concat(a, f(*b))
+            }
+        }
+    }
+}
+
+pub type RecursiveRope = RecursiveTree<RopeLayer<ArenaIndex>, ArenaIndex>;
+
+/// A rope, along with the length of every node
+#[derive(Debug, Clone)]
+pub struct Rope {
+    tree: RecursiveRope,
+    // in arena order, so the root's length is first
+    lengths: Vec<usize>,
+}
+
+impl Rope {
+    /// Annotate each node of 'tree' with its length, bottom-up
+    pub fn new(tree: RecursiveRope) -> Self {
+        let mut lengths = vec![0; tree.elems.len()];
+        // children always have higher indices than their parents
+        for (idx, layer) in tree.elems.iter().enumerate().rev() {
+            lengths[idx] = match layer {
+                RopeLayer::Leaf(s) => s.chars().count(),
+                RopeLayer::Concat(ArenaIndex(a), ArenaIndex(b)) => lengths[*a] + lengths[*b],
+            };
+        }
+        Self { tree, lengths }
+    }
+
+    /// Split 's' in half repeatedly, until each leaf has at most 'leaf_len' chars
+    pub fn from_str(s: &str, leaf_len: usize) -> Self {
+        let chars: Vec<char> = s.chars().collect();
+        let tree = RecursiveRope::expand_layers(&chars[..], |chars| {
+            if chars.len() <= leaf_len.max(1) {
+                RopeLayer::Leaf(chars.iter().collect())
+            } else {
+                let (a, b) = chars.split_at(chars.len() / 2);
+                RopeLayer::Concat(a, b)
+            }
+        });
+        Self::new(tree)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lengths[0]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn tree(&self) -> &RecursiveRope {
+        &self.tree
+    }
+
+    /// A rope with the contents of 'self' followed by those of 'other', both copied into the new
+    /// rope's arena
+    pub fn concat(&self, other: &Rope) -> Rope {
+        // layers from either rope, with a new root joining them
+        #[derive(Clone, Copy)]
+        enum Seed {
+            Root,
+            Left(usize),
+            Right(usize),
+        }
+
+        let tree = RecursiveRope::expand_layers(Seed::Root, |seed| match seed {
+            Seed::Root => RopeLayer::Concat(Seed::Left(0), Seed::Right(0)),
+            Seed::Left(idx) => (&self.tree.elems[idx]).map_layer(|ArenaIndex(a)| Seed::Left(a)),
+            Seed::Right(idx) => (&other.tree.elems[idx]).map_layer(|ArenaIndex(a)| Seed::Right(a)),
+        });
+        Self::new(tree)
+    }
+
+    /// The chars in 'range', clamped to the length of the rope. Only the nodes on either edge of
+    /// the range are visited, along with the leaves within it.
+    pub fn slice(&self, range: Range<usize>) -> Rope {
+        let end = range.end.min(self.len());
+        let start = range.start.min(end);
+        let tree = RecursiveRope::expand_layers((0, start..end), |(mut idx, mut range)| loop {
+            match &self.tree.elems[idx] {
+                RopeLayer::Concat(ArenaIndex(a), ArenaIndex(b)) => {
+                    // the concat node's weight: the length of its left side
+                    let weight = self.lengths[*a];
+                    if range.end <= weight {
+                        idx = *a;
+                    } else if range.start >= weight {
+                        range = range.start - weight..range.end - weight;
+                        idx = *b;
+                    } else {
+                        break RopeLayer::Concat(
+                            (*a, range.start..weight),
+                            (*b, 0..range.end - weight),
+                        );
+                    }
+                }
+                RopeLayer::Leaf(s) => {
+                    break RopeLayer::Leaf(s.chars().skip(range.start).take(range.len()).collect())
+                }
+            }
+        });
+        Self::new(tree)
+    }
+
+    /// The char at 'idx', if the rope is long enough
+    pub fn index(&self, idx: usize) -> Option<char> {
+        let slice = self.slice(idx..idx + 1);
+        slice.to_string().chars().next()
+    }
+}
+
+impl std::fmt::Display for Rope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = self
+            .tree
+            .as_ref()
+            .collapse_layers(|layer: RopeLayer<String>| match layer {
+                RopeLayer::Leaf(s) => s,
+                RopeLayer::Concat(a, b) => a + &b,
+            });
+        f.write_str(&s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ropes() {
+        let text = "the quick brown fox jumps over the lazy dög";
+        let rope = Rope::from_str(text, 4);
+        assert_eq!(rope.len(), text.chars().count());
+        assert_eq!(rope.to_string(), text);
+        assert_eq!(rope.index(0), Some('t'));
+        assert_eq!(rope.index(42), Some('g'));
+        assert_eq!(rope.index(41), Some('ö'));
+        assert_eq!(rope.index(43), None);
+
+        for range in [0..0, 0..43, 4..9, 3..30, 40..100, 50..60] {
+            let expected: String = text
+                .chars()
+                .skip(range.start)
+                .take(range.end.saturating_sub(range.start))
+                .collect();
+            let slice = rope.slice(range);
+            assert_eq!(slice.to_string(), expected);
+            assert_eq!(slice.len(), expected.chars().count());
+        }
+
+        // a slice within a single leaf is a copy of just that part of it
+        assert_eq!(rope.slice(0..2).tree().as_ref().collapse_layers(count), 1);
+
+        let joined = rope.slice(4..9).concat(&rope.slice(34..43));
+        assert_eq!(joined.to_string(), "quick lazy dög");
+        assert_eq!(joined.len(), 14);
+        assert_eq!(joined.lengths[..3], [14, 5, 9]);
+        assert_eq!(joined.index(7), Some('a'));
+        assert_eq!(joined.slice(3..8).to_string(), "ck la");
+    }
+
+    fn count(layer: RopeLayer<usize>) -> usize {
+        match layer {
+            RopeLayer::Leaf(_) => 1,
+            RopeLayer::Concat(a, b) => 1 + a + b,
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! A trie of words, with each node's children keyed by the next char of the words below it. The trie
+//! is expanded from a word list one char at a time, and prefix queries find the node for a prefix by
+//! walking down from the root via 'layer', then collapse only that node's subtree, in place.
+
+use std::collections::HashMap;
+
+use crate::map_layer::MapLayer;
+use crate::recursive::{Collapse, Expand};
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrieLayer<A> {
+    pub node: HashMap<char, A>,
+    /// whether a word ends here
+    pub terminal: bool,
+}
+
+impl<A, B> MapLayer<B> for TrieLayer<A> {
+    type To = TrieLayer<B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        TrieLayer {
+            node: self.node.into_iter().map(|(c, a)| (c, f(a))).collect(),
+            terminal: self.terminal,
+        }
+    }
+}
+
+impl<A: Copy, B> MapLayer<B> for &TrieLayer<A> {
+    type To = TrieLayer<B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        TrieLayer {
+            node: self.node.iter().map(|(c, a)| (*c, f(*a))).collect(),
+            terminal: self.terminal,
+        }
+    }
+}
+
+pub type RecursiveTrie = RecursiveTree<TrieLayer<ArenaIndex>, ArenaIndex>;
+
+/// Each layer is expanded from the rest of every word below it
+pub fn from_words<'a>(words: impl IntoIterator<Item = &'a str>) -> RecursiveTrie {
+    let words: Vec<&str> = words.into_iter().collect();
+    RecursiveTrie::expand_layers(words, |words| {
+        let mut node: HashMap<char, Vec<&str>> = HashMap::new();
+        let mut terminal = false;
+        for word in words {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(c) => node.entry(c).or_default().push(chars.as_str()),
+                None => terminal = true,
+            }
+        }
+        TrieLayer { node, terminal }
+    })
+}
+
+/// The node reached by following 'prefix' down from the root, if any word starts with it
+pub fn find(trie: &RecursiveTrie, prefix: &str) -> Option<ArenaIndex> {
+    prefix
+        .chars()
+        .try_fold(trie.root(), |idx, c| trie.layer(idx).node.get(&c).copied())
+}
+
+pub fn contains(trie: &RecursiveTrie, word: &str) -> bool {
+    find(trie, word).is_some_and(|idx| trie.layer(idx).terminal)
+}
+
+/// Every word starting with 'prefix', sorted. Only the subtree below the prefix is collapsed.
+pub fn completions(trie: &RecursiveTrie, prefix: &str) -> Vec<String> {
+    let Some(idx) = find(trie, prefix) else {
+        return Vec::new();
+    };
+    let mut suffixes = trie
+        .subtree_view(idx)
+        .collapse_layers(|layer: TrieLayer<Vec<String>>| {
+            let mut suffixes = Vec::new();
+            if layer.terminal {
+                suffixes.push(String::new());
+            }
+            for (c, below) in layer.node {
+                suffixes.extend(below.into_iter().map(|suffix| format!("{}{}", c, suffix)));
+            }
+            suffixes
+        });
+    suffixes.sort();
+    suffixes
+        .into_iter()
+        .map(|suffix| format!("{}{}", prefix, suffix))
+        .collect()
+}
+
+/// The number of words starting with 'prefix'
+pub fn count_with_prefix(trie: &RecursiveTrie, prefix: &str) -> usize {
+    find(trie, prefix).map_or(0, |idx| {
+        trie.subtree_view(idx)
+            .collapse_layers(|layer: TrieLayer<usize>| {
+                layer.terminal as usize + layer.node.values().sum::<usize>()
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes() {
+        let trie = from_words(["car", "cart", "care", "cat", "dog", "", "ca"]);
+        assert!(contains(&trie, "car"));
+        assert!(contains(&trie, "ca"));
+        assert!(contains(&trie, ""));
+        assert!(!contains(&trie, "c"));
+        assert!(!contains(&trie, "cars"));
+
+        assert_eq!(completions(&trie, "car"), vec!["car", "care", "cart"]);
+        assert_eq!(
+            completions(&trie, "ca"),
+            vec!["ca", "car", "care", "cart", "cat"]
+        );
+        assert_eq!(completions(&trie, "x"), Vec::<String>::new());
+        assert_eq!(count_with_prefix(&trie, ""), 7);
+        assert_eq!(count_with_prefix(&trie, "d"), 1);
+        assert_eq!(count_with_prefix(&trie, "dot"), 0);
+    }
+}
@@ -0,0 +1,176 @@
+//! Export of weighted trees as folded stacks, the input format of flamegraph tooling
+//! (eg 'flamegraph.pl', inferno, speedscope).
+//!
+//! Each line is a path of frames from the root to some node, followed by a weight:
+//! 'src;examples;expr.rs 1790'. 'to_folded' exports any arena-backed tree, given a frame and a
+//! weight for each node. Otherwise, 'FoldedStacks' is built bottom-up by collapsing a tree, with
+//! each algebra step adding its node's frame to the stacks produced by its children via 'under'.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::map_layer::LayerArity;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// Weighted stacks for some subtree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FoldedStacks {
+    // frames are stored leaf-first so that adding a parent frame is a push
+    stacks: Vec<(Vec<String>, u64)>,
+}
+
+impl FoldedStacks {
+    /// A single stack with no frames, to be placed under frames added by ancestors
+    pub fn weight(weight: u64) -> Self {
+        Self {
+            stacks: vec![(Vec::new(), weight)],
+        }
+    }
+
+    /// Combine the stacks of sibling subtrees
+    pub fn merge(all: impl IntoIterator<Item = FoldedStacks>) -> Self {
+        Self {
+            stacks: all.into_iter().flat_map(|s| s.stacks).collect(),
+        }
+    }
+
+    /// Place all stacks under a parent frame
+    pub fn under(mut self, frame: impl Into<String>) -> Self {
+        let frame = frame.into();
+        for (frames, _) in self.stacks.iter_mut() {
+            frames.push(frame.clone());
+        }
+        self
+    }
+
+    pub fn total_weight(&self) -> u64 {
+        self.stacks.iter().map(|(_, weight)| weight).sum()
+    }
+
+    /// Folded lines, sorted and with duplicate stacks summed. Zero-weight stacks are omitted.
+    pub fn lines(&self) -> Vec<String> {
+        let mut folded: BTreeMap<String, u64> = BTreeMap::new();
+        for (frames, weight) in self.stacks.iter().filter(|(_, w)| *w > 0) {
+            let mut path = String::new();
+            for (idx, frame) in frames.iter().rev().enumerate() {
+                if idx > 0 {
+                    path.push(';');
+                }
+                // ';' separates frames and whitespace separates the weight, so neither can appear in a frame
+                path.extend(frame.chars().map(|c| match c {
+                    ';' => ':',
+                    c if c.is_whitespace() => '_',
+                    c => c,
+                }));
+            }
+            *folded.entry(path).or_default() += weight;
+        }
+
+        folded
+            .into_iter()
+            .map(|(path, weight)| format!("{} {}", path, weight))
+            .collect()
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for line in self.lines() {
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Export an arena-backed tree, with each node's frame given by 'label' and its own weight, not
+/// including its descendants', given by 'weight'. Each node's weight is placed on the stack of
+/// frames from the root down to it.
+pub fn to_folded<U, L: Into<String>>(
+    tree: &RecursiveTree<U, ArenaIndex>,
+    mut label: impl FnMut(ArenaIndex, &U) -> L,
+    mut weight: impl FnMut(ArenaIndex, &U) -> u64,
+) -> FoldedStacks
+where
+    U: LayerArity<Child = ArenaIndex>,
+{
+    let mut stacks = Vec::new();
+    // nodes still to be visited, each with its ancestors' frames, root-first
+    let mut todo = vec![(tree.root(), Vec::new())];
+    while let Some((idx, mut frames)) = todo.pop() {
+        let layer = tree.layer(idx);
+        frames.push(label(idx, layer).into());
+        for child in layer.children() {
+            todo.push((*child, frames.clone()));
+        }
+        frames.reverse();
+        stacks.push((frames, weight(idx, layer)));
+    }
+    FoldedStacks { stacks }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{BlocAllocExpr, Expr};
+    use crate::recursive::{Collapse, Expand};
+    use crate::recursive_tree::fixtures;
+
+    // evaluation cost: each operation costs 1 plus the cost of its operands, literals are free
+    fn cost_stacks(layer: Expr<FoldedStacks>) -> FoldedStacks {
+        match layer {
+            Expr::Add(a, b) => FoldedStacks::merge([a, b, FoldedStacks::weight(1)]).under("add"),
+            Expr::Sub(a, b) => FoldedStacks::merge([a, b, FoldedStacks::weight(1)]).under("sub"),
+            Expr::Mul(a, b) => FoldedStacks::merge([a, b, FoldedStacks::weight(1)]).under("mul"),
+            Expr::LiteralInt(_) => FoldedStacks::weight(0),
+        }
+    }
+
+    #[test]
+    fn expression_cost() {
+        let expr = BlocAllocExpr::expand_layers(2, |depth| match depth {
+            0 => Expr::LiteralInt(1),
+            1 => Expr::Mul(0, 0),
+            _ => Expr::Add(1, 1),
+        });
+
+        let stacks = expr.collapse_layers(cost_stacks);
+        assert_eq!(stacks.total_weight(), 3);
+        assert_eq!(stacks.lines(), vec!["add 1", "add;mul 2"]);
+    }
+
+    #[test]
+    fn export_tree() {
+        // ((1 + 2) * (3 - 4)) + 5, with operations weighing 1 and literals their value
+        let stacks = to_folded(
+            &fixtures::tree(),
+            |_, layer| match layer {
+                Expr::Add(..) => "add".to_string(),
+                Expr::Sub(..) => "sub".to_string(),
+                Expr::Mul(..) => "mul".to_string(),
+                Expr::LiteralInt(x) => x.to_string(),
+            },
+            |_, layer| match layer {
+                Expr::LiteralInt(x) => *x as u64,
+                _ => 1,
+            },
+        );
+        assert_eq!(
+            stacks.lines(),
+            vec![
+                "add 1",
+                "add;5 5",
+                "add;mul 1",
+                "add;mul;add 1",
+                "add;mul;add;1 1",
+                "add;mul;add;2 2",
+                "add;mul;sub 1",
+                "add;mul;sub;3 3",
+                "add;mul;sub;4 4",
+            ]
+        );
+    }
+
+    #[test]
+    fn escape_frames() {
+        let stacks = FoldedStacks::weight(7).under("my file;1").under("root");
+        assert_eq!(stacks.lines(), vec!["root;my_file:1 7"]);
+    }
+}
@@ -0,0 +1,201 @@
+//! JSON documents as a recursive structure.
+//!
+//! 'JsonLayer' is a single layer of a 'serde_json::Value'. 'Value' implements 'Project' and
+//! 'CoProject' in terms of it, so any 'Value' can be collapsed or expanded directly, and it can be
+//! converted to and from a 'RecursiveJson' for repeated traversals over a compact arena.
+
+use std::borrow::Cow;
+
+use serde_json::{Map, Number, Value};
+
+use crate::map_layer::{CoProject, EdgeLabeled, LayerArity, MapLayer, Project};
+use crate::recursive::{Collapse, Expand};
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// A single layer of a JSON document. Object fields are kept in the order of the 'serde_json::Map'
+/// they came from: sorted by key, unless serde_json's 'preserve_order' feature is enabled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonLayer<A> {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Vec<A>),
+    Object(Vec<(String, A)>),
+}
+
+/// Borrowed version of 'JsonLayer', produced by mapping over a '&JsonLayer'
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonLayerRef<'a, A> {
+    Null,
+    Bool(bool),
+    Number(&'a Number),
+    String(&'a str),
+    Array(Vec<A>),
+    Object(Vec<(&'a str, A)>),
+}
+
+impl<A, B> MapLayer<B> for JsonLayer<A> {
+    type To = JsonLayer<B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            JsonLayer::Null => JsonLayer::Null,
+            JsonLayer::Bool(b) => JsonLayer::Bool(b),
+            JsonLayer::Number(n) => JsonLayer::Number(n),
+            JsonLayer::String(s) => JsonLayer::String(s),
+            JsonLayer::Array(xs) => JsonLayer::Array(xs.into_iter().map(f).collect()),
+            JsonLayer::Object(xs) => {
+                JsonLayer::Object(xs.into_iter().map(|(k, v)| (k, f(v))).collect())
+            }
+        }
+    }
+}
+
+impl<A> LayerArity for JsonLayer<A> {
+    type Child = A;
+
+    fn child_count(&self) -> usize {
+        match self {
+            JsonLayer::Array(elems) => elems.len(),
+            JsonLayer::Object(fields) => fields.len(),
+            _ => 0,
+        }
+    }
+
+    fn child(&self, position: usize) -> Option<&A> {
+        match self {
+            JsonLayer::Array(elems) => elems.get(position),
+            JsonLayer::Object(fields) => fields.get(position).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+/// Object fields are labeled by their keys, and array elements by their indices
+impl<A> EdgeLabeled for JsonLayer<A> {
+    fn edge_label(&self, position: usize) -> Option<Cow<'_, str>> {
+        match self {
+            JsonLayer::Array(elems) if position < elems.len() => Some(position.to_string().into()),
+            JsonLayer::Object(fields) => fields.get(position).map(|(key, _)| key.as_str().into()),
+            _ => None,
+        }
+    }
+}
+
+impl<'a, A: Copy, B: 'a> MapLayer<B> for &'a JsonLayer<A> {
+    type To = JsonLayerRef<'a, B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            JsonLayer::Null => JsonLayerRef::Null,
+            JsonLayer::Bool(b) => JsonLayerRef::Bool(*b),
+            JsonLayer::Number(n) => JsonLayerRef::Number(n),
+            JsonLayer::String(s) => JsonLayerRef::String(s),
+            JsonLayer::Array(xs) => JsonLayerRef::Array(xs.iter().map(|x| f(*x)).collect()),
+            JsonLayer::Object(xs) => {
+                JsonLayerRef::Object(xs.iter().map(|(k, v)| (k.as_str(), f(*v))).collect())
+            }
+        }
+    }
+}
+
+impl Project for Value {
+    type To = JsonLayer<Value>;
+
+    fn project(self) -> Self::To {
+        match self {
+            Value::Null => JsonLayer::Null,
+            Value::Bool(b) => JsonLayer::Bool(b),
+            Value::Number(n) => JsonLayer::Number(n),
+            Value::String(s) => JsonLayer::String(s),
+            Value::Array(xs) => JsonLayer::Array(xs),
+            Value::Object(xs) => JsonLayer::Object(xs.into_iter().collect()),
+        }
+    }
+}
+
+impl<'a> Project for &'a Value {
+    type To = JsonLayer<&'a Value>;
+
+    fn project(self) -> Self::To {
+        match self {
+            Value::Null => JsonLayer::Null,
+            Value::Bool(b) => JsonLayer::Bool(*b),
+            Value::Number(n) => JsonLayer::Number(n.clone()),
+            Value::String(s) => JsonLayer::String(s.clone()),
+            Value::Array(xs) => JsonLayer::Array(xs.iter().collect()),
+            Value::Object(xs) => {
+                JsonLayer::Object(xs.iter().map(|(k, v)| (k.clone(), v)).collect())
+            }
+        }
+    }
+}
+
+impl CoProject for Value {
+    type From = JsonLayer<Value>;
+
+    fn coproject(layer: Self::From) -> Self {
+        match layer {
+            JsonLayer::Null => Value::Null,
+            JsonLayer::Bool(b) => Value::Bool(b),
+            JsonLayer::Number(n) => Value::Number(n),
+            JsonLayer::String(s) => Value::String(s),
+            JsonLayer::Array(xs) => Value::Array(xs),
+            JsonLayer::Object(xs) => Value::Object(xs.into_iter().collect::<Map<_, _>>()),
+        }
+    }
+}
+
+pub type RecursiveJson = RecursiveTree<JsonLayer<ArenaIndex>, ArenaIndex>;
+
+pub fn from_value(value: &Value) -> RecursiveJson {
+    RecursiveJson::expand_layers(value, Project::project)
+}
+
+pub fn to_value(json: RecursiveJson) -> Value {
+    json.collapse_layers(Value::coproject)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn example() -> Value {
+        json!({
+            "name": "recursion",
+            "version": [0, 1, 0],
+            "nested": {"a": {"b": {"c": null}}, "flag": true, "ratio": 0.5}
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        let value = example();
+        assert_eq!(to_value(from_value(&value)), value);
+    }
+
+    #[test]
+    fn collapse_value_directly() {
+        let depth = example().collapse_layers(|layer| match layer {
+            JsonLayer::Array(xs) => xs.into_iter().max().unwrap_or(0) + 1,
+            JsonLayer::Object(xs) => xs.into_iter().map(|(_k, v)| v).max().unwrap_or(0) + 1,
+            _ => 1,
+        });
+        assert_eq!(depth, 5);
+    }
+
+    #[test]
+    fn collapse_borrowed_arena() {
+        let json = from_value(&example());
+        let keys: usize = json.as_ref().collapse_layers(|layer| match layer {
+            JsonLayerRef::Array(xs) => xs.into_iter().sum(),
+            JsonLayerRef::Object(xs) => xs.len() + xs.into_iter().map(|(_k, v)| v).sum::<usize>(),
+            _ => 0,
+        });
+        assert_eq!(keys, 8);
+    }
+}
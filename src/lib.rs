@@ -3,13 +3,42 @@
 //! Generic utilities for expanding and collapsing user-defined recursive structures
 //! of any type. Define recursive algorithms by writing functions that expand or
 //! collapse a single layer of your structure.
+//!
+//! The core traits and arena-backed trees have no dependencies. Everything else is behind a cargo
+//! feature: 'async' (on by default) for async expansion and emission via 'futures', 'json',
+//! 'cbor', 'protobuf', 'rkyv' and 'rowan' for interop, 'proptest' and 'arbitrary' for testing
+//! layers, 'rand' for sampling nodes and subtrees of arena-backed trees, and 'expr_example' for
+//! the example structures in 'examples', with 'bigint' for arbitrary-precision evaluation of
+//! expressions.
+//!
+//! The crate builds for wasm32-unknown-unknown with any features other than 'proptest' and 'git',
+//! which need an OS. The 'wasm' example runs the expression language in a browser.
 
+pub mod annotated;
+pub mod codec;
+pub mod dag;
+pub mod emit;
+pub mod flamegraph;
+#[cfg(any(test, feature = "json"))]
+pub mod json;
+pub mod list;
 pub mod map_layer;
+pub mod merkle;
+pub mod pretty;
 pub mod recursive;
 pub mod recursive_tree;
+pub mod render;
+pub mod rewrite;
+pub mod spanned;
 pub mod stack_machine_lazy;
+#[cfg(any(test, feature = "rowan"))]
+pub mod syntax;
+#[cfg(any(test, feature = "proptest"))]
+pub mod testing;
 // using cfg flag to make expr examples available in a benchmark context
 #[cfg(any(test, feature = "expr_example"))]
 pub mod examples;
 
-pub use crate::recursive::{Collapse, Expand, ExpandAsync};
+#[cfg(any(test, feature = "async"))]
+pub use crate::recursive::ExpandAsync;
+pub use crate::recursive::{Collapse, Expand, TryCollapse, TryExpand};
@@ -0,0 +1,148 @@
+//! Lists as a recursive structure, one element per layer.
+//!
+//! A list is about the simplest structure that collapse and expand apply to, but it's also a useful
+//! one: 'RecursiveList' can be built from any iterator and converted back to a 'Vec', and since its
+//! layers can be collapsed lazily, eg via 'collapse_layers_lazy', a collapse can stop partway
+//! through without visiting the rest of the list.
+
+use std::borrow::Cow;
+
+use crate::map_layer::{EdgeLabeled, LayerArity, MapLayer};
+use crate::recursive::{collapse_layers_into, Expand};
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// A single layer of a list: an element followed by the rest of the list, or its end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListLayer<T, A> {
+    Cons(T, A),
+    Nil,
+}
+
+impl<T, A, B> MapLayer<B> for ListLayer<T, A> {
+    type To = ListLayer<T, B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            ListLayer::Cons(x, a) => ListLayer::Cons(x, f(a)),
+            ListLayer::Nil => ListLayer::Nil,
+        }
+    }
+}
+
+impl<T, A> LayerArity for ListLayer<T, A> {
+    type Child = A;
+
+    fn child_count(&self) -> usize {
+        match self {
+            ListLayer::Cons(..) => 1,
+            ListLayer::Nil => 0,
+        }
+    }
+
+    fn child(&self, position: usize) -> Option<&A> {
+        match self {
+            ListLayer::Cons(_, a) if position == 0 => Some(a),
+            _ => None,
+        }
+    }
+}
+
+impl<T, A> EdgeLabeled for ListLayer<T, A> {
+    fn edge_label(&self, position: usize) -> Option<Cow<'_, str>> {
+        self.child(position).map(|_| "tail".into())
+    }
+}
+
+impl<'a, T, A: Copy, B: 'a> MapLayer<B> for &'a ListLayer<T, A> {
+    type To = ListLayer<&'a T, B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            ListLayer::Cons(x, a) => ListLayer::Cons(x, f(*a)),
+            ListLayer::Nil => ListLayer::Nil,
+        }
+    }
+}
+
+pub type RecursiveList<T> = RecursiveTree<ListLayer<T, ArenaIndex>, ArenaIndex>;
+
+impl<T> FromIterator<T> for RecursiveList<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::expand_layers(iter.into_iter(), |mut iter| match iter.next() {
+            Some(x) => ListLayer::Cons(x, iter),
+            None => ListLayer::Nil,
+        })
+    }
+}
+
+impl<T> From<Vec<T>> for RecursiveList<T> {
+    fn from(xs: Vec<T>) -> Self {
+        xs.into_iter().collect()
+    }
+}
+
+impl<T> From<RecursiveList<T>> for Vec<T> {
+    fn from(list: RecursiveList<T>) -> Self {
+        // layers are collapsed from the end of the list, so elements are pushed in reverse
+        let mut xs = Vec::new();
+        collapse_layers_into(list, &mut xs, |xs, layer| {
+            if let ListLayer::Cons(x, ()) = layer {
+                xs.push(x)
+            }
+        });
+        xs.reverse();
+        xs
+    }
+}
+
+impl<T> IntoIterator for RecursiveList<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Vec::from(self).into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::recursive::Collapse;
+    use crate::recursive_tree::lazy::Thunk;
+
+    #[test]
+    fn conversions() {
+        let list = RecursiveList::from(vec![1, 2, 3]);
+        let sum = list.as_ref().collapse_layers(|layer| match layer {
+            ListLayer::Cons(x, sum) => x + sum,
+            ListLayer::Nil => 0,
+        });
+        assert_eq!(sum, 6);
+        assert_eq!(list.into_iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        let empty: RecursiveList<u8> = std::iter::empty().collect();
+        assert_eq!(Vec::from(empty), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn lazy() {
+        let list: RecursiveList<u32> = (1..=100).collect();
+        let visited = Cell::new(0);
+        let first_even =
+            list.as_ref()
+                .collapse_layers_lazy(|layer: ListLayer<&u32, Thunk<Option<u32>>>| {
+                    visited.set(visited.get() + 1);
+                    match layer {
+                        ListLayer::Cons(x, _) if x % 2 == 0 => Some(*x),
+                        ListLayer::Cons(_, rest) => rest.force(),
+                        ListLayer::Nil => None,
+                    }
+                });
+        assert_eq!(first_even, Some(2));
+        assert_eq!(visited.get(), 2);
+    }
+}
@@ -1,6 +1,8 @@
+use std::borrow::Cow;
+
 /// Provides the ability to map over some structure 'Layer',
-/// such that 'Self' is 'Layer<Unwrapped>', via a function 'Fn(Unwrapped) -> B'
-/// producing a value 'To' such 'To' is 'Layer<B>'.
+/// such that 'Self' is `Layer<Unwrapped>`, via a function `Fn(Unwrapped) -> B`
+/// producing a value 'To' such 'To' is `Layer<B>`.
 ///
 /// The function provided to map_layer MUST be strictly applied.
 pub trait MapLayer<B> {
@@ -8,13 +10,50 @@ pub trait MapLayer<B> {
     type Unwrapped; // A
     type To; // Layer<B>
     /// Additional constraint not present in haskell, req'd for stack machine eval:
-    ///   for any F<A>, F<B>, etc, where all structure but B/A is identical,
+    ///   for any `F<A>`, `F<B>`, etc, where all structure but B/A is identical,
     ///   fmap must visit nodes in the same order each time it is called
-    ///   given that F<B> is created by mapping some function over F<A>
+    ///   given that `F<B>` is created by mapping some function over `F<A>`
     ///   note that enforcing this property may be problematic for, Hashmaps/Sets/etc
     fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, f: F) -> Self::To;
 }
 
+/// The children of a layer, accessed in place rather than by mapping over it, so that generic
+/// utilities (eg iterating over leaves, or exporting to another format) can be written once for
+/// every layer type. Positions are in the order 'map_layer' visits children.
+pub trait LayerArity {
+    type Child;
+
+    fn child_count(&self) -> usize;
+
+    /// The child at 'position', if there is one
+    fn child(&self, position: usize) -> Option<&Self::Child>;
+
+    fn children(&self) -> impl Iterator<Item = &Self::Child> {
+        (0..self.child_count()).filter_map(|position| self.child(position))
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.child_count() == 0
+    }
+}
+
+/// A label for the edge to each child of a layer, eg the name of a directory entry, or 'lhs' and
+/// 'rhs' for the operands of a binary operator, so that everything that names edges (paths to
+/// nodes, DOT and mermaid export) agrees on what each is called.
+pub trait EdgeLabeled: LayerArity {
+    /// The label of the edge to the child at 'position', if there is one
+    fn edge_label(&self, position: usize) -> Option<Cow<'_, str>>;
+
+    /// Each child along with the label of the edge to it, or an empty label if it has none. Layers
+    /// that find a label by walking their children, eg the keys of a map, should override this to
+    /// walk them just once.
+    fn labeled_children(&self) -> impl Iterator<Item = (Cow<'_, str>, &Self::Child)> {
+        self.children()
+            .enumerate()
+            .map(|(position, child)| (self.edge_label(position).unwrap_or_default(), child))
+    }
+}
+
 // basically just From/To but we want something clearly context-specific and, idk, lawful probably
 pub trait Project {
     // A
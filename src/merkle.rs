@@ -0,0 +1,280 @@
+//! Merkle hashing: a content hash for every node, computed from the node's own data and the hashes
+//! of its children. Structurally identical subtrees hash identically, which is the basis for
+//! caching, deduplication and change detection.
+
+use std::collections::HashMap;
+use std::hash::Hasher;
+
+use crate::map_layer::MapLayer;
+use crate::recursive::Collapse;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// Byte serialization of a single layer with its children already replaced by their hashes.
+///
+/// Implementations should write a distinct tag per variant so that eg 'Add(a, b)' and 'Sub(a, b)'
+/// don't collide, and should not rely on 'std::hash::Hash' impls that vary across platforms.
+pub trait HashLayer {
+    fn hash_layer<H: Hasher>(&self, state: &mut H);
+}
+
+fn hash_one<H: Hasher + Default, L: HashLayer>(layer: &L) -> u64 {
+    let mut state = H::default();
+    layer.hash_layer(&mut state);
+    state.finish()
+}
+
+/// Root hash of any collapsible tree
+pub fn merkle_hash<H, Tree, Wrapped>(tree: Tree) -> u64
+where
+    H: Hasher + Default,
+    Tree: Collapse<u64, Wrapped>,
+    Wrapped: HashLayer,
+{
+    tree.collapse_layers(|layer: Wrapped| hash_one::<H, _>(&layer))
+}
+
+/// An arena-backed tree along with the merkle hash of every subtree
+#[derive(Debug, Clone)]
+pub struct MerkleTree<L> {
+    tree: RecursiveTree<L, ArenaIndex>,
+    // in arena order, so the root hash is first
+    hashes: Vec<u64>,
+}
+
+impl<L> MerkleTree<L> {
+    /// Hash every node of the tree, bottom-up
+    pub fn new<H, Hashable>(tree: RecursiveTree<L, ArenaIndex>) -> Self
+    where
+        H: Hasher + Default,
+        for<'a> &'a L: MapLayer<u64, Unwrapped = ArenaIndex, To = Hashable>,
+        Hashable: HashLayer,
+    {
+        let mut hashes = vec![0; tree.elems.len()];
+        // children always have higher indices than their parents
+        for (idx, layer) in tree.elems.iter().enumerate().rev() {
+            let with_hashes = layer.map_layer(|ArenaIndex(child)| hashes[child]);
+            hashes[idx] = hash_one::<H, _>(&with_hashes);
+        }
+
+        Self { tree, hashes }
+    }
+
+    pub fn root_hash(&self) -> u64 {
+        self.hashes[0]
+    }
+
+    pub fn tree(&self) -> &RecursiveTree<L, ArenaIndex> {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> RecursiveTree<L, ArenaIndex> {
+        self.tree
+    }
+
+    /// Collapse the tree, with each algebra step also receiving the hash of the current subtree
+    pub fn collapse_with_hashes<'a, A, O>(
+        &'a self,
+        mut collapse_layer: impl FnMut(u64, O) -> A,
+    ) -> A
+    where
+        &'a L: MapLayer<A, To = O, Unwrapped = ArenaIndex>,
+    {
+        let mut results: Vec<Option<A>> = std::iter::repeat_with(|| None)
+            .take(self.hashes.len())
+            .collect();
+
+        for (idx, layer) in self.tree.elems.iter().enumerate().rev() {
+            let layer = layer.map_layer(|ArenaIndex(child)| {
+                results[child]
+                    .take()
+                    .expect("every node is the child of exactly one parent")
+            });
+            results[idx] = Some(collapse_layer(self.hashes[idx], layer));
+        }
+
+        results[0].take().expect("trees are nonempty")
+    }
+
+    /// Collapse the tree, reusing the result for any subtree whose hash is already in 'cache', eg
+    /// from collapsing a previous version of the tree, and adding the result for each subtree that
+    /// isn't. Cached subtrees are skipped entirely, along with everything below them.
+    pub fn collapse_cached<'a, A, O>(
+        &'a self,
+        cache: &mut CollapseCache<A>,
+        mut collapse_layer: impl FnMut(O) -> A,
+    ) -> A
+    where
+        A: Clone,
+        &'a L: MapLayer<A, To = O, Unwrapped = ArenaIndex>,
+        for<'b> &'b L: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        // a layer is needed if its parent is needed and wasn't cached. Parents come first.
+        let mut needed = vec![false; self.hashes.len()];
+        needed[0] = true;
+        for (idx, layer) in self.tree.elems.iter().enumerate() {
+            if needed[idx] && !cache.results.contains_key(&self.hashes[idx]) {
+                layer.map_layer(|ArenaIndex(child)| needed[child] = true);
+            }
+        }
+
+        let mut results: Vec<Option<A>> = std::iter::repeat_with(|| None)
+            .take(self.hashes.len())
+            .collect();
+        for (idx, layer) in self.tree.elems.iter().enumerate().rev() {
+            if !needed[idx] {
+                continue;
+            }
+            let hash = self.hashes[idx];
+            let result = match cache.results.get(&hash) {
+                Some(result) => result.clone(),
+                None => {
+                    let layer = layer.map_layer(|ArenaIndex(child)| {
+                        results[child]
+                            .take()
+                            .expect("every node is the child of exactly one parent")
+                    });
+                    let result = collapse_layer(layer);
+                    cache.results.insert(hash, result.clone());
+                    result
+                }
+            };
+            results[idx] = Some(result);
+        }
+
+        results[0].take().expect("trees are nonempty")
+    }
+}
+
+/// Results of collapsing subtrees, keyed by their merkle hash, to be reused when collapsing other
+/// trees that share them, eg successive versions of a file tree or syntax tree. A cache must only
+/// be used with a single algebra, and hashes from a single hasher. It's never evicted from, so
+/// should be cleared or dropped once it's grown too large.
+#[derive(Debug, Clone)]
+pub struct CollapseCache<A> {
+    results: HashMap<u64, A>,
+}
+
+impl<A> Default for CollapseCache<A> {
+    fn default() -> Self {
+        Self {
+            results: HashMap::new(),
+        }
+    }
+}
+
+impl<A> CollapseCache<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cached result for the subtree with the given hash, if any
+    pub fn get(&self, hash: u64) -> Option<&A> {
+        self.results.get(&hash)
+    }
+
+    /// How many distinct subtrees have results cached
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.results.clear()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::examples::expr::{eval::eval_layer, BlocAllocExpr, Expr};
+    use crate::recursive::Expand;
+    use crate::recursive_tree::fixtures::complete_layer;
+
+    // a complete tree of additions, with 'literal' at each leaf
+    fn with_leaves(literal: i64) -> impl Fn(usize) -> Expr<usize> {
+        move |depth| match complete_layer(depth) {
+            Expr::LiteralInt(_) => Expr::LiteralInt(literal),
+            layer => layer,
+        }
+    }
+
+    fn example(literal: i64) -> BlocAllocExpr {
+        BlocAllocExpr::expand_layers(3, with_leaves(literal))
+    }
+
+    fn with_sub_root(literal: i64) -> BlocAllocExpr {
+        let layer = with_leaves(literal);
+        BlocAllocExpr::expand_layers(3, |depth| match layer(depth) {
+            Expr::Add(a, b) if depth == 3 => Expr::Sub(a, b),
+            layer => layer,
+        })
+    }
+
+    #[test]
+    fn content_addressed() {
+        let a = merkle_hash::<DefaultHasher, _, _>(example(1).as_ref());
+        let b = merkle_hash::<DefaultHasher, _, _>(example(1).as_ref());
+        let c = merkle_hash::<DefaultHasher, _, _>(example(2).as_ref());
+        let d = merkle_hash::<DefaultHasher, _, _>(with_sub_root(1).as_ref());
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(a, d);
+    }
+
+    #[test]
+    fn annotated_tree() {
+        let hashed = MerkleTree::new::<DefaultHasher, _>(example(1));
+        assert_eq!(
+            hashed.root_hash(),
+            merkle_hash::<DefaultHasher, _, _>(example(1).as_ref())
+        );
+
+        // every level of the example is two copies of the level below, so there are only
+        // as many distinct subtrees as there are levels
+        let mut distinct = HashSet::new();
+        let result = hashed.collapse_with_hashes(|hash, layer: Expr<i64>| {
+            distinct.insert(hash);
+            eval_layer(layer)
+        });
+        assert_eq!(result, 8);
+        assert_eq!(distinct.len(), 4);
+    }
+
+    #[test]
+    fn cached_across_trees() {
+        let mut cache = CollapseCache::new();
+        let mut collapsed = 0;
+        let mut eval = |layer: Expr<i64>| {
+            collapsed += 1;
+            eval_layer(layer)
+        };
+
+        let first = MerkleTree::new::<DefaultHasher, _>(example(1));
+        assert_eq!(first.collapse_cached(&mut cache, &mut eval), 8);
+        // the two copies of each level below the root are collapsed once
+        assert_eq!(cache.len(), 4);
+
+        // only the root differs, so its children are reused
+        let second = MerkleTree::new::<DefaultHasher, _>(with_sub_root(1));
+        assert_eq!(second.collapse_cached(&mut cache, &mut eval), 0);
+        assert_eq!(cache.len(), 5);
+        assert_eq!(cache.get(second.root_hash()), Some(&0));
+
+        // a different leaf changes every subtree
+        let third = MerkleTree::new::<DefaultHasher, _>(example(2));
+        assert_eq!(third.collapse_cached(&mut cache, &mut eval), 16);
+        assert_eq!(cache.len(), 9);
+
+        // the first tree is cached in full
+        assert_eq!(first.collapse_cached(&mut cache, &mut eval), 8);
+        // four levels of layers for the first, each a copy of the level below it, the root of
+        // the second, and then four for the third
+        assert_eq!(collapsed, 4 + 1 + 4);
+    }
+}
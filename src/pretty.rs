@@ -0,0 +1,206 @@
+//! Wadler-style pretty printing: build a 'Doc' for each layer via collapse, then lay the whole
+//! document out at some target width. Groups are printed on a single line if they fit in the
+//! remaining width, and otherwise have their line breaks rendered as newlines.
+
+use crate::recursive::Collapse;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Doc {
+    Nil,
+    Text(String),
+    /// a line break, rendered as 'flat' when the enclosing group fits on one line
+    Line {
+        flat: &'static str,
+    },
+    Nest(usize, Box<Doc>),
+    Concat(Vec<Doc>),
+    Group(Box<Doc>),
+}
+
+impl Doc {
+    pub fn nil() -> Self {
+        Doc::Nil
+    }
+
+    /// Text must not contain newlines, use 'Doc::line' instead
+    pub fn text(s: impl Into<String>) -> Self {
+        Doc::Text(s.into())
+    }
+
+    /// A space if the enclosing group fits, otherwise a newline
+    pub fn line() -> Self {
+        Doc::Line { flat: " " }
+    }
+
+    /// Nothing if the enclosing group fits, otherwise a newline
+    pub fn softline() -> Self {
+        Doc::Line { flat: "" }
+    }
+
+    pub fn concat(docs: impl IntoIterator<Item = Doc>) -> Self {
+        Doc::Concat(docs.into_iter().collect())
+    }
+
+    pub fn intersperse(docs: impl IntoIterator<Item = Doc>, sep: Doc) -> Self {
+        let mut res = Vec::new();
+        for (idx, doc) in docs.into_iter().enumerate() {
+            if idx > 0 {
+                res.push(sep.clone());
+            }
+            res.push(doc);
+        }
+        Doc::Concat(res)
+    }
+
+    pub fn append(self, other: Doc) -> Self {
+        match self {
+            Doc::Concat(mut docs) => {
+                docs.push(other);
+                Doc::Concat(docs)
+            }
+            doc => Doc::Concat(vec![doc, other]),
+        }
+    }
+
+    /// Indent line breaks within this doc by an additional 'indent' columns
+    pub fn nest(self, indent: usize) -> Self {
+        Doc::Nest(indent, Box::new(self))
+    }
+
+    /// Lay out this doc on a single line if it fits
+    pub fn group(self) -> Self {
+        Doc::Group(Box::new(self))
+    }
+
+    pub fn render(&self, width: usize) -> String {
+        let mut out = String::new();
+        let mut col = 0;
+        let mut todo = vec![(0, Mode::Break, self)];
+
+        while let Some((indent, mode, doc)) = todo.pop() {
+            match doc {
+                Doc::Nil => {}
+                Doc::Text(s) => {
+                    out.push_str(s);
+                    col += s.chars().count();
+                }
+                Doc::Line { flat } => match mode {
+                    Mode::Flat => {
+                        out.push_str(flat);
+                        col += flat.len();
+                    }
+                    Mode::Break => {
+                        out.push('\n');
+                        out.extend(std::iter::repeat_n(' ', indent));
+                        col = indent;
+                    }
+                },
+                Doc::Nest(i, doc) => todo.push((indent + i, mode, doc)),
+                Doc::Concat(docs) => todo.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+                Doc::Group(doc) => {
+                    let mode = match mode {
+                        Mode::Flat => Mode::Flat,
+                        Mode::Break if fits(width.saturating_sub(col), doc, &todo) => Mode::Flat,
+                        Mode::Break => Mode::Break,
+                    };
+                    todo.push((indent, mode, doc));
+                }
+            }
+        }
+
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+// does 'doc' laid out flat, followed by everything up to the next line break in 'rest', fit in 'remaining'
+fn fits(remaining: usize, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut remaining = remaining as isize;
+    let mut todo = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+
+    loop {
+        let (mode, doc) = match todo.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some((_, mode, doc)) => (*mode, *doc),
+                None => return true,
+            },
+        };
+
+        match doc {
+            Doc::Nil => {}
+            Doc::Text(s) => remaining -= s.chars().count() as isize,
+            Doc::Line { flat } => match mode {
+                Mode::Flat => remaining -= flat.len() as isize,
+                Mode::Break => return true,
+            },
+            Doc::Nest(_, doc) | Doc::Group(doc) => todo.push((mode, doc)),
+            Doc::Concat(docs) => todo.extend(docs.iter().rev().map(|doc| (mode, doc))),
+        }
+
+        if remaining < 0 {
+            return false;
+        }
+    }
+}
+
+/// Render any tree via an algebra from layers to docs
+pub fn pretty<Tree, Wrapped>(
+    tree: Tree,
+    width: usize,
+    collapse_layer: impl FnMut(Wrapped) -> Doc,
+) -> String
+where
+    Tree: Collapse<Doc, Wrapped>,
+{
+    tree.collapse_layers(collapse_layer).render(width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(items: &[&str]) -> Doc {
+        Doc::text("[")
+            .append(
+                Doc::softline()
+                    .append(Doc::intersperse(
+                        items.iter().map(|s| Doc::text(*s)),
+                        Doc::text(",").append(Doc::line()),
+                    ))
+                    .nest(2),
+            )
+            .append(Doc::softline())
+            .append(Doc::text("]"))
+            .group()
+    }
+
+    #[test]
+    fn fits_on_one_line() {
+        assert_eq!(list(&["a", "b", "c"]).render(80), "[a, b, c]");
+    }
+
+    #[test]
+    fn breaks_when_too_wide() {
+        assert_eq!(
+            list(&["alpha", "beta", "gamma"]).render(10),
+            "[\n  alpha,\n  beta,\n  gamma\n]"
+        );
+    }
+
+    #[test]
+    fn inner_groups_stay_flat() {
+        let doc = Doc::text("outer")
+            .append(Doc::line().append(list(&["a", "b"])).nest(2))
+            .append(Doc::line())
+            .append(list(&["c", "d"]))
+            .group();
+        assert_eq!(doc.render(12), "outer\n  [a, b]\n[c, d]");
+    }
+}
@@ -2,19 +2,43 @@
 //! repeatedly expanding or collapsing it one layer at a time.
 //!
 
+use std::collections::VecDeque;
+
+#[cfg(any(test, feature = "async"))]
 use futures::future::BoxFuture;
 
+use crate::map_layer::MapLayer;
+
 /// Support for collapsing a structure into a single value, one layer at a time
 pub trait Collapse<A, Wrapped> {
     fn collapse_layers<F: FnMut(Wrapped) -> A>(self, collapse_layer: F) -> A;
 }
 
+/// Support for collapsing a structure into a single value via a fallible function, one layer at a time.
+/// Stops at the first error, without visiting any further layers.
+pub trait TryCollapse<A, Wrapped> {
+    fn try_collapse_layers<E, F: FnMut(Wrapped) -> Result<A, E>>(
+        self,
+        collapse_layer: F,
+    ) -> Result<A, E>;
+}
+
 /// Support for expanding a structure from a seed value, one layer at a time
 pub trait Expand<A, Wrapped> {
     fn expand_layers<F: Fn(A) -> Wrapped>(a: A, expand_layer: F) -> Self;
 }
 
+/// Support for expanding a structure from a seed value via a fallible function, one layer at a time.
+/// Stops at the first error, without expanding any further layers.
+pub trait TryExpand<A, Wrapped>: Sized {
+    fn try_expand_layers<E, F: Fn(A) -> Result<Wrapped, E>>(
+        a: A,
+        expand_layer: F,
+    ) -> Result<Self, E>;
+}
+
 /// Support for asynchronously expanding a structure from a seed value, one layer at a time.
+#[cfg(any(test, feature = "async"))]
 pub trait ExpandAsync<A, Wrapped> {
     fn expand_layers_async<
         'a,
@@ -28,3 +52,194 @@ pub trait ExpandAsync<A, Wrapped> {
         Self: Sized,
         A: Send + 'a;
 }
+
+/// Collapse a structure by writing each layer into a single shared output 'out', eg a 'String' or a
+/// 'Vec', instead of building a new value per layer and copying children's results into it.
+/// Layers are visited children first, so a layer with a single child, eg a list's, sees elements
+/// from the last to the first, as in a right fold.
+pub fn collapse_layers_into<Tree, Wrapped, B>(
+    tree: Tree,
+    out: &mut B,
+    mut write_layer: impl FnMut(&mut B, Wrapped),
+) where
+    Tree: Collapse<(), Wrapped>,
+{
+    tree.collapse_layers(|layer| write_layer(out, layer))
+}
+
+/// Collapse a structure where each step also has access to the original subtree of each child, as
+/// well as the result of collapsing it (a paramorphism). The original subtrees are rebuilt from the
+/// bottom up via 'embed', and a copy of each child's subtree is made for each layer, so 'S' should
+/// be cheap to clone (eg an 'Rc') unless the structure is small.
+pub fn collapse_layers_with_subtrees<Tree, Wrapped, Embedded, S, A>(
+    tree: Tree,
+    mut embed: impl FnMut(Embedded) -> S,
+    mut collapse_layer: impl FnMut(Wrapped) -> A,
+) -> A
+where
+    Tree: Collapse<(S, A), Wrapped>,
+    Wrapped: MapLayer<S, Unwrapped = (S, A), To = Embedded>,
+    Embedded: MapLayer<(S, A), Unwrapped = S, To = Wrapped> + Clone,
+{
+    tree.collapse_layers(|layer: Wrapped| {
+        // children are visited in the same order by both 'map_layer' calls
+        let mut results = VecDeque::new();
+        let subtrees = layer.map_layer(|(subtree, result)| {
+            results.push_back(result);
+            subtree
+        });
+        let subtree = embed(subtrees.clone());
+        let layer = subtrees.map_layer(|subtree| (subtree, results.pop_front().unwrap()));
+        (subtree, collapse_layer(layer))
+    })
+    .1
+}
+
+/// Combine two functions collapsing the same kind of layer into one that computes both results, so
+/// that both can be had from a single pass over a structure. Any non-recursive parts of the layer,
+/// eg the value of a literal, are cloned so each function gets its own copy of the layer.
+pub fn product<Wrapped, Shape, LA, LB, A, B>(
+    mut collapse_a: impl FnMut(LA) -> A,
+    mut collapse_b: impl FnMut(LB) -> B,
+) -> impl FnMut(Wrapped) -> (A, B)
+where
+    Wrapped: MapLayer<(), Unwrapped = (A, B), To = Shape>,
+    Shape: MapLayer<A, Unwrapped = (), To = LA> + MapLayer<B, Unwrapped = (), To = LB> + Clone,
+{
+    move |layer: Wrapped| {
+        // children are visited in the same order by each 'map_layer' call
+        let mut results = VecDeque::new();
+        let shape = layer.map_layer(|result| results.push_back(result));
+        let mut bs = VecDeque::new();
+        let layer_a = shape.clone().map_layer(|()| {
+            let (a, b) = results.pop_front().unwrap();
+            bs.push_back(b);
+            a
+        });
+        let layer_b = shape.map_layer(|()| bs.pop_front().unwrap());
+        (collapse_a(layer_a), collapse_b(layer_b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{naive::ExprAST, BlocAllocExpr, DFSStackExpr, Expr};
+    use crate::map_layer::Project;
+
+    // 1 - (2 * 0), failing on any literal zero
+    fn example() -> ExprAST {
+        ExprAST::Sub(
+            Box::new(ExprAST::LiteralInt(1)),
+            Box::new(ExprAST::Mul(
+                Box::new(ExprAST::LiteralInt(2)),
+                Box::new(ExprAST::LiteralInt(0)),
+            )),
+        )
+    }
+
+    fn no_zeros(visited: &mut usize) -> impl FnMut(Expr<i64>) -> Result<i64, String> + '_ {
+        |layer| {
+            *visited += 1;
+            match layer {
+                Expr::LiteralInt(0) => Err("zero".to_string()),
+                Expr::LiteralInt(x) => Ok(x),
+                Expr::Add(a, b) => Ok(a + b),
+                Expr::Sub(a, b) => Ok(a - b),
+                Expr::Mul(a, b) => Ok(a * b),
+            }
+        }
+    }
+
+    #[test]
+    fn try_collapse_short_circuits() {
+        let expr = example();
+        let arena = BlocAllocExpr::expand_layers(&expr, Project::project);
+        let stack = DFSStackExpr::expand_layers(&expr, Project::project);
+
+        let mut visited = 0;
+        assert_eq!(
+            arena.as_ref().try_collapse_layers(no_zeros(&mut visited)),
+            Err("zero".to_string())
+        );
+        // arena collapse visits nodes in reverse bfs order, so the zero literal is visited first
+        assert_eq!(visited, 1);
+
+        let mut visited = 0;
+        assert!(stack.try_collapse_layers(no_zeros(&mut visited)).is_err());
+        assert!(visited < 5);
+
+        let mut visited = 0;
+        assert!(expr.try_collapse_layers(no_zeros(&mut visited)).is_err());
+        assert!(visited < 5);
+    }
+
+    #[test]
+    fn collapse_with_subtrees() {
+        // 1 - (2 * 0), rendered with each 'Mul' operand annotated by its own value
+        let render = |subtree: &ExprAST| format!("{:?}", subtree);
+        let expr = example();
+        let embed = |layer: Expr<ExprAST>| match layer {
+            Expr::Add(a, b) => ExprAST::Add(Box::new(a), Box::new(b)),
+            Expr::Sub(a, b) => ExprAST::Sub(Box::new(a), Box::new(b)),
+            Expr::Mul(a, b) => ExprAST::Mul(Box::new(a), Box::new(b)),
+            Expr::LiteralInt(x) => ExprAST::LiteralInt(x),
+        };
+        let arena = BlocAllocExpr::expand_layers(&expr, Project::project);
+
+        let result = collapse_layers_with_subtrees(
+            arena.as_ref(),
+            embed,
+            |layer: Expr<(ExprAST, String)>| match layer {
+                Expr::Mul((a, _), (b, _)) => format!("[{}] * [{}]", render(&a), render(&b)),
+                Expr::Add((_, a), (_, b)) => format!("{} + {}", a, b),
+                Expr::Sub((_, a), (_, b)) => format!("{} - {}", a, b),
+                Expr::LiteralInt(x) => x.to_string(),
+            },
+        );
+        assert_eq!(result, "1 - [LiteralInt(2)] * [LiteralInt(0)]");
+    }
+
+    #[test]
+    fn product_of_collapses() {
+        // 1 - (2 * 0), evaluated and counted at once
+        let arena = BlocAllocExpr::expand_layers(&example(), Project::project);
+        let count = |layer: Expr<usize>| match layer {
+            Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) => 1 + a + b,
+            Expr::LiteralInt(_) => 1,
+        };
+        let eval = |layer: Expr<i64>| match layer {
+            Expr::Add(a, b) => a + b,
+            Expr::Sub(a, b) => a - b,
+            Expr::Mul(a, b) => a * b,
+            Expr::LiteralInt(x) => x,
+        };
+        assert_eq!(arena.as_ref().collapse_layers(product(count, eval)), (5, 1));
+        // both sides may produce the same type
+        assert_eq!(
+            arena.as_ref().collapse_layers(product(count, count)),
+            (5, 5)
+        );
+    }
+
+    #[test]
+    fn try_collapse_ok() {
+        let expr = ExprAST::Add(
+            Box::new(ExprAST::LiteralInt(1)),
+            Box::new(ExprAST::LiteralInt(2)),
+        );
+        let arena = BlocAllocExpr::expand_layers(&expr, Project::project);
+        let mut visited = 0;
+        assert_eq!(arena.try_collapse_layers(no_zeros(&mut visited)), Ok(3));
+        assert_eq!(visited, 3);
+    }
+
+    #[test]
+    fn collapse_into() {
+        use crate::examples::linked_list::{from_str, to_str};
+
+        let s = "abc".repeat(1000);
+        assert_eq!(to_str(from_str(&s)), s);
+        assert_eq!(to_str(from_str("")), "");
+    }
+}
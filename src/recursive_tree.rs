@@ -1,17 +1,56 @@
+mod ancestors;
+#[cfg(any(test, feature = "rkyv"))]
+pub mod archived;
 pub mod arena_eval;
+mod arity;
+mod budget;
+mod checkpoint;
+mod dirty;
+mod events;
+#[cfg(test)]
+pub(crate) mod fixtures;
+mod graft;
+pub mod lazy;
+#[cfg(any(test, feature = "rand"))]
+mod sample;
+#[cfg(any(test, feature = "json", feature = "cbor"))]
+mod serialize;
+mod sidecar;
 pub mod stack_machine_eval;
+mod subtree;
+mod truncated;
+mod two_phase;
 
-pub use crate::recursive_tree::{arena_eval::ArenaIndex, stack_machine_eval::StackMarker};
+pub use crate::recursive_tree::{
+    arena_eval::{ArenaIndex, CollapseBuffer},
+    arity::TreeStats,
+    budget::{Budget, BudgetReport},
+    checkpoint::Checkpoint,
+    dirty::DirtyFlags,
+    events::{Event, EventError, TreeBuilder},
+    graft::Sharing,
+    sidecar::{Origins, Sidecar},
+    stack_machine_eval::StackMarker,
+    subtree::SubtreeView,
+    truncated::{DebugTruncated, Hole},
+    two_phase::{Filled, Unfilled},
+};
+
+use crate::map_layer::MapLayer;
 
 /// A recursive structure with layers of partially-applied type `Layer`,
 /// where `Index` is the type that `Layer` is parameterized over and `Wrapped` is `Layer<Index>`
 ///
 /// Stored as a flat vector of layers in topological order.
+///
+/// With the 'rkyv' feature, deserializing an archived tree checks it, as with 'from_layers'.
+#[derive(Debug, Clone)]
+#[cfg_attr(any(test, feature = "rkyv"), derive(rkyv::Archive, rkyv::Serialize))]
 pub struct RecursiveTree<Wrapped, Index> {
     // nonempty, in topological-sorted order
-    elems: Vec<Wrapped>,
+    pub(crate) elems: Vec<Wrapped>,
     // the index type over which 'Layer' is parameterized
-    _underlying: std::marker::PhantomData<Index>,
+    pub(crate) _underlying: std::marker::PhantomData<Index>,
 }
 
 impl<'a, F, U> RecursiveTree<F, U> {
@@ -23,12 +62,59 @@ impl<'a, F, U> RecursiveTree<F, U> {
     }
 }
 
+impl<Wrapped> RecursiveTree<Wrapped, ArenaIndex> {
+    /// A tree of 'elems', root first, if they describe one that can be stored in an arena: every
+    /// node other than the root must be referenced exactly once, by a node that precedes it. This
+    /// is the checked counterpart to expansion, eg for layers rearranged from those of other trees.
+    pub fn from_layers(elems: Vec<Wrapped>) -> Option<Self>
+    where
+        for<'a> &'a Wrapped: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        is_valid_tree(&elems).then_some(Self {
+            elems,
+            _underlying: std::marker::PhantomData,
+        })
+    }
+}
+
 /// A reference to some recursive structure with layers of partially-applied type `Layer`,
 /// where `Index` is the type that `Layer` is parameterized over and `Wrapped` is `Layer<Index>`
 ///
 /// Stored as a flat vector of layers in topological order.
 pub struct RecursiveTreeRef<'a, Wrapped, Index> {
-    elems: &'a [Wrapped],
+    pub(crate) elems: &'a [Wrapped],
     // the index type over which 'Layer' is parameterized
-    _underlying: std::marker::PhantomData<Index>,
+    pub(crate) _underlying: std::marker::PhantomData<Index>,
+}
+
+/// Whether 'elems' describes a tree that can be stored in an arena, checked in one linear pass:
+/// it must be nonempty, and every node other than the root must be referenced exactly once, by a
+/// node that precedes it. Collapse relies on this, so trees from untrusted sources must be checked.
+pub(crate) fn is_valid_tree<'a, Wrapped>(elems: &'a [Wrapped]) -> bool
+where
+    &'a Wrapped: MapLayer<(), Unwrapped = ArenaIndex>,
+{
+    is_valid_partial_tree(elems, 0)
+}
+
+/// Like 'is_valid_tree', for the start of a tree whose last 'pending' nodes are still to be
+/// appended, each of which must be referenced by one of 'elems'
+pub(crate) fn is_valid_partial_tree<'a, Wrapped>(elems: &'a [Wrapped], pending: usize) -> bool
+where
+    &'a Wrapped: MapLayer<(), Unwrapped = ArenaIndex>,
+{
+    let len = elems.len().saturating_add(pending);
+    let mut referenced = vec![false; len];
+    let mut valid = len > 0;
+    for (idx, layer) in elems.iter().enumerate() {
+        layer.map_layer(|ArenaIndex(child)| {
+            if child <= idx || child >= len || referenced[child] {
+                valid = false;
+            } else {
+                referenced[child] = true;
+            }
+        });
+    }
+    // every node but the root must be reachable
+    valid && referenced.iter().skip(1).all(|r| *r)
 }
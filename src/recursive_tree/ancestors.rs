@@ -0,0 +1,98 @@
+//! Collapse where each layer can see a bounded window of its ancestors, eg for context-sensitive
+//! lints like 'no unsafe block inside a const fn', without passing an inherited context down
+//! through every layer as 'collapse_layers_with_context' does.
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+impl<U> RecursiveTree<U, ArenaIndex> {
+    /// Collapse the tree, with each layer also given up to 'window' of its nearest ancestors, as
+    /// they are in the tree, outermost first, so that its parent (if any) is last. Ancestors are
+    /// found by walking up parent pointers, so each layer costs at most 'window' steps.
+    pub fn collapse_layers_with_ancestors<'a, A, O>(
+        &'a self,
+        window: usize,
+        mut collapse_layer: impl FnMut(&[&'a U], O) -> A,
+    ) -> A
+    where
+        &'a U: MapLayer<A, Unwrapped = ArenaIndex, To = O>,
+        for<'b> &'b U: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        let parents = self.parents();
+        let mut ancestors = Vec::with_capacity(window);
+        let mut results: Vec<Option<A>> = std::iter::repeat_with(|| None)
+            .take(self.elems.len())
+            .collect();
+
+        // children always have higher indices than their parents
+        for (idx, layer) in self.elems.iter().enumerate().rev() {
+            ancestors.clear();
+            let mut next = parents[ArenaIndex(idx)];
+            while let Some(parent) = next.filter(|_| ancestors.len() < window) {
+                ancestors.push(&self.elems[parent.0]);
+                next = parents[parent];
+            }
+            ancestors.reverse();
+
+            let layer = layer.map_layer(|ArenaIndex(child)| {
+                results[child]
+                    .take()
+                    .expect("every node is the child of exactly one parent")
+            });
+            results[idx] = Some(collapse_layer(&ancestors, layer));
+        }
+
+        results[0].take().expect("trees are nonempty")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::examples::expr::Expr;
+    use crate::recursive_tree::arena_eval::ArenaIndex;
+    use crate::recursive_tree::fixtures::tree;
+
+    #[test]
+    fn windows() {
+        let tree = tree();
+        // the literals with a multiplication within 'window' layers above them
+        let under_mul = |window| {
+            tree.collapse_layers_with_ancestors(
+                window,
+                |ancestors: &[&Expr<ArenaIndex>], layer: Expr<Vec<i64>>| {
+                    assert!(ancestors.len() <= window);
+                    match layer {
+                        Expr::LiteralInt(x) => {
+                            let mul = ancestors.iter().any(|a| matches!(a, Expr::Mul(..)));
+                            if mul {
+                                vec![x]
+                            } else {
+                                vec![]
+                            }
+                        }
+                        Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) => [a, b].concat(),
+                    }
+                },
+            )
+        };
+        assert_eq!(under_mul(0), Vec::<i64>::new());
+        assert_eq!(under_mul(1), Vec::<i64>::new());
+        assert_eq!(under_mul(2), vec![1, 2, 3, 4]);
+        assert_eq!(under_mul(10), vec![1, 2, 3, 4]);
+
+        // with a window spanning the whole tree, every layer but the root sees it first, and the
+        // literals see their depths
+        let root = tree.layer(tree.root());
+        let depths = tree.collapse_layers_with_ancestors(
+            10,
+            |ancestors: &[&Expr<ArenaIndex>], layer: Expr<Vec<usize>>| match layer {
+                Expr::LiteralInt(_) => {
+                    assert!(std::ptr::eq(ancestors[0], root));
+                    vec![ancestors.len()]
+                }
+                Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) => [a, b].concat(),
+            },
+        );
+        assert_eq!(depths, vec![3, 3, 3, 3, 1]);
+    }
+}
@@ -0,0 +1,149 @@
+//! Zero-copy collapse of arena-backed trees archived with rkyv.
+//!
+//! A `RecursiveTree<Layer<ArenaIndex>, ArenaIndex>` can be serialized with 'rkyv::to_bytes' and later
+//! accessed in place (eg from a memory-mapped file) as an 'ArchivedRecursiveTree'. Once validated,
+//! it can be collapsed via the same 'RecursiveTreeRef' machinery as an in-memory tree, given a
+//! 'MapLayer' impl for references to the archived layer type with 'Unwrapped = ArenaIndex'.
+//!
+//! Archived trees can also be deserialized into owned trees, which are checked in the same way,
+//! failing with 'InvalidTree' if the archive does not describe a valid tree.
+
+use std::fmt;
+
+use rkyv::rancor::{Fallible, Source};
+use rkyv::vec::ArchivedVec;
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::arena_eval::{ArchivedArenaIndex, ArenaIndex};
+use crate::recursive_tree::{
+    is_valid_tree, ArchivedRecursiveTree, RecursiveTree, RecursiveTreeRef,
+};
+
+/// The error for archived layers that do not describe a tree, when they're deserialized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidTree;
+
+impl fmt::Display for InvalidTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "layers do not describe a tree in topological order")
+    }
+}
+
+impl std::error::Error for InvalidTree {}
+
+impl From<ArchivedArenaIndex> for ArenaIndex {
+    fn from(idx: ArchivedArenaIndex) -> Self {
+        // an index too big for this platform is out of range, and so rejected by 'as_ref', rather
+        // than truncated to some index that may be valid
+        ArenaIndex(usize::try_from(idx.0.to_native()).unwrap_or(usize::MAX))
+    }
+}
+
+impl From<&ArchivedArenaIndex> for ArenaIndex {
+    fn from(idx: &ArchivedArenaIndex) -> Self {
+        ArenaIndex::from(*idx)
+    }
+}
+
+impl<Wrapped: rkyv::Archive> ArchivedRecursiveTree<Wrapped, ArenaIndex> {
+    /// Borrow the archived layers as a tree that can be collapsed in place.
+    ///
+    /// Archives may come from untrusted storage, and collapse relies on every index being valid, so
+    /// this checks that each node is referenced exactly once, by a node that precedes it. Returns
+    /// 'None' if the archive does not describe a valid tree.
+    pub fn as_ref<'a>(&'a self) -> Option<RecursiveTreeRef<'a, rkyv::Archived<Wrapped>, ArenaIndex>>
+    where
+        &'a rkyv::Archived<Wrapped>: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        let elems: &'a [rkyv::Archived<Wrapped>] = self.elems.as_slice();
+
+        is_valid_tree(elems).then_some(RecursiveTreeRef {
+            elems,
+            _underlying: std::marker::PhantomData,
+        })
+    }
+}
+
+// written by hand rather than derived, so that a corrupt archive can't be deserialized into a
+// tree that collapse would index out of bounds
+impl<Wrapped, D> rkyv::Deserialize<RecursiveTree<Wrapped, ArenaIndex>, D>
+    for ArchivedRecursiveTree<Wrapped, ArenaIndex>
+where
+    Wrapped: rkyv::Archive,
+    ArchivedVec<rkyv::Archived<Wrapped>>: rkyv::Deserialize<Vec<Wrapped>, D>,
+    for<'a> &'a Wrapped: MapLayer<(), Unwrapped = ArenaIndex>,
+    D: Fallible + ?Sized,
+    D::Error: Source,
+{
+    fn deserialize(
+        &self,
+        deserializer: &mut D,
+    ) -> Result<RecursiveTree<Wrapped, ArenaIndex>, D::Error> {
+        let elems = self.elems.deserialize(deserializer)?;
+        RecursiveTree::from_layers(elems).ok_or_else(|| D::Error::new(InvalidTree))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{eval::eval_layer, BlocAllocExpr, Expr};
+    use crate::recursive::{Collapse, Expand};
+    use crate::recursive_tree::fixtures::complete_layer;
+    use rkyv::rancor::Error;
+
+    #[test]
+    fn collapse_archived() {
+        let tree = BlocAllocExpr::expand_layers(10, complete_layer);
+
+        let bytes = rkyv::to_bytes::<Error>(&tree).unwrap();
+        let archived =
+            rkyv::access::<ArchivedRecursiveTree<Expr<ArenaIndex>, ArenaIndex>, Error>(&bytes)
+                .unwrap();
+
+        assert_eq!(archived.as_ref().unwrap().collapse_layers(eval_layer), 1024);
+    }
+
+    #[test]
+    fn reject_invalid_indices() {
+        // both children point at the same node
+        let tree = BlocAllocExpr {
+            elems: vec![
+                Expr::Add(ArenaIndex(1), ArenaIndex(1)),
+                Expr::LiteralInt(1),
+                Expr::LiteralInt(2),
+            ],
+            _underlying: std::marker::PhantomData,
+        };
+
+        let bytes = rkyv::to_bytes::<Error>(&tree).unwrap();
+        let archived =
+            rkyv::access::<ArchivedRecursiveTree<Expr<ArenaIndex>, ArenaIndex>, Error>(&bytes)
+                .unwrap();
+
+        assert!(archived.as_ref().is_none());
+        // nor can it be deserialized
+        let deserialized = rkyv::deserialize::<BlocAllocExpr, Error>(archived);
+        assert!(deserialized.is_err());
+    }
+
+    #[test]
+    fn deserialize_archived() {
+        let tree = BlocAllocExpr::expand_layers(3, complete_layer);
+
+        let bytes = rkyv::to_bytes::<Error>(&tree).unwrap();
+        let tree = rkyv::from_bytes::<BlocAllocExpr, Error>(&bytes).unwrap();
+        assert_eq!(tree.collapse_layers(eval_layer), 8);
+
+        // an out of range child, which would be read out of bounds by collapse
+        let corrupt = BlocAllocExpr {
+            elems: vec![
+                Expr::Add(ArenaIndex(1), ArenaIndex(1_000_000)),
+                Expr::LiteralInt(1),
+            ],
+            _underlying: std::marker::PhantomData,
+        };
+        let bytes = rkyv::to_bytes::<Error>(&corrupt).unwrap();
+        assert!(rkyv::from_bytes::<BlocAllocExpr, Error>(&bytes).is_err());
+    }
+}
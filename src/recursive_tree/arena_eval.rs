@@ -1,26 +1,132 @@
 //! Recursive structure that uses an arena to quickly collapse recursive structures.
 
-use std::collections::VecDeque;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
 use std::mem::MaybeUninit;
 
-use futures::future::BoxFuture;
-use futures::FutureExt;
+#[cfg(any(test, feature = "async"))]
+use futures::{
+    future::BoxFuture,
+    stream::{FuturesUnordered, StreamExt},
+    FutureExt,
+};
 
 use crate::map_layer::MapLayer;
-use crate::recursive::{Collapse, Expand, ExpandAsync};
+#[cfg(any(test, feature = "async"))]
+use crate::recursive::ExpandAsync;
+use crate::recursive::{Collapse, Expand, TryCollapse, TryExpand};
 use crate::recursive_tree::{RecursiveTree, RecursiveTreeRef};
 
-/// Used to mark structures stored in an 'RecursiveTree<Layer<ArenaIndex>, ArenaIndex>'
+/// Used to mark structures stored in an `RecursiveTree<Layer<ArenaIndex>, ArenaIndex>`
 ///
 /// Has the same memory cost as a boxed pointer and provides the fastest
 /// 'Collapse::collapse_layers' implementation
-#[derive(Debug, Clone, Copy)]
-pub struct ArenaIndex(usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    any(test, feature = "rkyv"),
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    rkyv(derive(Debug, Clone, Copy))
+)]
+#[cfg_attr(
+    any(test, feature = "json", feature = "cbor"),
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct ArenaIndex(pub(crate) usize);
 
 impl ArenaIndex {
     fn head() -> Self {
         ArenaIndex(0)
     }
+
+    // the index of a seed just pushed onto the frontier during breadth-first expansion, as each
+    // layer is stored in the order its seed is popped off
+    pub(crate) fn enqueued(elems: usize, frontier: usize) -> Self {
+        ArenaIndex(
+            elems
+                .checked_add(frontier)
+                .expect("arena index overflowed usize"),
+        )
+    }
+}
+
+// Checks the invariants unchecked collapse relies on, as each child is taken: every child follows
+// its parent in the arena, and is taken exactly once. Trees are only built in ways that uphold
+// these, so the checks are only made in debug builds, to catch a bug in how some tree was built
+// before it's undefined behavior.
+struct ChildCheck {
+    #[cfg(debug_assertions)]
+    taken: Vec<bool>,
+}
+
+impl ChildCheck {
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    fn new(len: usize) -> Self {
+        debug_assert!(len > 0, "corrupt arena: no root");
+        Self {
+            #[cfg(debug_assertions)]
+            taken: vec![false; len],
+        }
+    }
+
+    #[inline(always)]
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    fn take(&mut self, parent: usize, child: usize) {
+        #[cfg(debug_assertions)]
+        {
+            assert!(
+                parent < child && child < self.taken.len(),
+                "corrupt arena: node {} has out of order child {}",
+                parent,
+                child
+            );
+            assert!(
+                !std::mem::replace(&mut self.taken[child], true),
+                "corrupt arena: node {} has multiple parents",
+                child
+            );
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for ArenaIndex {
+    fn arbitrary(_u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        // placeholder, only meaningful once the layer containing it is inserted into a tree
+        Ok(ArenaIndex::head())
+    }
+}
+
+/// Generates trees breadth-first, one arbitrary layer at a time. Node count is bounded by the size
+/// of the input, so input that runs out before every branch is closed off by a leaf is rejected.
+#[cfg(feature = "arbitrary")]
+impl<'a, Underlying> arbitrary::Arbitrary<'a> for RecursiveTree<Underlying, ArenaIndex>
+where
+    Underlying:
+        arbitrary::Arbitrary<'a> + MapLayer<ArenaIndex, Unwrapped = ArenaIndex, To = Underlying>,
+{
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let max_nodes = u.arbitrary_len::<Underlying>()?.max(1);
+        let mut elems = Vec::new();
+        // root is allocated up front, each child position allocates the next idx in bfs order
+        let mut allocated = 1;
+
+        while elems.len() < allocated {
+            let layer = Underlying::arbitrary(u)?.map_layer(|_| {
+                allocated += 1;
+                ArenaIndex(allocated - 1)
+            });
+            if allocated > max_nodes {
+                return Err(arbitrary::Error::NotEnoughData);
+            }
+            elems.push(layer);
+        }
+
+        Ok(Self {
+            elems,
+            _underlying: std::marker::PhantomData,
+        })
+    }
 }
 
 impl<A, Underlying, Wrapped> Expand<A, Wrapped> for RecursiveTree<Underlying, ArenaIndex>
@@ -38,7 +144,7 @@ where
             let layer = layer.map_layer(|aa| {
                 frontier.push_back(aa);
                 // idx of pointed-to element determined from frontier + elems size
-                ArenaIndex(elems.len() + frontier.len())
+                ArenaIndex::enqueued(elems.len(), frontier.len())
             });
 
             elems.push(layer);
@@ -51,6 +157,38 @@ where
     }
 }
 
+impl<A, Underlying, Wrapped> TryExpand<A, Wrapped> for RecursiveTree<Underlying, ArenaIndex>
+where
+    Wrapped: MapLayer<ArenaIndex, Unwrapped = A, To = Underlying>,
+{
+    fn try_expand_layers<E, F: Fn(A) -> Result<Wrapped, E>>(
+        a: A,
+        expand_layer: F,
+    ) -> Result<Self, E> {
+        let mut frontier = VecDeque::from([a]);
+        let mut elems = vec![];
+
+        // expand to build a vec of elems while preserving topo order
+        while let Some(seed) = frontier.pop_front() {
+            let layer = expand_layer(seed)?;
+
+            let layer = layer.map_layer(|aa| {
+                frontier.push_back(aa);
+                // idx of pointed-to element determined from frontier + elems size
+                ArenaIndex::enqueued(elems.len(), frontier.len())
+            });
+
+            elems.push(layer);
+        }
+
+        Ok(Self {
+            elems,
+            _underlying: std::marker::PhantomData,
+        })
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
 impl<A, U: Send, O: MapLayer<ArenaIndex, Unwrapped = A, To = U>> ExpandAsync<A, O>
     for RecursiveTree<U, ArenaIndex>
 {
@@ -78,7 +216,7 @@ impl<A, U: Send, O: MapLayer<ArenaIndex, Unwrapped = A, To = U>> ExpandAsync<A,
                 let layer = layer.map_layer(|aa| {
                     frontier.push_back(aa);
                     // idx of pointed-to element determined from frontier + elems size
-                    ArenaIndex(elems.len() + frontier.len())
+                    ArenaIndex::enqueued(elems.len(), frontier.len())
                 });
 
                 elems.push(layer);
@@ -93,6 +231,147 @@ impl<A, U: Send, O: MapLayer<ArenaIndex, Unwrapped = A, To = U>> ExpandAsync<A,
     }
 }
 
+// a seed waiting to be expanded by 'expand_layers_prioritized', and the index its layer will have.
+// Seeds of equal priority are expanded in index order, ie the order they were produced in.
+struct Prioritized<P, A> {
+    priority: P,
+    idx: usize,
+    seed: A,
+}
+
+impl<P: Ord, A> Prioritized<P, A> {
+    fn key(&self) -> (&P, Reverse<usize>) {
+        (&self.priority, Reverse(self.idx))
+    }
+}
+
+impl<P: Ord, A> PartialEq for Prioritized<P, A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl<P: Ord, A> Eq for Prioritized<P, A> {}
+
+impl<P: Ord, A> PartialOrd for Prioritized<P, A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<P: Ord, A> Ord for Prioritized<P, A> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl<U> RecursiveTree<U, ArenaIndex> {
+    /// Like 'expand_layers', but with seeds expanded in order of 'priority', highest first, rather
+    /// than breadth-first, eg so that the most promising branches of a search are expanded first.
+    /// The tree is the same, though its layers may be stored in a different order, with each layer
+    /// still preceding its children. Only the order of calls to 'expand_layer' differs, so this
+    /// matters when expansion has side effects, eg IO, or is cut short.
+    pub fn expand_layers_prioritized<A, P: Ord, W>(
+        seed: A,
+        priority: impl Fn(&A) -> P,
+        expand_layer: impl Fn(A) -> W,
+    ) -> Self
+    where
+        W: MapLayer<ArenaIndex, Unwrapped = A, To = U>,
+    {
+        // each seed's index is assigned when it's enqueued, so it's known to its parent
+        let mut frontier = BinaryHeap::from([Prioritized {
+            priority: priority(&seed),
+            idx: 0,
+            seed,
+        }]);
+        let mut slots: Vec<Option<U>> = vec![None];
+
+        while let Some(Prioritized { idx, seed, .. }) = frontier.pop() {
+            let layer = expand_layer(seed).map_layer(|seed| {
+                let child = slots.len();
+                slots.push(None);
+                frontier.push(Prioritized {
+                    priority: priority(&seed),
+                    idx: child,
+                    seed,
+                });
+                ArenaIndex(child)
+            });
+            slots[idx] = Some(layer);
+        }
+
+        Self {
+            elems: slots
+                .into_iter()
+                .map(|layer| layer.expect("every layer is expanded"))
+                .collect(),
+            _underlying: std::marker::PhantomData,
+        }
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+impl<U: Send> RecursiveTree<U, ArenaIndex> {
+    /// Like 'expand_layers_async', but with up to 'limit' layers being expanded at once, eg to
+    /// overlap IO. Layers are stored as they complete rather than in breadth-first order, with each
+    /// layer still preceding its children. Stops at the first error, dropping any layers in flight.
+    pub fn expand_layers_async_bounded<
+        'a,
+        A: Send + 'a,
+        O: MapLayer<ArenaIndex, Unwrapped = A, To = U>,
+        E: Send + 'a,
+        F: Fn(A) -> BoxFuture<'a, Result<O, E>> + Send + Sync + 'a,
+    >(
+        seed: A,
+        limit: usize,
+        generate_layer: F,
+    ) -> BoxFuture<'a, Result<Self, E>>
+    where
+        U: 'a,
+    {
+        async move {
+            // each seed's index is assigned when it's enqueued, so it's known to its parent
+            let mut pending = VecDeque::from([(0, seed)]);
+            let mut in_flight = FuturesUnordered::new();
+            let mut slots: Vec<Option<U>> = vec![None];
+
+            loop {
+                while in_flight.len() < limit.max(1) {
+                    match pending.pop_front() {
+                        Some((idx, seed)) => {
+                            in_flight.push(generate_layer(seed).map(move |layer| (idx, layer)))
+                        }
+                        None => break,
+                    }
+                }
+
+                match in_flight.next().await {
+                    None => break,
+                    Some((idx, layer)) => {
+                        let layer = layer?.map_layer(|seed| {
+                            let child = slots.len();
+                            slots.push(None);
+                            pending.push_back((child, seed));
+                            ArenaIndex(child)
+                        });
+                        slots[idx] = Some(layer);
+                    }
+                }
+            }
+
+            Ok(Self {
+                elems: slots
+                    .into_iter()
+                    .map(|layer| layer.expect("every layer is expanded"))
+                    .collect(),
+                _underlying: std::marker::PhantomData,
+            })
+        }
+        .boxed()
+    }
+}
+
 impl<A, Wrapped, Underlying> Collapse<A, Wrapped> for RecursiveTree<Underlying, ArenaIndex>
 where
     Underlying: MapLayer<A, To = Wrapped, Unwrapped = ArenaIndex>,
@@ -102,14 +381,18 @@ where
         let mut results = std::iter::repeat_with(|| MaybeUninit::<A>::uninit())
             .take(self.elems.len())
             .collect::<Vec<_>>();
+        let mut check = ChildCheck::new(self.elems.len());
 
         for (idx, node) in self.elems.into_iter().enumerate().rev() {
             let alg_res = {
                 // each node is only referenced once so just remove it, also we know it's there so unsafe is fine
-                let node = node.map_layer(|ArenaIndex(x)| unsafe {
-                    let maybe_uninit =
-                        std::mem::replace(results.get_unchecked_mut(x), MaybeUninit::uninit());
-                    maybe_uninit.assume_init()
+                let node = node.map_layer(|ArenaIndex(x)| {
+                    check.take(idx, x);
+                    unsafe {
+                        let maybe_uninit =
+                            std::mem::replace(results.get_unchecked_mut(x), MaybeUninit::uninit());
+                        maybe_uninit.assume_init()
+                    }
                 });
                 collapse_layer(node)
             };
@@ -131,18 +414,47 @@ where
     &'a U: MapLayer<A, To = O, Unwrapped = ArenaIndex>,
 {
     // TODO: 'checked' compile flag to control whether this gets a vec of maybeuninit or a vec of Option w/ unwrap
-    fn collapse_layers<F: FnMut(O) -> A>(self, mut collapse_layer: F) -> A {
+    fn collapse_layers<F: FnMut(O) -> A>(self, collapse_layer: F) -> A {
         let mut results = std::iter::repeat_with(|| MaybeUninit::<A>::uninit())
             .take(self.elems.len())
             .collect::<Vec<_>>();
+        self.collapse_layers_in_slots(&mut results, collapse_layer)
+    }
+}
+
+impl<'a, U> RecursiveTreeRef<'a, U, ArenaIndex> {
+    /// Like 'collapse_layers', with each node's result stored in 'results' rather than in a
+    /// buffer allocated for each collapse, eg for hot paths that collapse many trees of similar
+    /// sizes. 'results' must have a slot for every node, and any slots beyond those are left
+    /// alone. Every slot is uninitialized again once the collapse is done, so the same slice can
+    /// be passed to the next one; results that were never taken, eg if 'collapse_layer' panics,
+    /// are leaked rather than dropped.
+    pub fn collapse_layers_in_slots<A, O: 'a>(
+        self,
+        results: &mut [MaybeUninit<A>],
+        mut collapse_layer: impl FnMut(O) -> A,
+    ) -> A
+    where
+        &'a U: MapLayer<A, To = O, Unwrapped = ArenaIndex>,
+    {
+        assert!(
+            results.len() >= self.elems.len(),
+            "a slot for each of the {} nodes, not {}",
+            self.elems.len(),
+            results.len()
+        );
+        let mut check = ChildCheck::new(self.elems.len());
 
         for (idx, node) in self.elems.iter().enumerate().rev() {
             let alg_res = {
                 // each node is only referenced once so just remove it, also we know it's there so unsafe is fine
-                let node = node.map_layer(|ArenaIndex(x)| unsafe {
-                    let maybe_uninit =
-                        std::mem::replace(results.get_unchecked_mut(x), MaybeUninit::uninit());
-                    maybe_uninit.assume_init()
+                let node = node.map_layer(|ArenaIndex(x)| {
+                    check.take(idx, x);
+                    unsafe {
+                        let maybe_uninit =
+                            std::mem::replace(results.get_unchecked_mut(x), MaybeUninit::uninit());
+                        maybe_uninit.assume_init()
+                    }
                 });
                 collapse_layer(node)
             };
@@ -158,3 +470,272 @@ where
         }
     }
 }
+
+/// A buffer of result slots to be reused across collapses, growing to fit the largest tree
+/// collapsed with it, so that collapsing many trees allocates only when one is larger than any
+/// before it
+pub struct CollapseBuffer<A> {
+    // uninitialized between collapses
+    slots: Vec<MaybeUninit<A>>,
+}
+
+impl<A> Default for CollapseBuffer<A> {
+    fn default() -> Self {
+        Self { slots: vec![] }
+    }
+}
+
+impl<A> CollapseBuffer<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A buffer that can collapse trees of up to 'nodes' nodes without growing
+    pub fn with_capacity(nodes: usize) -> Self {
+        let mut buffer = Self::new();
+        buffer.reserve(nodes);
+        buffer
+    }
+
+    /// How many nodes a tree can have to be collapsed without growing the buffer
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn reserve(&mut self, nodes: usize) {
+        if self.slots.len() < nodes {
+            self.slots.resize_with(nodes, MaybeUninit::uninit);
+        }
+    }
+
+    /// Collapse 'tree' as 'collapse_layers' does, with its results stored in this buffer
+    pub fn collapse_layers<'a, U, O: 'a>(
+        &mut self,
+        tree: RecursiveTreeRef<'a, U, ArenaIndex>,
+        collapse_layer: impl FnMut(O) -> A,
+    ) -> A
+    where
+        &'a U: MapLayer<A, To = O, Unwrapped = ArenaIndex>,
+    {
+        self.reserve(tree.elems.len());
+        tree.collapse_layers_in_slots(&mut self.slots, collapse_layer)
+    }
+}
+
+// results are stored as options rather than 'MaybeUninit' so that partial results are dropped on early return
+impl<A, Wrapped, Underlying> TryCollapse<A, Wrapped> for RecursiveTree<Underlying, ArenaIndex>
+where
+    Underlying: MapLayer<A, To = Wrapped, Unwrapped = ArenaIndex>,
+{
+    fn try_collapse_layers<E, F: FnMut(Wrapped) -> Result<A, E>>(
+        self,
+        mut collapse_layer: F,
+    ) -> Result<A, E> {
+        let mut results = std::iter::repeat_with(|| None)
+            .take(self.elems.len())
+            .collect::<Vec<Option<A>>>();
+
+        for (idx, node) in self.elems.into_iter().enumerate().rev() {
+            let node = node.map_layer(|ArenaIndex(x)| results[x].take().unwrap());
+            results[idx] = Some(collapse_layer(node)?);
+        }
+
+        Ok(results[ArenaIndex::head().0].take().unwrap())
+    }
+}
+
+impl<'a, A, O: 'a, U> TryCollapse<A, O> for RecursiveTreeRef<'a, U, ArenaIndex>
+where
+    &'a U: MapLayer<A, To = O, Unwrapped = ArenaIndex>,
+{
+    fn try_collapse_layers<E, F: FnMut(O) -> Result<A, E>>(
+        self,
+        mut collapse_layer: F,
+    ) -> Result<A, E> {
+        let mut results = std::iter::repeat_with(|| None)
+            .take(self.elems.len())
+            .collect::<Vec<Option<A>>>();
+
+        for (idx, node) in self.elems.iter().enumerate().rev() {
+            let node = node.map_layer(|ArenaIndex(x)| results[x].take().unwrap());
+            results[idx] = Some(collapse_layer(node)?);
+        }
+
+        Ok(results[ArenaIndex::head().0].take().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll};
+
+    use super::*;
+    use crate::examples::expr::{eval::eval_layer, BlocAllocExpr, Expr};
+    use crate::recursive_tree::fixtures::complete_layer;
+
+    // pending once, so that other expansions get a chance to start
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn bounded_expansion() {
+        let in_flight = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        let expand = |depth: usize| {
+            let (in_flight, most) = (&in_flight, &most);
+            async move {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                YieldNow(false).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok::<_, ()>(complete_layer(depth))
+            }
+            .boxed()
+        };
+
+        let tree =
+            futures::executor::block_on(BlocAllocExpr::expand_layers_async_bounded(6, 3, expand))
+                .unwrap();
+        assert_eq!(most.load(Ordering::SeqCst), 3);
+        assert!(crate::recursive_tree::is_valid_tree(&tree.elems));
+        assert_eq!(
+            tree.as_ref().collapse_layers(eval_layer),
+            BlocAllocExpr::expand_layers(6, complete_layer).collapse_layers(eval_layer)
+        );
+
+        let failing = futures::executor::block_on(BlocAllocExpr::expand_layers_async_bounded(
+            6,
+            3,
+            |depth: usize| {
+                async move {
+                    if depth == 2 {
+                        Err(depth)
+                    } else {
+                        Ok(complete_layer(depth))
+                    }
+                }
+                .boxed()
+            },
+        ));
+        assert_eq!(failing.unwrap_err(), 2);
+    }
+
+    #[test]
+    fn prioritized_expansion() {
+        // a complete binary tree with the seeds 0 to 14, where greater seeds go first
+        let expanded = std::cell::RefCell::new(Vec::new());
+        let expand = |n: usize| {
+            expanded.borrow_mut().push(n);
+            if n < 7 {
+                Expr::Add(2 * n + 1, 2 * n + 2)
+            } else {
+                Expr::LiteralInt(n as i64)
+            }
+        };
+        let tree = BlocAllocExpr::expand_layers_prioritized(0, |n| *n, expand);
+        assert_eq!(
+            expanded.take(),
+            vec![0, 2, 6, 14, 13, 5, 12, 11, 1, 4, 10, 9, 3, 8, 7]
+        );
+        assert!(crate::recursive_tree::is_valid_tree(&tree.elems));
+        assert_eq!(
+            tree.collapse_layers(eval_layer),
+            BlocAllocExpr::expand_layers(0, expand).collapse_layers(eval_layer)
+        );
+
+        // equal priorities are expanded in the order they're produced, ie breadth-first
+        let bfs = BlocAllocExpr::expand_layers_prioritized(0, |_| (), expand);
+        assert_eq!(bfs.elems, BlocAllocExpr::expand_layers(0, expand).elems);
+    }
+
+    #[test]
+    fn collapse_in_buffer() {
+        // results that count how many of them are alive, to check none are leaked or dropped twice
+        let alive = std::rc::Rc::new(());
+        let count = |layer: Expr<(i64, std::rc::Rc<()>)>| {
+            let value = eval_layer(layer.map_layer(|(value, _)| value));
+            (value, alive.clone())
+        };
+
+        let mut buffer = CollapseBuffer::with_capacity(7);
+        assert_eq!(buffer.capacity(), 7);
+        for depth in [2, 1, 3, 0, 2] {
+            let tree = BlocAllocExpr::expand_layers(depth, complete_layer);
+            let (value, _) = buffer.collapse_layers(tree.as_ref(), count);
+            assert_eq!(value, 1 << depth);
+            assert_eq!(std::rc::Rc::strong_count(&alive), 1);
+        }
+        // grown to fit the largest tree, of 15 nodes
+        assert_eq!(buffer.capacity(), 15);
+
+        // or into a slice of the caller's own, larger than the tree
+        let mut slots: Vec<MaybeUninit<i64>> = std::iter::repeat_with(MaybeUninit::uninit)
+            .take(10)
+            .collect();
+        let tree = BlocAllocExpr::expand_layers(2, complete_layer);
+        for _ in 0..3 {
+            assert_eq!(
+                tree.as_ref()
+                    .collapse_layers_in_slots(&mut slots, eval_layer),
+                4
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "a slot for each of the 7 nodes, not 6")]
+    fn collapse_in_short_slice() {
+        let mut slots: Vec<MaybeUninit<i64>> = std::iter::repeat_with(MaybeUninit::uninit)
+            .take(6)
+            .collect();
+        BlocAllocExpr::expand_layers(2, complete_layer)
+            .as_ref()
+            .collapse_layers_in_slots(&mut slots, eval_layer);
+    }
+
+    #[test]
+    fn corrupt_indices() {
+        let lit = Expr::LiteralInt(1);
+        let valid = vec![Expr::Add(ArenaIndex(1), ArenaIndex(2)), lit, lit];
+        assert!(BlocAllocExpr::from_layers(valid).is_some());
+
+        // a cycle, a child with two parents, a missing child, and no root at all
+        for elems in [
+            vec![Expr::Add(ArenaIndex(0), ArenaIndex(1)), lit],
+            vec![Expr::Add(ArenaIndex(1), ArenaIndex(1)), lit],
+            vec![Expr::Add(ArenaIndex(1), ArenaIndex(2)), lit],
+            vec![],
+        ] {
+            assert!(BlocAllocExpr::from_layers(elems.clone()).is_none());
+
+            // built without being checked, collapse catches them in debug builds, before reading
+            // results that were never written
+            if cfg!(debug_assertions) {
+                let tree = BlocAllocExpr {
+                    elems,
+                    _underlying: std::marker::PhantomData,
+                };
+                let by_ref = std::panic::catch_unwind(|| tree.as_ref().collapse_layers(eval_layer));
+                assert!(by_ref.is_err());
+                let owned = std::panic::catch_unwind(|| tree.collapse_layers(eval_layer));
+                assert!(owned.is_err());
+            }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Utilities for arena-backed trees of any layer type, written once in terms of 'LayerArity'
+//! rather than per layer type: iterating over leaves and summary statistics, and, for layers that
+//! are also 'EdgeLabeled', paths to nodes and export to Graphviz's DOT format or mermaid.
+
+use std::borrow::Cow;
+use std::fmt::Write;
+
+use crate::map_layer::{EdgeLabeled, LayerArity};
+use crate::recursive_tree::{arena_eval::ArenaIndex, sidecar::Sidecar, RecursiveTree};
+
+/// Summary statistics of the shape of a tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    /// the most children of any one node
+    pub max_arity: usize,
+    /// the number of edges on the longest path from the root to a leaf
+    pub depth: usize,
+}
+
+impl<U> RecursiveTree<U, ArenaIndex>
+where
+    U: LayerArity<Child = ArenaIndex>,
+{
+    /// The index of every leaf, in arena order
+    pub fn leaves(&self) -> impl Iterator<Item = ArenaIndex> + '_ {
+        self.elems
+            .iter()
+            .enumerate()
+            .filter(|(_, layer)| layer.is_leaf())
+            .map(|(idx, _)| ArenaIndex(idx))
+    }
+
+    /// Statistics of the shape of the tree, computed in a single pass
+    pub fn stats(&self) -> TreeStats {
+        // parents come first, so each node's depth is known before its children's
+        let mut depths = Sidecar::filled(self, 0);
+        let mut stats = TreeStats {
+            nodes: self.elems.len(),
+            leaves: 0,
+            max_arity: 0,
+            depth: 0,
+        };
+        for (idx, layer) in self.elems.iter().enumerate() {
+            let depth = depths[ArenaIndex(idx)];
+            stats.depth = stats.depth.max(depth);
+            stats.max_arity = stats.max_arity.max(layer.child_count());
+            if layer.is_leaf() {
+                stats.leaves += 1;
+            }
+            for child in layer.children() {
+                depths[*child] = depth + 1;
+            }
+        }
+        stats
+    }
+}
+
+impl<U> RecursiveTree<U, ArenaIndex>
+where
+    U: EdgeLabeled<Child = ArenaIndex>,
+{
+    /// The labels of the edges from the root to the node at 'idx', eg the names of the directories
+    /// leading to a file, and then its own name, or 'None' if any of those edges has no label
+    pub fn path_to(&self, idx: ArenaIndex) -> Option<Vec<Cow<'_, str>>> {
+        // each node's parent and its position within it. Only those before 'idx' can be on its
+        // path, as parents come first.
+        let mut parents = vec![None; idx.0 + 1];
+        for (parent, layer) in self.elems[..idx.0].iter().enumerate() {
+            for (position, child) in layer.children().enumerate() {
+                if child.0 <= idx.0 {
+                    parents[child.0] = Some((parent, position));
+                }
+            }
+        }
+
+        let mut path = vec![];
+        let mut next = parents[idx.0];
+        while let Some((parent, position)) = next {
+            path.push(self.elems[parent].edge_label(position)?);
+            next = parents[parent];
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// The tree in Graphviz's DOT format, with each node labeled by 'label' and each edge by its
+    /// 'EdgeLabeled' label, eg to render with 'dot -Tsvg'
+    pub fn to_dot(&self, mut label: impl FnMut(&U) -> String) -> String {
+        let escape = |label: &str| label.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut dot = String::from("digraph {\n");
+        for (idx, layer) in self.elems.iter().enumerate() {
+            writeln!(dot, "    n{} [label=\"{}\"];", idx, escape(&label(layer))).unwrap();
+            for (edge, child) in layer.labeled_children() {
+                let edge = escape(&edge);
+                writeln!(dot, "    n{} -> n{} [label=\"{}\"];", idx, child.0, edge).unwrap();
+            }
+        }
+        dot.push('}');
+        dot
+    }
+
+    /// The tree as a mermaid flowchart, labeled in the same way as 'to_dot', eg to embed in
+    /// markdown
+    pub fn to_mermaid(&self, mut label: impl FnMut(&U) -> String) -> String {
+        // mermaid has no escapes within quoted labels, other than as html entities
+        let escape = |label: &str| label.replace('"', "#quot;");
+        let mut mermaid = String::from("flowchart TD");
+        for (idx, layer) in self.elems.iter().enumerate() {
+            write!(mermaid, "\n    n{}[\"{}\"]", idx, escape(&label(layer))).unwrap();
+            for (edge, child) in layer.labeled_children() {
+                let edge = escape(&edge);
+                write!(mermaid, "\n    n{} -->|\"{}\"| n{}", idx, edge, child.0).unwrap();
+            }
+        }
+        mermaid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{BlocAllocExpr, Expr};
+    use crate::json::from_value;
+    use crate::recursive::Expand;
+    use crate::recursive_tree::fixtures::tree;
+
+    #[test]
+    fn generic_utilities() {
+        let tree = tree();
+        assert_eq!(
+            tree.leaves().collect::<Vec<_>>(),
+            [2, 5, 6, 7, 8].map(ArenaIndex)
+        );
+        assert_eq!(
+            tree.stats(),
+            TreeStats {
+                nodes: 9,
+                leaves: 5,
+                max_arity: 2,
+                depth: 3
+            }
+        );
+        assert_eq!(tree.layer(tree.root()).child(1), Some(&ArenaIndex(2)));
+        assert_eq!(tree.layer(ArenaIndex(2)).child(0), None);
+
+        let small = BlocAllocExpr::expand_layers(0, |n| match n {
+            0 => Expr::Sub(1, 2),
+            n => Expr::LiteralInt(n as i64),
+        });
+        let dot = small.to_dot(|layer| match layer {
+            Expr::LiteralInt(x) => x.to_string(),
+            _ => "-".to_string(),
+        });
+        assert_eq!(
+            dot,
+            [
+                "digraph {",
+                "    n0 [label=\"-\"];",
+                "    n0 -> n1 [label=\"lhs\"];",
+                "    n0 -> n2 [label=\"rhs\"];",
+                "    n1 [label=\"1\"];",
+                "    n2 [label=\"2\"];",
+                "}",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            small.to_mermaid(|layer| format!("{:?}", layer)),
+            [
+                "flowchart TD",
+                "    n0[\"Sub(ArenaIndex(1), ArenaIndex(2))\"]",
+                "    n0 -->|\"lhs\"| n1",
+                "    n0 -->|\"rhs\"| n2",
+                "    n1[\"LiteralInt(1)\"]",
+                "    n2[\"LiteralInt(2)\"]",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            tree.path_to(ArenaIndex(7)),
+            Some(vec!["lhs".into(), "rhs".into(), "lhs".into()])
+        );
+        assert_eq!(tree.path_to(tree.root()), Some(vec![]));
+
+        // the same utilities, for a different layer type
+        let json = from_value(&serde_json::json!({"a": [1, 2, 3], "b": {}}));
+        assert_eq!(
+            json.stats(),
+            TreeStats {
+                nodes: 6,
+                leaves: 4,
+                max_arity: 3,
+                depth: 2
+            }
+        );
+        assert_eq!(
+            json.path_to(ArenaIndex(5)),
+            Some(vec!["a".into(), "2".into()])
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Expansion with a bound on the size of the tree, for structures too big to expand in full, eg a
+//! crawl of a huge directory tree. Once the budget is spent, each remaining seed is stored as a
+//! truncation marker, a layer with no children, and handed back along with the position of its
+//! marker, so that it can be reported, or expanded later on.
+
+use std::collections::VecDeque;
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// Limits on the size of a tree being expanded. Expansion stops once either is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Budget {
+    /// the most layers to allocate
+    pub max_nodes: Option<usize>,
+    /// the most bytes to allocate, for the layers of the tree and the seeds waiting to be expanded.
+    /// Only their own size is counted, not that of anything they own, eg the contents of a 'Vec'.
+    pub max_bytes: Option<usize>,
+}
+
+impl Budget {
+    fn exceeded<U, A>(&self, layers: usize, seeds: usize) -> bool {
+        let bytes = layers * std::mem::size_of::<U>() + seeds * std::mem::size_of::<A>();
+        self.max_nodes.is_some_and(|max| layers >= max)
+            || self.max_bytes.is_some_and(|max| bytes >= max)
+    }
+}
+
+/// What 'expand_layers_budgeted' left out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BudgetReport<A> {
+    /// each seed that wasn't expanded, along with the index of the truncation marker in its place
+    pub cut: Vec<(ArenaIndex, A)>,
+}
+
+impl<A> BudgetReport<A> {
+    /// Whether the whole tree was expanded
+    pub fn is_complete(&self) -> bool {
+        self.cut.is_empty()
+    }
+}
+
+impl<U> RecursiveTree<U, ArenaIndex> {
+    /// Like 'expand_layers', but within 'budget': once it's spent, each remaining seed is stored
+    /// as the layer 'truncate' gives for it, which must have no children. Layers are expanded
+    /// breadth-first, so the tree is cut off at the shallowest depth possible. The budget is
+    /// checked before each layer is expanded, so the tree can go over it by the children of the
+    /// last layer expanded.
+    pub fn expand_layers_budgeted<A, W>(
+        seed: A,
+        budget: Budget,
+        expand_layer: impl Fn(A) -> W,
+        truncate: impl Fn(&A) -> W,
+    ) -> (Self, BudgetReport<A>)
+    where
+        W: MapLayer<ArenaIndex, Unwrapped = A, To = U>,
+    {
+        let mut frontier = VecDeque::from([seed]);
+        let mut elems = vec![];
+        let mut cut = vec![];
+
+        while let Some(seed) = frontier.pop_front() {
+            // every seed has a layer allocated for it, whether or not it's expanded
+            let allocated = elems.len() + 1 + frontier.len();
+            let layer = if budget.exceeded::<U, A>(allocated, frontier.len()) {
+                let marker = truncate(&seed).map_layer(|_| -> ArenaIndex {
+                    panic!("truncation markers can't have children")
+                });
+                cut.push((ArenaIndex(elems.len()), seed));
+                marker
+            } else {
+                expand_layer(seed).map_layer(|seed| {
+                    frontier.push_back(seed);
+                    ArenaIndex::enqueued(elems.len(), frontier.len())
+                })
+            };
+            elems.push(layer);
+        }
+
+        let tree = Self {
+            elems,
+            _underlying: std::marker::PhantomData,
+        };
+        (tree, BudgetReport { cut })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{eval::eval_layer, BlocAllocExpr, Expr};
+    use crate::recursive::{Collapse, Expand};
+    use crate::recursive_tree::fixtures::complete_layer;
+
+    // subtrees that weren't expanded count for nothing
+    fn truncate(_: &usize) -> Expr<usize> {
+        Expr::LiteralInt(0)
+    }
+
+    #[test]
+    fn budgets() {
+        let (full, report) =
+            BlocAllocExpr::expand_layers_budgeted(5, Budget::default(), complete_layer, truncate);
+        assert!(report.is_complete());
+        assert_eq!(
+            full.elems,
+            BlocAllocExpr::expand_layers(5, complete_layer).elems
+        );
+
+        let nodes = Budget {
+            max_nodes: Some(10),
+            ..Budget::default()
+        };
+        let (tree, report) =
+            BlocAllocExpr::expand_layers_budgeted(5, nodes, complete_layer, truncate);
+        // three levels in full, then two layers of the fourth, after which each remaining seed is
+        // cut, with its marker taking up a node
+        assert_eq!(tree.elems.len(), 1 + 2 + 4 + 4);
+        assert_eq!(report.cut.len(), 2 + 4);
+        for (idx, depth) in report.cut.iter() {
+            assert_eq!(tree.elems[idx.0], Expr::LiteralInt(0));
+            assert!(*depth == 3 || *depth == 2);
+        }
+        assert_eq!(tree.collapse_layers(eval_layer), 0);
+
+        let bytes = Budget {
+            max_bytes: Some(4 * std::mem::size_of::<Expr<ArenaIndex>>()),
+            ..Budget::default()
+        };
+        let (tree, report) =
+            BlocAllocExpr::expand_layers_budgeted(5, bytes, complete_layer, truncate);
+        // the root and its first child are expanded, and then the next layer would go over
+        assert_eq!(tree.elems.len(), 5);
+        assert_eq!(report.cut.len(), 3);
+
+        let (root, report) = BlocAllocExpr::expand_layers_budgeted(
+            5,
+            Budget {
+                max_nodes: Some(1),
+                ..Budget::default()
+            },
+            complete_layer,
+            truncate,
+        );
+        assert_eq!(root.elems, vec![Expr::LiteralInt(0)]);
+        assert_eq!(report.cut, vec![(ArenaIndex(0), 5)]);
+    }
+}
@@ -0,0 +1,112 @@
+//! Expansion that can be stopped and picked up again later, eg for a long crawl that shouldn't
+//! start over from scratch after a crash. A checkpoint is the state of a breadth-first expansion
+//! between two layers: the layers expanded so far, in arena order, and the frontier of seeds still
+//! to be expanded, whose layers will follow them in order. With the 'json' or 'cbor' features it
+//! can be serialized, to be persisted or sent elsewhere, and is checked when it's deserialized.
+
+use std::collections::VecDeque;
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// A partially expanded tree, along with the seeds still to be expanded. Expanding it to
+/// completion gives the same tree as 'expand_layers' would have, however many steps it's done in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint<U, A> {
+    // the layers expanded so far, whose children may be seeds in the frontier
+    pub(crate) elems: Vec<U>,
+    // the seed for the layer at arena index 'elems.len() + idx' is at 'idx'
+    pub(crate) frontier: VecDeque<A>,
+}
+
+impl<U, A> Checkpoint<U, A> {
+    /// A checkpoint from before anything has been expanded
+    pub fn new(seed: A) -> Self {
+        Self {
+            elems: vec![],
+            frontier: VecDeque::from([seed]),
+        }
+    }
+
+    /// How many layers have been expanded so far
+    pub fn expanded(&self) -> usize {
+        self.elems.len()
+    }
+
+    /// The seeds still to be expanded, in the order they will be
+    pub fn pending(&self) -> impl ExactSizeIterator<Item = &A> {
+        self.frontier.iter()
+    }
+
+    /// Whether every layer has been expanded
+    pub fn is_complete(&self) -> bool {
+        self.frontier.is_empty()
+    }
+
+    /// Expand at most 'max_layers' more layers, returning whether every layer has now been
+    /// expanded
+    pub fn expand_layers<W>(&mut self, max_layers: usize, expand_layer: impl Fn(A) -> W) -> bool
+    where
+        W: MapLayer<ArenaIndex, Unwrapped = A, To = U>,
+    {
+        for _ in 0..max_layers {
+            let Some(seed) = self.frontier.pop_front() else {
+                break;
+            };
+            let layer = expand_layer(seed).map_layer(|seed| {
+                self.frontier.push_back(seed);
+                ArenaIndex::enqueued(self.elems.len(), self.frontier.len())
+            });
+            self.elems.push(layer);
+        }
+        self.is_complete()
+    }
+
+    /// Expand every remaining layer, giving the whole tree
+    pub fn finish<W>(mut self, expand_layer: impl Fn(A) -> W) -> RecursiveTree<U, ArenaIndex>
+    where
+        W: MapLayer<ArenaIndex, Unwrapped = A, To = U>,
+    {
+        self.expand_layers(usize::MAX, expand_layer);
+        RecursiveTree {
+            elems: self.elems,
+            _underlying: std::marker::PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::BlocAllocExpr;
+    use crate::recursive::Expand;
+    use crate::recursive_tree::fixtures::complete_layer;
+    use crate::recursive_tree::is_valid_partial_tree;
+
+    #[test]
+    fn steps() {
+        let full = BlocAllocExpr::expand_layers(4, complete_layer);
+        for step in 1..8 {
+            let mut checkpoint = Checkpoint::new(4);
+            let mut steps = 0;
+            while !checkpoint.expand_layers(step, complete_layer) {
+                steps += 1;
+                assert_eq!(checkpoint.expanded(), steps * step);
+                // every seed pending is for a layer that's already referenced
+                assert!(is_valid_partial_tree(
+                    &checkpoint.elems,
+                    checkpoint.frontier.len()
+                ));
+            }
+            assert_eq!(checkpoint.finish(complete_layer).elems, full.elems);
+        }
+
+        let mut checkpoint = Checkpoint::new(4);
+        checkpoint.expand_layers(3, complete_layer);
+        assert_eq!(
+            checkpoint.pending().copied().collect::<Vec<_>>(),
+            vec![2, 2, 2, 2]
+        );
+        assert_eq!(checkpoint.finish(complete_layer).elems, full.elems);
+    }
+}
@@ -0,0 +1,138 @@
+//! Dirty flags for incremental recomputation over arena-backed trees, eg for an incremental
+//! compiler, or an aggregation kept up to date as the tree changes.
+//!
+//! Once a result has been computed for every subtree, eg with 'Sidecar::collapse_layers', changes
+//! to layers are recorded by marking them dirty. A layer's result depends on those of all of its
+//! descendants, so marking a layer marks each of its ancestors too, stopping at the first that's
+//! already dirty: its ancestors must be dirty as well. The dirty layers are then exactly those
+//! whose results are stale, and are recomputed bottom-up, from the results of their children.
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, sidecar::Sidecar, RecursiveTree};
+
+/// Which layers of a tree are dirty, along with the parent pointers used to mark their ancestors
+#[derive(Debug, Clone)]
+pub struct DirtyFlags {
+    parents: Sidecar<Option<ArenaIndex>>,
+    dirty: Sidecar<bool>,
+    // every dirty layer, in the order they were marked
+    marked: Vec<ArenaIndex>,
+}
+
+impl DirtyFlags {
+    /// Flags for 'tree', none of them dirty
+    pub fn new<U>(tree: &RecursiveTree<U, ArenaIndex>) -> Self
+    where
+        for<'a> &'a U: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        Self {
+            parents: tree.parents(),
+            dirty: Sidecar::filled(tree, false),
+            marked: vec![],
+        }
+    }
+
+    /// Mark the layer at 'idx' dirty, along with each of its ancestors
+    pub fn mark(&mut self, idx: ArenaIndex) {
+        let mut next = Some(idx);
+        while let Some(idx) = next {
+            if self.dirty[idx] {
+                break;
+            }
+            self.dirty[idx] = true;
+            self.marked.push(idx);
+            next = self.parents[idx];
+        }
+    }
+
+    pub fn is_dirty(&self, idx: ArenaIndex) -> bool {
+        self.dirty[idx]
+    }
+
+    /// Every dirty layer, bottom-up: each comes after all of its dirty descendants
+    pub fn dirty(&self) -> impl Iterator<Item = ArenaIndex> {
+        let mut dirty = self.marked.clone();
+        // children always have higher indices than their parents
+        dirty.sort_unstable_by_key(|idx| std::cmp::Reverse(idx.0));
+        dirty.into_iter()
+    }
+
+    /// Clear every flag, eg once the dirty layers' results have been recomputed
+    pub fn clear(&mut self) {
+        for idx in self.marked.drain(..) {
+            self.dirty[idx] = false;
+        }
+    }
+
+    /// Recompute the result of each dirty layer, bottom-up, from those of its children, then clear
+    /// every flag. Clean layers keep the results they have, so only the dirty layers of a large
+    /// tree are visited. Returns how many layers were recomputed.
+    pub fn recompute<'a, U, A, O>(
+        &mut self,
+        tree: &'a RecursiveTree<U, ArenaIndex>,
+        results: &mut Sidecar<A>,
+        mut collapse_layer: impl FnMut(O) -> A,
+    ) -> usize
+    where
+        A: Clone,
+        &'a U: MapLayer<A, Unwrapped = ArenaIndex, To = O>,
+    {
+        let dirty: Vec<ArenaIndex> = self.dirty().collect();
+        for idx in dirty.iter() {
+            let layer = tree.layer(*idx).map_layer(|child| results[child].clone());
+            results[*idx] = collapse_layer(layer);
+        }
+        self.clear();
+        dirty.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{eval::eval_layer, BlocAllocExpr, Expr};
+    use crate::recursive::{Collapse, Expand};
+    use crate::recursive_tree::fixtures::complete_layer;
+
+    // a complete binary tree of additions of the given depth
+    fn tree(depth: usize) -> BlocAllocExpr {
+        BlocAllocExpr::expand_layers(depth, complete_layer)
+    }
+
+    #[test]
+    fn incremental() {
+        let mut tree = tree(4);
+        let mut sums = Sidecar::collapse_layers(&tree, eval_layer);
+        let mut flags = DirtyFlags::new(&tree);
+        assert_eq!(flags.recompute(&tree, &mut sums, eval_layer), 0);
+
+        // the last layer is a leaf four levels down
+        let leaf = ArenaIndex(tree.elems.len() - 1);
+        tree.set_layer(leaf, Expr::LiteralInt(10));
+        flags.mark(leaf);
+        assert!(flags.is_dirty(tree.root()));
+        assert!(!flags.is_dirty(ArenaIndex(1)));
+        // the path from the leaf up to the root
+        let path: Vec<ArenaIndex> = flags.dirty().collect();
+        assert_eq!(path.len(), 5);
+        assert!(path.windows(2).all(|w| w[0].0 > w[1].0));
+
+        // a sibling's path joins the first one's at their parent
+        let sibling = ArenaIndex(tree.elems.len() - 2);
+        tree.set_layer(sibling, Expr::LiteralInt(5));
+        flags.mark(sibling);
+        assert_eq!(flags.dirty().count(), 6);
+
+        assert_eq!(flags.recompute(&tree, &mut sums, eval_layer), 6);
+        assert_eq!(sums[tree.root()], 16 - 2 + 10 + 5);
+        assert_eq!(sums[tree.root()], tree.as_ref().collapse_layers(eval_layer));
+        assert_eq!(flags.dirty().count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "same children")]
+    fn restructuring() {
+        let mut tree = tree(1);
+        tree.set_layer(tree.root(), Expr::LiteralInt(0));
+    }
+}
@@ -0,0 +1,327 @@
+//! Construction of arena-backed trees from a stream of start and end events, as produced by
+//! event-based (SAX-like, or pull) parsers, without building any intermediate tree first.
+//!
+//! Events describe a tree in pre-order: each node's 'Start' is followed by the events for each of
+//! its children in turn, and then its 'End'. A node's place in the arena is reserved at its start,
+//! so every node comes before its children, as collapse requires, and its layer is stored at its
+//! end, once its children's indices are known. Only the nodes that are still open are held apart
+//! from the arena, so memory use beyond the tree itself is bounded by its depth.
+
+use std::fmt;
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// An event in a pre-order description of a tree. 'S' is a layer with '()' in place of each child,
+/// whose children are the nodes started and ended between it and its 'End'.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<S> {
+    Start(S),
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventError {
+    /// an 'End' with no node open
+    UnmatchedEnd,
+    /// a 'Start' after the root was ended
+    MultipleRoots,
+    /// the events ended with this many nodes still open
+    Unclosed(usize),
+    /// there were no events
+    Empty,
+    /// the layer for the node at 'node' doesn't fit the children it was given, eg as it has a
+    /// different number of them
+    Arity { node: ArenaIndex, children: usize },
+}
+
+impl fmt::Display for EventError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventError::UnmatchedEnd => write!(f, "end event with no node open"),
+            EventError::MultipleRoots => write!(f, "start event after the root was ended"),
+            EventError::Unclosed(open) => write!(f, "{} nodes were never ended", open),
+            EventError::Empty => write!(f, "no events"),
+            EventError::Arity { node, children } => {
+                write!(f, "node {} doesn't fit its {} children", node.0, children)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EventError {}
+
+/// Builds a tree one event at a time, eg from within a parser's callbacks
+pub struct TreeBuilder<S, U> {
+    // a slot for every node started so far, filled once it's ended
+    elems: Vec<Option<U>>,
+    // each open node, outermost first: its index, its shell, and its children so far
+    open: Vec<(usize, S, Vec<ArenaIndex>)>,
+    // the first error to leave the builder without a layer for some node, after which no tree
+    // can be built
+    failed: Option<EventError>,
+}
+
+impl<S, U> Default for TreeBuilder<S, U> {
+    fn default() -> Self {
+        Self {
+            elems: vec![],
+            open: vec![],
+            failed: None,
+        }
+    }
+}
+
+impl<S, U> TreeBuilder<S, U> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a node, as a child of the innermost open node, if any
+    pub fn start(&mut self, shell: S) -> Result<(), EventError> {
+        self.check()?;
+        if self.open.is_empty() && !self.elems.is_empty() {
+            return Err(EventError::MultipleRoots);
+        }
+        let idx = self.elems.len();
+        if let Some((_, _, children)) = self.open.last_mut() {
+            children.push(ArenaIndex(idx));
+        }
+        self.elems.push(None);
+        self.open.push((idx, shell, vec![]));
+        Ok(())
+    }
+
+    /// End the innermost open node, filling each child position of its shell in order
+    pub fn end(&mut self) -> Result<(), EventError>
+    where
+        S: MapLayer<ArenaIndex, Unwrapped = (), To = U>,
+        for<'a> &'a U: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        self.end_with(|shell, children| {
+            let mut children = children.into_iter();
+            let mut missing = false;
+            let layer = shell.map_layer(|()| {
+                children.next().unwrap_or_else(|| {
+                    missing = true;
+                    ArenaIndex(usize::MAX)
+                })
+            });
+            (!missing && children.next().is_none()).then_some(layer)
+        })
+    }
+
+    /// End the innermost open node, with its layer built from its shell and its children by
+    /// 'fill', eg for layers with any number of children, like arrays, whose shells can't have
+    /// a position for each of them up front. 'fill' returns nothing if there are too many or too
+    /// few children. The layer must have exactly the children it was given, in order, as collapse
+    /// relies on every child being where it was reserved. If it doesn't, the node is left without
+    /// a layer, so this and every later call, including 'finish', fails.
+    pub fn end_with(
+        &mut self,
+        fill: impl FnOnce(S, Vec<ArenaIndex>) -> Option<U>,
+    ) -> Result<(), EventError>
+    where
+        for<'a> &'a U: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        self.check()?;
+        let (idx, shell, children) = self.open.pop().ok_or(EventError::UnmatchedEnd)?;
+        let error = EventError::Arity {
+            node: ArenaIndex(idx),
+            children: children.len(),
+        };
+        let layer = fill(shell, children.clone()).filter(|layer| {
+            let mut filled = Vec::with_capacity(children.len());
+            layer.map_layer(|child| filled.push(child));
+            filled == children
+        });
+        match layer {
+            Some(layer) => {
+                self.elems[idx] = Some(layer);
+                Ok(())
+            }
+            None => {
+                // the shell was consumed by 'fill', so the node can't be ended again
+                self.failed = Some(error.clone());
+                Err(error)
+            }
+        }
+    }
+
+    // fails with the error that left some node without a layer, if any
+    fn check(&self) -> Result<(), EventError> {
+        match &self.failed {
+            Some(error) => Err(error.clone()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn push(&mut self, event: Event<S>) -> Result<(), EventError>
+    where
+        S: MapLayer<ArenaIndex, Unwrapped = (), To = U>,
+        for<'a> &'a U: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        match event {
+            Event::Start(shell) => self.start(shell),
+            Event::End => self.end(),
+        }
+    }
+
+    /// The tree, once its root has been ended
+    pub fn finish(self) -> Result<RecursiveTree<U, ArenaIndex>, EventError> {
+        self.check()?;
+        if !self.open.is_empty() {
+            return Err(EventError::Unclosed(self.open.len()));
+        }
+        if self.elems.is_empty() {
+            return Err(EventError::Empty);
+        }
+        // every node was ended, as none are open and none failed to end, but a missing layer
+        // would make for an invalid tree, so it's checked rather than assumed
+        let unended = self.elems.iter().filter(|layer| layer.is_none()).count();
+        if unended > 0 {
+            return Err(EventError::Unclosed(unended));
+        }
+        Ok(RecursiveTree {
+            elems: self.elems.into_iter().flatten().collect(),
+            _underlying: std::marker::PhantomData,
+        })
+    }
+}
+
+impl<U> RecursiveTree<U, ArenaIndex> {
+    /// Build a tree from the events describing it, in pre-order
+    pub fn from_events<S>(events: impl IntoIterator<Item = Event<S>>) -> Result<Self, EventError>
+    where
+        S: MapLayer<ArenaIndex, Unwrapped = (), To = U>,
+        for<'a> &'a U: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        let mut builder = TreeBuilder::new();
+        for event in events {
+            builder.push(event)?;
+        }
+        builder.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{eval::eval_layer, BlocAllocExpr, Expr};
+    use crate::json::JsonLayer;
+    use crate::recursive::Collapse;
+
+    // ((1 + 2) * 3) - 4
+    fn events() -> Vec<Event<Expr<()>>> {
+        use Event::{End, Start};
+        vec![
+            Start(Expr::Sub((), ())),
+            Start(Expr::Mul((), ())),
+            Start(Expr::Add((), ())),
+            Start(Expr::LiteralInt(1)),
+            End,
+            Start(Expr::LiteralInt(2)),
+            End,
+            End,
+            Start(Expr::LiteralInt(3)),
+            End,
+            End,
+            Start(Expr::LiteralInt(4)),
+            End,
+            End,
+        ]
+    }
+
+    #[test]
+    fn from_events() {
+        let tree = BlocAllocExpr::from_events(events()).unwrap();
+        assert!(crate::recursive_tree::is_valid_tree(&tree.elems));
+        assert_eq!(tree.collapse_layers(eval_layer), 5);
+
+        // arrays of any length, with each shell's children collected by 'end_with'
+        let mut builder = TreeBuilder::new();
+        let array = |shell, children| match shell {
+            JsonLayer::Array(_) => Some(JsonLayer::Array(children)),
+            _ => None,
+        };
+        builder.start(JsonLayer::Array(vec![])).unwrap();
+        for x in [1, 2] {
+            builder.start(JsonLayer::Number(x.into())).unwrap();
+            builder.end().unwrap();
+        }
+        builder.start(JsonLayer::Array(vec![])).unwrap();
+        builder.end_with(array).unwrap();
+        builder.end_with(array).unwrap();
+        let tree = builder.finish().unwrap();
+        assert_eq!(crate::json::to_value(tree), serde_json::json!([1, 2, []]));
+    }
+
+    #[test]
+    fn errors() {
+        let build = |events: &[Event<Expr<()>>]| BlocAllocExpr::from_events(events.to_vec());
+        let events = events();
+        assert_eq!(
+            build(&events[..events.len() - 1]).unwrap_err(),
+            EventError::Unclosed(1)
+        );
+        assert_eq!(build(&[]).unwrap_err(), EventError::Empty);
+        assert_eq!(build(&[Event::End]).unwrap_err(), EventError::UnmatchedEnd);
+        let twice = [events.clone(), events.clone()].concat();
+        assert_eq!(build(&twice).unwrap_err(), EventError::MultipleRoots);
+        // an addition with only one operand
+        let short = [
+            Event::Start(Expr::Add((), ())),
+            Event::Start(Expr::LiteralInt(1)),
+            Event::End,
+            Event::End,
+        ];
+        assert_eq!(
+            build(&short).unwrap_err(),
+            EventError::Arity {
+                node: ArenaIndex(0),
+                children: 1
+            }
+        );
+        // and a literal with one
+        let long = [
+            Event::Start(Expr::LiteralInt(0)),
+            Event::Start(Expr::LiteralInt(1)),
+            Event::End,
+            Event::End,
+        ];
+        assert_eq!(
+            build(&long).unwrap_err(),
+            EventError::Arity {
+                node: ArenaIndex(0),
+                children: 1
+            }
+        );
+
+        // a layer filled with children other than those it was given
+        let mut builder = TreeBuilder::new();
+        builder.start(Expr::Add((), ())).unwrap();
+        for x in [1, 2] {
+            builder.start(Expr::LiteralInt(x)).unwrap();
+            builder.end().unwrap();
+        }
+        let swapped = builder.end_with(|_, children| Some(Expr::Add(children[1], children[0])));
+        let error = EventError::Arity {
+            node: ArenaIndex(0),
+            children: 2,
+        };
+        assert_eq!(swapped.unwrap_err(), error);
+
+        // a failed end leaves its node without a layer, so the builder can't be used any further
+        assert_eq!(builder.end().unwrap_err(), error);
+        assert_eq!(builder.finish().unwrap_err(), error);
+        let mut builder = TreeBuilder::new();
+        builder.start(Expr::Add((), ())).unwrap();
+        let error = EventError::Arity {
+            node: ArenaIndex(0),
+            children: 0,
+        };
+        assert_eq!(builder.end().unwrap_err(), error);
+        assert_eq!(builder.start(Expr::LiteralInt(1)).unwrap_err(), error);
+        assert_eq!(builder.finish().unwrap_err(), error);
+    }
+}
@@ -0,0 +1,32 @@
+//! Trees shared by the tests of arena-backed trees and of what's built on them
+
+use crate::examples::expr::{BlocAllocExpr, Expr};
+use crate::recursive::Expand;
+
+/// The layer for each seed of ((1 + 2) * (3 - 4)) + 5, where seeds are numbered breadth-first,
+/// so that each is the index its layer is expanded to
+pub(crate) fn tree_layer(n: u32) -> Expr<u32> {
+    match n {
+        0 => Expr::Add(1, 2),
+        1 => Expr::Mul(3, 4),
+        2 => Expr::LiteralInt(5),
+        3 => Expr::Add(5, 6),
+        4 => Expr::Sub(7, 8),
+        n => Expr::LiteralInt(n as i64 - 4),
+    }
+}
+
+/// ((1 + 2) * (3 - 4)) + 5, laid out breadth-first, so that subtrees' layers are interleaved in
+/// the arena: subtrees of sizes 9, 7, 1, 3 and 3, and then four leaves
+pub(crate) fn tree() -> BlocAllocExpr {
+    BlocAllocExpr::expand_layers(0, tree_layer)
+}
+
+/// The layer for each seed of a complete binary tree of additions of depth 'depth', with a one at
+/// each leaf
+pub(crate) fn complete_layer(depth: usize) -> Expr<usize> {
+    match depth {
+        0 => Expr::LiteralInt(1),
+        _ => Expr::Add(depth - 1, depth - 1),
+    }
+}
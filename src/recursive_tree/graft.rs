@@ -0,0 +1,169 @@
+//! Local edits to arena-backed trees. Each edit rebuilds the arena in a single pass, re-expanding
+//! from the existing layers so that the result is in the same topological order as any other tree.
+
+use std::cell::RefCell;
+
+use crate::map_layer::MapLayer;
+use crate::recursive::Expand;
+use crate::recursive_tree::{arena_eval::ArenaIndex, sidecar::Origins, RecursiveTree};
+
+impl<U> RecursiveTree<U, ArenaIndex> {
+    /// The index of the root layer. Any other layer can be reached from it via 'layer', to find
+    /// positions to edit.
+    pub fn root(&self) -> ArenaIndex {
+        ArenaIndex(0)
+    }
+
+    /// The layer at 'idx', with its children as indices
+    pub fn layer(&self, idx: ArenaIndex) -> &U {
+        &self.elems[idx.0]
+    }
+
+    /// Replace the layer at 'idx' in place with one with the same children, eg to change the value
+    /// of a leaf, returning the old layer. Panics if the children differ, as the arena's structure
+    /// can only be changed by rebuilding it.
+    pub fn set_layer(&mut self, idx: ArenaIndex, layer: U) -> U
+    where
+        for<'a> &'a U: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        let children = |layer: &U| {
+            let mut children = vec![];
+            layer.map_layer(|child| children.push(child));
+            children
+        };
+        assert!(
+            children(&layer) == children(&self.elems[idx.0]),
+            "replacement layers must have the same children"
+        );
+        std::mem::replace(&mut self.elems[idx.0], layer)
+    }
+}
+
+impl<U> RecursiveTree<U, ArenaIndex>
+where
+    U: MapLayer<ArenaIndex, Unwrapped = ArenaIndex, To = U> + Clone,
+{
+    /// Copy the subtree rooted at 'idx' out into its own arena
+    pub fn subtree(&self, idx: ArenaIndex) -> Self {
+        self.subtree_tracked(idx).0
+    }
+
+    /// Like 'subtree', along with where each of its layers came from in this tree, to carry
+    /// sidecars over to it
+    pub fn subtree_tracked(&self, idx: ArenaIndex) -> (Self, Origins) {
+        let origins = RefCell::new(vec![]);
+        let tree = Self::expand_layers(idx, |ArenaIndex(idx)| {
+            origins.borrow_mut().push(Some(ArenaIndex(idx)));
+            self.elems[idx].clone()
+        });
+        (tree, Origins(origins.into_inner()))
+    }
+
+    /// Replace the subtree rooted at 'at' with 'replacement'
+    pub fn graft(&self, at: ArenaIndex, replacement: &Self) -> Self {
+        self.graft_many(&[at], replacement)
+    }
+
+    /// Replace each of the subtrees rooted at 'at' with a copy of 'replacement'. Positions must not
+    /// be nested within each other, as the outermost would replace the rest.
+    pub fn graft_many(&self, at: &[ArenaIndex], replacement: &Self) -> Self {
+        self.graft_many_tracked(at, replacement).0
+    }
+
+    /// Like 'graft_many', along with where each layer of the result came from in this tree, to
+    /// carry sidecars over to it. Layers copied from 'replacement' have no origin.
+    pub fn graft_many_tracked(&self, at: &[ArenaIndex], replacement: &Self) -> (Self, Origins) {
+        // replacement layers are appended after the existing ones, with their indices shifted to
+        // match, and only reachable via 'at'
+        let offset = self.elems.len();
+        let shifted: Vec<U> = replacement
+            .elems
+            .iter()
+            .map(|layer| {
+                layer
+                    .clone()
+                    .map_layer(|ArenaIndex(idx)| ArenaIndex(idx + offset))
+            })
+            .collect();
+
+        // layers are expanded in arena order, so each origin is pushed at its layer's index
+        let origins = RefCell::new(vec![]);
+        let tree = Self::expand_layers(ArenaIndex(0), |idx| {
+            if at.contains(&idx) {
+                origins.borrow_mut().push(None);
+                shifted[0].clone()
+            } else if idx.0 >= offset {
+                origins.borrow_mut().push(None);
+                shifted[idx.0 - offset].clone()
+            } else {
+                origins.borrow_mut().push(Some(idx));
+                self.elems[idx.0].clone()
+            }
+        });
+        (tree, Origins(origins.into_inner()))
+    }
+}
+
+/// A child of a layer expanded by 'expand_layers_sharing': either a seed to expand further, or a
+/// subtree of the existing tree to keep as it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sharing<A> {
+    Expand(A),
+    Keep(ArenaIndex),
+}
+
+impl<U> RecursiveTree<U, ArenaIndex> {
+    /// Expand a new tree from 'seed', where each child is either expanded further or a subtree of
+    /// this one, kept as it is (an apomorphism). Arena-backed trees can't share layers, so kept
+    /// subtrees are copied, but 'expand_layer' is only called along the paths that change, eg the
+    /// search path of an insertion.
+    pub fn expand_layers_sharing<A, W>(&self, seed: A, expand_layer: impl Fn(A) -> W) -> Self
+    where
+        W: MapLayer<ArenaIndex, Unwrapped = Sharing<A>, To = U>,
+        for<'a> &'a U: MapLayer<Sharing<A>, Unwrapped = ArenaIndex, To = W>,
+    {
+        Self::expand_layers(Sharing::Expand(seed), |child| match child {
+            Sharing::Expand(seed) => expand_layer(seed),
+            Sharing::Keep(ArenaIndex(idx)) => self.elems[idx].map_layer(Sharing::Keep),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::examples::expr::{eval::eval_layer, BlocAllocExpr, Expr};
+    use crate::recursive::{Collapse, Expand};
+    use crate::recursive_tree::arena_eval::ArenaIndex;
+
+    #[test]
+    fn graft_and_subtree() {
+        // (1 + 2) * 3
+        let tree = BlocAllocExpr::expand_layers(0, |n| match n {
+            0 => Expr::Mul(1, 2),
+            1 => Expr::Add(3, 4),
+            2 => Expr::LiteralInt(3),
+            3 => Expr::LiteralInt(1),
+            _ => Expr::LiteralInt(2),
+        });
+        let sum = match tree.layer(tree.root()) {
+            Expr::Mul(lhs, _) => *lhs,
+            _ => unreachable!(),
+        };
+        assert_eq!(tree.subtree(sum).collapse_layers(eval_layer), 3);
+
+        // (1 + 2) * (1 + 2)
+        let grafted = tree.graft(ArenaIndex(2), &tree.subtree(sum));
+        assert_eq!(grafted.elems.len(), 7);
+        assert_eq!(grafted.collapse_layers(eval_layer), 9);
+
+        // replacing the root replaces everything
+        let root = tree.graft(ArenaIndex(0), &tree.subtree(ArenaIndex(2)));
+        assert_eq!(root.elems.len(), 1);
+        assert_eq!(root.collapse_layers(eval_layer), 3);
+
+        // ((1 + 2) + 2) * (1 + 2)
+        let twice = tree.graft_many(&[ArenaIndex(2), ArenaIndex(3)], &tree.subtree(sum));
+        assert_eq!(twice.elems.len(), 9);
+        assert_eq!(twice.collapse_layers(eval_layer), 15);
+    }
+}
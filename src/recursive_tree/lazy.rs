@@ -0,0 +1,155 @@
+//! Demand-driven collapse of arena-backed trees. Collapse is strict: every child is collapsed
+//! before its parent, so there's no way to skip a subtree whose result isn't needed, eg the right
+//! hand side of 'false && x'. Here collapse starts from the root instead, and each child is passed
+//! to the collapse function as a 'Thunk' that collapses it only if forced.
+//!
+//! Forcing a thunk collapses that child recursively, on the call stack, so very deep trees can
+//! overflow it. Prefer 'collapse_layers' unless skipping subtrees is required.
+//!
+//! Subtrees can be collapsed lazily in place too, via 'SubtreeView', eg to search below a node
+//! found by walking down from the root.
+
+use std::rc::Rc;
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTreeRef, SubtreeView};
+
+// the state shared between thunks: the arena from the root being collapsed onwards, the arena
+// index of that root, and the function collapsing each layer
+struct Lazy<'a, U, F> {
+    elems: &'a [U],
+    root: usize,
+    collapse_layer: F,
+}
+
+trait Force<A> {
+    fn force(self: Rc<Self>, idx: ArenaIndex) -> A;
+}
+
+impl<'a, A, O, U, F> Force<A> for Lazy<'a, U, F>
+where
+    &'a U: MapLayer<Thunk<'a, A>, To = O, Unwrapped = ArenaIndex>,
+    F: Fn(O) -> A + 'a,
+    A: 'a,
+{
+    fn force(self: Rc<Self>, ArenaIndex(idx): ArenaIndex) -> A {
+        let layer = self.elems[idx - self.root].map_layer(|child| Thunk {
+            lazy: self.clone(),
+            idx: child,
+        });
+        (self.collapse_layer)(layer)
+    }
+}
+
+/// A child that hasn't been collapsed yet
+pub struct Thunk<'a, A> {
+    lazy: Rc<dyn Force<A> + 'a>,
+    idx: ArenaIndex,
+}
+
+impl<A> Thunk<'_, A> {
+    /// Collapse the subtree rooted at this child
+    pub fn force(self) -> A {
+        self.lazy.force(self.idx)
+    }
+}
+
+impl<'a, U> RecursiveTreeRef<'a, U, ArenaIndex> {
+    /// Collapse the tree starting from the root, visiting only those layers whose thunk is forced
+    pub fn collapse_layers_lazy<A, O, F>(self, collapse_layer: F) -> A
+    where
+        &'a U: MapLayer<Thunk<'a, A>, To = O, Unwrapped = ArenaIndex>,
+        F: Fn(O) -> A + 'a,
+        A: 'a,
+    {
+        let lazy = Rc::new(Lazy {
+            elems: self.elems,
+            root: 0,
+            collapse_layer,
+        });
+        lazy.force(ArenaIndex(0))
+    }
+}
+
+impl<'a, U> SubtreeView<'a, U> {
+    /// Collapse the subtree starting from its root, visiting only those layers whose thunk is
+    /// forced
+    pub fn collapse_layers_lazy<A, O, F>(self, collapse_layer: F) -> A
+    where
+        &'a U: MapLayer<Thunk<'a, A>, To = O, Unwrapped = ArenaIndex>,
+        F: Fn(O) -> A + 'a,
+        A: 'a,
+    {
+        let lazy = Rc::new(Lazy {
+            elems: self.elems,
+            root: self.root,
+            collapse_layer,
+        });
+        lazy.force(ArenaIndex(self.root))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::examples::expr::lang::parse::parse;
+    use crate::examples::expr::lang::{Expr, Value};
+
+    fn int(thunk: Thunk<Value>) -> i64 {
+        match thunk.force() {
+            Value::Int(x) => x,
+            value => panic!("expected an int, got {:?}", value),
+        }
+    }
+
+    fn bool_(thunk: Thunk<Value>) -> bool {
+        match thunk.force() {
+            Value::Bool(x) => x,
+            value => panic!("expected a bool, got {:?}", value),
+        }
+    }
+
+    // evaluates just the operators used below
+    fn eval_layer(layer: Expr<Thunk<Value>>) -> Value {
+        match layer {
+            Expr::And(a, b) => Value::Bool(bool_(a) && bool_(b)),
+            Expr::Or(a, b) => Value::Bool(bool_(a) || bool_(b)),
+            Expr::Lt(a, b) => Value::Bool(int(a) < int(b)),
+            Expr::Eq(a, b) => Value::Bool(int(a) == int(b)),
+            Expr::Div(a, b) => Value::Int(int(a) / int(b)),
+            Expr::LiteralInt(x) => Value::Int(x),
+            _ => unreachable!("not in this expression"),
+        }
+    }
+
+    #[test]
+    fn short_circuits() {
+        let expr = parse("(1 < 2 || 1 / 0 == 1) && (2 < 1 && 1 / 0 == 1)").unwrap();
+        let visited = Cell::new(0);
+        let result = expr
+            .as_ref()
+            .collapse_layers_lazy(|layer: Expr<Thunk<Value>>| {
+                visited.set(visited.get() + 1);
+                eval_layer(layer)
+            });
+        assert_eq!(result, Value::Bool(false));
+        // neither division is visited, nor anything else on the right of '||' or the inner '&&'
+        assert_eq!(visited.get(), 9);
+
+        // just the right hand side of the outer '&&'
+        let Expr::And(_, rhs) = expr.layer(expr.root()) else {
+            unreachable!("the root is '&&'")
+        };
+        visited.set(0);
+        let result = expr
+            .subtree_view(*rhs)
+            .collapse_layers_lazy(|layer: Expr<Thunk<Value>>| {
+                visited.set(visited.get() + 1);
+                eval_layer(layer)
+            });
+        assert_eq!(result, Value::Bool(false));
+        assert_eq!(visited.get(), 4);
+    }
+}
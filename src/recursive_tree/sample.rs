@@ -0,0 +1,132 @@
+//! Random sampling of the nodes and subtrees of arena-backed trees, eg to pick mutation points when
+//! fuzzing, to test algebras on random parts of a real tree, or for crossover in genetic
+//! programming: graft a random subtree of one tree in place of a random subtree of another.
+
+use rand::Rng;
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, sidecar::Sidecar, RecursiveTree};
+
+impl<U> RecursiveTree<U, ArenaIndex> {
+    /// A node chosen uniformly at random
+    pub fn sample_node(&self, rng: &mut impl Rng) -> ArenaIndex {
+        ArenaIndex(rng.gen_range(0..self.elems.len()))
+    }
+
+    /// A node chosen at random, weighted by the size of the subtree rooted at it, so that eg the
+    /// root is chosen as often as all of its children together
+    pub fn sample_node_by_size(&self, rng: &mut impl Rng) -> ArenaIndex
+    where
+        for<'a> &'a U: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        // the first node whose running total of sizes is beyond a point chosen within the total
+        let sizes = self.subtree_sizes();
+        let mut running = 0;
+        let totals: Vec<usize> = sizes
+            .iter()
+            .map(|(_, size)| {
+                running += size;
+                running
+            })
+            .collect();
+        let point = rng.gen_range(0..running);
+        ArenaIndex(totals.partition_point(|total| *total <= point))
+    }
+
+    /// A copy of a subtree chosen uniformly at random from those with at most 'max_size' nodes,
+    /// along with the index of its root, or nothing if there are none, ie if 'max_size' is zero
+    pub fn sample_subtree(&self, max_size: usize, rng: &mut impl Rng) -> Option<(ArenaIndex, Self)>
+    where
+        U: MapLayer<ArenaIndex, Unwrapped = ArenaIndex, To = U> + Clone,
+        for<'a> &'a U: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        let sizes = self.subtree_sizes();
+        let small: Vec<ArenaIndex> = sizes
+            .iter()
+            .filter(|(_, size)| **size <= max_size)
+            .map(|(idx, _)| idx)
+            .collect();
+        if small.is_empty() {
+            return None;
+        }
+        let idx = small[rng.gen_range(0..small.len())];
+        Some((idx, self.subtree(idx)))
+    }
+
+    // the number of nodes in the subtree rooted at each node
+    fn subtree_sizes(&self) -> Sidecar<usize>
+    where
+        for<'a> &'a U: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        let mut sizes = Sidecar::filled(self, 1);
+        // children always have higher indices than their parents
+        for (idx, layer) in self.elems.iter().enumerate().rev() {
+            let mut size = 1;
+            layer.map_layer(|child| size += sizes[child]);
+            sizes[ArenaIndex(idx)] = size;
+        }
+        sizes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::examples::expr::{BlocAllocExpr, Expr};
+    use crate::recursive::Expand;
+    use crate::recursive_tree::fixtures::tree;
+
+    #[test]
+    fn distributions() {
+        let tree = tree();
+        let mut rng = StdRng::seed_from_u64(0);
+        let samples = 30_000;
+
+        let mut uniform = [0; 9];
+        let mut by_size = [0; 9];
+        for _ in 0..samples {
+            uniform[tree.sample_node(&mut rng).0] += 1;
+            by_size[tree.sample_node_by_size(&mut rng).0] += 1;
+        }
+        // within a tenth of the expected count
+        let close = |count: usize, expected: usize| count.abs_diff(expected) * 10 < expected;
+        for count in uniform {
+            assert!(close(count, samples / 9), "{:?}", uniform);
+        }
+        let sizes = [9, 7, 1, 3, 3, 1, 1, 1, 1];
+        let total: usize = sizes.iter().sum();
+        for (count, size) in by_size.into_iter().zip(sizes) {
+            assert!(close(count, samples * size / total), "{:?}", by_size);
+        }
+    }
+
+    #[test]
+    fn subtrees() {
+        let tree = tree();
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(tree.sample_subtree(0, &mut rng).is_none());
+        for _ in 0..100 {
+            let (idx, subtree) = tree.sample_subtree(3, &mut rng).unwrap();
+            assert!(subtree.elems.len() <= 3);
+            assert_eq!(subtree.elems, tree.subtree(idx).elems);
+        }
+
+        // crossover: a random subtree of one tree in place of a random node of another
+        let other = BlocAllocExpr::expand_layers(3, |depth| match depth {
+            0 => Expr::LiteralInt(10),
+            _ => Expr::Mul(depth - 1, depth - 1),
+        });
+        for _ in 0..100 {
+            let (_, donor) = other.sample_subtree(3, &mut rng).unwrap();
+            let at = tree.sample_node(&mut rng);
+            let child = tree.graft(at, &donor);
+            assert_eq!(
+                child.elems.len(),
+                tree.elems.len() - tree.subtree(at).elems.len() + donor.elems.len()
+            );
+            assert!(crate::recursive_tree::is_valid_tree(&child.elems));
+        }
+    }
+}
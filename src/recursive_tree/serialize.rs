@@ -0,0 +1,138 @@
+//! Serde support for arena-backed trees.
+//!
+//! A `RecursiveTree<Layer<ArenaIndex>, ArenaIndex>` is serialized as a flat sequence of its layers,
+//! in the same topological order as the arena, with each 'ArenaIndex' serialized as the position of
+//! the child it refers to. Layer types only need to derive 'Serialize' and 'Deserialize' as usual,
+//! generic over their child type. Deserialized trees are checked before they're returned, so input
+//! from untrusted sources can't produce a tree that collapse would misbehave on.
+//!
+//! A 'Checkpoint' is serialized as a map of the layers expanded so far, serialized the same way,
+//! and the seeds still to be expanded. It's checked in the same way when it's deserialized, with
+//! each pending seed standing in for the layer it will be expanded into.
+
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, checkpoint::Checkpoint, RecursiveTree};
+
+impl<Wrapped: Serialize> Serialize for RecursiveTree<Wrapped, ArenaIndex> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.elems.serialize(serializer)
+    }
+}
+
+impl<'de, Wrapped> Deserialize<'de> for RecursiveTree<Wrapped, ArenaIndex>
+where
+    Wrapped: Deserialize<'de>,
+    for<'a> &'a Wrapped: MapLayer<(), Unwrapped = ArenaIndex>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let elems = Vec::<Wrapped>::deserialize(deserializer)?;
+        RecursiveTree::from_layers(elems)
+            .ok_or_else(|| D::Error::custom("layers do not describe a tree in topological order"))
+    }
+}
+
+impl<U: Serialize, A: Serialize> Serialize for Checkpoint<U, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Checkpoint", 2)?;
+        state.serialize_field("elems", &self.elems)?;
+        state.serialize_field("frontier", &self.frontier)?;
+        state.end()
+    }
+}
+
+impl<'de, U, A> Deserialize<'de> for Checkpoint<U, A>
+where
+    U: Deserialize<'de>,
+    A: Deserialize<'de>,
+    for<'a> &'a U: MapLayer<(), Unwrapped = ArenaIndex>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        struct Fields<U, A> {
+            elems: Vec<U>,
+            frontier: std::collections::VecDeque<A>,
+        }
+
+        let Fields { elems, frontier } = Fields::deserialize(deserializer)?;
+        if !crate::recursive_tree::is_valid_partial_tree(&elems, frontier.len()) {
+            return Err(D::Error::custom(
+                "layers and pending seeds do not describe a tree in topological order",
+            ));
+        }
+        Ok(Checkpoint { elems, frontier })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{eval::eval_layer, BlocAllocExpr, Expr};
+    use crate::recursive::{Collapse, Expand};
+    use crate::recursive_tree::fixtures::complete_layer;
+
+    #[test]
+    fn round_trip() {
+        let tree = BlocAllocExpr::expand_layers(2, complete_layer);
+
+        let json = serde_json::to_string(&tree).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"Add":[1,2]},{"Add":[3,4]},{"Add":[5,6]},{"LiteralInt":1},{"LiteralInt":1},{"LiteralInt":1},{"LiteralInt":1}]"#
+        );
+        let tree: BlocAllocExpr = serde_json::from_str(&json).unwrap();
+        assert_eq!(tree.as_ref().collapse_layers(eval_layer), 4);
+    }
+
+    #[test]
+    fn reject_invalid_indices() {
+        for json in [
+            // empty
+            "[]",
+            // both children point at the same node
+            r#"[{"Add":[1,1]},{"LiteralInt":1},{"LiteralInt":2}]"#,
+            // a child that precedes its parent
+            r#"[{"LiteralInt":1},{"Add":[0,2]},{"LiteralInt":2}]"#,
+            // out of bounds
+            r#"[{"Add":[1,3]},{"LiteralInt":1},{"LiteralInt":2}]"#,
+        ] {
+            let err = serde_json::from_str::<BlocAllocExpr>(json).unwrap_err();
+            assert!(err.to_string().contains("topological order"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn resume_checkpoint() {
+        let mut checkpoint = Checkpoint::new(2);
+        assert!(!checkpoint.expand_layers(2, complete_layer));
+
+        let json = serde_json::to_string(&checkpoint).unwrap();
+        assert_eq!(
+            json,
+            r#"{"elems":[{"Add":[1,2]},{"Add":[3,4]}],"frontier":[1,0,0]}"#
+        );
+        let resumed: Checkpoint<Expr<ArenaIndex>, usize> = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            resumed
+                .finish(complete_layer)
+                .as_ref()
+                .collapse_layers(eval_layer),
+            4
+        );
+
+        for json in [
+            // a pending seed that no layer refers to
+            r#"{"elems":[{"Add":[1,2]}],"frontier":[1,0,0]}"#,
+            // a child beyond the pending seeds
+            r#"{"elems":[{"Add":[1,3]}],"frontier":[1,0]}"#,
+            // nothing at all
+            r#"{"elems":[],"frontier":[]}"#,
+        ] {
+            let err =
+                serde_json::from_str::<Checkpoint<Expr<ArenaIndex>, usize>>(json).unwrap_err();
+            assert!(err.to_string().contains("topological order"), "{}", err);
+        }
+    }
+}
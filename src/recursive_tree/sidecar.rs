@@ -0,0 +1,208 @@
+//! Per-layer metadata for arena-backed trees, eg hashes, dirty flags or the results of some
+//! analysis, stored in a vector alongside the arena rather than in the layers themselves, so that
+//! annotating a tree doesn't mean rebuilding it with a new layer type.
+//!
+//! A sidecar is indexed by the same 'ArenaIndex' as the tree it was made for. Edits that rebuild
+//! the arena, eg 'graft_many_tracked' or 'subtree_tracked', give the 'Origins' of each layer of
+//! the new tree, which a sidecar can be carried over with.
+
+use std::ops::{Index, IndexMut};
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// A value for each layer of some arena-backed tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sidecar<M> {
+    // in arena order, so the root's value is first
+    pub(crate) values: Vec<M>,
+}
+
+/// Where each layer of a tree rebuilt from another came from: the index of the layer it was copied
+/// from, or nothing for a new layer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origins(pub(crate) Vec<Option<ArenaIndex>>);
+
+impl Origins {
+    /// The layer of the original tree that the layer at 'idx' was copied from, if any
+    pub fn origin(&self, idx: ArenaIndex) -> Option<ArenaIndex> {
+        self.0[idx.0]
+    }
+}
+
+impl<M> Sidecar<M> {
+    /// A sidecar for 'tree', with each layer's value given by 'f'
+    pub fn new<U>(
+        tree: &RecursiveTree<U, ArenaIndex>,
+        mut f: impl FnMut(ArenaIndex, &U) -> M,
+    ) -> Self {
+        let values = tree
+            .elems
+            .iter()
+            .enumerate()
+            .map(|(idx, layer)| f(ArenaIndex(idx), layer))
+            .collect();
+        Self { values }
+    }
+
+    /// A sidecar for 'tree' with the same value for every layer
+    pub fn filled<U>(tree: &RecursiveTree<U, ArenaIndex>, value: M) -> Self
+    where
+        M: Clone,
+    {
+        Self {
+            values: vec![value; tree.elems.len()],
+        }
+    }
+
+    /// The result of collapsing each subtree of 'tree', computed bottom-up in a single pass
+    pub fn collapse_layers<'a, U, O>(
+        tree: &'a RecursiveTree<U, ArenaIndex>,
+        mut collapse_layer: impl FnMut(O) -> M,
+    ) -> Self
+    where
+        M: Clone,
+        &'a U: MapLayer<M, Unwrapped = ArenaIndex, To = O>,
+    {
+        let mut values: Vec<Option<M>> = std::iter::repeat_with(|| None)
+            .take(tree.elems.len())
+            .collect();
+        // children always have higher indices than their parents
+        for (idx, layer) in tree.elems.iter().enumerate().rev() {
+            let layer = layer.map_layer(|ArenaIndex(child)| {
+                values[child]
+                    .clone()
+                    .expect("children follow their parents")
+            });
+            values[idx] = Some(collapse_layer(layer));
+        }
+        Self {
+            values: values.into_iter().flatten().collect(),
+        }
+    }
+
+    /// Each layer's index, along with its value
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (ArenaIndex, &M)> {
+        self.values
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| (ArenaIndex(idx), value))
+    }
+
+    /// Transform each layer's value
+    pub fn map<N>(self, f: impl FnMut(M) -> N) -> Sidecar<N> {
+        Sidecar {
+            values: self.values.into_iter().map(f).collect(),
+        }
+    }
+
+    /// Carry this sidecar over to a tree rebuilt from the one it was made for, with 'origins'
+    /// from the edit that rebuilt it. Layers that were copied keep their values, and new ones are
+    /// given values by 'fill'.
+    pub fn reindex(&self, origins: &Origins, mut fill: impl FnMut(ArenaIndex) -> M) -> Self
+    where
+        M: Clone,
+    {
+        let values = origins
+            .0
+            .iter()
+            .enumerate()
+            .map(|(idx, origin)| match origin {
+                Some(origin) => self.values[origin.0].clone(),
+                None => fill(ArenaIndex(idx)),
+            })
+            .collect();
+        Self { values }
+    }
+}
+
+impl<U> RecursiveTree<U, ArenaIndex> {
+    /// The parent of each layer, or nothing for the root
+    pub fn parents(&self) -> Sidecar<Option<ArenaIndex>>
+    where
+        for<'a> &'a U: MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        let mut parents = Sidecar::filled(self, None);
+        for (idx, layer) in self.elems.iter().enumerate() {
+            layer.map_layer(|child| parents[child] = Some(ArenaIndex(idx)));
+        }
+        parents
+    }
+}
+
+impl<M> Index<ArenaIndex> for Sidecar<M> {
+    type Output = M;
+
+    fn index(&self, idx: ArenaIndex) -> &M {
+        &self.values[idx.0]
+    }
+}
+
+impl<M> IndexMut<ArenaIndex> for Sidecar<M> {
+    fn index_mut(&mut self, idx: ArenaIndex) -> &mut M {
+        &mut self.values[idx.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{eval::eval_layer, BlocAllocExpr, Expr};
+    use crate::recursive::Expand;
+
+    #[test]
+    fn carried_over_edits() {
+        // (1 + 2) * 3
+        let tree = BlocAllocExpr::expand_layers(0, |n| match n {
+            0 => Expr::Mul(1, 2),
+            1 => Expr::Add(3, 4),
+            2 => Expr::LiteralInt(3),
+            3 => Expr::LiteralInt(1),
+            _ => Expr::LiteralInt(2),
+        });
+        // the number of layers in each subtree, computed bottom-up
+        let mut sizes = Sidecar::filled(&tree, 1);
+        for idx in (0..tree.elems.len()).rev() {
+            let mut size = 1;
+            tree.layer(ArenaIndex(idx))
+                .map_layer(|child| size += sizes[child]);
+            sizes[ArenaIndex(idx)] = size;
+        }
+        assert_eq!(
+            sizes.iter().map(|(_, size)| *size).collect::<Vec<_>>(),
+            vec![5, 3, 1, 1, 1]
+        );
+
+        // copied layers keep their sizes, even though they've moved
+        let (sum, origins) = tree.subtree_tracked(ArenaIndex(1));
+        assert_eq!(origins.origin(ArenaIndex(2)), Some(ArenaIndex(4)));
+        let sum_sizes = sizes.reindex(&origins, |_| unreachable!());
+        assert_eq!(sum_sizes.iter().len(), 3);
+        assert_eq!(sum_sizes[sum.root()], 3);
+
+        // (1 + 2) * (1 + 2), where the grafted layers are new
+        let (grafted, origins) = tree.graft_many_tracked(&[ArenaIndex(2)], &sum);
+        let labels = Sidecar::new(&tree, |ArenaIndex(idx), _| Some(idx));
+        let labels = labels.reindex(&origins, |_| None);
+        assert_eq!(
+            labels.iter().map(|(_, label)| *label).collect::<Vec<_>>(),
+            vec![Some(0), Some(1), None, Some(3), Some(4), None, None]
+        );
+        assert_eq!(labels.iter().len(), grafted.elems.len());
+
+        assert_eq!(
+            Sidecar::collapse_layers(&tree, eval_layer)[ArenaIndex(1)],
+            3
+        );
+        assert_eq!(
+            tree.parents().iter().map(|(_, p)| *p).collect::<Vec<_>>(),
+            vec![
+                None,
+                Some(ArenaIndex(0)),
+                Some(ArenaIndex(0)),
+                Some(ArenaIndex(1)),
+                Some(ArenaIndex(1))
+            ]
+        );
+    }
+}
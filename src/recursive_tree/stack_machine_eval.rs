@@ -3,7 +3,7 @@
 //!
 use crate::{
     map_layer::MapLayer,
-    recursive::{Collapse, Expand},
+    recursive::{Collapse, Expand, TryCollapse, TryExpand},
     recursive_tree::{RecursiveTree, RecursiveTreeRef},
 };
 
@@ -45,6 +45,36 @@ impl<A, U, O: MapLayer<StackMarker, Unwrapped = A, To = U>> Expand<A, O>
     }
 }
 
+impl<A, U, O: MapLayer<StackMarker, Unwrapped = A, To = U>> TryExpand<A, O>
+    for RecursiveTree<U, StackMarker>
+{
+    fn try_expand_layers<E, F: Fn(A) -> Result<O, E>>(a: A, generate_layer: F) -> Result<Self, E> {
+        let mut frontier = Vec::from([a]);
+        let mut elems = vec![];
+
+        // unfold to build a vec of elems while preserving topo order
+        while let Some(seed) = frontier.pop() {
+            let layer = generate_layer(seed)?;
+
+            let mut topush = Vec::new();
+            let layer = layer.map_layer(|aa| {
+                topush.push(aa);
+                StackMarker
+            });
+            frontier.extend(topush.into_iter().rev());
+
+            elems.push(layer);
+        }
+
+        elems.reverse();
+
+        Ok(Self {
+            elems,
+            _underlying: std::marker::PhantomData,
+        })
+    }
+}
+
 impl<A, O, U: MapLayer<A, To = O, Unwrapped = StackMarker>> Collapse<A, O>
     for RecursiveTree<U, StackMarker>
 {
@@ -78,3 +108,42 @@ where
         result_stack.pop().unwrap()
     }
 }
+
+impl<A, O, U: MapLayer<A, To = O, Unwrapped = StackMarker>> TryCollapse<A, O>
+    for RecursiveTree<U, StackMarker>
+{
+    fn try_collapse_layers<E, F: FnMut(O) -> Result<A, E>>(
+        self,
+        mut fold_layer: F,
+    ) -> Result<A, E> {
+        let mut result_stack = Vec::new();
+
+        for layer in self.elems.into_iter() {
+            let layer = layer.map_layer(|_| result_stack.pop().unwrap());
+
+            result_stack.push(fold_layer(layer)?);
+        }
+
+        Ok(result_stack.pop().unwrap())
+    }
+}
+
+impl<'a, A, O: 'a, U> TryCollapse<A, O> for RecursiveTreeRef<'a, U, StackMarker>
+where
+    &'a U: MapLayer<A, To = O, Unwrapped = StackMarker>,
+{
+    fn try_collapse_layers<E, F: FnMut(O) -> Result<A, E>>(
+        self,
+        mut fold_layer: F,
+    ) -> Result<A, E> {
+        let mut result_stack = Vec::with_capacity(32);
+
+        for layer in self.elems.iter() {
+            let layer = layer.map_layer(|_| result_stack.pop().unwrap());
+
+            result_stack.push(fold_layer(layer)?);
+        }
+
+        Ok(result_stack.pop().unwrap())
+    }
+}
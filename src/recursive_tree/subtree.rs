@@ -0,0 +1,157 @@
+//! Borrowed views of subtrees of arena-backed trees, collapsed in place rather than copied out.
+//!
+//! A subtree's layers all follow its root in the arena, but are interleaved with the layers of
+//! other subtrees that follow it too. A view holds every layer from its root onwards, and all of
+//! its bookkeeping is relative to the root: the layer at arena index 'idx' is at 'idx - root'
+//! within the view. Collapsing a view first finds which of its layers are reachable from the root,
+//! and skips the rest.
+
+use crate::map_layer::MapLayer;
+use crate::recursive::Collapse;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// The subtree rooted at some layer of a tree, borrowed from it
+pub struct SubtreeView<'a, U> {
+    // the arena index of the root, which every index within the view is relative to
+    pub(crate) root: usize,
+    // the root and every layer after it, some of which may not be in the subtree
+    pub(crate) elems: &'a [U],
+}
+
+impl<U> RecursiveTree<U, ArenaIndex> {
+    /// A view of the subtree rooted at 'idx', which can be collapsed without copying it out as
+    /// 'subtree' does
+    pub fn subtree_view(&self, idx: ArenaIndex) -> SubtreeView<'_, U> {
+        SubtreeView {
+            root: idx.0,
+            elems: &self.elems[idx.0..],
+        }
+    }
+}
+
+impl<'a, U> SubtreeView<'a, U> {
+    // the position within the view of a child of one of its layers
+    fn position(&self, ArenaIndex(child): ArenaIndex) -> usize {
+        debug_assert!(child > self.root, "children follow their parents");
+        child - self.root
+    }
+
+    /// Collapse the subtree, with each layer given a context passed down to it from its parent,
+    /// eg its depth, or the variables in scope. 'descend' gives the contexts of a layer's
+    /// children, given its own, as a layer of the same shape (with the same number of children,
+    /// visited in the same order).
+    pub fn collapse_layers_with_context<C, D, A, O>(
+        self,
+        context: C,
+        mut descend: impl FnMut(&C, &'a U) -> D,
+        mut collapse_layer: impl FnMut(C, O) -> A,
+    ) -> A
+    where
+        D: MapLayer<(), Unwrapped = C>,
+        &'a U: MapLayer<A, Unwrapped = ArenaIndex, To = O> + MapLayer<(), Unwrapped = ArenaIndex>,
+    {
+        // contexts are passed down in arena order, so each layer's is known before its children's.
+        // Layers outside the subtree are never given one.
+        let mut contexts: Vec<Option<C>> = std::iter::repeat_with(|| None)
+            .take(self.elems.len())
+            .collect();
+        contexts[0] = Some(context);
+        for (idx, layer) in self.elems.iter().enumerate() {
+            let Some(context) = &contexts[idx] else {
+                continue;
+            };
+            let mut children = Vec::new();
+            descend(context, layer).map_layer(|child| children.push(child));
+            let mut children = children.into_iter();
+            layer.map_layer(|child| {
+                let context = children.next().expect("a context for each child");
+                contexts[self.position(child)] = Some(context);
+            });
+            debug_assert!(children.next().is_none(), "a context for each child");
+        }
+
+        let mut results: Vec<Option<A>> = std::iter::repeat_with(|| None)
+            .take(self.elems.len())
+            .collect();
+        for (idx, layer) in self.elems.iter().enumerate().rev() {
+            let Some(context) = contexts[idx].take() else {
+                continue;
+            };
+            let layer = layer.map_layer(|child| results[self.position(child)].take().unwrap());
+            results[idx] = Some(collapse_layer(context, layer));
+        }
+        results[0].take().unwrap()
+    }
+}
+
+impl<'a, A, O, U> Collapse<A, O> for SubtreeView<'a, U>
+where
+    &'a U: MapLayer<A, Unwrapped = ArenaIndex, To = O> + MapLayer<(), Unwrapped = ArenaIndex>,
+{
+    fn collapse_layers<F: FnMut(O) -> A>(self, mut collapse_layer: F) -> A {
+        let mut reachable = vec![false; self.elems.len()];
+        reachable[0] = true;
+        for (idx, layer) in self.elems.iter().enumerate() {
+            if reachable[idx] {
+                layer.map_layer(|child| reachable[self.position(child)] = true);
+            }
+        }
+
+        let mut results: Vec<Option<A>> = std::iter::repeat_with(|| None)
+            .take(self.elems.len())
+            .collect();
+        for (idx, layer) in self.elems.iter().enumerate().rev() {
+            if reachable[idx] {
+                let layer = layer.map_layer(|child| results[self.position(child)].take().unwrap());
+                results[idx] = Some(collapse_layer(layer));
+            }
+        }
+        results[0].take().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::examples::expr::{eval::eval_layer, Expr};
+    use crate::map_layer::MapLayer;
+    use crate::recursive::Collapse;
+    use crate::recursive_tree::arena_eval::ArenaIndex;
+    use crate::recursive_tree::fixtures::tree;
+
+    #[test]
+    fn views() {
+        let tree = tree();
+        for idx in 0..9 {
+            let idx = ArenaIndex(idx);
+            assert_eq!(
+                tree.subtree_view(idx).collapse_layers(eval_layer),
+                tree.subtree(idx).collapse_layers(eval_layer)
+            );
+        }
+        assert_eq!(
+            tree.subtree_view(tree.root()).collapse_layers(eval_layer),
+            2
+        );
+    }
+
+    #[test]
+    fn contexts() {
+        let tree = tree();
+        // each literal weighted by its depth within the subtree
+        let weighted = |idx| {
+            tree.subtree_view(ArenaIndex(idx))
+                .collapse_layers_with_context(
+                    0,
+                    |depth, layer: &Expr<ArenaIndex>| layer.map_layer(|_| depth + 1),
+                    |depth, layer: Expr<i64>| match layer {
+                        Expr::LiteralInt(x) => x * depth,
+                        Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) => a + b,
+                    },
+                )
+        };
+        assert_eq!(weighted(0), (1 + 2 + 3 + 4) * 3 + 5);
+        // the subtree '3 - 4', at arena index 4
+        assert_eq!(weighted(4), 3 + 4);
+        assert_eq!(weighted(8), 0);
+    }
+}
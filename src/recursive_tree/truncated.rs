@@ -0,0 +1,195 @@
+//! Size-bounded debug output for arena-backed trees, so that eg logging a tree with millions of
+//! nodes in an error path prints a readable outline of its top rather than gigabytes of text.
+//!
+//! The tree is written top-down in the box-drawing style of 'render::render_tree', each layer as
+//! its 'Debug' output with a '_' in place of each child. Subtrees below the maximum depth are
+//! elided, as is everything after the maximum number of nodes has been written, with a count of the
+//! nodes left out in their place. Only the nodes written, and those counted, are ever visited.
+
+use std::fmt;
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// Stands in for each child of a layer in its 'Debug' output
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Hole;
+
+impl fmt::Debug for Hole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("_")
+    }
+}
+
+/// A tree formatted with 'Debug' at most 'max_nodes' nodes, and 'max_depth' levels below the root
+pub struct DebugTruncated<'a, U> {
+    elems: &'a [U],
+    max_nodes: usize,
+    max_depth: usize,
+}
+
+impl<U> RecursiveTree<U, ArenaIndex> {
+    /// Format the tree with 'Debug', truncated to at most 'max_nodes' nodes, and to 'max_depth'
+    /// levels below the root
+    pub fn debug_truncated(&self, max_nodes: usize, max_depth: usize) -> DebugTruncated<'_, U> {
+        DebugTruncated {
+            elems: &self.elems,
+            max_nodes,
+            max_depth,
+        }
+    }
+}
+
+impl<'a, U> DebugTruncated<'a, U>
+where
+    &'a U: MapLayer<(), Unwrapped = ArenaIndex>,
+{
+    fn children(&self, idx: usize) -> Vec<usize> {
+        let mut children = vec![];
+        self.elems[idx].map_layer(|ArenaIndex(child)| children.push(child));
+        children
+    }
+
+    // the number of nodes below the node at 'idx'
+    fn descendants(&self, idx: usize) -> usize {
+        let mut stack = self.children(idx);
+        let mut count = 0;
+        while let Some(idx) = stack.pop() {
+            count += 1;
+            stack.extend(self.children(idx));
+        }
+        count
+    }
+}
+
+impl<'a, U> fmt::Debug for DebugTruncated<'a, U>
+where
+    &'a U: MapLayer<Hole, Unwrapped = ArenaIndex> + MapLayer<(), Unwrapped = ArenaIndex>,
+    <&'a U as MapLayer<Hole>>::To: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // nodes still to be written, each with its depth, the prefix for the lines below it, and
+        // the connector to its parent. Popped in pre-order.
+        let mut stack = vec![(0, 0, String::new(), "")];
+        let mut written = 0;
+        let mut elided = 0;
+        while let Some((idx, depth, prefix, connector)) = stack.pop() {
+            if written == self.max_nodes {
+                break;
+            }
+            written += 1;
+            if written > 1 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "{}{}{:?}",
+                prefix,
+                connector,
+                self.elems[idx].map_layer(|_| Hole)
+            )?;
+
+            let indent = match connector {
+                "├── " => "│   ",
+                "└── " => "    ",
+                _ => "",
+            };
+            let prefix = format!("{}{}", prefix, indent);
+            let children = self.children(idx);
+            if depth == self.max_depth && !children.is_empty() {
+                let below = self.descendants(idx);
+                elided += below;
+                write!(f, "\n{}└── … {} nodes", prefix, below)?;
+                continue;
+            }
+            let last = children.len().saturating_sub(1);
+            for (position, child) in children.into_iter().enumerate().rev() {
+                let connector = if position == last {
+                    "└── "
+                } else {
+                    "├── "
+                };
+                stack.push((child, depth + 1, prefix.clone(), connector));
+            }
+        }
+
+        let rest = self.elems.len() - written - elided;
+        if rest > 0 {
+            if written > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "… {} more nodes", rest)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::examples::expr::{BlocAllocExpr, Expr};
+    use crate::recursive::Expand;
+    use crate::recursive_tree::fixtures::tree;
+
+    #[test]
+    fn truncation() {
+        let tree = tree();
+        assert_eq!(
+            format!("{:?}", tree.debug_truncated(100, 100)),
+            [
+                "Add(_, _)",
+                "├── Mul(_, _)",
+                "│   ├── Add(_, _)",
+                "│   │   ├── LiteralInt(1)",
+                "│   │   └── LiteralInt(2)",
+                "│   └── Sub(_, _)",
+                "│       ├── LiteralInt(3)",
+                "│       └── LiteralInt(4)",
+                "└── LiteralInt(5)",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            format!("{:?}", tree.debug_truncated(100, 1)),
+            [
+                "Add(_, _)",
+                "├── Mul(_, _)",
+                "│   └── … 6 nodes",
+                "└── LiteralInt(5)",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            format!("{:?}", tree.debug_truncated(3, 100)),
+            [
+                "Add(_, _)",
+                "├── Mul(_, _)",
+                "│   ├── Add(_, _)",
+                "… 6 more nodes"
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            format!("{:?}", tree.debug_truncated(1, 0)),
+            ["Add(_, _)", "└── … 8 nodes"].join("\n")
+        );
+        assert_eq!(
+            format!("{:?}", tree.debug_truncated(0, 100)),
+            "… 9 more nodes"
+        );
+    }
+
+    #[test]
+    fn huge() {
+        // 0 - (0 - (0 - ...)), a million subtractions deep
+        let tree = BlocAllocExpr::expand_layers(1_000_000, |n| match n {
+            0 => Expr::LiteralInt(0),
+            n => Expr::Sub(0, n - 1),
+        });
+        let output = format!("{:?}", tree.debug_truncated(20, 5));
+        assert!(output.len() < 1000, "{}", output);
+        // everything below the sixth subtraction
+        assert!(output.ends_with("└── … 1999990 nodes"), "{}", output);
+        let output = format!("{:?}", tree.debug_truncated(10, 100));
+        assert!(output.ends_with("\n… 1999991 more nodes"), "{}", output);
+    }
+}
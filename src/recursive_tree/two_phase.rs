@@ -0,0 +1,219 @@
+//! Expansion in two phases: first the shape of the tree, with each node's payload left as a cheap
+//! placeholder, and then the payloads themselves, by index, in a separate pass that can run in
+//! parallel or concurrently. Expensive per-node data, eg file hashes or fetched metadata, then
+//! doesn't hold up the structural build, which needs nothing but each node's children.
+//!
+//! Payloads are stored in a 'Sidecar' alongside the tree, rather than in its layers, so the shape
+//! is usable (eg to count, print or plan work over) before any of them have been computed.
+
+use std::collections::VecDeque;
+
+#[cfg(any(test, feature = "async"))]
+use futures::{future::BoxFuture, stream, FutureExt, StreamExt, TryStreamExt};
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, sidecar::Sidecar, RecursiveTree};
+
+/// The shape of a tree, along with a placeholder for each node's payload, eg the path of a file
+/// whose hash is still to be computed
+#[derive(Debug, Clone)]
+pub struct Unfilled<U, Q> {
+    shape: RecursiveTree<U, ArenaIndex>,
+    placeholders: Sidecar<Q>,
+}
+
+/// A tree along with the payload for each of its nodes
+pub type Filled<U, P> = (RecursiveTree<U, ArenaIndex>, Sidecar<P>);
+
+impl<U> RecursiveTree<U, ArenaIndex> {
+    /// Expand the shape of a tree breadth-first, as 'expand_layers' does, with each layer's
+    /// payload left as the placeholder returned alongside it, to be filled in later
+    pub fn expand_shape<A, Q, W>(seed: A, expand_layer: impl Fn(A) -> (W, Q)) -> Unfilled<U, Q>
+    where
+        W: MapLayer<ArenaIndex, Unwrapped = A, To = U>,
+    {
+        let mut frontier = VecDeque::from([seed]);
+        let mut elems = vec![];
+        let mut placeholders = vec![];
+
+        while let Some(seed) = frontier.pop_front() {
+            let (layer, placeholder) = expand_layer(seed);
+            let layer = layer.map_layer(|seed| {
+                frontier.push_back(seed);
+                ArenaIndex::enqueued(elems.len(), frontier.len())
+            });
+            elems.push(layer);
+            placeholders.push(placeholder);
+        }
+
+        Unfilled {
+            shape: RecursiveTree {
+                elems,
+                _underlying: std::marker::PhantomData,
+            },
+            placeholders: Sidecar {
+                values: placeholders,
+            },
+        }
+    }
+}
+
+impl<U, Q> Unfilled<U, Q> {
+    /// The shape of the tree, complete but for its payloads
+    pub fn shape(&self) -> &RecursiveTree<U, ArenaIndex> {
+        &self.shape
+    }
+
+    /// Each node's placeholder, by index
+    pub fn placeholders(&self) -> &Sidecar<Q> {
+        &self.placeholders
+    }
+
+    /// Fill in every payload, one node at a time in arena order
+    pub fn fill<P>(self, mut fill: impl FnMut(ArenaIndex, &U, Q) -> P) -> Filled<U, P> {
+        let values = self
+            .placeholders
+            .values
+            .into_iter()
+            .zip(&self.shape.elems)
+            .enumerate()
+            .map(|(idx, (placeholder, layer))| fill(ArenaIndex(idx), layer, placeholder))
+            .collect();
+        (self.shape, Sidecar { values })
+    }
+
+    /// Fill in every payload on up to 'threads' threads, each filling a contiguous run of nodes.
+    /// Not available for wasm32-unknown-unknown, which can't spawn threads.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn fill_parallel<P: Send>(
+        self,
+        threads: usize,
+        fill: impl Fn(ArenaIndex, &U, Q) -> P + Sync,
+    ) -> Filled<U, P>
+    where
+        U: Sync,
+        Q: Send,
+    {
+        let mut placeholders = self.placeholders.values;
+        let chunk = placeholders.len().div_ceil(threads.max(1));
+        // split off from the back, so each run is taken from the end of what's left
+        let mut runs = vec![];
+        while !placeholders.is_empty() {
+            let start = placeholders.len().saturating_sub(chunk);
+            runs.push((start, placeholders.split_off(start)));
+        }
+
+        let elems = &self.shape.elems;
+        let fill = &fill;
+        let values = std::thread::scope(|scope| {
+            let handles: Vec<_> = runs
+                .into_iter()
+                .rev()
+                .map(|(start, run)| {
+                    scope.spawn(move || {
+                        run.into_iter()
+                            .enumerate()
+                            .map(|(offset, placeholder)| {
+                                let idx = start + offset;
+                                fill(ArenaIndex(idx), &elems[idx], placeholder)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("fill panicked"))
+                .collect()
+        });
+        (self.shape, Sidecar { values })
+    }
+}
+
+#[cfg(any(test, feature = "async"))]
+impl<U: Send, Q: Send> Unfilled<U, Q> {
+    /// Fill in every payload asynchronously, with up to 'limit' in flight at once, eg to overlap
+    /// IO. Stops at the first error, dropping any payloads in flight.
+    pub fn fill_async<'a, P: Send + 'a, E: Send + 'a>(
+        self,
+        limit: usize,
+        fill: impl Fn(ArenaIndex, Q) -> BoxFuture<'a, Result<P, E>> + Send + 'a,
+    ) -> BoxFuture<'a, Result<Filled<U, P>, E>>
+    where
+        U: 'a,
+        Q: 'a,
+    {
+        async move {
+            let values = stream::iter(self.placeholders.values.into_iter().enumerate())
+                .map(move |(idx, placeholder)| fill(ArenaIndex(idx), placeholder))
+                // payloads complete in any order, but are kept in arena order
+                .buffered(limit.max(1))
+                .try_collect()
+                .await?;
+            Ok((self.shape, Sidecar { values }))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{eval::eval_layer, BlocAllocExpr, Expr};
+    use crate::recursive::Collapse;
+    use crate::recursive_tree::fixtures::{tree, tree_layer};
+
+    // ((1 + 2) * (3 - 4)) + 5, with each node's seed as its placeholder
+    fn unfilled() -> Unfilled<Expr<ArenaIndex>, u32> {
+        BlocAllocExpr::expand_shape(0, |n| (tree_layer(n), n))
+    }
+
+    // the payload for a node, eg a hash of its seed
+    fn payload(idx: ArenaIndex, layer: &Expr<ArenaIndex>, seed: u32) -> String {
+        format!("{}:{:?}@{}", seed, layer, idx.0)
+    }
+
+    #[test]
+    fn fill() {
+        let unfilled = unfilled();
+        // the shape is usable before any payloads are filled in
+        assert_eq!(unfilled.shape().clone().collapse_layers(eval_layer), 2);
+        let seeds: Vec<u32> = unfilled.placeholders().iter().map(|(_, s)| *s).collect();
+        assert_eq!(seeds, [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let expected = tree();
+        let (tree, payloads) = unfilled.clone().fill(payload);
+        assert_eq!(tree.elems, expected.elems);
+        assert_eq!(
+            payloads[ArenaIndex(4)],
+            "4:Sub(ArenaIndex(7), ArenaIndex(8))@4"
+        );
+
+        // the same payloads, however many threads fill them in
+        for threads in [0, 1, 2, 4, 9, 20] {
+            let (parallel, parallel_payloads) = unfilled.clone().fill_parallel(threads, payload);
+            assert_eq!(parallel.elems, expected.elems);
+            assert_eq!(parallel_payloads, payloads);
+        }
+
+        let (_, async_payloads) =
+            futures::executor::block_on(unfilled.clone().fill_async(3, |idx, seed| {
+                async move { Ok::<_, ()>(idx.0 as u32 * 10 + seed) }.boxed()
+            }))
+            .unwrap();
+        let expected: Vec<u32> = (0..9).map(|n| n * 11).collect();
+        assert_eq!(async_payloads.values, expected);
+
+        let failed = futures::executor::block_on(unfilled.fill_async(3, |idx, _| {
+            async move {
+                if idx.0 == 5 {
+                    Err(idx)
+                } else {
+                    Ok(())
+                }
+            }
+            .boxed()
+        }));
+        assert_eq!(failed.unwrap_err(), ArenaIndex(5));
+    }
+}
@@ -0,0 +1,126 @@
+//! Rendering of trees as indented box-drawing output, in the style of the Unix 'tree' command.
+//!
+//! 'render_tree' renders any arena-backed tree, given a label for each node and an ordering of
+//! each node's children, eg by name. 'TreeLines' is for trees that are easier to label bottom-up,
+//! by collapsing them, with each node's label placed above its already-built children, which are
+//! drawn in the order they are provided.
+//!
+//! Either way, lines are written top-down in a single pass, with the prefix for each subtree's
+//! lines passed down to it rather than added to each of them at every level above it.
+
+use std::cmp::Ordering;
+
+use crate::map_layer::LayerArity;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// A subtree to be rendered, built bottom-up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeLines {
+    label: String,
+    children: Vec<TreeLines>,
+}
+
+impl TreeLines {
+    pub fn leaf(label: impl Into<String>) -> Self {
+        Self::node(label, [])
+    }
+
+    pub fn node(label: impl Into<String>, children: impl IntoIterator<Item = TreeLines>) -> Self {
+        Self {
+            label: label.into(),
+            children: children.into_iter().collect(),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        write_lines(self, |node| {
+            (node.label.as_str(), node.children.iter().collect())
+        })
+    }
+}
+
+/// Render an arena-backed tree, with each node labeled by 'label' and each node's children drawn
+/// in the order given by 'order'. Children are sorted stably, so '|_, _| Ordering::Equal' keeps
+/// them in the order they're stored in.
+pub fn render_tree<U, L: AsRef<str>>(
+    tree: &RecursiveTree<U, ArenaIndex>,
+    mut label: impl FnMut(ArenaIndex, &U) -> L,
+    mut order: impl FnMut(ArenaIndex, ArenaIndex) -> Ordering,
+) -> String
+where
+    U: LayerArity<Child = ArenaIndex>,
+{
+    write_lines(tree.root(), |idx| {
+        let layer = tree.layer(idx);
+        let mut children: Vec<_> = layer.children().copied().collect();
+        children.sort_by(|a, b| order(*a, *b));
+        (label(idx, layer), children)
+    })
+}
+
+// write every node's label, and then its children's, with each child's lines prefixed by its
+// connector to its parent and by its ancestors' indentation
+fn write_lines<N, L: AsRef<str>>(root: N, mut visit: impl FnMut(N) -> (L, Vec<N>)) -> String {
+    let mut out = String::new();
+    // nodes still to be written, each with the prefix for its first line and then for the rest of
+    // its lines and its children's. Popped in pre-order.
+    let mut stack = vec![(root, String::new(), String::new())];
+    while let Some((node, first, rest)) = stack.pop() {
+        let (label, children) = visit(node);
+        if !first.is_empty() {
+            out.push('\n');
+        }
+        let mut lines = label.as_ref().lines();
+        out.push_str(&first);
+        out.push_str(lines.next().unwrap_or_default());
+        for line in lines {
+            out.push('\n');
+            out.push_str(&rest);
+            out.push_str(line);
+        }
+
+        let last = children.len().saturating_sub(1);
+        for (position, child) in children.into_iter().enumerate().rev() {
+            let (connector, indent) = if position == last {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            stack.push((
+                child,
+                format!("{}{}", rest, connector),
+                format!("{}{}", rest, indent),
+            ));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_drawing() {
+        let tree = TreeLines::node(
+            "root",
+            [
+                TreeLines::node("a", [TreeLines::leaf("a1"), TreeLines::leaf("a2")]),
+                TreeLines::node("b", [TreeLines::leaf("b1")]),
+            ],
+        );
+        assert_eq!(
+            tree.render(),
+            "root\n├── a\n│   ├── a1\n│   └── a2\n└── b\n    └── b1"
+        );
+    }
+
+    #[test]
+    fn multiline_labels() {
+        let tree = TreeLines::node(
+            "root",
+            [TreeLines::leaf("one\ntwo"), TreeLines::leaf("three")],
+        );
+        assert_eq!(tree.render(), "root\n├── one\n│   two\n└── three");
+    }
+}
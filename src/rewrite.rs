@@ -0,0 +1,101 @@
+//! Rewrite rules written as patterns over a single layer, via 'rewrite!'.
+//!
+//! A rule matches a layer whose children are already-built subtrees, eg `Expr<ExprAST>`, and
+//! produces a replacement subtree. Written by hand, each rule is a closure with a 'match' that
+//! spells out the full path of every constructor and ends in '_ => None'. 'rewrite!' takes the
+//! layer and subtree types up front, so patterns can use bare variant names:
+//!
+//! ```ignore
+//! rewrite! { Expr, ExprAST;
+//!     Add(x, LiteralInt(0)) | Add(LiteralInt(0), x) => x,
+//!     Sub(a, b) if a == b => ExprAST::LiteralInt(0),
+//! }
+//! ```
+//!
+//! Each pattern is a variant of the layer type. Any of its arguments written as a constructor call
+//! (eg 'LiteralInt(0)') is matched against a variant of the subtree type, and any other argument
+//! is an ordinary pattern (a binding, literal or '_'). Patterns nest no deeper than that, as
+//! subtrees are usually boxed. The result is a closure from `&Layer<Subtree>` to
+//! `Option<Subtree>`, returning the right hand side of the first arm that matches, if any.
+
+/// The right hand side of a rule: either a new subtree, or a reference to one that was matched,
+/// which is cloned
+pub trait Replacement<T> {
+    fn into_replacement(self) -> T;
+}
+
+impl<T> Replacement<T> for T {
+    fn into_replacement(self) -> T {
+        self
+    }
+}
+
+impl<T: Clone> Replacement<T> for &T {
+    fn into_replacement(self) -> T {
+        self.clone()
+    }
+}
+
+/// Build a rewrite rule from patterns over a single layer. See the 'rewrite' module for details.
+#[macro_export]
+macro_rules! rewrite {
+    // a single pattern, with arguments moved into '[...]' as they're translated
+    (@pattern $layer:ident, $subtree:ident; $variant:ident [$($done:tt)*]) => {
+        $layer::$variant($($done)*)
+    };
+    (@pattern $layer:ident, $subtree:ident; $variant:ident [$($done:tt)*]
+        $constructor:ident ($($inner:tt)*) $(, $($rest:tt)*)?) => {
+        $crate::rewrite!(@pattern $layer, $subtree; $variant
+            [$($done)* $subtree::$constructor($($inner)*),] $($($rest)*)?)
+    };
+    (@pattern $layer:ident, $subtree:ident; $variant:ident [$($done:tt)*]
+        $arg:pat $(, $($rest:tt)*)?) => {
+        $crate::rewrite!(@pattern $layer, $subtree; $variant [$($done)* $arg,] $($($rest)*)?)
+    };
+    ($layer:ident, $subtree:ident;
+        $( $($variant:ident ($($args:tt)*))|+ $(if $guard:expr)? => $replacement:expr ),* $(,)?) => {
+        |layer: &$layer<$subtree>| -> Option<$subtree> {
+            match layer {
+                $(
+                    $( $crate::rewrite!(@pattern $layer, $subtree; $variant [] $($args)*) )|+
+                    $(if $guard)? => Some($crate::rewrite::Replacement::into_replacement(
+                        $replacement
+                    )),
+                )*
+                _ => None,
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::examples::expr::lang::{Expr, ExprAST};
+
+    #[test]
+    fn patterns() {
+        let rule = rewrite! { Expr, ExprAST;
+            Add(x, LiteralInt(0)) | Add(LiteralInt(0), x) => x,
+            Sub(LiteralInt(a), LiteralInt(b)) if a >= b => ExprAST::LiteralInt(a - b),
+            Mul(LiteralInt(-1), Var(name)) => ExprAST::Var(format!("-{}", name)),
+            Let(name, value, Var(body)) if name == body => value,
+        };
+        let int = ExprAST::LiteralInt;
+        let var = |name: &str| ExprAST::Var(name.to_string());
+
+        assert_eq!(rule(&Expr::Add(var("x"), int(0))), Some(var("x")));
+        assert_eq!(rule(&Expr::Add(int(0), var("y"))), Some(var("y")));
+        assert_eq!(rule(&Expr::Add(int(1), var("y"))), None);
+
+        assert_eq!(rule(&Expr::Sub(int(3), int(2))), Some(int(1)));
+        // the guard doesn't hold
+        assert_eq!(rule(&Expr::Sub(int(2), int(3))), None);
+
+        assert_eq!(rule(&Expr::Mul(int(-1), var("z"))), Some(var("-z")));
+
+        let let_ = |body| Expr::Let("x".to_string(), int(5), body);
+        assert_eq!(rule(&let_(var("x"))), Some(int(5)));
+        assert_eq!(rule(&let_(var("y"))), None);
+        assert_eq!(rule(&Expr::LiteralInt(0)), None);
+    }
+}
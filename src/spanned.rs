@@ -0,0 +1,107 @@
+//! Source locations for any layer type. Wrapping a layer type in 'Spanned' gives a layer type with
+//! the same children, where every node also carries the byte range of the source it came from.
+//! Parsers can expand directly into a spanned tree, and errors found while collapsing it can be
+//! reported against the span of the node they occurred in.
+//!
+//! Only the owned 'MapLayer' impl is generic: a blanket impl for `&Spanned<L>` overflows trait
+//! resolution wherever '&L: MapLayer' is required, so by-reference impls should be written for
+//! each concrete layer type instead.
+
+use std::ops::Range;
+
+use crate::map_layer::MapLayer;
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// A range of bytes in some source text
+pub type Span = Range<usize>;
+
+/// Some value, usually a layer or an error, along with the span of source it corresponds to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned<T> {
+    pub span: Span,
+    pub value: T,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(span: Span, value: T) -> Self {
+        Self { span, value }
+    }
+}
+
+impl<B, L: MapLayer<B>> MapLayer<B> for Spanned<L> {
+    type To = Spanned<L::To>;
+    type Unwrapped = L::Unwrapped;
+
+    #[inline(always)]
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, f: F) -> Self::To {
+        Spanned {
+            span: self.span,
+            value: self.value.map_layer(f),
+        }
+    }
+}
+
+/// Drop the span from every layer of a tree
+pub fn strip_spans<L, Index>(tree: RecursiveTree<Spanned<L>, Index>) -> RecursiveTree<L, Index> {
+    RecursiveTree {
+        elems: tree.elems.into_iter().map(|layer| layer.value).collect(),
+        _underlying: std::marker::PhantomData,
+    }
+}
+
+/// Span of the node at the end of 'path', where each step is the position of a child within its
+/// parent layer, in 'map_layer' order.
+pub fn span_at<'a, L>(
+    tree: &'a RecursiveTree<Spanned<L>, ArenaIndex>,
+    path: &[usize],
+) -> Option<Span>
+where
+    &'a L: MapLayer<ArenaIndex, Unwrapped = ArenaIndex>,
+{
+    let mut idx = 0;
+    for step in path {
+        let mut children = Vec::new();
+        tree.elems[idx].value.map_layer(|ArenaIndex(child)| {
+            children.push(child);
+            ArenaIndex(child)
+        });
+        idx = *children.get(*step)?;
+    }
+    Some(tree.elems[idx].span.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::{eval::eval_layer, Expr};
+    use crate::recursive::{Collapse, Expand};
+
+    #[test]
+    fn spans() {
+        let source = "1 + 2 * 3";
+        // each seed is the span of the subexpression
+        let tree =
+            RecursiveTree::<Spanned<Expr<ArenaIndex>>, ArenaIndex>::expand_layers(0..9, |span| {
+                let layer = match &source[span.clone()] {
+                    "1 + 2 * 3" => Expr::Add(0..1, 4..9),
+                    "2 * 3" => Expr::Mul(4..5, 8..9),
+                    literal => Expr::LiteralInt(literal.parse().unwrap()),
+                };
+                Spanned::new(span, layer)
+            });
+
+        // the span of the first literal that isn't '1'
+        let found = tree
+            .clone()
+            .collapse_layers(|layer: Spanned<Expr<Option<Span>>>| match layer.value {
+                Expr::LiteralInt(1) => None,
+                Expr::LiteralInt(_) => Some(layer.span),
+                Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) => a.or(b),
+            });
+        assert_eq!(found, Some(4..5));
+
+        assert_eq!(span_at(&tree, &[1, 1]), Some(8..9));
+        assert_eq!(span_at(&tree, &[0, 0]), None);
+        assert_eq!(strip_spans(tree).collapse_layers(eval_layer), 7);
+    }
+}
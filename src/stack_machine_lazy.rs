@@ -1,6 +1,6 @@
 use crate::{
     map_layer::{CoProject, MapLayer, Project},
-    Collapse, Expand,
+    Collapse, Expand, TryCollapse,
 };
 
 impl<
@@ -17,6 +17,23 @@ impl<
     }
 }
 
+impl<
+        // F, a type parameter of kind * -> * that cannot be represented in rust
+        Seed: Project<To = GenerateExpr>,
+        Out,
+        GenerateExpr: MapLayer<(), Unwrapped = Seed, To = U>, // F<Seed>
+        ConsumeExpr,                                          // F<Out>
+        U: MapLayer<Out, To = ConsumeExpr, Unwrapped = ()>,   // F<()>
+    > TryCollapse<Out, ConsumeExpr> for Seed
+{
+    fn try_collapse_layers<E, F: FnMut(ConsumeExpr) -> Result<Out, E>>(
+        self,
+        collapse_layer: F,
+    ) -> Result<Out, E> {
+        unfold_and_fold_result(self, |seed| Ok(seed.project()), collapse_layer)
+    }
+}
+
 impl<
         // F, a type parameter of kind * -> * that cannot be represented in rust
         Seed: Project<To = GenerateExpr>,
@@ -65,7 +82,7 @@ pub fn unfold_and_fold_result<
                 let node = node.map_layer(|seed| topush.push(State::PreVisit(seed)));
 
                 todo.push(State::PostVisit(node));
-                todo.extend(topush.into_iter());
+                todo.extend(topush);
             }
             State::PostVisit(node) => {
                 let node = node.map_layer(|_: ()| vals.pop().unwrap());
@@ -103,7 +120,7 @@ where
                 let node = node.map_layer(|seed| topush.push(State::PreVisit(seed)));
 
                 todo.push(State::PostVisit(node));
-                todo.extend(topush.into_iter());
+                todo.extend(topush);
             }
             State::PostVisit(node) => {
                 let node = node.map_layer(|_: ()| vals.pop().unwrap());
@@ -160,7 +177,7 @@ where
                 let layer: U1 = layer.map_layer(|seed| topush.push(State::PreVisit(seed)));
 
                 todo.push(State::Annotate(layer));
-                todo.extend(topush.into_iter());
+                todo.extend(topush);
             }
             State::Annotate(layer) => {
                 let layer2 = layer
@@ -0,0 +1,182 @@
+//! Interop with lossless syntax trees as used by rust-analyzer, via 'rowan'.
+//!
+//! A 'rowan::GreenNode' can be expanded into a 'RecursiveSyntax' and collapsed back without losing
+//! any information (including whitespace and comment tokens), so analyses written as collapses over
+//! 'SyntaxLayer' can run over any rowan-based syntax tree. '&GreenNodeData' also implements 'Project',
+//! so one-off collapses can run directly over the green tree without building an arena first.
+
+use std::borrow::Cow;
+
+use rowan::{GreenNode, GreenNodeData, GreenToken, GreenTokenData, NodeOrToken, SyntaxKind};
+
+use crate::map_layer::{EdgeLabeled, LayerArity, MapLayer, Project};
+use crate::recursive::{Collapse, Expand};
+use crate::recursive_tree::{arena_eval::ArenaIndex, RecursiveTree};
+
+/// A single layer of a syntax tree: either an interior node with an ordered list of children,
+/// or a leaf token carrying source text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxLayer<A> {
+    Node { kind: SyntaxKind, children: Vec<A> },
+    Token { kind: SyntaxKind, text: String },
+}
+
+/// Borrowed version of 'SyntaxLayer', produced by mapping over a '&SyntaxLayer'
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxLayerRef<'a, A> {
+    Node { kind: SyntaxKind, children: Vec<A> },
+    Token { kind: SyntaxKind, text: &'a str },
+}
+
+impl<A, B> MapLayer<B> for SyntaxLayer<A> {
+    type To = SyntaxLayer<B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, f: F) -> Self::To {
+        match self {
+            SyntaxLayer::Node { kind, children } => SyntaxLayer::Node {
+                kind,
+                children: children.into_iter().map(f).collect(),
+            },
+            SyntaxLayer::Token { kind, text } => SyntaxLayer::Token { kind, text },
+        }
+    }
+}
+
+impl<A> LayerArity for SyntaxLayer<A> {
+    type Child = A;
+
+    fn child_count(&self) -> usize {
+        match self {
+            SyntaxLayer::Node { children, .. } => children.len(),
+            SyntaxLayer::Token { .. } => 0,
+        }
+    }
+
+    fn child(&self, position: usize) -> Option<&A> {
+        match self {
+            SyntaxLayer::Node { children, .. } => children.get(position),
+            SyntaxLayer::Token { .. } => None,
+        }
+    }
+}
+
+/// Children are labeled by their indices
+impl<A> EdgeLabeled for SyntaxLayer<A> {
+    fn edge_label(&self, position: usize) -> Option<Cow<'_, str>> {
+        self.child(position).map(|_| position.to_string().into())
+    }
+}
+
+impl<'a, A: Copy, B: 'a> MapLayer<B> for &'a SyntaxLayer<A> {
+    type To = SyntaxLayerRef<'a, B>;
+    type Unwrapped = A;
+
+    fn map_layer<F: FnMut(Self::Unwrapped) -> B>(self, mut f: F) -> Self::To {
+        match self {
+            SyntaxLayer::Node { kind, children } => SyntaxLayerRef::Node {
+                kind: *kind,
+                children: children.iter().map(|x| f(*x)).collect(),
+            },
+            SyntaxLayer::Token { kind, text } => SyntaxLayerRef::Token { kind: *kind, text },
+        }
+    }
+}
+
+/// An element of a green tree, borrowed from some root 'GreenNode'
+pub type GreenElementRef<'a> = NodeOrToken<&'a GreenNodeData, &'a GreenTokenData>;
+
+impl<'a> Project for GreenElementRef<'a> {
+    type To = SyntaxLayer<GreenElementRef<'a>>;
+
+    fn project(self) -> Self::To {
+        match self {
+            NodeOrToken::Node(node) => SyntaxLayer::Node {
+                kind: node.kind(),
+                children: node.children().collect(),
+            },
+            NodeOrToken::Token(token) => SyntaxLayer::Token {
+                kind: token.kind(),
+                text: token.text().to_string(),
+            },
+        }
+    }
+}
+
+pub type RecursiveSyntax = RecursiveTree<SyntaxLayer<ArenaIndex>, ArenaIndex>;
+
+pub fn from_green(root: &GreenNodeData) -> RecursiveSyntax {
+    RecursiveSyntax::expand_layers(NodeOrToken::Node(root), Project::project)
+}
+
+/// Rebuild a green tree. Trees built via 'from_green' always have a node at the root.
+pub fn to_green(tree: RecursiveSyntax) -> NodeOrToken<GreenNode, GreenToken> {
+    tree.collapse_layers(|layer| match layer {
+        SyntaxLayer::Node { kind, children } => NodeOrToken::Node(GreenNode::new(kind, children)),
+        SyntaxLayer::Token { kind, text } => NodeOrToken::Token(GreenToken::new(kind, &text)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rowan::GreenNodeBuilder;
+
+    const ROOT: SyntaxKind = SyntaxKind(0);
+    const BIN_EXPR: SyntaxKind = SyntaxKind(1);
+    const NUMBER: SyntaxKind = SyntaxKind(2);
+    const PLUS: SyntaxKind = SyntaxKind(3);
+    const WHITESPACE: SyntaxKind = SyntaxKind(4);
+
+    // 1 + (2 + 3), with whitespace
+    fn example() -> GreenNode {
+        let mut builder = GreenNodeBuilder::new();
+        builder.start_node(ROOT);
+        builder.start_node(BIN_EXPR);
+        builder.token(NUMBER, "1");
+        builder.token(WHITESPACE, " ");
+        builder.token(PLUS, "+");
+        builder.token(WHITESPACE, " ");
+        builder.start_node(BIN_EXPR);
+        builder.token(NUMBER, "2");
+        builder.token(PLUS, "+");
+        builder.token(NUMBER, "3");
+        builder.finish_node();
+        builder.finish_node();
+        builder.finish_node();
+        builder.finish()
+    }
+
+    #[test]
+    fn round_trip() {
+        let green = example();
+        assert_eq!(to_green(from_green(&green)), NodeOrToken::Node(green));
+    }
+
+    #[test]
+    fn lossless_text() {
+        let tree = from_green(&example());
+        let text = tree.as_ref().collapse_layers(|layer| match layer {
+            SyntaxLayerRef::Node { children, .. } => children.concat(),
+            SyntaxLayerRef::Token { text, .. } => text.to_string(),
+        });
+        assert_eq!(text, "1 + 2+3");
+    }
+
+    #[test]
+    fn collapse_green_directly() {
+        let green = example();
+        let nesting = NodeOrToken::Node(&*green).collapse_layers(|layer| match layer {
+            SyntaxLayer::Node { kind, children } => {
+                let max = children.into_iter().max().unwrap_or(0);
+                if kind == BIN_EXPR {
+                    max + 1
+                } else {
+                    max
+                }
+            }
+            SyntaxLayer::Token { .. } => 0,
+        });
+        assert_eq!(nesting, 2);
+    }
+}
@@ -0,0 +1,418 @@
+//! Proptest strategies for generating random recursive structures.
+//!
+//! Trees are generated as a 'Shape', a boxed tree of layers with '()' in every
+//! child position, which can then be expanded into any representation that
+//! supports 'Expand'. This makes it easy to build the same random tree in
+//! multiple representations and check that algebras agree across them.
+//!
+//! 'assert_equivalent' packages this up as a differential test: the same algebra is run over a
+//! naive boxed representation and over an arena-backed 'RecursiveTree', whose collapse relies on
+//! unsafe code, and the results must agree.
+//!
+//! Failing cases shrink structurally, by hoisting subtrees into their parent's position
+//! and by replacing branches with leaves, so counterexamples minimize to a handful of nodes.
+
+use std::fmt::Debug;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::strategy::{NewTree, ValueTree};
+use proptest::test_runner::{TestError, TestRunner};
+
+use crate::map_layer::MapLayer;
+use crate::recursive::{Collapse, Expand};
+
+/// A generated tree, stored as a layer with no child values ('Layer<()>')
+/// plus the subtrees that fill its child positions, in 'map_layer' order.
+#[derive(Debug, Clone)]
+pub struct Shape<Shell> {
+    pub layer: Shell,
+    pub children: Vec<Shape<Shell>>,
+}
+
+impl<Shell> Shape<Shell> {
+    pub fn leaf(layer: Shell) -> Self {
+        Self {
+            layer,
+            children: Vec::new(),
+        }
+    }
+
+    /// Number of nodes in this tree
+    pub fn size(&self) -> usize {
+        1 + self.children.iter().map(Shape::size).sum::<usize>()
+    }
+
+    /// Number of layers on the longest path from the root to a leaf
+    pub fn depth(&self) -> usize {
+        1 + self.children.iter().map(Shape::depth).max().unwrap_or(0)
+    }
+
+    /// Expand this shape into some recursive structure, eg `RecursiveTree<Layer<ArenaIndex>, ArenaIndex>`
+    pub fn expand<Wrapped, Tree>(self) -> Tree
+    where
+        Shell: MapLayer<Shape<Shell>, Unwrapped = (), To = Wrapped>,
+        Tree: Expand<Shape<Shell>, Wrapped>,
+    {
+        Tree::expand_layers(self, |Shape { layer, children }| {
+            let mut children = children.into_iter();
+            // children were generated in map_layer order, so they can be handed back out the same way
+            layer.map_layer(|()| children.next().unwrap())
+        })
+    }
+
+    /// Build some naive boxed representation of this shape bottom-up, via 'embed'. This recurses
+    /// on the call stack, which is fine for generated trees of modest depth.
+    pub fn embed<Boxed, Wrapped>(self, embed: &impl Fn(Wrapped) -> Boxed) -> Boxed
+    where
+        Shell: MapLayer<Boxed, Unwrapped = (), To = Wrapped>,
+    {
+        let mut children = self.children.into_iter().map(|child| child.embed(embed));
+        embed(self.layer.map_layer(|()| children.next().unwrap()))
+    }
+}
+
+/// Controls the size distribution of generated trees, see 'Strategy::prop_recursive'
+#[derive(Debug, Clone, Copy)]
+pub struct TreeSize {
+    /// maximum number of branch layers between the root and any leaf
+    pub depth: u32,
+    /// number of nodes to aim for, generation bails out to leaves past this point
+    pub desired_size: u32,
+    /// expected number of children per branch layer
+    pub expected_branch_size: u32,
+}
+
+impl Default for TreeSize {
+    fn default() -> Self {
+        Self {
+            depth: 8,
+            desired_size: 256,
+            expected_branch_size: 2,
+        }
+    }
+}
+
+/// Generate random trees of layers. 'leaf' must only produce layers with no child positions,
+/// 'node' produces layers with any number of child positions, each of which is filled with a random subtree.
+pub fn arb_shape<Shell>(
+    leaf: impl Strategy<Value = Shell> + 'static,
+    node: impl Strategy<Value = Shell> + 'static,
+    size: TreeSize,
+) -> ShapeStrategy<Shell>
+where
+    Shell: MapLayer<(), Unwrapped = (), To = Shell> + Clone + Debug + 'static,
+{
+    let leaf = leaf.boxed();
+    let node = node.boxed();
+    let shapes = leaf
+        .clone()
+        .prop_map(Shape::leaf)
+        .prop_recursive(
+            size.depth,
+            size.desired_size,
+            size.expected_branch_size,
+            move |inner| {
+                node.clone()
+                    .prop_flat_map(move |layer| {
+                        let mut arity = 0;
+                        let layer = layer.map_layer(|()| arity += 1);
+                        (Just(layer), vec(inner.clone(), arity))
+                    })
+                    .prop_map(|(layer, children)| Shape { layer, children })
+            },
+        )
+        .boxed();
+
+    ShapeStrategy { shapes, leaf }
+}
+
+/// Strategy returned by 'arb_shape', generates trees with structure-aware shrinking.
+#[derive(Debug)]
+pub struct ShapeStrategy<Shell> {
+    shapes: BoxedStrategy<Shape<Shell>>,
+    leaf: BoxedStrategy<Shell>,
+}
+
+impl<Shell: Clone + Debug> Strategy for ShapeStrategy<Shell> {
+    type Tree = ShapeValueTree<Shell>;
+    type Value = Shape<Shell>;
+
+    fn new_tree(&self, runner: &mut TestRunner) -> NewTree<Self> {
+        let shape = self.shapes.new_tree(runner)?.current();
+        // branches shrink to the simplest possible leaf
+        let mut leaf = self.leaf.new_tree(runner)?;
+        while leaf.simplify() {}
+
+        Ok(ShapeValueTree::new(shape, leaf.current()))
+    }
+}
+
+/// A single step towards a smaller tree, addressing nodes by preorder index
+#[derive(Debug, Clone, Copy)]
+enum Shrink {
+    /// replace a node with one of its children, dropping its other children
+    Hoist { node: usize, child: usize },
+    /// replace a branch node with a leaf
+    Leaf { node: usize },
+}
+
+/// Value tree for 'ShapeStrategy'. Every shrink step strictly reduces the number of nodes,
+/// so shrinking always terminates. Leaf payloads are not shrunk.
+#[derive(Debug)]
+pub struct ShapeValueTree<Shell> {
+    current: Shape<Shell>,
+    minimal_leaf: Shell,
+    // remaining shrink steps for 'current', next step at the end
+    candidates: Vec<Shrink>,
+    // state prior to the last simplification, restored if it went too far
+    prev: Option<(Shape<Shell>, Vec<Shrink>)>,
+}
+
+impl<Shell: Clone + Debug> ShapeValueTree<Shell> {
+    fn new(current: Shape<Shell>, minimal_leaf: Shell) -> Self {
+        let candidates = Self::candidates(&current);
+        Self {
+            current,
+            minimal_leaf,
+            candidates,
+            prev: None,
+        }
+    }
+
+    // larger reductions come first: nodes are visited root first, and hoists are tried before leaves
+    fn candidates(shape: &Shape<Shell>) -> Vec<Shrink> {
+        let mut candidates = Vec::new();
+        let mut stack = vec![shape];
+        let mut node = 0;
+        while let Some(shape) = stack.pop() {
+            if !shape.children.is_empty() {
+                for child in 0..shape.children.len() {
+                    candidates.push(Shrink::Hoist { node, child });
+                }
+                candidates.push(Shrink::Leaf { node });
+            }
+            stack.extend(shape.children.iter().rev());
+            node += 1;
+        }
+        candidates.reverse();
+        candidates
+    }
+
+    fn apply(&self, shrink: Shrink) -> Shape<Shell> {
+        let target = match shrink {
+            Shrink::Hoist { node, .. } | Shrink::Leaf { node } => node,
+        };
+
+        let mut shape = self.current.clone();
+        let mut stack = vec![&mut shape];
+        let mut node = 0;
+        while let Some(shape) = stack.pop() {
+            if node == target {
+                *shape = match shrink {
+                    Shrink::Hoist { child, .. } => shape.children.swap_remove(child),
+                    Shrink::Leaf { .. } => Shape::leaf(self.minimal_leaf.clone()),
+                };
+                break;
+            }
+            stack.extend(shape.children.iter_mut().rev());
+            node += 1;
+        }
+        shape
+    }
+}
+
+impl<Shell: Clone + Debug> ValueTree for ShapeValueTree<Shell> {
+    type Value = Shape<Shell>;
+
+    fn current(&self) -> Self::Value {
+        self.current.clone()
+    }
+
+    fn simplify(&mut self) -> bool {
+        match self.candidates.pop() {
+            None => false,
+            Some(shrink) => {
+                let next = self.apply(shrink);
+                let candidates = Self::candidates(&next);
+                let prev = std::mem::replace(&mut self.current, next);
+                let prev_candidates = std::mem::replace(&mut self.candidates, candidates);
+                self.prev = Some((prev, prev_candidates));
+                true
+            }
+        }
+    }
+
+    fn complicate(&mut self) -> bool {
+        match self.prev.take() {
+            None => false,
+            Some((prev, prev_candidates)) => {
+                self.current = prev;
+                self.candidates = prev_candidates;
+                true
+            }
+        }
+    }
+}
+
+/// Generate random trees of layers, expanded into some recursive structure. See 'arb_shape'.
+pub fn arb_tree<Shell, Wrapped, Tree>(
+    leaf: impl Strategy<Value = Shell> + 'static,
+    node: impl Strategy<Value = Shell> + 'static,
+    size: TreeSize,
+) -> impl Strategy<Value = Tree>
+where
+    Shell: MapLayer<(), Unwrapped = (), To = Shell> + Clone + Debug + 'static,
+    Shell: MapLayer<Shape<Shell>, Unwrapped = (), To = Wrapped>,
+    Tree: Expand<Shape<Shell>, Wrapped> + Debug,
+{
+    arb_shape(leaf, node, size).prop_map(Shape::expand)
+}
+
+/// Differentially test two backends: for each tree produced by 'generator', build it via both
+/// 'boxed_impl' and 'arena_impl', collapse each with 'algebra', and panic if the results differ,
+/// reporting the smallest disagreeing tree found by shrinking.
+///
+/// Despite the names, any two representations can be compared, eg an arena and a stack machine.
+pub fn assert_equivalent<Shell, Boxed, Arena, O, A>(
+    boxed_impl: impl Fn(Shape<Shell>) -> Boxed,
+    arena_impl: impl Fn(Shape<Shell>) -> Arena,
+    algebra: impl Fn(O) -> A,
+    generator: impl Strategy<Value = Shape<Shell>>,
+) where
+    Shell: Clone + Debug,
+    for<'a> &'a Boxed: Collapse<A, O>,
+    Arena: Collapse<A, O>,
+    A: PartialEq + Debug,
+{
+    let mut runner = TestRunner::default();
+    let result = runner.run(&generator, |shape| {
+        let boxed = boxed_impl(shape.clone()).collapse_layers(&algebra);
+        let arena = arena_impl(shape).collapse_layers(&algebra);
+        prop_assert_eq!(boxed, arena);
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => {}
+        Err(TestError::Fail(reason, minimal)) => {
+            panic!(
+                "backends disagree: {}\nminimal input: {:#?}",
+                reason, minimal
+            )
+        }
+        Err(TestError::Abort(reason)) => panic!("differential test aborted: {}", reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::expr::naive::ExprAST;
+    use crate::examples::expr::{BlocAllocExpr, DFSStackExpr, Expr};
+    use crate::map_layer::Project;
+
+    // random trees can easily overflow, so use wrapping arithmetic
+    fn eval_wrapping(layer: Expr<i64>) -> i64 {
+        match layer {
+            Expr::Add(a, b) => a.wrapping_add(b),
+            Expr::Sub(a, b) => a.wrapping_sub(b),
+            Expr::Mul(a, b) => a.wrapping_mul(b),
+            Expr::LiteralInt(x) => x,
+        }
+    }
+
+    fn arb_expr_shape(size: TreeSize) -> impl Strategy<Value = Shape<Expr<()>>> {
+        arb_shape(
+            any::<i8>().prop_map(|x| Expr::LiteralInt(x as i64)),
+            prop_oneof![
+                Just(Expr::Add((), ())),
+                Just(Expr::Sub((), ())),
+                Just(Expr::Mul((), ())),
+            ],
+            size,
+        )
+    }
+
+    fn embed(layer: Expr<ExprAST>) -> ExprAST {
+        match layer {
+            Expr::Add(a, b) => ExprAST::Add(Box::new(a), Box::new(b)),
+            Expr::Sub(a, b) => ExprAST::Sub(Box::new(a), Box::new(b)),
+            Expr::Mul(a, b) => ExprAST::Mul(Box::new(a), Box::new(b)),
+            Expr::LiteralInt(x) => ExprAST::LiteralInt(x),
+        }
+    }
+
+    #[test]
+    fn backends_agree() {
+        assert_equivalent(
+            |shape| shape.embed(&embed),
+            Shape::expand::<_, BlocAllocExpr>,
+            eval_wrapping,
+            arb_expr_shape(TreeSize::default()),
+        );
+        assert_equivalent(
+            |shape| shape.embed(&embed),
+            Shape::expand::<_, DFSStackExpr>,
+            eval_wrapping,
+            arb_expr_shape(TreeSize::default()),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "backends disagree")]
+    fn detects_disagreement() {
+        // a broken backend, which swaps the operands of every subtraction
+        let broken = |shape: Shape<Expr<()>>| -> BlocAllocExpr {
+            let swapped = shape.embed(&|layer| match layer {
+                Expr::Sub(a, b) => embed(Expr::Sub(b, a)),
+                layer => embed(layer),
+            });
+            BlocAllocExpr::expand_layers(&swapped, Project::project)
+        };
+        assert_equivalent(
+            |shape| shape.embed(&embed),
+            broken,
+            eval_wrapping,
+            arb_expr_shape(TreeSize::default()),
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn arena_and_stack_machine_agree(shape in arb_expr_shape(TreeSize::default())) {
+            let arena: BlocAllocExpr = shape.clone().expand();
+            let stack: DFSStackExpr = shape.expand();
+
+            prop_assert_eq!(arena.collapse_layers(eval_wrapping), stack.collapse_layers(eval_wrapping));
+        }
+
+        #[test]
+        fn respects_depth(shape in arb_expr_shape(TreeSize { depth: 3, ..TreeSize::default() })) {
+            // depth counts branch layers, plus one for the leaves
+            prop_assert!(shape.depth() <= 4);
+        }
+    }
+
+    #[test]
+    fn shrinks_to_minimal_counterexample() {
+        fn contains_mul(shape: &Shape<Expr<()>>) -> bool {
+            matches!(shape.layer, Expr::Mul(..)) || shape.children.iter().any(contains_mul)
+        }
+
+        let mut runner = TestRunner::default();
+        let result = runner.run(&arb_expr_shape(TreeSize::default()), |shape| {
+            prop_assert!(!contains_mul(&shape));
+            Ok(())
+        });
+
+        match result {
+            Err(proptest::test_runner::TestError::Fail(_, minimal)) => {
+                // a single multiplication of two leaves
+                assert!(matches!(minimal.layer, Expr::Mul(..)));
+                assert_eq!(minimal.size(), 3);
+            }
+            other => panic!("expected a shrunk failure, got {:?}", other),
+        }
+    }
+}